@@ -0,0 +1,98 @@
+//! Instructions per second of the CPU and lines per second of the PPU
+//! renderers, to compare the performance work against.
+//! ```shell
+//! cargo bench --bench throughput
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use gb::cpu::{Cpu, Registers};
+use gb::mmu::Mmu;
+use gb::model::Model;
+use gb::ppu::{Ppu, Renderer, BGP, LCDC, OBP0};
+
+// Instructions executed per iteration
+const STEPS: u64 = 10_000;
+// Lines of a frame, VBlank included
+const LINES: u64 = 154;
+
+// LD HL,0xc000; then 256 times LD A,(HL); ADD A,B; LD (HL+),A; INC B;
+// XOR C; RLCA; DEC E; JR NZ,-9; and JR -14 to start again
+const LOOP: &[u8] = &[
+    0x21, 0x00, 0xc0, 0x7e, 0x80, 0x22, 0x04, 0xa9, 0x07, 0x1d, 0x20, 0xf7, 0x18, 0xf2,
+];
+
+fn run(cpu: &mut Cpu, mmu: &mut Mmu) {
+    for _ in 0..STEPS {
+        cpu.step(mmu);
+    }
+}
+
+fn cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(STEPS));
+
+    // The CPU alone, on RAM without any hardware
+    let mut mmu = Mmu::flat();
+    for (addr, &byte) in (0x0100..).zip(LOOP) {
+        mmu.write(addr, byte);
+    }
+    let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+    group.bench_function("flat", |b| b.iter(|| run(&mut cpu, &mut mmu)));
+
+    // With the PPU, the timer and the APU advancing after each instruction
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + LOOP.len()].copy_from_slice(LOOP);
+    let mut mmu = Mmu::after_boot(rom, Model::Dmg);
+    let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+    group.bench_function("hardware", |b| b.iter(|| run(&mut cpu, &mut mmu)));
+    group.finish();
+}
+
+/// PPU drawing a background of varied tiles and 10 objects, with the LCD on
+fn ppu_with_scene(renderer: Renderer) -> Ppu {
+    let mut ppu = Ppu::with_model(Model::Dmg);
+    ppu.set_renderer(renderer);
+    for addr in 0x8000..0x9800u16 {
+        ppu.write(addr, (addr as u8).wrapping_mul(37));
+    }
+    for addr in 0x9800..0x9c00u16 {
+        ppu.write(addr, addr as u8);
+    }
+    for (i, addr) in (0xfe00..0xfe28u16).step_by(4).enumerate() {
+        let i = i as u8;
+        for (offset, value) in [16 + i * 12, 8 + i * 15, i, (i & 1) << 5]
+            .into_iter()
+            .enumerate()
+        {
+            ppu.write(addr + offset as u16, value);
+        }
+    }
+    ppu.write(BGP, 0xe4);
+    ppu.write(OBP0, 0xd2);
+    // LCD, objects and background on
+    ppu.write(LCDC, 0x93);
+    ppu
+}
+
+fn ppu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ppu");
+    group.throughput(Throughput::Elements(LINES));
+    for (name, renderer) in [
+        ("scanline", Renderer::Scanline),
+        ("pixel_fifo", Renderer::PixelFifo),
+    ] {
+        let mut ppu = ppu_with_scene(renderer);
+        // One frame, 4 T-cycles at a time as after each M-cycle of the CPU
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for _ in 0..LINES * 456 / 4 {
+                    ppu.tick(4);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cpu, ppu);
+criterion_main!(benches);
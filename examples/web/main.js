@@ -0,0 +1,87 @@
+// Browser frontend: one frame per animation frame, audio queued on the
+// AudioContext, keyboard bindings of the gui binary
+import init, { WebEmulator } from "./pkg/gb_web.js";
+
+// Bit n of the buttons is gb::joypad::Button::ALL[n]
+const BUTTONS = {
+  ArrowRight: 0x01,
+  ArrowLeft: 0x02,
+  ArrowUp: 0x04,
+  ArrowDown: 0x08,
+  KeyX: 0x10,
+  KeyZ: 0x20,
+  Backspace: 0x40,
+  Enter: 0x80,
+};
+
+const screen = document.getElementById("screen").getContext("2d");
+let emulator = null;
+let audio = null;
+// Time of the AudioContext when the queued samples end
+let audioEnd = 0;
+let buttons = 0;
+
+function playSamples(samples) {
+  const frames = samples.length / 2;
+  if (frames === 0) {
+    return;
+  }
+  const buffer = audio.createBuffer(2, frames, audio.sampleRate);
+  const left = buffer.getChannelData(0);
+  const right = buffer.getChannelData(1);
+  for (let i = 0; i < frames; i++) {
+    left[i] = samples[2 * i];
+    right[i] = samples[2 * i + 1];
+  }
+  const source = audio.createBufferSource();
+  source.buffer = buffer;
+  source.connect(audio.destination);
+  // Restart with a small latency after an underrun
+  audioEnd = Math.max(audioEnd, audio.currentTime + 0.05);
+  source.start(audioEnd);
+  audioEnd += buffer.duration;
+}
+
+function frame() {
+  emulator.set_buttons(buttons);
+  emulator.run_frame();
+  const pixels = new Uint8ClampedArray(emulator.framebuffer());
+  screen.putImageData(new ImageData(pixels, 160, 144), 0, 0);
+  playSamples(emulator.audio_samples());
+  requestAnimationFrame(frame);
+}
+
+document.addEventListener("keydown", (event) => {
+  if (event.code in BUTTONS) {
+    buttons |= BUTTONS[event.code];
+  } else if (event.code === "F5" && emulator) {
+    emulator.save_state();
+  } else if (event.code === "F7" && emulator) {
+    emulator.load_state();
+  } else {
+    return;
+  }
+  event.preventDefault();
+});
+
+document.addEventListener("keyup", (event) => {
+  if (event.code in BUTTONS) {
+    buttons &= ~BUTTONS[event.code];
+    event.preventDefault();
+  }
+});
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const rom = new Uint8Array(await event.target.files[0].arrayBuffer());
+  // Created on a user gesture, for the browser to allow the sound
+  audio = audio || new AudioContext();
+  const start = emulator === null;
+  emulator?.free();
+  emulator = new WebEmulator(rom, audio.sampleRate);
+  event.target.blur();
+  if (start) {
+    requestAnimationFrame(frame);
+  }
+});
+
+await init();
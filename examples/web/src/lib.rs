@@ -0,0 +1,66 @@
+//! Bindings of the emulator for the browser frontend of index.html. The
+//! rendering, the audio and the keyboard are handled in JavaScript.
+
+use wasm_bindgen::prelude::*;
+
+use gb::emulator::Options;
+use gb::Emulator;
+
+#[wasm_bindgen]
+pub struct WebEmulator {
+    emulator: Emulator,
+    // Quick save of the F5 and F7 keys
+    state: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl WebEmulator {
+    /// Power on with the content of a ROM file, compressed or not, and
+    /// produce the audio at the rate of the AudioContext
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], sample_rate: u32) -> Result<WebEmulator, JsError> {
+        let rom = gb::rom::decompress(rom.to_vec())?;
+        let options = Options {
+            sample_rate: Some(sample_rate),
+            ..Default::default()
+        };
+        Ok(Self {
+            emulator: Emulator::new(rom, &options),
+            state: None,
+        })
+    }
+
+    pub fn run_frame(&mut self) {
+        self.emulator.run_frame();
+    }
+
+    /// The screen in RGBA8, for an ImageData of 160x144
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.emulator.framebuffer().to_vec()
+    }
+
+    /// Audio of the last frame, interleaved left and right
+    pub fn audio_samples(&self) -> Vec<f32> {
+        self.emulator.audio_samples().to_vec()
+    }
+
+    /// Buttons held, bit n being `gb::joypad::Button::ALL[n]`
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.emulator.set_buttons(buttons);
+    }
+
+    pub fn save_state(&mut self) {
+        self.state = Some(self.emulator.save_state());
+    }
+
+    /// False without a saved state
+    pub fn load_state(&mut self) -> Result<bool, JsError> {
+        match &self.state {
+            Some(state) => {
+                self.emulator.load_state(state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
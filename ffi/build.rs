@@ -0,0 +1,10 @@
+// Regenerate include/gb.h from the functions of src/lib.rs
+fn main() {
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+    cbindgen::generate_with_config(&dir, config)
+        .expect("Unable to generate the C header")
+        .write_to_file(format!("{}/include/gb.h", dir));
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
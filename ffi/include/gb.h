@@ -0,0 +1,109 @@
+#ifndef GB_H
+#define GB_H
+
+/* Generated by cbindgen from ffi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define GB_SCREEN_WIDTH 160
+
+#define GB_SCREEN_HEIGHT 144
+
+/**
+ * Size of the framebuffer, in RGBA8
+ */
+#define GB_FRAMEBUFFER_SIZE ((160 * 144) * 4)
+
+#define GB_BUTTON_RIGHT 1
+
+#define GB_BUTTON_LEFT 2
+
+#define GB_BUTTON_UP 4
+
+#define GB_BUTTON_DOWN 8
+
+#define GB_BUTTON_A 16
+
+#define GB_BUTTON_B 32
+
+#define GB_BUTTON_SELECT 64
+
+#define GB_BUTTON_START 128
+
+/**
+ * Opaque handle on an emulator
+ */
+typedef struct GbEmulator GbEmulator;
+
+/**
+ * A console without cartridge, to free with gb_emulator_free
+ */
+struct GbEmulator *gb_emulator_new(void);
+
+/**
+ * # Safety
+ * `emulator` comes from gb_emulator_new and is not used afterwards
+ */
+void gb_emulator_free(struct GbEmulator *emulator);
+
+/**
+ * Insert the cartridge `rom` of `len` bytes, copied, and power on the console
+ *
+ * # Safety
+ * `rom` points to `len` readable bytes
+ */
+void gb_emulator_load_rom(struct GbEmulator *emulator, const uint8_t *rom, size_t len);
+
+/**
+ * # Safety
+ * `emulator` comes from gb_emulator_new
+ */
+void gb_emulator_run_frame(struct GbEmulator *emulator);
+
+/**
+ * The screen, GB_FRAMEBUFFER_SIZE bytes in RGBA8
+ *
+ * # Safety
+ * `emulator` comes from gb_emulator_new
+ */
+const uint8_t *gb_emulator_framebuffer(const struct GbEmulator *emulator);
+
+/**
+ * Audio of the last frame, interleaved left and right. The number of values
+ * is written to `len`.
+ *
+ * # Safety
+ * `emulator` comes from gb_emulator_new and `len` is writable
+ */
+const float *gb_emulator_audio_samples(const struct GbEmulator *emulator, size_t *len);
+
+/**
+ * Buttons held, a combination of the GB_BUTTON_ masks
+ *
+ * # Safety
+ * `emulator` comes from gb_emulator_new
+ */
+void gb_emulator_set_buttons(struct GbEmulator *emulator, uint8_t buttons);
+
+/**
+ * Write the state of the machine to `out` if it fits in `capacity` bytes.
+ * Returns the size of the state, call with a null `out` to get it first.
+ *
+ * # Safety
+ * `out` points to `capacity` writable bytes
+ */
+size_t gb_emulator_save_state(const struct GbEmulator *emulator, uint8_t *out, size_t capacity);
+
+/**
+ * Restore a state saved with the same ROM, false if it is invalid
+ *
+ * # Safety
+ * `data` points to `len` readable bytes
+ */
+bool gb_emulator_load_state(struct GbEmulator *emulator, const uint8_t *data, size_t len);
+
+#endif /* GB_H */
@@ -0,0 +1,216 @@
+//! C interface of the emulator, see include/gb.h. The emulators are created
+//! and freed by this library, the pointers returned stay valid until the
+//! next call on the same emulator.
+
+use std::slice;
+
+use gb::Emulator;
+
+// Literals, for cbindgen to write them in the header
+pub const GB_SCREEN_WIDTH: usize = 160;
+pub const GB_SCREEN_HEIGHT: usize = 144;
+/// Size of the framebuffer, in RGBA8
+pub const GB_FRAMEBUFFER_SIZE: usize = 160 * 144 * 4;
+
+// Masks of the buttons for gb_emulator_set_buttons, same as gb::joypad::Button
+pub const GB_BUTTON_RIGHT: u8 = 0x01;
+pub const GB_BUTTON_LEFT: u8 = 0x02;
+pub const GB_BUTTON_UP: u8 = 0x04;
+pub const GB_BUTTON_DOWN: u8 = 0x08;
+pub const GB_BUTTON_A: u8 = 0x10;
+pub const GB_BUTTON_B: u8 = 0x20;
+pub const GB_BUTTON_SELECT: u8 = 0x40;
+pub const GB_BUTTON_START: u8 = 0x80;
+
+/// Opaque handle on an emulator
+pub struct GbEmulator(Emulator);
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// A console without cartridge, to free with gb_emulator_free
+#[no_mangle]
+pub extern "C" fn gb_emulator_new() -> *mut GbEmulator {
+    Box::into_raw(Box::new(GbEmulator(Emulator::new(
+        Vec::new(),
+        &Default::default(),
+    ))))
+}
+
+/// # Safety
+/// `emulator` comes from gb_emulator_new and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_free(emulator: *mut GbEmulator) {
+    if !emulator.is_null() {
+        drop(Box::from_raw(emulator));
+    }
+}
+
+/// Insert the cartridge `rom` of `len` bytes, copied, and power on the console
+///
+/// # Safety
+/// `rom` points to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_load_rom(
+    emulator: *mut GbEmulator,
+    rom: *const u8,
+    len: usize,
+) {
+    if let Some(emulator) = emulator.as_mut() {
+        emulator.0 = Emulator::new(bytes(rom, len), &Default::default());
+    }
+}
+
+/// # Safety
+/// `emulator` comes from gb_emulator_new
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_run_frame(emulator: *mut GbEmulator) {
+    if let Some(emulator) = emulator.as_mut() {
+        emulator.0.run_frame();
+    }
+}
+
+/// The screen, GB_FRAMEBUFFER_SIZE bytes in RGBA8
+///
+/// # Safety
+/// `emulator` comes from gb_emulator_new
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_framebuffer(emulator: *const GbEmulator) -> *const u8 {
+    match emulator.as_ref() {
+        Some(emulator) => emulator.0.framebuffer().as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Audio of the last frame, interleaved left and right. The number of values
+/// is written to `len`.
+///
+/// # Safety
+/// `emulator` comes from gb_emulator_new and `len` is writable
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_audio_samples(
+    emulator: *const GbEmulator,
+    len: *mut usize,
+) -> *const f32 {
+    let samples = match emulator.as_ref() {
+        Some(emulator) => emulator.0.audio_samples(),
+        None => &[],
+    };
+    if let Some(len) = len.as_mut() {
+        *len = samples.len();
+    }
+    samples.as_ptr()
+}
+
+/// Buttons held, a combination of the GB_BUTTON_ masks
+///
+/// # Safety
+/// `emulator` comes from gb_emulator_new
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_set_buttons(emulator: *mut GbEmulator, buttons: u8) {
+    if let Some(emulator) = emulator.as_mut() {
+        emulator.0.set_buttons(buttons);
+    }
+}
+
+/// Write the state of the machine to `out` if it fits in `capacity` bytes.
+/// Returns the size of the state, call with a null `out` to get it first.
+///
+/// # Safety
+/// `out` points to `capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_save_state(
+    emulator: *const GbEmulator,
+    out: *mut u8,
+    capacity: usize,
+) -> usize {
+    let Some(emulator) = emulator.as_ref() else {
+        return 0;
+    };
+    let state = emulator.0.save_state();
+    if !out.is_null() && state.len() <= capacity {
+        slice::from_raw_parts_mut(out, state.len()).copy_from_slice(&state);
+    }
+    state.len()
+}
+
+/// Restore a state saved with the same ROM, false if it is invalid
+///
+/// # Safety
+/// `data` points to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn gb_emulator_load_state(
+    emulator: *mut GbEmulator,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    match emulator.as_mut() {
+        Some(emulator) => emulator.0.load_state(bytes(data, len)).is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gb::joypad::Button;
+    use gb::ppu::{FRAMEBUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(GB_SCREEN_WIDTH, SCREEN_WIDTH);
+        assert_eq!(GB_SCREEN_HEIGHT, SCREEN_HEIGHT);
+        assert_eq!(GB_FRAMEBUFFER_SIZE, FRAMEBUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_buttons() {
+        for (n, mask) in [
+            GB_BUTTON_RIGHT,
+            GB_BUTTON_LEFT,
+            GB_BUTTON_UP,
+            GB_BUTTON_DOWN,
+            GB_BUTTON_A,
+            GB_BUTTON_B,
+            GB_BUTTON_SELECT,
+            GB_BUTTON_START,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(Button::ALL[n].mask(), mask);
+        }
+    }
+
+    #[test]
+    fn test_emulator() {
+        let rom = vec![0u8; 0x8000];
+        unsafe {
+            let emulator = gb_emulator_new();
+            gb_emulator_load_rom(emulator, rom.as_ptr(), rom.len());
+            gb_emulator_set_buttons(emulator, GB_BUTTON_START);
+            gb_emulator_run_frame(emulator);
+            assert!(!gb_emulator_framebuffer(emulator).is_null());
+            let mut len = 0;
+            gb_emulator_audio_samples(emulator, &mut len);
+            assert!(len > 0);
+
+            let size = gb_emulator_save_state(emulator, std::ptr::null_mut(), 0);
+            let mut state = vec![0; size];
+            assert_eq!(
+                gb_emulator_save_state(emulator, state.as_mut_ptr(), size),
+                size
+            );
+            gb_emulator_run_frame(emulator);
+            assert!(gb_emulator_load_state(emulator, state.as_ptr(), size));
+            assert_eq!((*emulator).0.frame(), 1);
+            assert!(!gb_emulator_load_state(emulator, state.as_ptr(), 4));
+            gb_emulator_free(emulator);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Parse any annotation file, and the expressions of its Data annotations.
+//! The includes are left out, they would read the files of the machine.
+#![no_main]
+
+use gb::annotations::{Annotation, Purpose};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let text: String = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("@include"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    if let Ok(annotations) = Annotation::parse(&text) {
+        for annotation in annotations.values().flatten() {
+            if annotation.purpose == Purpose::Data {
+                let _ = annotation.data();
+            }
+        }
+        // The file written back parses to the same annotations
+        let config = Annotation::to_config(&annotations);
+        assert_eq!(Annotation::parse(&config).ok(), Some(annotations));
+    }
+});
@@ -0,0 +1,28 @@
+//! Decode any byte stream: every instruction consumes at least its opcode,
+//! and exactly `instruction_len` bytes when it is valid
+#![no_main]
+
+use gb::decoder::{decode, instruction_len};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut bytes = data.iter().copied();
+    let mut offset = 0;
+    while offset < data.len() {
+        let opcode = data[offset];
+        let result = decode(&mut bytes);
+        let consumed = data.len() - bytes.len();
+        let len = consumed - offset;
+        assert!(len > 0, "{:02x} at {} consumed nothing", opcode, offset);
+        if result.is_ok() {
+            assert_eq!(
+                len,
+                instruction_len(opcode) as usize,
+                "{:02x} at {}",
+                opcode,
+                offset
+            );
+        }
+        offset = consumed;
+    }
+});
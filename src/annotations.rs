@@ -1,9 +1,51 @@
+//! Annotations of a ROM, shared by the disassembler and the debugger. The
+//! file has one annotation per line: the address in hex, a letter for the
+//! purpose (Comment, Section, Goto, Label, Data) and the value. The value
+//! of Data is the number of bytes, optionally preceded by their type
+//! (`db`, `dw`, `str` or `tiles`). Lines starting with `#` are ignored.
+//! The addresses are offsets in the ROM, or `bank:address` as seen by the CPU.
+//! ```text
+//! 0x0150 S Entry point
+//! 0x0150 L main
+//! 0x0104 D tiles 0x30
+//! 02:4000 L level_data
+//! ```
+//! A Data annotation can cover an inclusive range instead, its value is then
+//! the optional type followed by an optional comment. `ADDRESS*COUNT` repeats
+//! an annotation COUNT times, after each data region or every STRIDE bytes
+//! with `ADDRESS*COUNT/STRIDE`, all in hex. The repeated labels are numbered
+//! from 0.
+//! ```text
+//! 0x0104-0x0133 D tiles Nintendo logo
+//! 0x4000*0x10 D tiles 0x80
+//! 0x3000*4/0x100 L table
+//! ```
+//! The number of bytes is an expression without spaces: numbers in hex, with
+//! or without `0x`, or in decimal with `0d`, labels of the file, `+`, `-`,
+//! `*` and parentheses.
+//! ```text
+//! 0x0200 L start
+//! 0x0280 L end
+//! 0x0200 D db end-start
+//! 0x4000 D tiles 0x10*0d16
+//! ```
+//! `@include audio.ann` adds the annotations of another file, relative to the
+//! directory of the file including it.
+
 use std::{
-    collections::BTreeMap, error::Error, fmt::Display, fs::File, io::Read, num::ParseIntError,
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::Read,
+    num::ParseIntError,
+    path::{Path, PathBuf},
 };
 
 use itertools::Itertools;
 
+use crate::disassembler::BANK_SIZE;
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Purpose {
     Comment,
@@ -13,6 +55,44 @@ pub enum Purpose {
     Data,
 }
 
+/// How the bytes of a Data annotation are shown
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DataKind {
+    /// Not shown, when the type is omitted
+    Skip,
+    /// Hex dump (`db`)
+    Bytes,
+    /// Little-endian 16-bit words (`dw`)
+    Words,
+    /// ASCII text (`str`)
+    Text,
+    /// 2bpp tiles (`tiles`)
+    Tiles,
+}
+
+impl DataKind {
+    fn from_name(name: &str) -> Result<Self, AnnotationError> {
+        Ok(match name {
+            "db" => DataKind::Bytes,
+            "dw" => DataKind::Words,
+            "str" => DataKind::Text,
+            "tiles" => DataKind::Tiles,
+            _ => return Err(AnnotationError::InvalidMnemonic(name.to_string())),
+        })
+    }
+
+    /// Name of the type in the annotations, `skip` when omitted
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataKind::Skip => "skip",
+            DataKind::Bytes => "db",
+            DataKind::Words => "dw",
+            DataKind::Text => "str",
+            DataKind::Tiles => "tiles",
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Annotation {
     pub location: usize,
@@ -31,32 +111,139 @@ impl Purpose {
             _ => return Err(AnnotationError::InvalidMnemonic(mnemonic.to_string())),
         })
     }
+
+    fn mnemonic(&self) -> &'static str {
+        match self {
+            Purpose::Comment => "C",
+            Purpose::Section => "S",
+            Purpose::Goto => "G",
+            Purpose::Label => "L",
+            Purpose::Data => "D",
+        }
+    }
 }
 
 impl Annotation {
+    /// Annotations of `data`, the included files are relative to the
+    /// current directory
     pub fn parse(data: &str) -> Result<BTreeMap<usize, Vec<Annotation>>, AnnotationError> {
-        let annotations = data
-            .split('\n')
-            .filter(|l| !l.trim().is_empty())
-            .filter(|l| !l.starts_with('#'))
-            .map(Annotation::from_line)
-            .collect::<Result<Vec<Annotation>, AnnotationError>>()?;
-
-        Ok(annotations
-            .iter()
-            .sorted_by_key(|a| a.location)
-            .group_by(|a| a.location)
-            .into_iter()
-            .map(|(key, group)| (key, group.cloned().collect()))
-            .collect::<BTreeMap<usize, Vec<Annotation>>>())
+        let mut source = Source::default();
+        source.add(data, Path::new(""), None)?;
+        source.parse()
     }
 
+    /// Annotations of a file, the included files are relative to its directory
     pub fn parse_file(
-        file_name: &String,
+        file_name: impl AsRef<Path>,
     ) -> Result<BTreeMap<usize, Vec<Annotation>>, AnnotationError> {
-        let mut tmp = String::new();
-        File::open(file_name).and_then(|mut f| f.read_to_string(&mut tmp))?;
-        Self::parse(&tmp)
+        let mut source = Source::default();
+        source.add_file(file_name.as_ref())?;
+        source.parse()
+    }
+
+    /// Annotation file content, one line per annotation. The ranges and the
+    /// repeated annotations are written one by one.
+    pub fn to_config(annotations: &BTreeMap<usize, Vec<Annotation>>) -> String {
+        annotations
+            .values()
+            .flatten()
+            .map(|a| {
+                format!(
+                    "0x{:04x} {} {}\n",
+                    a.location,
+                    a.purpose.mnemonic(),
+                    a.value
+                )
+            })
+            .collect()
+    }
+
+    pub fn save_file(
+        annotations: &BTreeMap<usize, Vec<Annotation>>,
+        file_name: impl AsRef<Path>,
+    ) -> Result<(), AnnotationError> {
+        Ok(std::fs::write(file_name, Self::to_config(annotations))?)
+    }
+
+    /// Type and number of bytes of a Data annotation
+    pub fn data(&self) -> Result<(DataKind, usize), AnnotationError> {
+        parse_data(&self.value, &BTreeMap::new())
+    }
+
+    /// Number of bytes covered by a Data annotation
+    pub fn data_len(&self) -> Result<usize, AnnotationError> {
+        Ok(self.data()?.1)
+    }
+
+    // Annotations of a line with a range or a repeat count. The expressions
+    // of the Data annotations are replaced by their value.
+    fn expand_line(
+        line: &str,
+        labels: &BTreeMap<String, usize>,
+    ) -> Result<Vec<Self>, AnnotationError> {
+        let (location, rest) = line.split_once(' ').ok_or(AnnotationError::MissingField)?;
+        if let Some((start, end)) = location.split_once('-') {
+            let (start, end) = (parse_location(start)?, parse_location(end)?);
+            let (purpose, value) = rest.split_once(' ').unwrap_or((rest, ""));
+            if Purpose::from_char(purpose)? != Purpose::Data || end < start {
+                return Err(AnnotationError::InvalidRange(location.to_string()));
+            }
+            let (kind, comment) = value.trim().split_once(' ').unwrap_or((value.trim(), ""));
+            let (kind, comment) = match DataKind::from_name(kind) {
+                Ok(_) => (format!("{} ", kind), comment.trim()),
+                // Skipped bytes
+                Err(_) => (String::new(), value.trim()),
+            };
+            let mut annotations = vec![Annotation {
+                location: start,
+                purpose: Purpose::Data,
+                value: format!("{}0x{:x}", kind, end - start + 1),
+            }];
+            if !comment.is_empty() {
+                annotations.push(Annotation {
+                    location: start,
+                    purpose: Purpose::Comment,
+                    value: comment.to_string(),
+                });
+            }
+            return Ok(annotations);
+        }
+
+        let Some((start, repeat)) = location.split_once('*') else {
+            return Ok(vec![Self::from_line(line)?.evaluate(labels)?]);
+        };
+        let (count, stride) = match repeat.split_once('/') {
+            Some((count, stride)) => (count, Some(parse_location(stride)?)),
+            None => (repeat, None),
+        };
+        let count = parse_location(count)?;
+        let first = Self::from_line(&format!("{} {}", start, rest))?.evaluate(labels)?;
+        let stride = match (stride, &first.purpose) {
+            (Some(stride), _) => stride,
+            (None, Purpose::Data) => first.data_len()?,
+            (None, _) => return Err(AnnotationError::MissingField),
+        };
+        Ok((0..count)
+            .map(|index| Annotation {
+                location: first.location + index * stride,
+                purpose: first.purpose.clone(),
+                value: match first.purpose {
+                    Purpose::Label => format!("{}_{}", first.value, index),
+                    _ => first.value.clone(),
+                },
+            })
+            .collect())
+    }
+
+    // Data annotation with the value of its expression
+    fn evaluate(mut self, labels: &BTreeMap<String, usize>) -> Result<Self, AnnotationError> {
+        if self.purpose == Purpose::Data {
+            self.value = match parse_data(&self.value, labels)? {
+                (DataKind::Skip, len) => format!("0x{:x}", len),
+                (kind, len) => format!("{} 0x{:x}", kind.name(), len),
+            };
+        }
+        Ok(self)
     }
 
     fn from_line(line: &str) -> Result<Self, AnnotationError> {
@@ -65,7 +252,7 @@ impl Annotation {
             Err(AnnotationError::MissingField)
         } else {
             Ok(Annotation {
-                location: usize::from_str_radix(items[0].trim_start_matches("0x"), 16)?,
+                location: parse_location(items[0])?,
                 purpose: Purpose::from_char(items[1])?,
                 value: items[2].to_string(),
             })
@@ -73,10 +260,193 @@ impl Annotation {
     }
 }
 
+// Lines of an annotation file and of the files it includes
+#[derive(Default)]
+struct Source {
+    // Path of each file, None for the data parsed directly
+    files: Vec<Option<PathBuf>>,
+    // Index of the file, number and text of the annotation lines
+    lines: Vec<(usize, usize, String)>,
+    // Files being read, to detect the loops
+    stack: Vec<PathBuf>,
+}
+
+impl Source {
+    fn add_file(&mut self, path: &Path) -> Result<(), AnnotationError> {
+        let mut data = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut data))?;
+        let canonical = path.canonicalize()?;
+        if self.stack.contains(&canonical) {
+            return Err(AnnotationError::IncludeLoop(path.to_path_buf()));
+        }
+        self.stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.add(&data, dir, Some(path))?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn add(&mut self, data: &str, dir: &Path, path: Option<&Path>) -> Result<(), AnnotationError> {
+        let file = self.files.len();
+        self.files.push(path.map(Path::to_path_buf));
+        for (index, line) in data.split('\n').enumerate() {
+            if let Some(include) = line.trim().strip_prefix("@include ") {
+                let result = self.add_file(&dir.join(include.trim()));
+                result.map_err(|err| self.error(file, index + 1, err))?;
+            } else if !line.trim().is_empty() && !line.starts_with('#') {
+                self.lines.push((file, index + 1, line.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    // Error on a line, with the name of the file if it was included
+    fn error(&self, file: usize, number: usize, err: AnnotationError) -> AnnotationError {
+        let err = AnnotationError::Line(number, Box::new(err));
+        match &self.files[file] {
+            Some(path) if file > 0 => AnnotationError::File(path.clone(), Box::new(err)),
+            _ => err,
+        }
+    }
+
+    fn parse(&self) -> Result<BTreeMap<usize, Vec<Annotation>>, AnnotationError> {
+        // The labels used in the expressions, the errors are reported below
+        let labels: BTreeMap<String, usize> = self
+            .lines
+            .iter()
+            .filter(|(_, _, line)| line.split(' ').nth(1) == Some("L"))
+            .filter_map(|(_, _, line)| Annotation::expand_line(line, &BTreeMap::new()).ok())
+            .flatten()
+            .map(|annotation| (annotation.value, annotation.location))
+            .collect();
+
+        let annotations = self
+            .lines
+            .iter()
+            .map(|(file, number, line)| {
+                Annotation::expand_line(line, &labels)
+                    .map_err(|err| self.error(*file, *number, err))
+            })
+            .flatten_ok()
+            .collect::<Result<Vec<Annotation>, AnnotationError>>()?;
+
+        Ok(annotations
+            .iter()
+            .sorted_by_key(|a| a.location)
+            .group_by(|a| a.location)
+            .into_iter()
+            .map(|(key, group)| (key, group.cloned().collect()))
+            .collect::<BTreeMap<usize, Vec<Annotation>>>())
+    }
+}
+
+// Type and number of bytes of the value of a Data annotation
+fn parse_data(
+    value: &str,
+    labels: &BTreeMap<String, usize>,
+) -> Result<(DataKind, usize), AnnotationError> {
+    let (kind, len) = match value.trim().split_once(' ') {
+        Some((kind, len)) => (DataKind::from_name(kind)?, len.trim()),
+        None => (DataKind::Skip, value.trim()),
+    };
+    let mut tokens = tokenize(len).into_iter().peekable();
+    let len = expression(&mut tokens, labels)?;
+    match tokens.next() {
+        Some(token) => Err(AnnotationError::InvalidExpression(token)),
+        None => Ok((kind, len)),
+    }
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<String>>;
+
+// Operators and parentheses, and the numbers and labels between them
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut word = false;
+    for c in text.chars() {
+        if "+-*()".contains(c) {
+            tokens.push(c.to_string());
+            word = false;
+        } else if word {
+            tokens.last_mut().unwrap().push(c);
+        } else {
+            tokens.push(c.to_string());
+            word = true;
+        }
+    }
+    tokens
+}
+
+// Sum of the terms
+fn expression(
+    tokens: &mut Tokens,
+    labels: &BTreeMap<String, usize>,
+) -> Result<usize, AnnotationError> {
+    let mut value = term(tokens, labels)?;
+    while let Some(operator) = tokens.next_if(|token| token == "+" || token == "-") {
+        let right = term(tokens, labels)?;
+        value = match operator.as_str() {
+            "+" => value.checked_add(right),
+            _ => value.checked_sub(right),
+        }
+        .ok_or(AnnotationError::InvalidExpression(operator))?;
+    }
+    Ok(value)
+}
+
+// Product of the factors
+fn term(tokens: &mut Tokens, labels: &BTreeMap<String, usize>) -> Result<usize, AnnotationError> {
+    let mut value = factor(tokens, labels)?;
+    while let Some(operator) = tokens.next_if(|token| token == "*") {
+        value = value
+            .checked_mul(factor(tokens, labels)?)
+            .ok_or(AnnotationError::InvalidExpression(operator))?;
+    }
+    Ok(value)
+}
+
+// Number, label or expression in parentheses
+fn factor(tokens: &mut Tokens, labels: &BTreeMap<String, usize>) -> Result<usize, AnnotationError> {
+    let token = tokens.next().ok_or(AnnotationError::MissingField)?;
+    match token.as_str() {
+        "(" => {
+            let value = expression(tokens, labels)?;
+            match tokens.next() {
+                Some(token) if token == ")" => Ok(value),
+                _ => Err(AnnotationError::InvalidExpression(token)),
+            }
+        }
+        "+" | "-" | "*" | ")" => Err(AnnotationError::InvalidExpression(token)),
+        _ => match labels.get(&token) {
+            Some(&address) => Ok(address),
+            None => match token.strip_prefix("0d") {
+                Some(decimal) => Ok(decimal.parse()?),
+                None => Ok(usize::from_str_radix(token.trim_start_matches("0x"), 16)?),
+            },
+        },
+    }
+}
+
+// Offset in the ROM of `0x1234` or `bank:address`
+fn parse_location(text: &str) -> Result<usize, ParseIntError> {
+    let hex = |text: &str| usize::from_str_radix(text.trim_start_matches("0x"), 16);
+    match text.split_once(':') {
+        Some((bank, address)) => Ok(hex(bank)? * BANK_SIZE + hex(address)? % BANK_SIZE),
+        None => hex(text),
+    }
+}
+
 #[derive(Debug)]
 pub enum AnnotationError {
     MissingField,
     InvalidMnemonic(String),
+    InvalidRange(String),
+    InvalidExpression(String),
+    /// Error on a line of the file, numbered from 1
+    Line(usize, Box<AnnotationError>),
+    /// Error in an included file
+    File(PathBuf, Box<AnnotationError>),
+    IncludeLoop(PathBuf),
     IOError(std::io::Error),
     ParseError(ParseIntError),
 }
@@ -86,6 +456,11 @@ impl Error for AnnotationError {
         match self {
             Self::MissingField => None,
             Self::InvalidMnemonic(_m) => None,
+            Self::InvalidRange(_) => None,
+            Self::InvalidExpression(_) => None,
+            Self::Line(_, err) => Some(err.as_ref()),
+            Self::File(_, err) => Some(err.as_ref()),
+            Self::IncludeLoop(_) => None,
             Self::IOError(err) => Some(err),
             Self::ParseError(err) => Some(err),
         }
@@ -109,6 +484,11 @@ impl Display for AnnotationError {
         match self {
             Self::MissingField => f.write_str("Missing Field in Annotation"),
             Self::InvalidMnemonic(m) => write!(f, "Invalid Mnemonic {}", m),
+            Self::InvalidRange(range) => write!(f, "Invalid range {}", range),
+            Self::InvalidExpression(token) => write!(f, "Invalid expression at {}", token),
+            Self::Line(number, err) => write!(f, "Line {}: {}", number, err),
+            Self::File(path, err) => write!(f, "{}: {}", path.display(), err),
+            Self::IncludeLoop(path) => write!(f, "{} includes itself", path.display()),
             Self::IOError(err) => write!(f, "IO Error {}", err),
             Self::ParseError(err) => write!(f, "Parse error: {}", err),
         }
@@ -146,6 +526,8 @@ mod tests {
             value: "some comment".to_string(),
         };
         assert_eq!(Annotation::from_line(line).unwrap(), expected);
+        let banked = Annotation::from_line("02:4abc L level").unwrap();
+        assert_eq!(banked.location, 0xabc + 2 * 0x4000);
     }
 
     #[test]
@@ -154,6 +536,26 @@ mod tests {
         assert!(Annotation::from_line(line).is_err());
     }
 
+    #[test]
+    fn test_annotation_data() {
+        let data = |value: &str| Annotation {
+            location: 0,
+            purpose: Purpose::Data,
+            value: value.to_string(),
+        };
+        assert_eq!(data("0x30").data().unwrap(), (DataKind::Skip, 0x30));
+        assert_eq!(data("dw 0x10").data().unwrap(), (DataKind::Words, 0x10));
+        assert_eq!(data("tiles 20").data().unwrap(), (DataKind::Tiles, 0x20));
+        assert!(matches!(
+            data("dq 0x10").data(),
+            Err(AnnotationError::InvalidMnemonic(_))
+        ));
+        assert!(matches!(
+            data("str").data(),
+            Err(AnnotationError::ParseError(_))
+        ));
+    }
+
     #[test]
     fn test_annotation_parse() {
         let data = "0x1234 C comment\n0x5678 S section".to_string();
@@ -175,6 +577,10 @@ mod tests {
             }],
         );
         assert_eq!(Annotation::parse(&data).unwrap(), expected);
+        assert_eq!(
+            Annotation::to_config(&expected),
+            "0x1234 C comment\n0x5678 S section\n"
+        );
     }
 
     #[test]
@@ -182,19 +588,122 @@ mod tests {
         let data = "0x1234 C value\n0x567w S test".to_string();
         assert!(matches!(
             Annotation::parse(&data).unwrap_err(),
-            AnnotationError::ParseError(_err)
+            AnnotationError::Line(2, err) if matches!(*err, AnnotationError::ParseError(_))
         ));
 
         let data = "0x1234 C\n0x567a S test".to_string();
         assert!(matches!(
             Annotation::parse(&data).unwrap_err(),
-            AnnotationError::MissingField
+            AnnotationError::Line(1, err) if matches!(*err, AnnotationError::MissingField)
         ));
 
-        let data = "0x1234 C test\n0x567a W test".to_string();
+        let data = "# header\n\n0x1234 C test\n0x567a W test".to_string();
+        let err = Annotation::parse(&data).unwrap_err();
         assert!(matches!(
-            Annotation::parse(&data).unwrap_err(),
-            AnnotationError::InvalidMnemonic(_err)
+            &err,
+            AnnotationError::Line(4, err) if matches!(**err, AnnotationError::InvalidMnemonic(_))
+        ));
+        assert_eq!(err.to_string(), "Line 4: Invalid Mnemonic W");
+    }
+
+    #[test]
+    fn test_annotation_expressions() {
+        let annotations = Annotation::parse(
+            "0x200 L start\n0x280 L end\n0x200 D db end-start\n0x300 D 0x10*4\n\
+             0x400 D tiles (0d16+2)*0x10\n0x500*2 D dw 0d10",
+        )
+        .unwrap();
+        let data = |location| annotations[&location].last().unwrap().data().unwrap();
+        assert_eq!(data(0x200), (DataKind::Bytes, 0x80));
+        assert_eq!(data(0x300), (DataKind::Skip, 0x40));
+        assert_eq!(data(0x400), (DataKind::Tiles, 0x120));
+        assert_eq!(data(0x50a), (DataKind::Words, 10));
+        assert_eq!(annotations[&0x200][1].value, "db 0x80");
+
+        let error = |data| match Annotation::parse(data) {
+            Err(AnnotationError::Line(number, err)) => (number, err.to_string()),
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(
+            error("0x200 L start\n0x300 D start-end"),
+            (2, "Parse error: invalid digit found in string".to_string())
+        );
+        assert_eq!(
+            error("0x300 D 1-2"),
+            (1, "Invalid expression at -".to_string())
+        );
+        assert_eq!(
+            error("0x300 D (1+2"),
+            (1, "Invalid expression at (".to_string())
+        );
+        assert_eq!(
+            error("0x300 D 1+"),
+            (1, "Missing Field in Annotation".to_string())
+        );
+    }
+
+    #[test]
+    fn test_annotation_include() {
+        let dir = std::env::temp_dir().join(format!("gb-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("banks")).unwrap();
+        std::fs::write(
+            dir.join("main.ann"),
+            "0x100 L start\n@include banks/audio.ann\n0x150 C main",
+        )
+        .unwrap();
+        std::fs::write(dir.join("end.ann"), "0x110 L end").unwrap();
+        std::fs::write(
+            dir.join("banks/audio.ann"),
+            "# audio\n0x4000 D db end-start\n@include ../end.ann",
+        )
+        .unwrap();
+        let annotations = Annotation::parse_file(dir.join("main.ann")).unwrap();
+        assert_eq!(annotations[&0x4000][0].value, "db 0x10");
+        assert_eq!(annotations[&0x150][0].value, "main");
+
+        std::fs::write(dir.join("end.ann"), "0x110 L end\n0x120 X bad").unwrap();
+        let err = Annotation::parse_file(dir.join("main.ann")).unwrap_err();
+        assert!(err.to_string().ends_with(&format!(
+            "{}: Line 2: Invalid Mnemonic X",
+            dir.join("banks/../end.ann").display()
+        )));
+
+        std::fs::write(dir.join("end.ann"), "@include main.ann").unwrap();
+        let err = Annotation::parse_file(dir.join("main.ann")).unwrap_err();
+        assert!(err.to_string().ends_with("main.ann includes itself"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_annotation_ranges() {
+        let annotations = Annotation::parse(
+            "0x104-0x133 D tiles Nintendo logo\n0x134-0x143 D title\n\
+             0x4000*3 D db 0x10\n0x3000*2/0x100 L table",
+        )
+        .unwrap();
+        let values = |location| {
+            annotations[&location]
+                .iter()
+                .map(|a| a.value.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(0x104), ["tiles 0x30", "Nintendo logo"]);
+        assert_eq!(values(0x134), ["0x10", "title"]);
+        assert_eq!(values(0x4020), ["db 0x10"]);
+        assert!(!annotations.contains_key(&0x4030));
+        assert_eq!(values(0x3100), ["table_1"]);
+
+        assert!(matches!(
+            Annotation::parse("0x20-0x10 D db"),
+            Err(AnnotationError::Line(1, err)) if matches!(*err, AnnotationError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            Annotation::parse("0x10-0x20 C comment"),
+            Err(AnnotationError::Line(1, err)) if matches!(*err, AnnotationError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            Annotation::parse("0x10*2 C comment"),
+            Err(AnnotationError::Line(1, err)) if matches!(*err, AnnotationError::MissingField)
         ));
     }
 }
@@ -0,0 +1,93 @@
+//! Volume envelope of the pulse and noise channels, controlled by NRx2
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Envelope {
+    // Initial volume (bits 4-7), direction (bit 3) and pace (bits 0-2)
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    /// The DAC of the channel is powered by the upper 5 bits of NRx2
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xf8 != 0
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn pace(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.pace();
+    }
+
+    /// Called by the frame sequencer at 64 Hz. The volume changes every
+    /// `pace` calls, a pace of 0 disables the envelope.
+    pub fn clock(&mut self) {
+        if self.pace() == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.pace();
+            if self.register & 0x08 != 0 {
+                self.volume = (self.volume + 1).min(15);
+            } else {
+                self.volume = self.volume.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_decrease() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x22);
+        envelope.trigger();
+        assert_eq!(envelope.volume(), 2);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 2);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 1);
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+    }
+
+    #[test]
+    fn test_envelope_increase() {
+        let mut envelope = Envelope::default();
+        envelope.write(0xe9);
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+
+        // Pace 0 keeps the initial volume
+        envelope.write(0x30);
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 3);
+    }
+}
@@ -0,0 +1,114 @@
+//! Length counter, silences a channel after a number of 256 Hz ticks
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Length {
+    counter: u16,
+    // 64, or 256 for the wave channel
+    max: u16,
+    enabled: bool,
+}
+
+impl Length {
+    pub fn new(max: u16) -> Self {
+        Self {
+            counter: 0,
+            max,
+            enabled: false,
+        }
+    }
+
+    /// Load the length from NRx1
+    pub fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Called by the frame sequencer at 256 Hz. Returns true when the
+    /// counter expires and the channel must be disabled.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    /// Update the length enable bit of NRx4 and reload the counter on
+    /// trigger. `clocked_next` tells whether the next step of the frame
+    /// sequencer clocks the length counters. Returns true if the channel
+    /// must be disabled.
+    pub fn write_control(&mut self, enable: bool, trigger: bool, clocked_next: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+        let mut expired = false;
+        // When the next step does not clock the length, enabling it clocks
+        // it once immediately
+        if !clocked_next && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0 && !trigger;
+        }
+        if trigger && self.counter == 0 {
+            // The reloaded counter gets the same extra clock
+            self.counter = if enable && !clocked_next {
+                self.max - 1
+            } else {
+                self.max
+            };
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_expires() {
+        let mut length = Length::new(64);
+        length.load(62);
+        assert!(!length.clock());
+        length.write_control(true, false, true);
+        assert!(!length.clock());
+        assert!(length.clock());
+        assert!(!length.clock());
+    }
+
+    #[test]
+    fn test_length_extra_clock_on_enable() {
+        let mut length = Length::new(64);
+        length.load(62);
+        assert!(!length.write_control(true, false, false));
+        assert!(length.clock());
+
+        // Already enabled: no extra clock
+        length.load(62);
+        assert!(!length.write_control(true, false, false));
+        assert!(!length.clock());
+
+        length.write_control(false, false, true);
+        length.load(63);
+        assert!(length.write_control(true, false, false));
+    }
+
+    #[test]
+    fn test_length_trigger_reload() {
+        let mut length = Length::new(256);
+        length.write_control(true, true, true);
+        assert_eq!(length.counter, 256);
+
+        let mut length = Length::new(256);
+        length.write_control(true, true, false);
+        assert_eq!(length.counter, 255);
+
+        // The length is only reloaded when it expired
+        length.load(200);
+        length.write_control(true, true, true);
+        assert_eq!(length.counter, 56);
+    }
+}
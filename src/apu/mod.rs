@@ -0,0 +1,496 @@
+//! Audio processing unit
+//! See https://gbdev.io/pandocs/Audio.html
+
+use noise::Noise;
+use square::Square;
+use wave::Wave;
+
+mod envelope;
+mod length;
+mod noise;
+mod square;
+mod sweep;
+mod wave;
+
+pub const NR10: u16 = 0xff10;
+pub const NR11: u16 = 0xff11;
+pub const NR12: u16 = 0xff12;
+pub const NR13: u16 = 0xff13;
+pub const NR14: u16 = 0xff14;
+pub const NR21: u16 = 0xff16;
+pub const NR22: u16 = 0xff17;
+pub const NR23: u16 = 0xff18;
+pub const NR24: u16 = 0xff19;
+pub const NR30: u16 = 0xff1a;
+pub const NR31: u16 = 0xff1b;
+pub const NR32: u16 = 0xff1c;
+pub const NR33: u16 = 0xff1d;
+pub const NR34: u16 = 0xff1e;
+pub const NR41: u16 = 0xff20;
+pub const NR42: u16 = 0xff21;
+pub const NR43: u16 = 0xff22;
+pub const NR44: u16 = 0xff23;
+pub const NR50: u16 = 0xff24;
+pub const NR51: u16 = 0xff25;
+pub const NR52: u16 = 0xff26;
+pub const WAVE_RAM: u16 = 0xff30;
+
+/// Frequency of the T-cycles clocking the APU
+pub const CLOCK_RATE: u32 = 4_194_304;
+/// Frequency of the samples produced by the APU, unless changed with `set_sample_rate`
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u32 = CLOCK_RATE / 512;
+
+const NR52_POWER: u8 = 0x80;
+
+trait Channel {
+    fn enabled(&self) -> bool;
+    /// Digital output of the channel, from 0 to 15
+    fn output(&self) -> u8;
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    // Master volume of each side
+    nr50: u8,
+    // Channels sent to the left (bits 4-7) and right (bits 0-3) outputs
+    nr51: u8,
+    powered: bool,
+    frame_sequencer_timer: u32,
+    frame_sequencer_step: u8,
+    // Channels 1 to 4 silenced in the mix, the emulation is not affected
+    muted: [bool; 4],
+    solo: Option<usize>,
+    sample_rate: u32,
+    // T-cycles elapsed since the last sample, multiplied by the sample rate
+    sample_timer: u64,
+    // Stereo samples, interleaved left then right
+    #[cfg_attr(feature = "serde", serde(skip))]
+    samples: Vec<f32>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            square1: Default::default(),
+            square2: Default::default(),
+            wave: Default::default(),
+            noise: Default::default(),
+            nr50: 0,
+            nr51: 0,
+            powered: false,
+            frame_sequencer_timer: 0,
+            frame_sequencer_step: 0,
+            muted: [false; 4],
+            solo: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_timer: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Change the frequency of the samples. Frontends pacing the emulation
+    /// with their own clock can adjust it slightly depending on the fill
+    /// level of their audio buffer (dynamic rate control).
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        assert!(sample_rate > 0, "The sample rate must not be 0");
+        self.sample_rate = sample_rate;
+    }
+
+    /// Move the samples produced so far to the end of `out`, as interleaved
+    /// left and right values between -1.0 and 1.0
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        out.append(&mut self.samples);
+    }
+
+    /// Number of samples (twice the number of stereo frames) waiting to be
+    /// drained. Up to one second is kept, later samples are dropped.
+    pub fn buffered_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Channels are numbered from 1 to 4, like in the register names
+    pub fn muted(&self, channel: usize) -> bool {
+        self.muted[channel - 1]
+    }
+
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        self.muted[channel - 1] = muted;
+    }
+
+    pub fn solo(&self) -> Option<usize> {
+        self.solo
+    }
+
+    /// Only play `channel` (1 to 4), whatever the muted channels. `None`
+    /// plays all the channels again.
+    pub fn set_solo(&mut self, channel: Option<usize>) {
+        assert!(
+            channel.is_none_or(|channel| (1..=4).contains(&channel)),
+            "Invalid channel {:?}",
+            channel
+        );
+        self.solo = channel;
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn keep_settings(&mut self, previous: &Self) {
+        self.muted = previous.muted;
+        self.solo = previous.solo;
+        self.sample_rate = previous.sample_rate;
+    }
+
+    fn audible(&self, index: usize) -> bool {
+        match self.solo {
+            Some(channel) => channel == index + 1,
+            None => !self.muted[index],
+        }
+    }
+
+    /// Advance the APU by `cycles` T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            // Run the channels up to the next sample
+            let until_sample =
+                (CLOCK_RATE as u64 - self.sample_timer).div_ceil(self.sample_rate as u64);
+            let step = cycles.min(until_sample as u32);
+            self.step(step);
+            cycles -= step;
+
+            self.sample_timer += step as u64 * self.sample_rate as u64;
+            if self.sample_timer >= CLOCK_RATE as u64 {
+                self.sample_timer -= CLOCK_RATE as u64;
+                if self.samples.len() < self.sample_rate as usize * 2 {
+                    let (left, right) = self.mix();
+                    self.samples.push(left);
+                    self.samples.push(right);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if !self.powered {
+            return;
+        }
+        self.square1.tick(cycles);
+        self.square2.tick(cycles);
+        self.wave.tick(cycles);
+        self.noise.tick(cycles);
+
+        self.frame_sequencer_timer += cycles;
+        while self.frame_sequencer_timer >= FRAME_SEQUENCER_PERIOD {
+            self.frame_sequencer_timer -= FRAME_SEQUENCER_PERIOD;
+            self.step_frame_sequencer();
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        // Length counters are clocked at 256 Hz
+        if self.frame_sequencer_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        // The sweep is clocked at 128 Hz
+        if self.frame_sequencer_step % 4 == 2 {
+            self.square1.clock_sweep();
+        }
+        // Envelopes are clocked at 64 Hz
+        if self.frame_sequencer_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    /// Whether the next step of the frame sequencer clocks the length counters
+    fn length_clocked_next(&self) -> bool {
+        self.frame_sequencer_step.is_multiple_of(2)
+    }
+
+    fn channels(&self) -> [&dyn Channel; 4] {
+        [&self.square1, &self.square2, &self.wave, &self.noise]
+    }
+
+    /// Mix the output of the channels into a stereo sample
+    fn mix(&self) -> (f32, f32) {
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, channel) in self.channels().iter().enumerate() {
+            // The DAC converts the 0-15 digital value to an analog one from -1 to 1
+            let analog = if channel.enabled() && self.audible(i) {
+                channel.output() as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            };
+            if self.nr51 & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if self.nr51 & (0x01 << i) != 0 {
+                right += analog;
+            }
+        }
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+        (
+            left / 4.0 * left_volume / 8.0,
+            right / 4.0 * right_volume / 8.0,
+        )
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            NR10..=NR14 => self.square1.read(addr - NR10),
+            NR21..=NR24 => self.square2.read(addr - NR21 + 1),
+            NR50 => self.nr50,
+            NR51 => self.nr51,
+            NR30..=NR34 => self.wave.read(addr - NR30),
+            NR41..=NR44 => self.noise.read(addr - NR41 + 1),
+            NR52 => {
+                let status = self
+                    .channels()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, channel)| (channel.enabled() as u8) << i)
+                    .sum::<u8>();
+                // Bits 4-6 are unused
+                ((self.powered as u8) << 7) | 0x70 | status
+            }
+            WAVE_RAM..=0xff3f => self.wave.read_ram((addr - WAVE_RAM) as usize),
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            NR52 => return self.write_nr52(value),
+            // The wave RAM is still accessible when the APU is off
+            WAVE_RAM..=0xff3f => return self.wave.write_ram((addr - WAVE_RAM) as usize, value),
+            _ => (),
+        }
+        // The registers are read-only while the APU is off
+        if !self.powered {
+            return;
+        }
+        match addr {
+            NR14 => self
+                .square1
+                .write_control(value, self.length_clocked_next()),
+            NR24 => self
+                .square2
+                .write_control(value, self.length_clocked_next()),
+            NR34 => self.wave.write_control(value, self.length_clocked_next()),
+            NR44 => self.noise.write_control(value, self.length_clocked_next()),
+            NR10..=NR13 => self.square1.write(addr - NR10, value),
+            NR21..=NR23 => self.square2.write(addr - NR21 + 1, value),
+            NR30..=NR33 => self.wave.write(addr - NR30, value),
+            NR41..=NR43 => self.noise.write(addr - NR41 + 1, value),
+            NR50 => self.nr50 = value,
+            NR51 => self.nr51 = value,
+            _ => (),
+        }
+    }
+
+    fn write_nr52(&mut self, value: u8) {
+        let powered = value & NR52_POWER != 0;
+        if self.powered && !powered {
+            // Turning the APU off clears all its registers
+            self.square1 = Default::default();
+            self.square2 = Default::default();
+            self.wave.power_off();
+            self.noise = Default::default();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.powered && powered {
+            self.frame_sequencer_timer = 0;
+            self.frame_sequencer_step = 0;
+        }
+        self.powered = powered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(NR52, NR52_POWER);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0xff);
+        apu
+    }
+
+    #[test]
+    fn test_apu_power() {
+        let mut apu = Apu::new();
+        apu.write(NR11, 0x80);
+        assert_eq!(apu.read(NR11), 0x3f);
+        assert_eq!(apu.read(NR52), 0x70);
+
+        apu.write(NR52, NR52_POWER);
+        apu.write(NR11, 0x80);
+        assert_eq!(apu.read(NR11), 0xbf);
+
+        apu.write(NR52, 0);
+        assert_eq!(apu.read(NR11), 0x3f);
+    }
+
+    #[test]
+    fn test_apu_channel_status() {
+        let mut apu = powered_apu();
+        apu.write(NR22, 0xf0);
+        apu.write(NR24, 0x80);
+        assert_eq!(apu.read(NR52), 0xf2);
+        apu.write(NR12, 0xf0);
+        apu.write(NR14, 0x80);
+        assert_eq!(apu.read(NR52), 0xf3);
+    }
+
+    #[test]
+    fn test_apu_wave_ram() {
+        let mut apu = Apu::new();
+        apu.write(WAVE_RAM, 0x12);
+        apu.write(NR52, NR52_POWER);
+        apu.write(WAVE_RAM + 15, 0x34);
+        apu.write(NR52, 0);
+        assert_eq!(apu.read(WAVE_RAM), 0x12);
+        assert_eq!(apu.read(WAVE_RAM + 15), 0x34);
+    }
+
+    #[test]
+    fn test_apu_length_counter() {
+        let mut apu = powered_apu();
+        apu.write(NR12, 0xf0);
+        // 4 ticks of the length counter, at 256 Hz
+        apu.write(NR11, 60);
+        apu.write(NR14, 0xc0);
+        apu.tick(FRAME_SEQUENCER_PERIOD * 5);
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+        apu.tick(FRAME_SEQUENCER_PERIOD * 2);
+        assert_eq!(apu.read(NR52) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_apu_length_enabled_between_clocks() {
+        let mut apu = powered_apu();
+        apu.write(NR12, 0xf0);
+        apu.write(NR11, 63);
+        apu.write(NR14, 0x80);
+        // The next step does not clock the length counters
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+        apu.write(NR14, 0x40);
+        assert_eq!(apu.read(NR52) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_apu_sweep_overflow() {
+        let mut apu = powered_apu();
+        apu.write(NR10, 0x11);
+        apu.write(NR12, 0xf0);
+        apu.write(NR13, 0x00);
+        apu.write(NR14, 0x85);
+        // The sweep is first clocked on step 2
+        apu.tick(FRAME_SEQUENCER_PERIOD * 2);
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        assert_eq!(apu.read(NR52) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn test_apu_samples() {
+        let mut apu = powered_apu();
+        apu.tick(CLOCK_RATE / 64);
+        assert_eq!(
+            apu.buffered_samples(),
+            DEFAULT_SAMPLE_RATE as usize / 64 * 2
+        );
+        let mut samples = vec![];
+        apu.drain_samples(&mut samples);
+        assert!(samples.iter().all(|&s| s == 0.0));
+        assert_eq!(apu.buffered_samples(), 0);
+
+        // Channel 1 only on the left at 1 kHz, with a 50% duty cycle
+        apu.write(NR51, 0x10);
+        apu.write(NR11, 0x80);
+        apu.write(NR12, 0xf0);
+        apu.write(NR13, (2048 - 131) as u8);
+        apu.write(NR14, 0x80 | ((2048 - 131) >> 8) as u8);
+        apu.tick(CLOCK_RATE / 64);
+        samples.clear();
+        apu.drain_samples(&mut samples);
+        let (left, right): (Vec<f32>, Vec<f32>) = samples
+            .chunks_exact(2)
+            .map(|sample| (sample[0], sample[1]))
+            .unzip();
+        assert!(right.iter().all(|&s| s == 0.0));
+        assert!(left.iter().all(|&s| s == 0.25 || s == -0.25));
+        let high = left.iter().filter(|&&s| s > 0.0).count();
+        assert!((355..395).contains(&high), "{}", high);
+    }
+
+    #[test]
+    fn test_apu_sample_rate() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(32_768);
+        apu.tick(CLOCK_RATE / 4);
+        assert_eq!(apu.buffered_samples(), 8_192 * 2);
+
+        // Samples are dropped after one second
+        apu.tick(CLOCK_RATE);
+        assert_eq!(apu.buffered_samples(), 32_768 * 2);
+        let mut samples = vec![1.0];
+        apu.drain_samples(&mut samples);
+        assert_eq!(samples.len(), 32_768 * 2 + 1);
+    }
+
+    #[test]
+    fn test_apu_mute_solo() {
+        let mut apu = powered_apu();
+        // Channel 1 on the left, channel 2 on the right
+        apu.write(NR51, 0x12);
+        apu.write(NR12, 0xf0);
+        apu.write(NR14, 0x80);
+        apu.write(NR22, 0xf0);
+        apu.write(NR24, 0x80);
+        let (left, right) = apu.mix();
+        assert!(left != 0.0 && right != 0.0);
+
+        apu.set_muted(2, true);
+        assert!(apu.muted(2));
+        assert_eq!(apu.mix(), (left, 0.0));
+
+        // Solo has priority over mute
+        apu.set_solo(Some(2));
+        assert_eq!(apu.mix(), (0.0, right));
+        apu.set_solo(Some(3));
+        assert_eq!(apu.mix(), (0.0, 0.0));
+        apu.set_solo(None);
+        apu.set_muted(2, false);
+        assert_eq!(apu.mix(), (left, right));
+
+        // The channels keep running
+        assert_eq!(apu.read(NR52) & 0x03, 0x03);
+    }
+}
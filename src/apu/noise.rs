@@ -0,0 +1,217 @@
+//! Noise channel (4), outputs the pseudo-random bits of a LFSR
+
+use super::envelope::Envelope;
+use super::length::Length;
+use super::Channel;
+
+// Base period of the LFSR clock in T-cycles, selected by the divisor code of NR43
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Noise {
+    length: Length,
+    envelope: Envelope,
+    // NR43: clock shift (bits 4-7), LFSR width (bit 3) and divisor code (bits 0-2)
+    polynomial: u8,
+    enabled: bool,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            length: Length::new(64),
+            envelope: Default::default(),
+            polynomial: 0,
+            enabled: false,
+            timer: DIVISORS[0],
+            lfsr: 0,
+        }
+    }
+}
+
+impl Noise {
+    /// Read NR41 to NR44, the write-only bits read as 1
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            1 => 0xff,
+            2 => self.envelope.read(),
+            3 => self.polynomial,
+            4 => 0xbf | ((self.length.enabled() as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write NR41 to NR43
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            1 => self.length.load((value & 0x3f) as u16),
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.polynomial = value,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write NR44. `length_clocked_next` tells whether the next step of the
+    /// frame sequencer clocks the length counter.
+    pub fn write_control(&mut self, value: u8, length_clocked_next: bool) {
+        let trigger = value & 0x80 != 0;
+        if self
+            .length
+            .write_control(value & 0x40 != 0, trigger, length_clocked_next)
+        {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.lfsr = 0x7fff;
+        self.envelope.trigger();
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.polynomial & 0x07) as usize] << (self.polynomial >> 4)
+    }
+
+    /// Shift the LFSR: the XOR of its 2 lowest bits is fed back into bit 14,
+    /// and also into bit 6 in 7-bit mode.
+    fn clock_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.polynomial & 0x08 != 0 {
+            self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        // The LFSR is not clocked at all with shifts 14 and 15
+        if self.polynomial >> 4 >= 14 {
+            return;
+        }
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.clock_lfsr();
+        }
+        self.timer -= cycles;
+    }
+
+    /// Called by the frame sequencer at 64 Hz
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Called by the frame sequencer at 256 Hz
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+}
+
+impl Channel for Noise {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        // The output is high when bit 0 is cleared
+        (!self.lfsr & 1) as u8 * self.envelope.volume()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing_noise(polynomial: u8) -> Noise {
+        let mut noise = Noise::default();
+        noise.write(2, 0xf0);
+        noise.write(3, polynomial);
+        noise.write_control(0x80, true);
+        noise
+    }
+
+    #[test]
+    fn test_noise_lfsr_15_bits() {
+        let mut noise = playing_noise(0x00);
+        let mut states = vec![];
+        for _ in 0..20 {
+            noise.tick(8);
+            states.push(noise.lfsr);
+        }
+        assert_eq!(
+            states,
+            [
+                0x3fff, 0x1fff, 0x0fff, 0x07ff, 0x03ff, 0x01ff, 0x00ff, 0x007f, 0x003f, 0x001f,
+                0x000f, 0x0007, 0x0003, 0x0001, 0x4000, 0x2000, 0x1000, 0x0800, 0x0400, 0x0200,
+            ]
+        );
+
+        // A maximal length sequence
+        let mut noise = playing_noise(0x00);
+        noise.tick(8 * 32767);
+        assert_eq!(noise.lfsr, 0x7fff);
+    }
+
+    #[test]
+    fn test_noise_lfsr_7_bits() {
+        let mut noise = playing_noise(0x08);
+        let mut states = vec![];
+        for _ in 0..10 {
+            noise.tick(8);
+            states.push(noise.lfsr);
+        }
+        assert_eq!(
+            states,
+            [0x3fbf, 0x1f9f, 0x0f8f, 0x0787, 0x0383, 0x0181, 0x40c0, 0x2020, 0x1010, 0x0808]
+        );
+
+        // The lowest 7 bits repeat every 127 clocks
+        let mut noise = playing_noise(0x08);
+        noise.tick(8 * 3);
+        let low = noise.lfsr & 0x7f;
+        noise.tick(8 * 127);
+        assert_eq!(noise.lfsr & 0x7f, low);
+    }
+
+    #[test]
+    fn test_noise_clock() {
+        // Divisor 48, shifted by 2
+        let mut noise = playing_noise(0x23);
+        noise.tick(191);
+        assert_eq!(noise.lfsr, 0x7fff);
+        noise.tick(1);
+        assert_eq!(noise.lfsr, 0x3fff);
+
+        let mut noise = playing_noise(0xe0);
+        noise.tick(1 << 20);
+        assert_eq!(noise.lfsr, 0x7fff);
+    }
+
+    #[test]
+    fn test_noise_output() {
+        let mut noise = playing_noise(0x00);
+        // Bit 0 is set after a trigger
+        assert_eq!(noise.output(), 0);
+        noise.tick(8 * 15);
+        assert_eq!(noise.lfsr, 0x4000);
+        assert_eq!(noise.output(), 15);
+    }
+}
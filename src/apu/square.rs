@@ -0,0 +1,219 @@
+//! Pulse channels (1 and 2)
+
+use super::envelope::Envelope;
+use super::length::Length;
+use super::sweep::Sweep;
+use super::Channel;
+
+// Waveforms of the 4 duty cycles, from step 0 in the highest bit to step 7
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// A pulse channel, driven by the registers NRx0 to NRx4. Channel 2 has
+/// no sweep unit: its NR20 register does not exist.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Square {
+    // Channel 1 only
+    sweep: Sweep,
+    duty: u8,
+    length: Length,
+    envelope: Envelope,
+    // 11 bits period value: the channel outputs a step every (2048 - frequency) * 4 T-cycles
+    frequency: u16,
+    enabled: bool,
+    timer: u32,
+    duty_step: u8,
+}
+
+impl Default for Square {
+    fn default() -> Self {
+        Self {
+            sweep: Default::default(),
+            duty: 0,
+            length: Length::new(64),
+            envelope: Default::default(),
+            frequency: 0,
+            enabled: false,
+            timer: 2048 * 4,
+            duty_step: 0,
+        }
+    }
+}
+
+impl Square {
+    /// Read NRx0 to NRx4, the write-only bits read as 1
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            0 => self.sweep.read(),
+            1 => 0x3f | (self.duty << 6),
+            2 => self.envelope.read(),
+            3 => 0xff,
+            4 => 0xbf | ((self.length.enabled() as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write NRx0 to NRx3
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                if !self.sweep.write(value) {
+                    self.enabled = false;
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load((value & 0x3f) as u16);
+            }
+            2 => {
+                self.envelope.write(value);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write NRx4. `length_clocked_next` tells whether the next step of the
+    /// frame sequencer clocks the length counter.
+    pub fn write_control(&mut self, value: u8, length_clocked_next: bool) {
+        self.frequency = (self.frequency & 0xff) | ((value as u16 & 0x07) << 8);
+        let trigger = value & 0x80 != 0;
+        if self
+            .length
+            .write_control(value & 0x40 != 0, trigger, length_clocked_next)
+        {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+        if !self.sweep.trigger(self.frequency) {
+            self.enabled = false;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    /// Called by the frame sequencer at 64 Hz
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Called by the frame sequencer at 128 Hz, only for channel 1
+    pub fn clock_sweep(&mut self) {
+        if self.enabled && !self.sweep.clock(&mut self.frequency) {
+            self.enabled = false;
+        }
+    }
+
+    /// Called by the frame sequencer at 256 Hz
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+}
+
+impl Channel for Square {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step)) & 1;
+        high * self.envelope.volume()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing_square(duty: u8) -> Square {
+        let mut square = Square::default();
+        square.write(1, duty << 6);
+        square.write(2, 0xf0);
+        // Period of 8 T-cycles per duty step
+        square.write(3, 0xfe);
+        square.write_control(0x87, true);
+        square
+    }
+
+    #[test]
+    fn test_square_duty_cycle() {
+        let mut square = playing_square(2);
+        let mut waveform = vec![];
+        for _ in 0..8 {
+            square.tick(8);
+            waveform.push(square.output());
+        }
+        // 50% duty cycle
+        assert_eq!(waveform, [0, 0, 0, 0, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn test_square_length() {
+        let mut square = playing_square(2);
+        square.write(1, 62);
+        square.write_control(0xc7, true);
+        assert!(square.enabled());
+        square.clock_length();
+        assert!(square.enabled());
+        square.clock_length();
+        assert!(!square.enabled());
+
+        // The length is reloaded by the trigger when it reached 0
+        square.write_control(0xc7, true);
+        for _ in 0..63 {
+            square.clock_length();
+        }
+        assert!(square.enabled());
+        square.clock_length();
+        assert!(!square.enabled());
+    }
+
+    #[test]
+    fn test_square_envelope() {
+        let mut square = playing_square(2);
+        square.write(2, 0xf1);
+        square.write_control(0x87, true);
+        square.tick(8 * 5);
+        assert_eq!(square.output(), 15);
+        square.clock_envelope();
+        assert_eq!(square.output(), 14);
+    }
+
+    #[test]
+    fn test_square_dac_disabled() {
+        let mut square = playing_square(2);
+        square.write(2, 0x07);
+        assert!(!square.enabled());
+        // Triggering does not enable a channel without DAC
+        square.write_control(0x87, true);
+        assert!(!square.enabled());
+    }
+}
@@ -0,0 +1,151 @@
+//! Frequency sweep of channel 1, controlled by NR10
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Sweep {
+    // Pace (bits 4-6), direction (bit 3) and step (bits 0-2)
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    // Copy of the frequency the sweep computes from
+    shadow: u16,
+    // A frequency was computed in decrease mode since the last trigger
+    negated: bool,
+}
+
+impl Sweep {
+    pub fn read(&self) -> u8 {
+        0x80 | self.register
+    }
+
+    /// Returns false if the channel must be disabled: switching from the
+    /// decrease to the increase mode after a computation stops the channel.
+    pub fn write(&mut self, value: u8) -> bool {
+        self.register = value & 0x7f;
+        !self.negated || self.decrease()
+    }
+
+    fn pace(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn step(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn decrease(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+
+    fn reload_timer(&mut self) {
+        // A pace of 0 is handled as 8 by the timer
+        self.timer = match self.pace() {
+            0 => 8,
+            pace => pace,
+        };
+    }
+
+    /// Next frequency, or None if it overflows 11 bits
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.step();
+        if self.decrease() {
+            self.negated = true;
+            Some(self.shadow - delta)
+        } else {
+            Some(self.shadow + delta).filter(|&frequency| frequency <= 0x7ff)
+        }
+    }
+
+    /// Returns false if the channel must be disabled because of an overflow
+    pub fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.reload_timer();
+        self.enabled = self.pace() != 0 || self.step() != 0;
+        self.negated = false;
+        // The overflow check is done immediately if the step is not 0
+        self.step() == 0 || self.calculate().is_some()
+    }
+
+    /// Called by the frame sequencer at 128 Hz, updates the frequency of the
+    /// channel. Returns false if the channel must be disabled.
+    pub fn clock(&mut self, frequency: &mut u16) -> bool {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return true;
+        }
+        self.reload_timer();
+        if !self.enabled || self.pace() == 0 {
+            return true;
+        }
+        match self.calculate() {
+            None => false,
+            Some(new) if self.step() != 0 => {
+                self.shadow = new;
+                *frequency = new;
+                // The new frequency is checked again, but not used
+                self.calculate().is_some()
+            }
+            Some(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_increase() {
+        let mut sweep = Sweep::default();
+        // Every 2 ticks, frequency += frequency / 2
+        sweep.write(0x21);
+        let mut frequency = 0x100;
+        assert!(sweep.trigger(frequency));
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x100);
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x180);
+        sweep.clock(&mut frequency);
+        sweep.clock(&mut frequency);
+        assert_eq!(frequency, 0x240);
+    }
+
+    #[test]
+    fn test_sweep_decrease() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x1a);
+        let mut frequency = 0x400;
+        sweep.trigger(frequency);
+        assert!(sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x300);
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x11);
+        // Checked on trigger
+        assert!(!sweep.trigger(0x600));
+
+        // Checked again after the update
+        let mut frequency = 0x500;
+        assert!(sweep.trigger(frequency));
+        assert!(!sweep.clock(&mut frequency));
+        assert_eq!(frequency, 0x780);
+    }
+
+    #[test]
+    fn test_sweep_negate_quirk() {
+        let mut sweep = Sweep::default();
+        sweep.write(0x19);
+        let mut frequency = 0x400;
+        sweep.trigger(frequency);
+        sweep.clock(&mut frequency);
+        assert!(!sweep.write(0x11));
+
+        // Fine without a computation in decrease mode since the trigger
+        sweep.write(0x18);
+        sweep.trigger(0x400);
+        assert!(sweep.write(0x10));
+    }
+}
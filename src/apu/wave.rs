@@ -0,0 +1,229 @@
+//! Wave channel (3), playing the 32 4-bit samples stored in the wave RAM
+
+use super::length::Length;
+use super::Channel;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Wave {
+    dac_enabled: bool,
+    length: Length,
+    // Bits 5-6 of NR32
+    volume: u8,
+    // 11 bits period value: the channel reads a sample every (2048 - frequency) * 2 T-cycles
+    frequency: u16,
+    enabled: bool,
+    timer: u32,
+    // Index of the sample being played in the wave RAM
+    position: usize,
+    // Last sample read from the wave RAM, it is not updated by a trigger
+    sample: u8,
+    ram: [u8; 16],
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Self {
+            dac_enabled: false,
+            length: Length::new(256),
+            volume: 0,
+            frequency: 0,
+            enabled: false,
+            timer: 2048 * 2,
+            position: 0,
+            sample: 0,
+            ram: [0; 16],
+        }
+    }
+}
+
+impl Wave {
+    /// Read NR30 to NR34, the write-only bits read as 1
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            0 => 0x7f | ((self.dac_enabled as u8) << 7),
+            1 => 0xff,
+            2 => 0x9f | (self.volume << 5),
+            3 => 0xff,
+            4 => 0xbf | ((self.length.enabled() as u8) << 6),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Write NR30 to NR33
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value as u16),
+            2 => self.volume = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            _ => unreachable!(),
+        }
+    }
+
+    /// While the channel is playing, the CPU can only access the byte of the
+    /// wave RAM being read by the channel (CGB behaviour, the DMG only allows
+    /// it on the exact cycle of the read).
+    fn ram_index(&self, index: usize) -> usize {
+        if self.enabled {
+            self.position / 2
+        } else {
+            index
+        }
+    }
+
+    pub fn read_ram(&self, index: usize) -> u8 {
+        self.ram[self.ram_index(index)]
+    }
+
+    pub fn write_ram(&mut self, index: usize, value: u8) {
+        self.ram[self.ram_index(index)] = value;
+    }
+
+    /// Clear the registers, the wave RAM is not affected
+    pub fn power_off(&mut self) {
+        *self = Self {
+            ram: self.ram,
+            ..Default::default()
+        };
+    }
+
+    /// Write NR34. `length_clocked_next` tells whether the next step of the
+    /// frame sequencer clocks the length counter.
+    pub fn write_control(&mut self, value: u8, length_clocked_next: bool) {
+        self.frequency = (self.frequency & 0xff) | ((value as u16 & 0x07) << 8);
+        let trigger = value & 0x80 != 0;
+        if self
+            .length
+            .write_control(value & 0x40 != 0, trigger, length_clocked_next)
+        {
+            self.enabled = false;
+        }
+        if trigger {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+            // The high nibble is played first
+            let byte = self.ram[self.position / 2];
+            self.sample = if self.position.is_multiple_of(2) {
+                byte >> 4
+            } else {
+                byte & 0x0f
+            };
+        }
+        self.timer -= cycles;
+    }
+
+    /// Called by the frame sequencer at 256 Hz
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+}
+
+impl Channel for Wave {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        // Volume 0 mutes the channel, then 100%, 50% and 25%
+        match self.volume {
+            0 => 0,
+            volume => self.sample >> (volume - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing_wave(volume: u8) -> Wave {
+        let mut wave = Wave::default();
+        for (i, value) in [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]
+            .iter()
+            .enumerate()
+        {
+            wave.write_ram(i, *value);
+        }
+        wave.write(0, 0x80);
+        wave.write(2, volume << 5);
+        // Period of 4 T-cycles per sample
+        wave.write(3, 0xfe);
+        wave.write_control(0x87, true);
+        wave
+    }
+
+    #[test]
+    fn test_wave_samples() {
+        let mut wave = playing_wave(1);
+        let mut samples = vec![];
+        for _ in 0..16 {
+            wave.tick(4);
+            samples.push(wave.output());
+        }
+        // The first sample is skipped after a trigger
+        assert_eq!(samples, (1..16).chain(0..1).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_wave_volume() {
+        for (volume, expected) in [(0, 0), (1, 15), (2, 7), (3, 3)] {
+            let mut wave = playing_wave(volume);
+            wave.tick(4 * 15);
+            assert_eq!(wave.output(), expected);
+        }
+    }
+
+    #[test]
+    fn test_wave_dac_and_length() {
+        let mut wave = playing_wave(1);
+        wave.write(0, 0x00);
+        assert!(!wave.enabled());
+
+        wave.write(0, 0x80);
+        wave.write(1, 0xff);
+        wave.write_control(0xc7, true);
+        assert!(wave.enabled());
+        wave.clock_length();
+        assert!(!wave.enabled());
+    }
+
+    #[test]
+    fn test_wave_ram_access_while_playing() {
+        let mut wave = playing_wave(1);
+        wave.tick(4 * 5);
+        // Sample 5 is being played, in the third byte
+        assert_eq!(wave.read_ram(0), 0x45);
+        wave.write_ram(7, 0x00);
+        assert_eq!(wave.ram[2], 0x00);
+        assert_eq!(wave.ram[7], 0xef);
+    }
+}
@@ -0,0 +1,13 @@
+//! Playback and recording of the APU samples
+
+#[cfg(feature = "audio")]
+mod output;
+mod resampler;
+mod ring;
+mod wav;
+
+#[cfg(feature = "audio")]
+pub use output::{AudioError, AudioOutput};
+pub use resampler::Resampler;
+pub use ring::SampleRing;
+pub use wav::WavDump;
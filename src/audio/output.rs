@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+
+use super::{Resampler, SampleRing};
+use crate::apu;
+
+// Samples queued for the sound card, in milliseconds. Enough to absorb the
+// jitter of a frame-paced emulation loop.
+const LATENCY_MS: u32 = 100;
+
+/// Plays the APU samples on the default output device
+pub struct AudioOutput {
+    // The sound stops when the stream is dropped
+    _stream: cpal::Stream,
+    ring: SampleRing,
+    resampler: Resampler,
+    resampled: Vec<f32>,
+    sample_rate: u32,
+    device_name: String,
+    volume: f32,
+}
+
+impl AudioOutput {
+    /// Play on the default output device
+    pub fn new() -> Result<Self, AudioError> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or(AudioError::NoDevice)?;
+        Self::open(device)
+    }
+
+    /// Play on the output device named `name`, see `devices`
+    pub fn with_device(name: &str) -> Result<Self, AudioError> {
+        let device = cpal::default_host()
+            .output_devices()?
+            .find(|device| device.name().is_ok_and(|n| n == name))
+            .ok_or(AudioError::NoDevice)?;
+        Self::open(device)
+    }
+
+    /// Names of the output devices
+    pub fn devices() -> Vec<String> {
+        match cpal::default_host().output_devices() {
+            Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn open(device: cpal::Device) -> Result<Self, AudioError> {
+        let supported = device.default_output_config()?;
+        let sample_rate = supported.sample_rate().0;
+        let ring = SampleRing::new((sample_rate * LATENCY_MS / 1000 * 2) as usize);
+
+        let config = supported.config();
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, ring.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, ring.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, ring.clone())?,
+            format => return Err(AudioError::UnsupportedFormat(format)),
+        };
+        stream.play()?;
+
+        Ok(Self {
+            _stream: stream,
+            ring,
+            resampler: Resampler::new(apu::DEFAULT_SAMPLE_RATE, sample_rate),
+            resampled: Vec::new(),
+            sample_rate,
+            device_name: device.name().unwrap_or_default(),
+            volume: 1.0,
+        })
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Master volume, from 0 (silent) to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Sample rate of the output device
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Sample rate of the samples given to `push`. Setting the sample rate
+    /// of the APU to the one of the device avoids the resampling.
+    pub fn set_input_rate(&mut self, input_rate: u32) {
+        self.resampler.set_input_rate(input_rate);
+    }
+
+    /// Queue samples produced by the APU
+    pub fn push(&mut self, samples: &[f32]) {
+        self.resampled.clear();
+        self.resampler.process(samples, &mut self.resampled);
+        for sample in &mut self.resampled {
+            *sample *= self.volume;
+        }
+        self.ring.push(&self.resampled);
+    }
+
+    /// Number of samples waiting to be played
+    pub fn queued(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.ring.underruns()
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.ring.overruns()
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    ring: SampleRing,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut frames = Vec::new();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            frames.resize(data.len() / channels * 2, 0.0);
+            ring.pop(&mut frames);
+            for (out, frame) in data.chunks_mut(channels).zip(frames.chunks_exact(2)) {
+                for (channel, sample) in out.iter_mut().enumerate() {
+                    let value = match (channels, channel) {
+                        (1, _) => (frame[0] + frame[1]) / 2.0,
+                        (_, 0) => frame[0],
+                        (_, 1) => frame[1],
+                        _ => 0.0,
+                    };
+                    *sample = T::from_sample(value);
+                }
+            }
+        },
+        |err| eprintln!("Audio stream error: {}", err),
+        None,
+    )
+}
+
+#[derive(Debug)]
+pub enum AudioError {
+    NoDevice,
+    UnsupportedFormat(SampleFormat),
+    DevicesError(cpal::DevicesError),
+    ConfigError(cpal::DefaultStreamConfigError),
+    BuildError(cpal::BuildStreamError),
+    PlayError(cpal::PlayStreamError),
+}
+
+impl Error for AudioError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NoDevice => None,
+            Self::UnsupportedFormat(_) => None,
+            Self::DevicesError(err) => Some(err),
+            Self::ConfigError(err) => Some(err),
+            Self::BuildError(err) => Some(err),
+            Self::PlayError(err) => Some(err),
+        }
+    }
+}
+
+impl From<cpal::DevicesError> for AudioError {
+    fn from(value: cpal::DevicesError) -> Self {
+        AudioError::DevicesError(value)
+    }
+}
+
+impl From<cpal::DefaultStreamConfigError> for AudioError {
+    fn from(value: cpal::DefaultStreamConfigError) -> Self {
+        AudioError::ConfigError(value)
+    }
+}
+
+impl From<cpal::BuildStreamError> for AudioError {
+    fn from(value: cpal::BuildStreamError) -> Self {
+        AudioError::BuildError(value)
+    }
+}
+
+impl From<cpal::PlayStreamError> for AudioError {
+    fn from(value: cpal::PlayStreamError) -> Self {
+        AudioError::PlayError(value)
+    }
+}
+
+impl Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoDevice => f.write_str("No audio output device"),
+            Self::UnsupportedFormat(format) => write!(f, "Unsupported sample format {}", format),
+            Self::DevicesError(err) => write!(f, "Audio device error: {}", err),
+            Self::ConfigError(err) => write!(f, "Audio configuration error: {}", err),
+            Self::BuildError(err) => write!(f, "Audio stream error: {}", err),
+            Self::PlayError(err) => write!(f, "Audio playback error: {}", err),
+        }
+    }
+}
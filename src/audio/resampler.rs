@@ -0,0 +1,72 @@
+/// Linear interpolation of interleaved stereo samples from one sample rate
+/// to another
+pub struct Resampler {
+    output_rate: u32,
+    // Input frames consumed for each output frame
+    step: f64,
+    // Position of the next output frame, relative to `previous`
+    position: f64,
+    // Last input frame of the previous call
+    previous: [f32; 2],
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        Self {
+            output_rate,
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.0,
+            previous: [0.0; 2],
+        }
+    }
+
+    pub fn set_input_rate(&mut self, input_rate: u32) {
+        self.step = input_rate as f64 / self.output_rate as f64;
+    }
+
+    /// Resample `input` and append the result to `output`
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let frames: Vec<[f32; 2]> = std::iter::once(self.previous)
+            .chain(input.chunks_exact(2).map(|frame| [frame[0], frame[1]]))
+            .collect();
+        let mut position = self.position;
+        while position + 1.0 < frames.len() as f64 {
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            for (a, b) in frames[index].iter().zip(frames[index + 1]) {
+                output.push(a + (b - a) * fraction);
+            }
+            position += self.step;
+        }
+        self.position = position - (frames.len() - 1) as f64;
+        self.previous = frames[frames.len() - 1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resampler_upsample() {
+        let mut resampler = Resampler::new(1, 2);
+        let mut output = vec![];
+        resampler.process(&[1.0, -1.0, 2.0, -2.0], &mut output);
+        assert_eq!(output, [0.0, 0.0, 0.5, -0.5, 1.0, -1.0, 1.5, -1.5]);
+        output.clear();
+        resampler.process(&[4.0, -4.0], &mut output);
+        assert_eq!(output, [2.0, -2.0, 3.0, -3.0]);
+    }
+
+    #[test]
+    fn test_resampler_rate() {
+        let mut resampler = Resampler::new(48_000, 44_100);
+        let mut output = vec![];
+        for _ in 0..10 {
+            resampler.process(&[0.5; 960], &mut output);
+        }
+        // 0.1 second of stereo samples, give or take a frame of rounding
+        assert!((4_409..=4_411).contains(&(output.len() / 2)));
+        assert!(output[2..].iter().all(|&s| (s - 0.5).abs() < 1e-6));
+    }
+}
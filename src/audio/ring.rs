@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Queue of interleaved stereo samples shared between the emulation thread,
+/// which pushes them, and the audio callback, which pops them. Clones share
+/// the same queue.
+#[derive(Clone)]
+pub struct SampleRing {
+    state: Arc<Mutex<RingState>>,
+}
+
+struct RingState {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    underruns: u64,
+    overruns: u64,
+}
+
+impl SampleRing {
+    /// `capacity` is the maximum number of samples (not frames) kept
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RingState {
+                samples: VecDeque::with_capacity(capacity),
+                capacity,
+                underruns: 0,
+                overruns: 0,
+            })),
+        }
+    }
+
+    /// Queue samples. When the queue is full, the oldest samples are
+    /// dropped so that the latency stays bounded.
+    pub fn push(&self, samples: &[f32]) {
+        let mut state = self.state.lock().unwrap();
+        let excess = (state.samples.len() + samples.len()).saturating_sub(state.capacity);
+        if excess > 0 {
+            state.overruns += 1;
+            // Keep whole frames to preserve the left/right order
+            let excess = excess.next_multiple_of(2).min(state.samples.len());
+            state.samples.drain(..excess);
+        }
+        let start = samples.len().saturating_sub(state.capacity);
+        state.samples.extend(&samples[start..]);
+    }
+
+    /// Fill `out` with the oldest samples. Missing samples are replaced by
+    /// silence. Returns the number of samples actually read.
+    pub fn pop(&self, out: &mut [f32]) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let count = out.len().min(state.samples.len());
+        for (out, sample) in out.iter_mut().zip(state.samples.drain(..count)) {
+            *out = sample;
+        }
+        if count < out.len() {
+            state.underruns += 1;
+            out[count..].fill(0.0);
+        }
+        count
+    }
+
+    /// Number of samples waiting to be played
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of times the audio callback ran out of samples
+    pub fn underruns(&self) -> u64 {
+        self.state.lock().unwrap().underruns
+    }
+
+    /// Number of times samples were dropped because the queue was full
+    pub fn overruns(&self) -> u64 {
+        self.state.lock().unwrap().overruns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_push_pop() {
+        let ring = SampleRing::new(8);
+        let consumer = ring.clone();
+        ring.push(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(consumer.len(), 4);
+
+        let mut out = [0.0; 2];
+        assert_eq!(consumer.pop(&mut out), 2);
+        assert_eq!(out, [1.0, 2.0]);
+        assert_eq!(consumer.underruns(), 0);
+    }
+
+    #[test]
+    fn test_ring_underrun() {
+        let ring = SampleRing::new(8);
+        ring.push(&[1.0, 2.0]);
+        let mut out = [5.0; 4];
+        assert_eq!(ring.pop(&mut out), 2);
+        assert_eq!(out, [1.0, 2.0, 0.0, 0.0]);
+        assert_eq!(ring.underruns(), 1);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_ring_overrun() {
+        let ring = SampleRing::new(4);
+        ring.push(&[1.0, 2.0, 3.0]);
+        ring.push(&[4.0, 5.0]);
+        assert_eq!(ring.overruns(), 1);
+        let mut out = [0.0; 4];
+        ring.pop(&mut out);
+        // The oldest frame is dropped
+        assert_eq!(out, [3.0, 4.0, 5.0, 0.0]);
+
+        ring.push(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        ring.pop(&mut out);
+        assert_eq!(out, [3.0, 4.0, 5.0, 6.0]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Records the APU samples to a 16-bit stereo .wav file, so that the output
+/// of sound test ROMs can be compared between runs.
+pub struct WavDump {
+    writer: hound::WavWriter<BufWriter<File>>,
+}
+
+impl WavDump {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32) -> Result<Self, hound::Error> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        Ok(Self {
+            writer: hound::WavWriter::create(path, spec)?,
+        })
+    }
+
+    /// Append interleaved stereo samples between -1.0 and 1.0
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), hound::Error> {
+        for sample in samples {
+            self.writer
+                .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+        }
+        Ok(())
+    }
+
+    /// Write the header with the final size. It is also done on drop, but
+    /// errors are then ignored.
+    pub fn finalize(self) -> Result<(), hound::Error> {
+        self.writer.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_dump() {
+        let path = std::env::temp_dir().join(format!("gb-dump-{}.wav", std::process::id()));
+        let mut dump = WavDump::create(&path, 32_768).unwrap();
+        dump.write(&[0.0, 1.0, -1.0, 0.5]).unwrap();
+        dump.write(&[2.0, -0.25]).unwrap();
+        dump.finalize().unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, 32_768);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, [0, 32767, -32767, 16383, 32767, -8191]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
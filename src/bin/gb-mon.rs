@@ -0,0 +1,54 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use clap::{Arg, Command};
+
+use gb::debugger::monitor::{self, Monitor};
+use gb::Emulator;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("gb-mon")
+        .about("Game Boy debugger on the command line, type help for the commands")
+        .arg(
+            Arg::new("rom")
+                .required(true)
+                .help("Can be zipped or gzipped"),
+        )
+        .get_matches();
+
+    let rom = gb::rom::read(matches.get_one::<String>("rom").unwrap())?;
+    let mut monitor = Monitor::new(Emulator::new(rom, &Default::default()));
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut last = None;
+    loop {
+        print!("(gb) ");
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        let command = match line.trim() {
+            "" => match &last {
+                Some(command) => command,
+                None => continue,
+            },
+            line => match line.parse() {
+                Ok(command) => last.insert(command),
+                Err(err) => {
+                    println!("{}", err);
+                    continue;
+                }
+            },
+        };
+        if *command == monitor::Command::Quit {
+            return Ok(());
+        }
+        match monitor.execute(command) {
+            Ok(output) => print!("{}", output),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
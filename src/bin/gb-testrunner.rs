@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, Command};
+
+use gb::debugger::breakpoints::Breakpoint;
+use gb::movie::rom_hash;
+use gb::Emulator;
+
+const EXTENSIONS: [&str; 4] = ["gb", "gbc", "gz", "zip"];
+
+// Registers B, C, D, E, H and L of the Mooneye test ROMs at LD B,B
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const FAILURE: [u8; 6] = [0x42; 6];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed,
+    Timeout,
+}
+
+/// How the outcome was decided
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Method {
+    Serial,
+    Breakpoint,
+    Screenshot,
+    None,
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Method::Serial => "serial",
+            Method::Breakpoint => "LD B,B",
+            Method::Screenshot => "screenshot",
+            Method::None => "-",
+        })
+    }
+}
+
+struct Report {
+    name: String,
+    outcome: Result<Outcome, String>,
+    method: Method,
+    frames: usize,
+    // Of the last frame
+    hash: u64,
+}
+
+/// The ROMs under `dir`, in its subdirectories too
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Hashes of the screens of the passed tests, a hash and the path of the
+/// ROM relative to the directory on each line, as written by `--hashes-out`
+fn read_hashes(path: &str) -> Result<HashMap<String, u64>, Box<dyn Error>> {
+    let mut hashes = HashMap::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("{}:{}: expected a hash and a ROM", path, number + 1);
+        let (hash, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let hash = u64::from_str_radix(hash, 16).map_err(|_| invalid())?;
+        hashes.insert(name.trim().to_string(), hash);
+    }
+    Ok(hashes)
+}
+
+/// Run the ROM up to a result or for `frames` frames
+fn run(rom: Vec<u8>, frames: usize, expected_hash: Option<u64>) -> (Outcome, Method, Emulator) {
+    let mut emulator = Emulator::new(rom, &Default::default());
+    emulator
+        .cpu_mut()
+        .breakpoints_mut()
+        .add("[PC] == 0x40".parse::<Breakpoint>().unwrap());
+    let mut output = Vec::new();
+    while emulator.frame() < frames {
+        if emulator.run_frame().is_some() {
+            let regs = *emulator.cpu().registers();
+            match [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] {
+                FIBONACCI => return (Outcome::Passed, Method::Breakpoint, emulator),
+                FAILURE => return (Outcome::Failed, Method::Breakpoint, emulator),
+                // Not the end of a test, the next frame resumes it
+                _ => continue,
+            }
+        }
+        output.extend(emulator.serial_output());
+        match gb::serial::test_result(&output) {
+            Some(true) => return (Outcome::Passed, Method::Serial, emulator),
+            Some(false) => return (Outcome::Failed, Method::Serial, emulator),
+            None => (),
+        }
+        if expected_hash == Some(rom_hash(emulator.framebuffer())) {
+            return (Outcome::Passed, Method::Screenshot, emulator);
+        }
+    }
+    (Outcome::Timeout, Method::None, emulator)
+}
+
+fn print_table(reports: &[Report]) {
+    let width = reports
+        .iter()
+        .map(|report| report.name.len())
+        .chain([3])
+        .max()
+        .unwrap();
+    println!(
+        "{:width$}  {:7}  {:10}  {:>6}  Screen hash",
+        "ROM", "Result", "Method", "Frames"
+    );
+    for report in reports {
+        let outcome = match &report.outcome {
+            Ok(outcome) => format!("{:?}", outcome),
+            Err(_) => "Error".to_string(),
+        };
+        print!(
+            "{:width$}  {:7}  {:10}  {:>6}",
+            report.name,
+            outcome,
+            report.method.to_string(),
+            report.frames
+        );
+        match &report.outcome {
+            Ok(_) => println!("  {:016x}", report.hash),
+            Err(err) => println!("  {}", err),
+        }
+    }
+    let count = |expected: Outcome| {
+        reports
+            .iter()
+            .filter(|report| report.outcome.as_ref().ok() == Some(&expected))
+            .count()
+    };
+    println!(
+        "\n{} passed, {} failed, {} timed out, {} errors out of {} ROMs",
+        count(Outcome::Passed),
+        count(Outcome::Failed),
+        count(Outcome::Timeout),
+        reports
+            .iter()
+            .filter(|report| report.outcome.is_err())
+            .count(),
+        reports.len()
+    );
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("gb-testrunner")
+        .about(
+            "Run the test ROMs of a directory without a window and print a summary of the \
+             results. The status is 0 when they all pass",
+        )
+        .arg(Arg::new("dir").required(true))
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("7200")
+                .help("Frames to run each ROM at most, two minutes by default"),
+        )
+        .arg(Arg::new("hashes").long("hashes").value_name("FILE").help(
+            "Hashes of the screens of the passed tests, for the ROMs reporting only on \
+                     the screen",
+        ))
+        .arg(
+            Arg::new("hashes-out")
+                .long("hashes-out")
+                .value_name("FILE")
+                .help("Write the hashes of the last screens, to review and pass to --hashes"),
+        )
+        .get_matches();
+
+    let dir = Path::new(matches.get_one::<String>("dir").unwrap());
+    let frames = *matches.get_one::<usize>("timeout").unwrap();
+    let hashes = match matches.get_one::<String>("hashes") {
+        Some(path) => read_hashes(path)?,
+        None => HashMap::new(),
+    };
+    let mut roms = Vec::new();
+    find_roms(dir, &mut roms)?;
+    roms.sort();
+
+    let mut reports = Vec::new();
+    for path in roms {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let report = match gb::rom::read(&path) {
+            Ok(rom) => {
+                let (outcome, method, emulator) = run(rom, frames, hashes.get(&name).copied());
+                Report {
+                    outcome: Ok(outcome),
+                    method,
+                    frames: emulator.frame(),
+                    hash: rom_hash(emulator.framebuffer()),
+                    name,
+                }
+            }
+            Err(err) => Report {
+                outcome: Err(err.to_string()),
+                method: Method::None,
+                frames: 0,
+                hash: 0,
+                name,
+            },
+        };
+        reports.push(report);
+    }
+    print_table(&reports);
+
+    if let Some(path) = matches.get_one::<String>("hashes-out") {
+        let lines: String = reports
+            .iter()
+            .filter(|report| report.outcome.is_ok())
+            .map(|report| format!("{:016x} {}\n", report.hash, report.name))
+            .collect();
+        fs::write(path, lines)?;
+    }
+    if !reports
+        .iter()
+        .all(|report| report.outcome == Ok(Outcome::Passed))
+    {
+        std::process::exit(1);
+    }
+    Ok(())
+}
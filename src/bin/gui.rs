@@ -0,0 +1,284 @@
+use std::error::Error;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, Command};
+use eframe::egui;
+
+use gb::annotations::Annotation;
+use gb::audio::WavDump;
+use gb::cheats::{Cheat, Cheats};
+use gb::gui::{MovieMode, MyApp};
+use gb::infrared::{self, TcpTransport};
+use gb::input::{InputMap, InputMapError};
+use gb::link::{Loopback, TcpLink};
+use gb::model::Model;
+use gb::movie::Movie;
+use gb::palette::DmgPalette;
+use gb::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use gb::settings::{Settings, SettingsError};
+use gb::Emulator;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let command = Command::new("gui")
+        .about("Game Boy emulator")
+        .arg(Arg::new("rom").help("Can be zipped or gzipped, - reads the standard input. Can also be opened from the File menu or dropped on the window"))
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .help("grey, green or 4 comma separated RRGGBB colors, for the games without a palette override")
+                .value_parser(clap::value_parser!(DmgPalette))
+                .default_value("grey"),
+        )
+        .arg(
+            Arg::new("input-map")
+                .long("input-map")
+                .help("Key bindings file"),
+        )
+        .arg(
+            Arg::new("annotations")
+                .long("annotations")
+                .help("Annotation file of the disassembler, shown in the disassembly"),
+        )
+        .arg(
+            Arg::new("dump-audio")
+                .long("dump-audio")
+                .value_name("WAV")
+                .help("Record all the audio to a .wav file"),
+        )
+        .arg(
+            Arg::new("screenshot-at-frame")
+                .long("screenshot-at-frame")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Save a screenshot after N frames"),
+        )
+        .arg(
+            Arg::new("serial")
+                .long("serial")
+                .action(ArgAction::SetTrue)
+                .help("Print the serial output, and exit when a test ROM prints Passed (status 0) or Failed (status 1)"),
+        )
+        .arg(
+            Arg::new("cheat")
+                .long("cheat")
+                .value_name("CODE")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(Cheat))
+                .help("Game Genie (ABC-DEF-GHI) or GameShark (01VVAAAA) code, can be repeated"),
+        )
+        .arg(
+            Arg::new("link-listen")
+                .long("link-listen")
+                .value_name("ADDR")
+                .conflicts_with_all(["link-connect", "link-loopback"])
+                .help("Wait for another emulator to connect its link cable to ADDR, such as 0.0.0.0:5000"),
+        )
+        .arg(
+            Arg::new("link-connect")
+                .long("link-connect")
+                .value_name("ADDR")
+                .conflicts_with("link-loopback")
+                .help("Connect the link cable to another emulator started with --link-listen"),
+        )
+        .arg(
+            Arg::new("link-loopback")
+                .long("link-loopback")
+                .action(ArgAction::SetTrue)
+                .help("Wire the output of the serial port to its input"),
+        )
+        .arg(
+            Arg::new("ir-listen")
+                .long("ir-listen")
+                .value_name("ADDR")
+                .conflicts_with_all(["ir-connect", "ir-loopback"])
+                .help("Wait for another emulator to connect its infrared port to ADDR"),
+        )
+        .arg(
+            Arg::new("ir-connect")
+                .long("ir-connect")
+                .value_name("ADDR")
+                .conflicts_with("ir-loopback")
+                .help("Face the infrared port of another emulator started with --ir-listen"),
+        )
+        .arg(
+            Arg::new("ir-loopback")
+                .long("ir-loopback")
+                .action(ArgAction::SetTrue)
+                .help("Reflect the infrared LED to the sensor of the same console"),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("MOVIE")
+                .conflicts_with("play")
+                .requires("rom")
+                .help("Record the joypad to a movie file"),
+        )
+        .arg(
+            Arg::new("play")
+                .long("play")
+                .value_name("MOVIE")
+                .requires("rom")
+                .help("Replay the joypad from a movie file"),
+        );
+    #[cfg(feature = "serde")]
+    let command = command.arg(
+        Arg::new("record-replay")
+            .long("record-replay")
+            .value_name("REPLAY")
+            .requires("rom")
+            .help("Record a replay from power on, to reproduce a bug exactly"),
+    );
+    #[cfg(feature = "serde")]
+    let command = command.arg(
+        Arg::new("load-state")
+            .long("load-state")
+            .value_name("SLOT")
+            .requires("rom")
+            .conflicts_with_all(["play", "record", "record-replay"])
+            .help("Start from a save state, the number of a slot next to the ROM or a file"),
+    );
+    let matches = command.get_matches();
+
+    let rom_path = matches.get_one::<String>("rom").map(PathBuf::from);
+    let rom = match &rom_path {
+        Some(path) => gb::rom::read(path)?,
+        None => Vec::new(),
+    };
+    let model = Model::from_rom(&rom);
+    let movie = match matches.get_one::<String>("play") {
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            if !movie.matches_rom(&rom) || movie.model != model {
+                return Err(format!("{} was recorded with another ROM", path).into());
+            }
+            Some(movie)
+        }
+        None => None,
+    };
+
+    // Without a cartridge the CPU reads 0xff everywhere and the screen stays blank
+    let emulator = Emulator::new(Vec::new(), &Default::default());
+    let sample_rate = emulator.mmu().apu().sample_rate();
+    let mut app = MyApp::new(emulator);
+    app.set_palette(*matches.get_one("palette").unwrap());
+    if let Some(path) = eframe::storage_dir("gb").map(|dir| dir.join("settings.cfg")) {
+        let settings = match Settings::parse_file(&path) {
+            Ok(settings) => settings,
+            Err(SettingsError::IOError(err)) if err.kind() == ErrorKind::NotFound => {
+                Settings::default()
+            }
+            Err(err) => {
+                eprintln!("Ignoring the settings {}: {}", path.display(), err);
+                Settings::default()
+            }
+        };
+        app.set_settings(settings, path);
+    }
+    if let Some(path) = &rom_path {
+        app.load_rom(rom.clone());
+        if path != Path::new("-") {
+            app.add_recent(path);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    if let Some(state) = matches.get_one::<String>("load-state") {
+        let path = match state.parse() {
+            Ok(slot) => gb::state::slot_path(rom_path.as_ref().unwrap(), slot),
+            Err(_) => PathBuf::from(state),
+        };
+        let in_file = |err| gb::Error::from(err).in_file(&path);
+        let data = std::fs::read(&path).map_err(|err| in_file(err.into()))?;
+        // Checked before the window opens, for the errors
+        Emulator::new(rom.clone(), &Default::default())
+            .load_state(&data)
+            .map_err(in_file)?;
+        app.load_state(data);
+    }
+    if let Some(path) = matches.get_one::<String>("input-map") {
+        app.set_input_map(InputMap::parse_file(path)?, PathBuf::from(path));
+    } else if let Some(path) = eframe::storage_dir("gb").map(|dir| dir.join("input.cfg")) {
+        let input_map = match InputMap::parse_file(&path) {
+            Ok(input_map) => input_map,
+            Err(InputMapError::IOError(err)) if err.kind() == ErrorKind::NotFound => {
+                InputMap::default()
+            }
+            Err(err) => {
+                eprintln!("Ignoring the key bindings {}: {}", path.display(), err);
+                InputMap::default()
+            }
+        };
+        app.set_input_map(input_map, path);
+    }
+    if let Some(path) = matches.get_one::<String>("annotations") {
+        app.set_annotations(Annotation::parse_file(path)?);
+    }
+    if let Some(path) = matches.get_one::<String>("dump-audio") {
+        app.set_wav_dump(WavDump::create(path, sample_rate)?);
+    }
+    if let Some(frame) = matches.get_one("screenshot-at-frame") {
+        app.set_screenshot_at_frame(*frame);
+    }
+    #[cfg(feature = "serde")]
+    if let Some(path) = matches.get_one::<String>("record-replay") {
+        app.set_replay_recording(PathBuf::from(path));
+    }
+    if matches.get_flag("serial") {
+        app.set_serial_echo();
+    }
+    if let Some(codes) = matches.get_many::<Cheat>("cheat") {
+        let mut cheats = Cheats::default();
+        for cheat in codes {
+            cheats.add(cheat.clone());
+        }
+        app.set_cheats(cheats);
+    }
+    if let Some(addr) = matches.get_one::<String>("link-listen") {
+        println!("Waiting for the other emulator on {}", addr);
+        app.set_link(Box::new(TcpLink::listen(addr)?));
+    } else if let Some(addr) = matches.get_one::<String>("link-connect") {
+        app.set_link(Box::new(TcpLink::connect(addr)?));
+    } else if matches.get_flag("link-loopback") {
+        app.set_link(Box::new(Loopback::default()));
+    }
+    if let Some(addr) = matches.get_one::<String>("ir-listen") {
+        println!("Waiting for the other emulator on {}", addr);
+        app.set_infrared(Box::new(TcpTransport::listen(addr)?));
+    } else if let Some(addr) = matches.get_one::<String>("ir-connect") {
+        app.set_infrared(Box::new(TcpTransport::connect(addr)?));
+    } else if matches.get_flag("ir-loopback") {
+        app.set_infrared(Box::new(infrared::Loopback::default()));
+    }
+    if let Some(movie) = movie {
+        app.set_movie(MovieMode::Play(movie));
+    } else if let Some(path) = matches.get_one::<String>("record") {
+        app.set_movie(MovieMode::Record(
+            Movie::new(model, &rom),
+            PathBuf::from(path),
+        ));
+    }
+    #[cfg(feature = "audio")]
+    match gb::audio::AudioOutput::new() {
+        Ok(audio) => app.set_audio_output(audio),
+        Err(err) => eprintln!("No sound: {}", err),
+    }
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([SCREEN_WIDTH as f32 * 3.0, SCREEN_HEIGHT as f32 * 3.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "gb",
+        options,
+        Box::new(|cc| {
+            if let Some(storage) = cc.storage {
+                app.restore(&cc.egui_ctx, storage);
+            }
+            Box::new(app)
+        }),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Cheat codes. The Game Genie sits between the cartridge and the console and
+//! replaces bytes of the ROM on the bus, the GameShark writes to the RAM at
+//! each VBlank:
+//! - `ABC-DEF` or `ABC-DEF-GHI` (Game Genie): the byte AB at the address FCDE
+//!   with F complemented, only when the ROM holds the byte GI rotated and
+//!   scrambled, if any. H is not used.
+//! - `TTVVAAAA` (GameShark): the byte VV at the address AAAA, its bytes
+//!   swapped. TT is the type, the bank of the external RAM for some codes.
+
+use std::{error::Error, fmt::Display, str::FromStr};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Code {
+    /// `value` read at `addr` of the ROM, when it holds `compare`
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// `value` written at `addr` after each frame. The bank is ignored,
+    /// there is a single bank of external RAM.
+    GameShark { bank: u8, addr: u16, value: u8 },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CheatError {
+    /// Neither a Game Genie code nor a GameShark code
+    InvalidCode(String),
+    /// A Game Genie code patching an address outside of the ROM
+    NotInRom(String),
+}
+
+impl Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidCode(code) => write!(
+                f,
+                "Invalid cheat code {}, expected ABC-DEF, ABC-DEF-GHI or 01VVAAAA",
+                code
+            ),
+            Self::NotInRom(code) => write!(f, "The Game Genie code {} is outside of the ROM", code),
+        }
+    }
+}
+
+impl Error for CheatError {}
+
+/// A code and its text, as entered
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cheat {
+    pub text: String,
+    pub code: Code,
+}
+
+impl FromStr for Cheat {
+    type Err = CheatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim().to_ascii_uppercase();
+        let invalid = || CheatError::InvalidCode(text.clone());
+        let digits: Vec<u8> = text
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+        let dashes: Vec<usize> = text.match_indices('-').map(|(i, _)| i).collect();
+        let code = match (digits.len(), dashes.as_slice()) {
+            (6, [3]) | (9, [3, 7]) => {
+                let addr = ((digits[5] ^ 0xf) as u16) << 12
+                    | (digits[2] as u16) << 8
+                    | (digits[3] as u16) << 4
+                    | digits[4] as u16;
+                if addr >= 0x8000 {
+                    return Err(CheatError::NotInRom(text));
+                }
+                let compare = (digits.len() == 9)
+                    .then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xba);
+                Code::GameGenie {
+                    addr,
+                    value: byte(0),
+                    compare,
+                }
+            }
+            (8, []) => Code::GameShark {
+                bank: byte(0),
+                value: byte(2),
+                addr: u16::from_le_bytes([byte(4), byte(6)]),
+            },
+            _ => return Err(invalid()),
+        };
+        Ok(Cheat { text, code })
+    }
+}
+
+impl Display for Cheat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    // With true for the codes enabled
+    cheats: Vec<(usize, Cheat, bool)>,
+    next_id: usize,
+}
+
+impl Cheats {
+    /// Returns the id of the cheat, enabled, to remove it or to disable it
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cheats.push((id, cheat, true));
+        id
+    }
+
+    /// False when there is no such cheat
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|(other, _, _)| *other != id);
+        self.cheats.len() != len
+    }
+
+    /// False when there is no such cheat
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        match self.cheats.iter_mut().find(|(other, _, _)| *other == id) {
+            Some((_, _, state)) => {
+                *state = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// The cheats, their ids and whether they are enabled, in the order they
+    /// were added
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Cheat, bool)> {
+        self.cheats
+            .iter()
+            .map(|(id, cheat, enabled)| (*id, cheat, *enabled))
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = Code> + '_ {
+        self.cheats
+            .iter()
+            .filter(|(_, _, enabled)| *enabled)
+            .map(|(_, cheat, _)| cheat.code)
+    }
+
+    /// The byte read at `addr` of the ROM, which holds `value`
+    pub fn patch(&self, addr: u16, value: u8) -> u8 {
+        self.enabled()
+            .find_map(|code| match code {
+                Code::GameGenie {
+                    addr: patched,
+                    value: replaced,
+                    compare,
+                } if patched == addr && compare.is_none_or(|compare| compare == value) => {
+                    Some(replaced)
+                }
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+
+    /// The addresses and values of the RAM codes, to write at each VBlank
+    pub fn writes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.enabled().filter_map(|code| match code {
+            Code::GameShark { addr, value, .. } => Some((addr, value)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(text: &str) -> Result<Code, CheatError> {
+        text.parse::<Cheat>().map(|cheat| cheat.code)
+    }
+
+    #[test]
+    fn test_cheat_parse() {
+        assert_eq!(
+            code("00a-17b-c49"),
+            Ok(Code::GameGenie {
+                addr: 0x4a17,
+                value: 0x00,
+                compare: Some(0xc8),
+            })
+        );
+        assert_eq!(
+            code("3E1-23F"),
+            Ok(Code::GameGenie {
+                addr: 0x0123,
+                value: 0x3e,
+                compare: None,
+            })
+        );
+        assert_eq!(
+            code(" 0163d2c1 "),
+            Ok(Code::GameShark {
+                bank: 0x01,
+                addr: 0xc1d2,
+                value: 0x63,
+            })
+        );
+        assert_eq!(
+            "00a-17b-c49".parse::<Cheat>().unwrap().to_string(),
+            "00A-17B-C49"
+        );
+        assert_eq!(
+            code("00A-177"),
+            Err(CheatError::NotInRom("00A-177".to_string()))
+        );
+        for invalid in [
+            "",
+            "00A17B",
+            "00A-17B-C4",
+            "0163D2C",
+            "0163-D2C1",
+            "00A-17G",
+        ] {
+            assert!(
+                matches!(code(invalid), Err(CheatError::InvalidCode(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut cheats = Cheats::default();
+        assert_eq!(cheats.patch(0x4a17, 0xc8), 0xc8);
+        let genie = cheats.add("00A-17B-C49".parse().unwrap());
+        let shark = cheats.add("0163D2C1".parse().unwrap());
+        assert_eq!(cheats.patch(0x4a17, 0xc8), 0x00);
+        // Another bank of the ROM
+        assert_eq!(cheats.patch(0x4a17, 0x12), 0x12);
+        assert_eq!(cheats.patch(0x4a18, 0xc8), 0xc8);
+        assert_eq!(cheats.writes().collect::<Vec<_>>(), [(0xc1d2, 0x63)]);
+
+        assert!(cheats.set_enabled(genie, false));
+        assert_eq!(cheats.patch(0x4a17, 0xc8), 0xc8);
+        assert!(cheats.remove(shark));
+        assert!(!cheats.remove(shark));
+        assert!(!cheats.set_enabled(shark, true));
+        assert_eq!(cheats.writes().count(), 0);
+        assert_eq!(
+            cheats
+                .iter()
+                .map(|(id, cheat, enabled)| (id, cheat.to_string(), enabled))
+                .collect::<Vec<_>>(),
+            [(genie, "00A-17B-C49".to_string(), false)]
+        );
+    }
+}
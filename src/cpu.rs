@@ -0,0 +1,1181 @@
+//! SM83 CPU, executing one instruction at a time
+//! See https://gbdev.io/pandocs/CPU_Instruction_Set.html
+
+use crate::debugger::breakpoints::{Breakpoints, Hit};
+use crate::debugger::call_stack::{CallFrame, CallKind, CallStack};
+use crate::debugger::profiler::Profiler;
+use crate::debugger::watches::Watches;
+use crate::mmu::Mmu;
+use crate::model::Model;
+
+pub const FLAG_Z: u8 = 0x80;
+pub const FLAG_N: u8 = 0x40;
+pub const FLAG_H: u8 = 0x20;
+pub const FLAG_C: u8 = 0x10;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl Registers {
+    /// State left by the boot ROM, which is not emulated
+    /// See https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    pub fn after_boot(model: Model) -> Self {
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg => (0x01, 0xb0, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d),
+        };
+        Self {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xfffe,
+            pc: 0x0100,
+        }
+    }
+
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    /// The lower 4 bits of F are always 0
+    pub fn set_af(&mut self, value: u16) {
+        [self.a, self.f] = value.to_be_bytes();
+        self.f &= 0xf0;
+    }
+
+    pub fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    pub fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.f |= flag;
+        } else {
+            self.f &= !flag;
+        }
+    }
+
+    fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = ((z as u8) << 7) | ((n as u8) << 6) | ((h as u8) << 5) | ((c as u8) << 4);
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cpu {
+    regs: Registers,
+    // Interrupt master enable
+    ime: bool,
+    // EI enables the interrupts after the next instruction
+    ime_pending: bool,
+    halted: bool,
+    // HALT with IME cleared and an interrupt pending: the next opcode is read twice
+    halt_bug: bool,
+    // An illegal opcode freezes the CPU, at its address
+    locked: Option<u16>,
+    // Address of the instruction executed by the last step
+    executed: Option<u16>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    breakpoints: Breakpoints,
+    // Breakpoint reached by the last step, the next one resumes
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hit: Option<Hit>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watches: Watches,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    profiler: Option<Box<Profiler>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    call_stack: CallStack,
+}
+
+impl Cpu {
+    pub fn new(regs: Registers) -> Self {
+        Self {
+            regs,
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            halt_bug: false,
+            locked: None,
+            executed: None,
+            breakpoints: Breakpoints::default(),
+            hit: None,
+            watches: Watches::default(),
+            profiler: None,
+            call_stack: CallStack::default(),
+        }
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.regs
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+        self.ime_pending = false;
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Address of the instruction executed by the last `step`, None when it
+    /// dispatched an interrupt or the CPU was halted
+    pub fn executed(&self) -> Option<u16> {
+        self.executed
+    }
+
+    /// Breakpoints checked before each instruction
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Address of the illegal opcode which froze the CPU, if any
+    pub fn locked(&self) -> Option<u16> {
+        self.locked
+    }
+
+    /// Breakpoint reached by the last `step`, which did not execute the
+    /// instruction
+    pub fn hit(&self) -> Option<Hit> {
+        self.hit
+    }
+
+    /// Registers and addresses compared after each instruction
+    pub fn watches(&self) -> &Watches {
+        &self.watches
+    }
+
+    pub fn watches_mut(&mut self) -> &mut Watches {
+        &mut self.watches
+    }
+
+    /// Count the cycles of each instruction, None to stop
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler.map(Box::new);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_deref_mut()
+    }
+
+    /// Calls, RSTs and interrupts not returned from yet
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Take the breakpoints, the watches and the profiler of `previous`,
+    /// replaced by a reset or a save state
+    pub fn keep_debugging(&mut self, previous: &mut Cpu) {
+        self.breakpoints = std::mem::take(&mut previous.breakpoints);
+        self.watches = std::mem::take(&mut previous.watches);
+        self.profiler = previous.profiler.take();
+    }
+
+    /// Execute one instruction, or dispatch an interrupt, and advance the
+    /// rest of the hardware accordingly. Returns the T-cycles elapsed, 0 when
+    /// a breakpoint is reached.
+    pub fn step(&mut self, mmu: &mut Mmu) -> u32 {
+        self.executed = None;
+        let resuming = self.hit.take().is_some();
+        let cycles = if let Some(cycles) = self.handle_interrupts(mmu) {
+            cycles
+        } else if self.halted || self.locked.is_some() {
+            4
+        } else {
+            if !resuming && !self.breakpoints.is_empty() {
+                self.hit = self.breakpoints.check(&self.regs, mmu);
+                if self.hit.is_some() {
+                    return 0;
+                }
+            }
+            self.executed = Some(self.regs.pc);
+            let enable_interrupts = self.ime_pending;
+            let sp = self.regs.sp;
+            let opcode = self.fetch(mmu);
+            let cycles = self.execute(opcode, mmu);
+            // RET, RETI and the conditional returns taken, for the step-outs
+            if matches!(opcode, 0xc0 | 0xc8 | 0xc9 | 0xd0 | 0xd8 | 0xd9)
+                && self.regs.sp == sp.wrapping_add(2)
+            {
+                self.breakpoints.returned(sp, &self.regs);
+                self.call_stack.returned(sp);
+            }
+            let kind = match opcode {
+                0xc4 | 0xcc | 0xd4 | 0xdc | 0xcd => Some(CallKind::Call),
+                0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => Some(CallKind::Rst),
+                _ => None,
+            };
+            if let (Some(kind), Some(caller)) = (kind, self.executed) {
+                if self.regs.sp == sp.wrapping_sub(2) {
+                    self.called(kind, caller, mmu);
+                }
+            }
+            if enable_interrupts && self.ime_pending {
+                self.ime = true;
+                self.ime_pending = false;
+            }
+            cycles
+        };
+        mmu.tick(cycles);
+        if !self.watches.is_empty() {
+            self.watches.update(self.executed, &self.regs, mmu);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(self.executed, cycles);
+        }
+        cycles
+    }
+
+    fn handle_interrupts(&mut self, mmu: &mut Mmu) -> Option<u32> {
+        let pending = mmu.interrupt_enable() & mmu.interrupt_flag() & 0x1f;
+        if pending == 0 {
+            return None;
+        }
+        // A pending interrupt always ends HALT, even when it is not serviced
+        let was_halted = std::mem::take(&mut self.halted);
+        if !self.ime {
+            return None;
+        }
+        self.ime = false;
+        let bit = pending.trailing_zeros() as u16;
+        mmu.acknowledge_interrupt(1 << bit);
+        let interrupted = self.regs.pc;
+        self.push(mmu, self.regs.pc);
+        self.regs.pc = 0x40 + bit * 8;
+        self.called(CallKind::Interrupt, interrupted, mmu);
+        Some(if was_halted { 24 } else { 20 })
+    }
+
+    // Record the call which just pushed its return address
+    fn called(&mut self, kind: CallKind, caller: u16, mmu: &Mmu) {
+        let sp = self.regs.sp;
+        self.call_stack.called(CallFrame {
+            kind,
+            caller,
+            target: self.regs.pc,
+            return_address: u16::from_le_bytes([mmu.peek(sp), mmu.peek(sp.wrapping_add(1))]),
+            sp,
+        });
+    }
+
+    fn fetch(&mut self, mmu: &Mmu) -> u8 {
+        let value = mmu.fetch(self.regs.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.regs.pc = self.regs.pc.wrapping_add(1);
+        }
+        value
+    }
+
+    fn fetch16(&mut self, mmu: &Mmu) -> u16 {
+        u16::from_le_bytes([self.fetch(mmu), self.fetch(mmu)])
+    }
+
+    fn push(&mut self, mmu: &mut Mmu, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        mmu.write(self.regs.sp, high);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        mmu.write(self.regs.sp, low);
+    }
+
+    fn pop(&mut self, mmu: &Mmu) -> u16 {
+        let low = mmu.read(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        let high = mmu.read(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        u16::from_be_bytes([high, low])
+    }
+
+    /// 8-bit operand encoded on 3 bits: B, C, D, E, H, L, (HL), A
+    fn r8(&self, mmu: &Mmu, index: u8) -> u8 {
+        match index {
+            0 => self.regs.b,
+            1 => self.regs.c,
+            2 => self.regs.d,
+            3 => self.regs.e,
+            4 => self.regs.h,
+            5 => self.regs.l,
+            6 => mmu.read(self.regs.hl()),
+            _ => self.regs.a,
+        }
+    }
+
+    fn set_r8(&mut self, mmu: &mut Mmu, index: u8, value: u8) {
+        match index {
+            0 => self.regs.b = value,
+            1 => self.regs.c = value,
+            2 => self.regs.d = value,
+            3 => self.regs.e = value,
+            4 => self.regs.h = value,
+            5 => self.regs.l = value,
+            6 => mmu.write(self.regs.hl(), value),
+            _ => self.regs.a = value,
+        }
+    }
+
+    /// 16-bit operand encoded on 2 bits: BC, DE, HL, SP
+    fn r16(&self, index: u8) -> u16 {
+        match index {
+            0 => self.regs.bc(),
+            1 => self.regs.de(),
+            2 => self.regs.hl(),
+            _ => self.regs.sp,
+        }
+    }
+
+    fn set_r16(&mut self, index: u8, value: u16) {
+        match index {
+            0 => self.regs.set_bc(value),
+            1 => self.regs.set_de(value),
+            2 => self.regs.set_hl(value),
+            _ => self.regs.sp = value,
+        }
+    }
+
+    /// Condition encoded on 2 bits: NZ, Z, NC, C
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.regs.flag(FLAG_Z),
+            1 => self.regs.flag(FLAG_Z),
+            2 => !self.regs.flag(FLAG_C),
+            _ => self.regs.flag(FLAG_C),
+        }
+    }
+
+    /// Execute `opcode` and return its duration in T-cycles. The opcode is
+    /// split in fields: xxyyyzzz, with yyy = ppq.
+    /// See https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+    fn execute(&mut self, opcode: u8, mmu: &mut Mmu) -> u32 {
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let p = y >> 1;
+        // Accessing (HL) instead of a register takes an extra memory cycle
+        let hl_cycles = |index: u8| if index == 6 { 4 } else { 0 };
+
+        match opcode {
+            0x00 => 4,
+            0x08 => {
+                let addr = self.fetch16(mmu);
+                let [high, low] = self.regs.sp.to_be_bytes();
+                mmu.write(addr, low);
+                mmu.write(addr.wrapping_add(1), high);
+                20
+            }
+            // STOP is followed by an ignored byte. Low power mode and the CGB
+            // speed switch are not emulated.
+            0x10 => {
+                self.fetch(mmu);
+                4
+            }
+            0x18 => {
+                let offset = self.fetch(mmu) as i8;
+                self.regs.pc = self.regs.pc.wrapping_add_signed(offset as i16);
+                12
+            }
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                let offset = self.fetch(mmu) as i8;
+                if self.condition(y - 4) {
+                    self.regs.pc = self.regs.pc.wrapping_add_signed(offset as i16);
+                    12
+                } else {
+                    8
+                }
+            }
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let value = self.fetch16(mmu);
+                self.set_r16(p, value);
+                12
+            }
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                let hl = self.regs.hl();
+                let value = self.r16(p);
+                let (result, carry) = hl.overflowing_add(value);
+                self.regs
+                    .set_flag(FLAG_H, (hl & 0x0fff) + (value & 0x0fff) > 0x0fff);
+                self.regs.set_flag(FLAG_C, carry);
+                self.regs.set_flag(FLAG_N, false);
+                self.regs.set_hl(result);
+                8
+            }
+            0x02 | 0x12 | 0x22 | 0x32 => {
+                mmu.write(self.indirect_address(p), self.regs.a);
+                8
+            }
+            0x0a | 0x1a | 0x2a | 0x3a => {
+                self.regs.a = mmu.read(self.indirect_address(p));
+                8
+            }
+            0x03 | 0x13 | 0x23 | 0x33 => {
+                self.set_r16(p, self.r16(p).wrapping_add(1));
+                8
+            }
+            0x0b | 0x1b | 0x2b | 0x3b => {
+                self.set_r16(p, self.r16(p).wrapping_sub(1));
+                8
+            }
+            0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c => {
+                let value = self.r8(mmu, y);
+                let result = value.wrapping_add(1);
+                self.regs.set_flag(FLAG_Z, result == 0);
+                self.regs.set_flag(FLAG_N, false);
+                self.regs.set_flag(FLAG_H, value & 0x0f == 0x0f);
+                self.set_r8(mmu, y, result);
+                4 + hl_cycles(y) * 2
+            }
+            0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d => {
+                let value = self.r8(mmu, y);
+                let result = value.wrapping_sub(1);
+                self.regs.set_flag(FLAG_Z, result == 0);
+                self.regs.set_flag(FLAG_N, true);
+                self.regs.set_flag(FLAG_H, value & 0x0f == 0);
+                self.set_r8(mmu, y, result);
+                4 + hl_cycles(y) * 2
+            }
+            0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => {
+                let value = self.fetch(mmu);
+                self.set_r8(mmu, y, value);
+                8 + hl_cycles(y)
+            }
+            // RLCA, RRCA, RLA and RRA always clear Z, unlike their CB versions
+            0x07 | 0x0f | 0x17 | 0x1f => {
+                self.regs.a = self.rotate(y, self.regs.a);
+                self.regs.set_flag(FLAG_Z, false);
+                4
+            }
+            0x27 => {
+                self.daa();
+                4
+            }
+            0x2f => {
+                self.regs.a = !self.regs.a;
+                self.regs.set_flag(FLAG_N, true);
+                self.regs.set_flag(FLAG_H, true);
+                4
+            }
+            0x37 | 0x3f => {
+                let carry = opcode == 0x37 || !self.regs.flag(FLAG_C);
+                self.regs.set_flag(FLAG_N, false);
+                self.regs.set_flag(FLAG_H, false);
+                self.regs.set_flag(FLAG_C, carry);
+                4
+            }
+            0x76 => {
+                let pending = mmu.interrupt_enable() & mmu.interrupt_flag() & 0x1f != 0;
+                if !self.ime && pending {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+                4
+            }
+            0x40..=0x7f => {
+                let value = self.r8(mmu, z);
+                self.set_r8(mmu, y, value);
+                4 + hl_cycles(y) + hl_cycles(z)
+            }
+            0x80..=0xbf => {
+                let value = self.r8(mmu, z);
+                self.alu(y, value);
+                4 + hl_cycles(z)
+            }
+            0xc0 | 0xc8 | 0xd0 | 0xd8 => {
+                if self.condition(y) {
+                    self.regs.pc = self.pop(mmu);
+                    20
+                } else {
+                    8
+                }
+            }
+            0xe0 => {
+                let addr = 0xff00 | self.fetch(mmu) as u16;
+                mmu.write(addr, self.regs.a);
+                12
+            }
+            0xf0 => {
+                let addr = 0xff00 | self.fetch(mmu) as u16;
+                self.regs.a = mmu.read(addr);
+                12
+            }
+            0xe8 => {
+                self.regs.sp = self.add_sp_offset(mmu);
+                16
+            }
+            0xf8 => {
+                let value = self.add_sp_offset(mmu);
+                self.regs.set_hl(value);
+                12
+            }
+            0xc1 | 0xd1 | 0xe1 | 0xf1 => {
+                let value = self.pop(mmu);
+                match p {
+                    3 => self.regs.set_af(value),
+                    _ => self.set_r16(p, value),
+                }
+                12
+            }
+            0xc9 => {
+                self.regs.pc = self.pop(mmu);
+                16
+            }
+            0xd9 => {
+                self.regs.pc = self.pop(mmu);
+                self.ime = true;
+                16
+            }
+            0xe9 => {
+                self.regs.pc = self.regs.hl();
+                4
+            }
+            0xf9 => {
+                self.regs.sp = self.regs.hl();
+                8
+            }
+            0xc2 | 0xca | 0xd2 | 0xda => {
+                let addr = self.fetch16(mmu);
+                if self.condition(y) {
+                    self.regs.pc = addr;
+                    16
+                } else {
+                    12
+                }
+            }
+            0xe2 => {
+                mmu.write(0xff00 | self.regs.c as u16, self.regs.a);
+                8
+            }
+            0xf2 => {
+                self.regs.a = mmu.read(0xff00 | self.regs.c as u16);
+                8
+            }
+            0xea => {
+                let addr = self.fetch16(mmu);
+                mmu.write(addr, self.regs.a);
+                16
+            }
+            0xfa => {
+                let addr = self.fetch16(mmu);
+                self.regs.a = mmu.read(addr);
+                16
+            }
+            0xc3 => {
+                self.regs.pc = self.fetch16(mmu);
+                16
+            }
+            0xcb => {
+                let opcode = self.fetch(mmu);
+                self.execute_extended(opcode, mmu)
+            }
+            0xf3 => {
+                self.ime = false;
+                self.ime_pending = false;
+                4
+            }
+            0xfb => {
+                self.ime_pending = true;
+                4
+            }
+            0xc4 | 0xcc | 0xd4 | 0xdc => {
+                let addr = self.fetch16(mmu);
+                if self.condition(y) {
+                    self.push(mmu, self.regs.pc);
+                    self.regs.pc = addr;
+                    24
+                } else {
+                    12
+                }
+            }
+            0xc5 | 0xd5 | 0xe5 | 0xf5 => {
+                let value = match p {
+                    3 => self.regs.af(),
+                    _ => self.r16(p),
+                };
+                self.push(mmu, value);
+                16
+            }
+            0xcd => {
+                let addr = self.fetch16(mmu);
+                self.push(mmu, self.regs.pc);
+                self.regs.pc = addr;
+                24
+            }
+            0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => {
+                let value = self.fetch(mmu);
+                self.alu(y, value);
+                8
+            }
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
+                self.push(mmu, self.regs.pc);
+                self.regs.pc = y as u16 * 8;
+                16
+            }
+            // 0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc and 0xfd
+            _ => {
+                self.locked = Some(self.executed.unwrap_or(self.regs.pc.wrapping_sub(1)));
+                4
+            }
+        }
+    }
+
+    fn execute_extended(&mut self, opcode: u8, mmu: &mut Mmu) -> u32 {
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let value = self.r8(mmu, z);
+        match opcode >> 6 {
+            0 => {
+                let result = self.rotate(y, value);
+                self.set_r8(mmu, z, result);
+            }
+            1 => {
+                self.regs.set_flag(FLAG_Z, value & (1 << y) == 0);
+                self.regs.set_flag(FLAG_N, false);
+                self.regs.set_flag(FLAG_H, true);
+                // BIT only reads (HL)
+                return if z == 6 { 12 } else { 8 };
+            }
+            2 => self.set_r8(mmu, z, value & !(1 << y)),
+            _ => self.set_r8(mmu, z, value | (1 << y)),
+        }
+        if z == 6 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Address used by LD (BC), LD (DE), LD (HL+) and LD (HL-)
+    fn indirect_address(&mut self, index: u8) -> u16 {
+        match index {
+            0 => self.regs.bc(),
+            1 => self.regs.de(),
+            2 => {
+                let hl = self.regs.hl();
+                self.regs.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            _ => {
+                let hl = self.regs.hl();
+                self.regs.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+        }
+    }
+
+    /// SP + signed offset, used by ADD SP,e and LD HL,SP+e. The flags are
+    /// computed on the lower byte as an unsigned addition.
+    fn add_sp_offset(&mut self, mmu: &Mmu) -> u16 {
+        let offset = self.fetch(mmu);
+        let sp = self.regs.sp;
+        self.regs.set_flags(
+            false,
+            false,
+            (sp & 0x0f) + (offset as u16 & 0x0f) > 0x0f,
+            (sp & 0xff) + offset as u16 > 0xff,
+        );
+        sp.wrapping_add_signed(offset as i8 as i16)
+    }
+
+    /// ADD, ADC, SUB, SBC, AND, XOR, OR and CP with A
+    fn alu(&mut self, operation: u8, value: u8) {
+        let a = self.regs.a;
+        let carry = self.regs.flag(FLAG_C) as u8;
+        match operation {
+            0 | 1 => {
+                let carry = if operation == 1 { carry } else { 0 };
+                let result = a as u16 + value as u16 + carry as u16;
+                self.regs.set_flags(
+                    result as u8 == 0,
+                    false,
+                    (a & 0x0f) + (value & 0x0f) + carry > 0x0f,
+                    result > 0xff,
+                );
+                self.regs.a = result as u8;
+            }
+            2 | 3 | 7 => {
+                let carry = if operation == 3 { carry } else { 0 };
+                let result = a.wrapping_sub(value).wrapping_sub(carry);
+                self.regs.set_flags(
+                    result == 0,
+                    true,
+                    (a & 0x0f) < (value & 0x0f) + carry,
+                    (a as u16) < value as u16 + carry as u16,
+                );
+                if operation != 7 {
+                    self.regs.a = result;
+                }
+            }
+            4 => {
+                self.regs.a &= value;
+                self.regs.set_flags(self.regs.a == 0, false, true, false);
+            }
+            5 => {
+                self.regs.a ^= value;
+                self.regs.set_flags(self.regs.a == 0, false, false, false);
+            }
+            _ => {
+                self.regs.a |= value;
+                self.regs.set_flags(self.regs.a == 0, false, false, false);
+            }
+        }
+    }
+
+    /// RLC, RRC, RL, RR, SLA, SRA, SWAP and SRL
+    fn rotate(&mut self, operation: u8, value: u8) -> u8 {
+        let carry = self.regs.flag(FLAG_C) as u8;
+        let (result, carry) = match operation {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => ((value << 1) | carry, value & 0x80 != 0),
+            3 => ((value >> 1) | (carry << 7), value & 0x01 != 0),
+            4 => (value << 1, value & 0x80 != 0),
+            5 => ((value >> 1) | (value & 0x80), value & 0x01 != 0),
+            6 => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+        self.regs.set_flags(result == 0, false, false, carry);
+        result
+    }
+
+    /// Adjust A to a valid BCD number after an addition or a subtraction
+    fn daa(&mut self) {
+        let mut a = self.regs.a;
+        let mut carry = self.regs.flag(FLAG_C);
+        if !self.regs.flag(FLAG_N) {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.regs.flag(FLAG_H) || a & 0x0f > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.regs.flag(FLAG_H) {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        let n = self.regs.flag(FLAG_N);
+        self.regs.set_flags(a == 0, n, false, carry);
+        self.regs.a = a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Opcode::{self, *};
+    use crate::interrupts::Interrupt;
+    use crate::mmu::{IE, IF};
+    use crate::program::Program;
+    use crate::slots::{AddrRegister, Register16::*, Register8::*, Slot};
+    use proptest::prelude::*;
+
+    /// CPU running `opcodes` from 0x0100 in the ROM
+    fn run(opcodes: impl IntoIterator<Item = Opcode>, steps: usize) -> (Cpu, Mmu) {
+        run_program(&Program::new(0x0100).ops(opcodes), steps)
+    }
+
+    /// CPU running `program` from the ROM, with the registers left by the
+    /// boot ROM
+    fn run_program(program: &Program, steps: usize) -> (Cpu, Mmu) {
+        let mut mmu = Mmu::new(program.rom(), Model::Dmg);
+        let mut cpu = Cpu::new(Registers {
+            pc: program.origin(),
+            ..Registers::after_boot(Model::Dmg)
+        });
+        for _ in 0..steps {
+            cpu.step(&mut mmu);
+        }
+        (cpu, mmu)
+    }
+
+    #[test]
+    fn test_cpu_loads() {
+        let (cpu, mmu) = run(
+            [
+                Ld(Slot::r16(BC), Slot::Data16(0x1234)),
+                Ld(Slot::r8(A), Slot::r8(B)),
+                Ld(Slot::r16(HL), Slot::Data16(0xc000)),
+                LdToMemInc(HL, A),
+                Ld(Slot::addr(AddrRegister::HL), Slot::r8(C)),
+                Ld(Slot::r8(E), Slot::addr(AddrRegister::HL)),
+            ],
+            6,
+        );
+        let regs = cpu.registers();
+        assert_eq!(regs.a, 0x12);
+        assert_eq!(regs.hl(), 0xc001);
+        assert_eq!(regs.e, 0x34);
+        assert_eq!(mmu.read(0xc000), 0x12);
+        assert_eq!(regs.pc, 0x010a);
+    }
+
+    #[test]
+    fn test_cpu_alu_flags() {
+        let ld_a = |value| Ld(Slot::r8(A), Slot::Data8(value));
+        let (cpu, _) = run([ld_a(0x0f), Add(Slot::r8(A), Slot::Data8(0x01))], 2);
+        assert_eq!(cpu.registers().a, 0x10);
+        assert_eq!(cpu.registers().f, FLAG_H);
+
+        let (cpu, _) = run([ld_a(0x10), Sub(Slot::Data8(0x20))], 2);
+        assert_eq!(cpu.registers().a, 0xf0);
+        assert_eq!(cpu.registers().f, FLAG_N | FLAG_C);
+
+        let (cpu, _) = run(
+            [
+                Xor(Slot::r8(A), Slot::r8(A)),
+                Cp(Slot::r8(A), Slot::Data8(0x00)),
+            ],
+            2,
+        );
+        assert_eq!(cpu.registers().f, FLAG_Z | FLAG_N);
+
+        let (cpu, _) = run([ld_a(0x45), Add(Slot::r8(A), Slot::Data8(0x38)), Daa], 3);
+        assert_eq!(cpu.registers().a, 0x83);
+    }
+
+    #[test]
+    fn test_cpu_extended() {
+        let b = Slot::r8(B);
+        let (cpu, _) = run(
+            [
+                Ld(b, Slot::Data8(0x81)),
+                RotLeftCircular(b),
+                ComplBit(7, b),
+                Swap(b),
+                SetBit(0, b),
+            ],
+            5,
+        );
+        assert_eq!(cpu.registers().b, 0x31);
+        assert_eq!(cpu.registers().f, 0);
+    }
+
+    #[test]
+    fn test_cpu_jumps_and_calls() {
+        let program = Program::new(0x0100)
+            .ops([Call(Slot::Data16(0x0110)), Jump(-2)])
+            .at(0x0110)
+            .ops([Push(BC), Pop(DE), Ret]);
+        let (cpu, _) = run_program(&program, 5);
+        assert_eq!(cpu.registers().de(), 0x0013);
+        assert_eq!(cpu.registers().sp, 0xfffe);
+        assert_eq!(cpu.registers().pc, 0x0103);
+    }
+
+    #[test]
+    fn test_cpu_call_stack() {
+        let mut rom = vec![0; 0x8000];
+        // EI; CALL 0x0110; ... 0x0110: RST 0x08; RET; 0x08: NOP; RET; 0x40: RETI
+        rom[0x100..0x106].copy_from_slice(&[0xfb, 0xcd, 0x10, 0x01, 0x18, 0xfe]);
+        rom[0x110..0x112].copy_from_slice(&[0xcf, 0xc9]);
+        rom[0x08..0x0a].copy_from_slice(&[0x00, 0xc9]);
+        rom[0x40] = 0xd9;
+        let mut mmu = Mmu::new(rom, Model::Dmg);
+        let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+        for _ in 0..4 {
+            cpu.step(&mut mmu);
+        }
+        let targets = |cpu: &Cpu| -> Vec<u16> {
+            let frames = cpu.call_stack().frames();
+            frames.iter().map(|frame| frame.target).collect()
+        };
+        assert_eq!(targets(&cpu), [0x0110, 0x0008]);
+        assert_eq!(cpu.call_stack().frames()[1].return_address, 0x0111);
+
+        mmu.write(IE, Interrupt::VBlank.mask());
+        mmu.write(IF, Interrupt::VBlank.mask());
+        cpu.step(&mut mmu);
+        let frame = cpu.call_stack().frames()[2];
+        assert_eq!(frame.kind, CallKind::Interrupt);
+        assert_eq!((frame.caller, frame.target), (0x0009, 0x0040));
+        cpu.step(&mut mmu);
+        assert_eq!(targets(&cpu), [0x0110, 0x0008]);
+        cpu.step(&mut mmu);
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.call_stack().depth(), 0);
+        assert_eq!(cpu.registers().pc, 0x0104);
+    }
+
+    #[test]
+    fn test_cpu_cycles() {
+        let mut rom = vec![0; 0x8000];
+        // JR NZ,+0 not taken, Z is set after boot; INC (HL); CALL 0x0200
+        rom[0x100..0x107].copy_from_slice(&[0x20, 0x00, 0x34, 0xcd, 0x00, 0x02, 0x00]);
+        let mut mmu = Mmu::new(rom, Model::Dmg);
+        let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+        assert_eq!(cpu.step(&mut mmu), 8);
+        assert_eq!(cpu.step(&mut mmu), 12);
+        assert_eq!(cpu.step(&mut mmu), 24);
+    }
+
+    #[test]
+    fn test_cpu_breakpoints() {
+        // LD A,0x42; INC A; JR -3
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x105].copy_from_slice(&[0x3e, 0x42, 0x3c, 0x18, 0xfd]);
+        let mut mmu = Mmu::new(rom, Model::Dmg);
+        let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+        let id = cpu.breakpoints_mut().add("A == 0x43".parse().unwrap());
+        cpu.step(&mut mmu);
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.step(&mut mmu), 0);
+        assert_eq!(cpu.hit(), Some(Hit { id, pc: 0x0103 }));
+        assert_eq!(cpu.executed(), None);
+        // Resumed, then reached again before INC A
+        assert_eq!(cpu.step(&mut mmu), 12);
+        assert_eq!(cpu.hit(), None);
+        assert_eq!(cpu.step(&mut mmu), 0);
+        assert_eq!(cpu.hit(), Some(Hit { id, pc: 0x0102 }));
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.registers().a, 0x44);
+        assert!(cpu.breakpoints_mut().remove(id));
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.hit(), None);
+    }
+
+    #[test]
+    fn test_cpu_watches() {
+        // LD A,0x42; LD (0xc000),A
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x105].copy_from_slice(&[0x3e, 0x42, 0xea, 0x00, 0xc0]);
+        let mut mmu = Mmu::new(rom, Model::Dmg);
+        let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+        let watch = cpu.watches_mut().add("0xc000".parse().unwrap());
+        cpu.step(&mut mmu);
+        cpu.step(&mut mmu);
+        let changes: Vec<_> = cpu.watches().changes().collect();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].id, changes[0].pc), (watch, Some(0x0102)));
+        assert_eq!(changes[0].new, 0x42);
+    }
+
+    #[test]
+    fn test_cpu_profiler() {
+        // NOP; JR -3
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0x00, 0x18, 0xfd]);
+        let mut mmu = Mmu::new(rom, Model::Dmg);
+        let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+        cpu.set_profiler(Some(Profiler::new()));
+        for _ in 0..10 {
+            cpu.step(&mut mmu);
+        }
+        let profiler = cpu.profiler().unwrap();
+        assert_eq!(profiler.total_cycles(), 5 * 4 + 5 * 12);
+        assert_eq!(profiler.hottest(1)[0].pc, 0x0101);
+    }
+
+    #[test]
+    fn test_cpu_interrupts() {
+        let (mut cpu, mut mmu) = run([Ei, Nop, Halt], 3);
+        assert!(cpu.ime());
+        assert!(cpu.halted());
+        mmu.write(IE, Interrupt::Timer.mask());
+        mmu.write(IF, Interrupt::Timer.mask());
+        assert_eq!(cpu.step(&mut mmu), 24);
+        assert_eq!(cpu.registers().pc, 0x0050);
+        assert!(!cpu.ime());
+        assert_eq!(mmu.read(IF) & 0x1f, 0);
+    }
+
+    #[test]
+    fn test_cpu_halt_bug() {
+        let mut rom = vec![0; 0x8000];
+        // HALT with IME cleared and an interrupt pending; INC A
+        rom[0x100..0x102].copy_from_slice(&[0x76, 0x3c]);
+        let mut mmu = Mmu::new(rom, Model::Dmg);
+        mmu.write(IE, Interrupt::VBlank.mask());
+        mmu.write(IF, Interrupt::VBlank.mask());
+        let mut cpu = Cpu::new(Registers::after_boot(Model::Dmg));
+        cpu.step(&mut mmu);
+        assert!(!cpu.halted());
+        cpu.step(&mut mmu);
+        cpu.step(&mut mmu);
+        // INC A is executed twice
+        assert_eq!(cpu.registers().a, 0x03);
+    }
+
+    /// Registers after running `program` from 0xc000 on a flat memory
+    fn execute(program: &[u8], regs: Registers) -> Registers {
+        let mut mmu = Mmu::flat();
+        for (addr, &byte) in (0xc000..).zip(program) {
+            mmu.write(addr, byte);
+        }
+        let end = 0xc000 + program.len() as u16;
+        let mut cpu = Cpu::new(Registers { pc: 0xc000, ..regs });
+        while cpu.registers().pc < end {
+            cpu.step(&mut mmu);
+        }
+        *cpu.registers()
+    }
+
+    /// Any registers, the lower bits of F are always 0
+    fn registers() -> impl Strategy<Value = Registers> {
+        any::<[u8; 8]>().prop_map(|[a, f, b, c, d, e, h, l]| Registers {
+            a,
+            f: f & 0xf0,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xfffe,
+            pc: 0,
+        })
+    }
+
+    fn bcd() -> impl Strategy<Value = u8> {
+        (0..100u8).prop_map(|value| ((value / 10) << 4) | (value % 10))
+    }
+
+    proptest! {
+        #[test]
+        fn test_xor_a_clears_a(regs in registers()) {
+            let after = execute(&[0xaf], regs);
+            prop_assert_eq!((after.a, after.f), (0, FLAG_Z));
+        }
+
+        #[test]
+        fn test_add_flags(regs in registers()) {
+            // ADD A,B; then SUB B restores A
+            let after = execute(&[0x80], regs);
+            let (a, b) = (regs.a, regs.b);
+            prop_assert_eq!(after.a, a.wrapping_add(b));
+            prop_assert_eq!(after.flag(FLAG_Z), after.a == 0);
+            prop_assert!(!after.flag(FLAG_N));
+            prop_assert_eq!(after.flag(FLAG_H), (a & 0xf) + (b & 0xf) > 0xf);
+            prop_assert_eq!(after.flag(FLAG_C), a as u16 + b as u16 > 0xff);
+            prop_assert_eq!(execute(&[0x80, 0x90], regs).a, a);
+        }
+
+        #[test]
+        fn test_cp_is_sub_without_result(regs in registers()) {
+            // CP B and SUB B
+            let (cp, sub) = (execute(&[0xb8], regs), execute(&[0x90], regs));
+            prop_assert_eq!(cp.f, sub.f);
+            prop_assert_eq!(cp.a, regs.a);
+            prop_assert_eq!(cp.flag(FLAG_Z), regs.a == regs.b);
+            prop_assert_eq!(cp.flag(FLAG_C), regs.a < regs.b);
+        }
+
+        #[test]
+        fn test_inc_dec_keep_carry(regs in registers()) {
+            // INC B; DEC B
+            for program in [&[0x04][..], &[0x05], &[0x04, 0x05]] {
+                prop_assert_eq!(execute(program, regs).flag(FLAG_C), regs.flag(FLAG_C));
+            }
+            prop_assert_eq!(execute(&[0x04, 0x05], regs).b, regs.b);
+        }
+
+        #[test]
+        fn test_logic_flags(regs in registers()) {
+            // AND B sets H, OR B and XOR B clear it
+            for (opcode, result, h) in [
+                (0xa0, regs.a & regs.b, true),
+                (0xb0, regs.a | regs.b, false),
+                (0xa8, regs.a ^ regs.b, false),
+            ] {
+                let after = execute(&[opcode], regs);
+                prop_assert_eq!(after.a, result);
+                let f = if result == 0 { FLAG_Z } else { 0 } | if h { FLAG_H } else { 0 };
+                prop_assert_eq!(after.f, f);
+            }
+        }
+
+        #[test]
+        fn test_involutions(regs in registers()) {
+            // SWAP A twice, CPL twice, RLC B then RRC B
+            prop_assert_eq!(execute(&[0xcb, 0x37, 0xcb, 0x37], regs).a, regs.a);
+            prop_assert_eq!(execute(&[0x2f, 0x2f], regs).a, regs.a);
+            prop_assert_eq!(execute(&[0xcb, 0x00, 0xcb, 0x08], regs).b, regs.b);
+        }
+
+        #[test]
+        fn test_scf_ccf(regs in registers()) {
+            let after = execute(&[0x37, 0x3f], regs);
+            prop_assert_eq!(after.f, regs.f & FLAG_Z);
+        }
+
+        #[test]
+        fn test_push_pop(regs in registers()) {
+            // PUSH BC; POP DE; PUSH AF; POP AF
+            let after = execute(&[0xc5, 0xd1, 0xf5, 0xf1], regs);
+            prop_assert_eq!(after.de(), regs.bc());
+            prop_assert_eq!((after.af(), after.sp), (regs.af(), regs.sp));
+        }
+
+        #[test]
+        fn test_daa_after_add(regs in registers(), a in bcd(), b in bcd()) {
+            // ADD A,B; DAA gives the decimal sum
+            let after = execute(&[0x80, 0x27], Registers { a, b, ..regs });
+            let decimal = |value: u8| (value >> 4) * 10 + (value & 0xf);
+            let sum = decimal(a) + decimal(b);
+            prop_assert_eq!(decimal(after.a), sum % 100);
+            prop_assert_eq!(after.flag(FLAG_C), sum >= 100);
+            prop_assert_eq!(after.flag(FLAG_Z), sum % 100 == 0);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Breakpoints checked by `Cpu::step` before each instruction: on an address,
+//! on a kind of instruction or on a condition over the registers and the
+//! memory. The instruction is not executed, the next step resumes it.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::cpu::Registers;
+use crate::debugger::expression::{parse_number, Expression, ExpressionError};
+use crate::decoder::{decode, MNEMONICS};
+use crate::mmu::Mmu;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Breakpoint {
+    /// Before the instruction at this address
+    Pc(u16),
+    /// Before any instruction with this mnemonic, as in `Opcode::mnemonic`
+    Opcode(String),
+    /// Before any instruction when the expression is not 0
+    Condition(Expression),
+    /// Before the instruction at `pc` with SP at or above `sp`, back from a
+    /// subroutine even if it is recursive
+    Return { pc: u16, sp: u16 },
+    /// After a return popping its address at or above `sp`, out of the
+    /// subroutine running when it was added
+    StepOut { sp: u16 },
+}
+
+impl FromStr for Breakpoint {
+    type Err = ExpressionError;
+
+    /// An address such as `0x0150`, a mnemonic such as `call` or an
+    /// expression such as `A==0x42 && HL>=0x8000`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(addr) = parse_number(s) {
+            return u16::try_from(addr)
+                .map(Breakpoint::Pc)
+                .map_err(|_| ExpressionError::InvalidNumber(s.to_string()));
+        }
+        let upper = s.to_ascii_uppercase();
+        if MNEMONICS.contains(&upper.as_str()) {
+            return Ok(Breakpoint::Opcode(upper));
+        }
+        s.parse().map(Breakpoint::Condition)
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Pc(addr) => write!(f, "0x{:04x}", addr),
+            Breakpoint::Opcode(mnemonic) => f.write_str(mnemonic),
+            Breakpoint::Condition(expression) => write!(f, "{}", expression),
+            Breakpoint::Return { pc, sp } => write!(f, "0x{:04x} with SP >= 0x{:04x}", pc, sp),
+            Breakpoint::StepOut { sp } => write!(f, "Return with SP >= 0x{:04x}", sp),
+        }
+    }
+}
+
+/// A breakpoint reached by `Cpu::step`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hit {
+    /// As returned by `Breakpoints::add`
+    pub id: usize,
+    /// Address of the instruction not executed yet
+    pub pc: u16,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    // With true for the transient breakpoints, removed when they are hit
+    breakpoints: Vec<(usize, Breakpoint, bool)>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    /// Returns the id of the breakpoint, to remove it and to identify the hits
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        self.push(breakpoint, false)
+    }
+
+    /// Add a breakpoint removed after its first hit
+    pub fn add_transient(&mut self, breakpoint: Breakpoint) -> usize {
+        self.push(breakpoint, true)
+    }
+
+    fn push(&mut self, breakpoint: Breakpoint, transient: bool) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push((id, breakpoint, transient));
+        id
+    }
+
+    /// False when there is no such breakpoint
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|(other, _, _)| *other != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    /// The breakpoints and their ids, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints
+            .iter()
+            .map(|(id, breakpoint, _)| (*id, breakpoint))
+    }
+
+    /// The first breakpoint reached before executing the instruction at
+    /// `regs.pc`, removed if it is transient
+    pub fn check(&mut self, regs: &Registers, mmu: &Mmu) -> Option<Hit> {
+        let pc = regs.pc;
+        // Decoded once, only for the breakpoints on opcodes
+        let mut mnemonic = None;
+        let mut mnemonic = || {
+            *mnemonic.get_or_insert_with(|| {
+                decode(&mut (0..3).map(|offset| mmu.peek(pc.wrapping_add(offset))))
+                    .map(|opcode| opcode.mnemonic())
+                    .ok()
+            })
+        };
+        let index = self
+            .breakpoints
+            .iter()
+            .position(|(_, breakpoint, _)| match breakpoint {
+                Breakpoint::Pc(addr) => *addr == pc,
+                Breakpoint::Opcode(name) => mnemonic() == Some(name.as_str()),
+                Breakpoint::Condition(expression) => expression.is_true(regs, mmu),
+                Breakpoint::Return { pc: addr, sp } => *addr == pc && regs.sp >= *sp,
+                Breakpoint::StepOut { .. } => false,
+            })?;
+        let (id, _, transient) = self.breakpoints[index];
+        if transient {
+            self.breakpoints.remove(index);
+        }
+        Some(Hit { id, pc })
+    }
+
+    /// Called by the CPU after a return which popped its address from
+    /// `popped_at`: the step-outs it satisfies stop before the next
+    /// instruction
+    pub fn returned(&mut self, popped_at: u16, regs: &Registers) {
+        for (_, breakpoint, _) in &mut self.breakpoints {
+            if let Breakpoint::StepOut { sp } = *breakpoint {
+                if popped_at >= sp {
+                    *breakpoint = Breakpoint::Return {
+                        pc: regs.pc,
+                        sp: regs.sp,
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    #[test]
+    fn test_breakpoint_parse() {
+        assert_eq!("0x0150".parse(), Ok(Breakpoint::Pc(0x150)));
+        assert_eq!("$c000".parse(), Ok(Breakpoint::Pc(0xc000)));
+        assert_eq!("call".parse(), Ok(Breakpoint::Opcode("CALL".into())));
+        let breakpoint: Breakpoint = "A==0x42 && HL>=0x8000".parse().unwrap();
+        assert!(matches!(breakpoint, Breakpoint::Condition(_)));
+        assert_eq!(breakpoint.to_string(), "A==0x42 && HL>=0x8000");
+        assert!("0x10000".parse::<Breakpoint>().is_err());
+        assert!("A ==".parse::<Breakpoint>().is_err());
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // CALL 0x0150
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xcd, 0x50, 0x01]);
+        let mmu = Mmu::after_boot(rom, Model::Dmg);
+        let mut regs = Registers::after_boot(Model::Dmg);
+
+        let mut breakpoints = Breakpoints::default();
+        assert_eq!(breakpoints.check(&regs, &mmu), None);
+        let pc = breakpoints.add(Breakpoint::Pc(0x150));
+        let call = breakpoints.add("CALL".parse().unwrap());
+        let condition = breakpoints.add("A == 0x42".parse().unwrap());
+        assert_eq!(breakpoints.iter().count(), 3);
+        assert_eq!(
+            breakpoints.check(&regs, &mmu),
+            Some(Hit {
+                id: call,
+                pc: 0x100
+            })
+        );
+
+        regs.pc = 0x150;
+        assert_eq!(breakpoints.check(&regs, &mmu).unwrap().id, pc);
+        assert!(breakpoints.remove(pc));
+        assert!(!breakpoints.remove(pc));
+        assert_eq!(breakpoints.check(&regs, &mmu), None);
+        regs.a = 0x42;
+        assert_eq!(breakpoints.check(&regs, &mmu).unwrap().id, condition);
+
+        breakpoints.clear();
+        assert!(breakpoints.is_empty());
+        assert_eq!(breakpoints.check(&regs, &mmu), None);
+    }
+
+    #[test]
+    fn test_breakpoints_transient() {
+        let mmu = Mmu::after_boot(Vec::new(), Model::Dmg);
+        let mut regs = Registers::after_boot(Model::Dmg);
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints.add_transient(Breakpoint::Return {
+            pc: 0x103,
+            sp: 0xfffe,
+        });
+        regs.pc = 0x103;
+        regs.sp = 0xfffc;
+        // A recursive call, deeper in the stack
+        assert_eq!(breakpoints.check(&regs, &mmu), None);
+        regs.sp = 0xfffe;
+        assert_eq!(breakpoints.check(&regs, &mmu), Some(Hit { id, pc: 0x103 }));
+        assert!(breakpoints.is_empty());
+
+        let id = breakpoints.add_transient(Breakpoint::StepOut { sp: 0xfffa });
+        breakpoints.returned(0xfff8, &regs);
+        assert_eq!(breakpoints.check(&regs, &mmu), None);
+        breakpoints.returned(0xfffc, &regs);
+        assert_eq!(breakpoints.check(&regs, &mmu), Some(Hit { id, pc: 0x103 }));
+        assert!(breakpoints.is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+//! Call stack inferred by the CPU from the calls, the RSTs and the
+//! interrupts dispatched, and from the returns. Games also move the stack
+//! by hand: a frame whose return address is left on the stack without
+//! returning is dropped once SP goes above it, and a return to an address
+//! pushed by the game, as in a jump table, keeps the frames.
+
+use std::fmt::Display;
+
+/// Frames kept, the oldest ones are dropped by the code calling without
+/// returning
+pub const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CallKind {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    /// Address of the call, or of the instruction interrupted
+    pub caller: u16,
+    /// Address called
+    pub target: u16,
+    pub return_address: u16,
+    /// Where the return address is on the stack
+    pub sp: u16,
+}
+
+impl Display for CallKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Call => write!(f, "call"),
+            Self::Rst => write!(f, "rst"),
+            Self::Interrupt => write!(f, "interrupt"),
+        }
+    }
+}
+
+impl Display for CallFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04x} {} from {:04x}, returns to {:04x}",
+            self.target, self.kind, self.caller, self.return_address
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    // The outermost frame first
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    /// The frames from the outermost one to the current one
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Called by the CPU once the return address of `frame` is pushed
+    pub fn called(&mut self, frame: CallFrame) {
+        // Those frames were overwritten, the stack was moved up
+        self.frames.retain(|outer| outer.sp > frame.sp);
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// Called by the CPU after a return which popped its address from
+    /// `popped_at`
+    pub fn returned(&mut self, popped_at: u16) {
+        // The frames below were left without returning
+        while self
+            .frames
+            .last()
+            .is_some_and(|frame| frame.sp <= popped_at)
+        {
+            self.frames.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(target: u16, sp: u16) -> CallFrame {
+        CallFrame {
+            kind: CallKind::Call,
+            caller: 0x0150,
+            target,
+            return_address: 0x0153,
+            sp,
+        }
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut stack = CallStack::default();
+        stack.called(frame(0x1000, 0xfffc));
+        stack.called(frame(0x2000, 0xfffa));
+        stack.called(frame(0x3000, 0xfff8));
+        // PUSH HL; RET jumps without leaving the function
+        stack.returned(0xfff6);
+        assert_eq!(stack.depth(), 3);
+        stack.returned(0xfff8);
+        assert_eq!(stack.frames().last().unwrap().target, 0x2000);
+
+        // The return address of 0x2000 was popped by hand
+        stack.returned(0xfffc);
+        assert_eq!(stack.depth(), 0);
+
+        // The stack was reset to 0xfffe before calling
+        stack.called(frame(0x1000, 0xfff0));
+        stack.called(frame(0x2000, 0xfffc));
+        assert_eq!(stack.frames(), [frame(0x2000, 0xfffc)]);
+
+        for _ in 0..MAX_DEPTH + 1 {
+            let sp = 0xfffa - stack.depth() as u16 * 2;
+            stack.called(frame(0x4000, sp));
+        }
+        assert_eq!(stack.depth(), MAX_DEPTH);
+        assert_eq!(stack.frames()[0].target, 0x4000);
+        assert_eq!(
+            stack.frames()[0].to_string(),
+            "4000 call from 0150, returns to 0153"
+        );
+    }
+}
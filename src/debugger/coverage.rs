@@ -0,0 +1,176 @@
+//! Bytes of the ROM executed or only read while running, to guide the
+//! disassembler: the regions never executed become Data annotations.
+//! ```no_run
+//! use gb::debugger::coverage::Coverage;
+//!
+//! let rom = gb::rom::read("game.gb").unwrap();
+//! let mut emulator = gb::Emulator::new(rom.clone(), &Default::default());
+//! let coverage = Coverage::new(rom.len());
+//! emulator.add_observer(Box::new(coverage.clone()));
+//! emulator.frames().take(3600).for_each(drop);
+//! let annotations = coverage.data_annotations();
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::annotations::{Annotation, Purpose};
+use crate::decoder::{instruction_len, Opcode};
+use crate::observer::Observer;
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+
+#[derive(Default)]
+struct State {
+    // EXECUTED and READ flags of each byte of the ROM
+    flags: Vec<u8>,
+    // Bytes of the current instruction still to be fetched
+    fetch: Option<(u16, u16)>,
+}
+
+/// An observer sharing its results with its clones, to add one to the
+/// emulator and read the other
+#[derive(Clone, Default)]
+pub struct Coverage(Arc<Mutex<State>>);
+
+impl Coverage {
+    /// For a ROM of `len` bytes, mapped without memory bank controller
+    pub fn new(len: usize) -> Self {
+        Self(Arc::new(Mutex::new(State {
+            flags: vec![0; len],
+            fetch: None,
+        })))
+    }
+
+    fn flags(&self) -> Vec<u8> {
+        self.0.lock().unwrap().flags.clone()
+    }
+
+    /// The byte at this offset of the ROM was executed, as an opcode or an
+    /// operand
+    pub fn executed(&self, offset: usize) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .flags
+            .get(offset)
+            .copied()
+            .unwrap_or(0)
+            & EXECUTED
+            != 0
+    }
+
+    /// The byte at this offset of the ROM was read as data
+    pub fn read(&self, offset: usize) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .flags
+            .get(offset)
+            .copied()
+            .unwrap_or(0)
+            & READ
+            != 0
+    }
+
+    /// Number of bytes executed and read as data
+    pub fn counts(&self) -> (usize, usize) {
+        let flags = self.flags();
+        let count = |flag| flags.iter().filter(|&&f| f & flag != 0).count();
+        (count(EXECUTED), count(READ))
+    }
+
+    /// A Data annotation for each region never executed, as bytes. The
+    /// regions read as data are commented.
+    pub fn data_annotations(&self) -> BTreeMap<usize, Vec<Annotation>> {
+        let flags = self.flags();
+        let mut annotations = BTreeMap::new();
+        let mut offset = 0;
+        while offset < flags.len() {
+            if flags[offset] & EXECUTED != 0 {
+                offset += 1;
+                continue;
+            }
+            let len = flags[offset..]
+                .iter()
+                .position(|&f| f & EXECUTED != 0)
+                .unwrap_or(flags.len() - offset);
+            let read = flags[offset..offset + len].iter().any(|&f| f & READ != 0);
+            annotations.insert(
+                offset,
+                vec![Annotation {
+                    location: offset,
+                    purpose: Purpose::Data,
+                    value: format!("db 0x{:x}", len),
+                }],
+            );
+            if read {
+                annotations.get_mut(&offset).unwrap().insert(
+                    0,
+                    Annotation {
+                        location: offset,
+                        purpose: Purpose::Comment,
+                        value: "Read while running".to_string(),
+                    },
+                );
+            }
+            offset += len;
+        }
+        annotations
+    }
+}
+
+impl Observer for Coverage {
+    fn on_instruction(&mut self, pc: u16, _opcode: &Opcode) {
+        self.0.lock().unwrap().fetch = Some((pc, 0));
+    }
+
+    fn on_read(&mut self, addr: u16, value: u8) {
+        let mut state = self.0.lock().unwrap();
+        let executed = match state.fetch {
+            // The opcode, then its operands
+            Some((pc, 0)) if addr == pc => {
+                state.fetch = Some((pc.wrapping_add(1), instruction_len(value) - 1));
+                true
+            }
+            Some((next, remaining)) if remaining > 0 && addr == next => {
+                state.fetch = Some((next.wrapping_add(1), remaining - 1));
+                true
+            }
+            _ => false,
+        };
+        if let Some(flags) = state.flags.get_mut(addr as usize).filter(|_| addr < 0x8000) {
+            *flags |= if executed { EXECUTED } else { READ };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn test_coverage() {
+        // LD A (0x0150); JR -5, with the data at 0x150
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x105].copy_from_slice(&[0xfa, 0x50, 0x01, 0x18, 0xfb]);
+        let mut emulator = Emulator::new(rom, &Default::default());
+        let coverage = Coverage::new(0x8000);
+        emulator.add_observer(Box::new(coverage.clone()));
+        emulator.run_frame();
+
+        assert!((0x100..0x105).all(|offset| coverage.executed(offset)));
+        assert!(!coverage.executed(0x105));
+        assert!(coverage.read(0x150));
+        assert!(!coverage.executed(0x150));
+        assert_eq!(coverage.counts(), (5, 1));
+
+        let annotations = coverage.data_annotations();
+        assert_eq!(
+            Annotation::to_config(&annotations),
+            "0x0000 D db 0x100\n0x0105 C Read while running\n0x0105 D db 0x7efb\n"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Lockstep comparison with a reference log in the format of Gameboy Doctor,
+//! https://github.com/robert/gameboy-doctor: one line per instruction with
+//! the registers and the 4 bytes at PC before it runs,
+//! `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`.
+//! The emulation stops at the first line which differs.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use crate::cpu::Registers;
+use crate::decoder::decode;
+use crate::emulator::Emulator;
+use crate::mmu::Mmu;
+use crate::ppu::DOTS_PER_FRAME;
+
+/// Value of LY in the logs, the emulators logging them do not draw
+pub const DOCTOR_LY: u8 = 0x90;
+
+// Cycles without executing an instruction before giving up, a second
+const MAX_IDLE_CYCLES: u32 = DOTS_PER_FRAME * 60;
+
+/// A line of the log
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DoctorState {
+    pub registers: Registers,
+    /// Bytes at PC, the instruction and its operands
+    pub pcmem: [u8; 4],
+}
+
+impl DoctorState {
+    pub fn capture(regs: &Registers, mmu: &Mmu) -> Self {
+        Self {
+            registers: *regs,
+            pcmem: std::array::from_fn(|offset| mmu.peek(regs.pc.wrapping_add(offset as u16))),
+        }
+    }
+
+    /// Names of the fields which differ from `other`
+    pub fn differences(&self, other: &DoctorState) -> Vec<&'static str> {
+        let (a, b) = (&self.registers, &other.registers);
+        [
+            ("A", a.a == b.a),
+            ("F", a.f == b.f),
+            ("B", a.b == b.b),
+            ("C", a.c == b.c),
+            ("D", a.d == b.d),
+            ("E", a.e == b.e),
+            ("H", a.h == b.h),
+            ("L", a.l == b.l),
+            ("SP", a.sp == b.sp),
+            ("PC", a.pc == b.pc),
+            ("PCMEM", self.pcmem == other.pcmem),
+        ]
+        .into_iter()
+        .filter(|(_, same)| !same)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+impl Display for DoctorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let r = &self.registers;
+        let m = &self.pcmem;
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc, m[0], m[1], m[2], m[3]
+        )
+    }
+}
+
+impl FromStr for DoctorState {
+    type Err = DoctorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DoctorError::InvalidLine(s.to_string());
+        let mut fields = s.split_whitespace().map(|field| field.split_once(':'));
+        let mut field = |name: &str| match fields.next() {
+            Some(Some((key, value))) if key == name => Ok(value),
+            _ => Err(invalid()),
+        };
+        let byte = |value: &str| u8::from_str_radix(value, 16).map_err(|_| invalid());
+        let word = |value: &str| u16::from_str_radix(value, 16).map_err(|_| invalid());
+        let registers = Registers {
+            a: byte(field("A")?)?,
+            f: byte(field("F")?)?,
+            b: byte(field("B")?)?,
+            c: byte(field("C")?)?,
+            d: byte(field("D")?)?,
+            e: byte(field("E")?)?,
+            h: byte(field("H")?)?,
+            l: byte(field("L")?)?,
+            sp: word(field("SP")?)?,
+            pc: word(field("PC")?)?,
+        };
+        let pcmem: Vec<u8> = field("PCMEM")?
+            .split(',')
+            .map(byte)
+            .collect::<Result<_, _>>()?;
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            registers,
+            pcmem: pcmem.try_into().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// The first line of the log which differs from the emulation
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    /// Number of the line, from 1
+    pub line: usize,
+    pub expected: DoctorState,
+    pub actual: DoctorState,
+    /// The line before, which matched, None for the first one
+    pub previous: Option<DoctorState>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {} differs", self.line)?;
+        if let Some(previous) = &self.previous {
+            let pc = previous.registers.pc;
+            match decode(&mut previous.pcmem.into_iter()) {
+                Ok(opcode) => write!(f, ", after {:04x} {}", pc, opcode)?,
+                Err(_) => write!(f, ", after {:04x}", pc)?,
+            }
+        }
+        writeln!(f, ": {}", self.actual.differences(&self.expected).join(" "))?;
+        if let Some(previous) = &self.previous {
+            writeln!(f, "previous {}", previous)?;
+        }
+        writeln!(f, "expected {}", self.expected)?;
+        write!(f, "actual   {}", self.actual)
+    }
+}
+
+/// Result of `lockstep`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comparison {
+    /// Lines which matched
+    pub lines: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// Run the emulator along the lines of `reference` from its current state,
+/// up to the first one which differs. LY reads `DOCTOR_LY` from then on, as
+/// in the logs.
+pub fn lockstep(
+    emulator: &mut Emulator,
+    reference: impl BufRead,
+) -> Result<Comparison, DoctorError> {
+    emulator.mmu_mut().set_ly_override(Some(DOCTOR_LY));
+    let mut previous = None;
+    let mut lines = 0;
+    for (index, line) in reference.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let expected: DoctorState = line.parse()?;
+        if previous.is_some() {
+            // Interrupts and HALT do not log a line
+            let mut idle = 0;
+            loop {
+                idle += emulator.step();
+                if emulator.cpu().executed().is_some() {
+                    break;
+                }
+                if idle > MAX_IDLE_CYCLES {
+                    return Err(DoctorError::Stuck(index + 1));
+                }
+            }
+        }
+        let actual = DoctorState::capture(emulator.cpu().registers(), emulator.mmu());
+        if actual != expected {
+            return Ok(Comparison {
+                lines,
+                divergence: Some(Divergence {
+                    line: index + 1,
+                    expected,
+                    actual,
+                    previous,
+                }),
+            });
+        }
+        previous = Some(actual);
+        lines += 1;
+    }
+    Ok(Comparison {
+        lines,
+        divergence: None,
+    })
+}
+
+#[derive(Debug)]
+pub enum DoctorError {
+    InvalidLine(String),
+    /// No instruction executed for a second before this line
+    Stuck(usize),
+    IOError(io::Error),
+}
+
+impl Error for DoctorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IOError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DoctorError {
+    fn from(value: io::Error) -> Self {
+        DoctorError::IOError(value)
+    }
+}
+
+impl Display for DoctorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLine(line) => write!(f, "Invalid line in the log: {}", line),
+            Self::Stuck(line) => write!(f, "No instruction executed before line {}", line),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02";
+
+    #[test]
+    fn test_doctor_state() {
+        let state: DoctorState = LINE.parse().unwrap();
+        assert_eq!(state.registers.hl(), 0x014d);
+        assert_eq!(state.pcmem, [0x00, 0xc3, 0x13, 0x02]);
+        assert_eq!(state.to_string(), LINE);
+        assert!(matches!(
+            LINE.replace("PCMEM:00,", "PCMEM:").parse::<DoctorState>(),
+            Err(DoctorError::InvalidLine(_))
+        ));
+        assert!(matches!(
+            LINE[2..].parse::<DoctorState>(),
+            Err(DoctorError::InvalidLine(_))
+        ));
+    }
+
+    // INC A; LD (0xff80) A; LD A (0xff44); JR -8
+    fn emulator() -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x108].copy_from_slice(&[0x3c, 0xe0, 0x80, 0xf0, 0x44, 0x18, 0xf9, 0x00]);
+        Emulator::new(rom, &Default::default())
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let mut reference = emulator();
+        reference.mmu_mut().set_ly_override(Some(DOCTOR_LY));
+        let mut log = String::new();
+        for _ in 0..20 {
+            let state = DoctorState::capture(reference.cpu().registers(), reference.mmu());
+            log += &format!("{}\n", state);
+            reference.step();
+        }
+        let comparison = lockstep(&mut emulator(), log.as_bytes()).unwrap();
+        assert_eq!(
+            comparison,
+            Comparison {
+                lines: 20,
+                divergence: None
+            }
+        );
+
+        // The reference reads another value of LY before line 4
+        let log = log.replacen("A:90", "A:91", 1);
+        let comparison = lockstep(&mut emulator(), log.as_bytes()).unwrap();
+        assert_eq!(comparison.lines, 3);
+        let divergence = comparison.divergence.unwrap();
+        assert_eq!(divergence.line, 4);
+        assert_eq!(divergence.actual.differences(&divergence.expected), ["A"]);
+        assert_eq!(divergence.previous.unwrap().registers.pc, 0x103);
+    }
+}
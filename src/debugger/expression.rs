@@ -0,0 +1,378 @@
+//! Conditions over the registers and the memory, such as
+//! `A==0x42 && HL>=0x8000` or `[0xff40] & 0x80 == 0`. The values are
+//! integers, the comparisons and the logical operators give 1 or 0.
+
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use crate::cpu::Registers;
+use crate::mmu::Mmu;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+}
+
+const REGISTER_NAMES: [(&str, Register); 14] = [
+    ("A", Register::A),
+    ("F", Register::F),
+    ("B", Register::B),
+    ("C", Register::C),
+    ("D", Register::D),
+    ("E", Register::E),
+    ("H", Register::H),
+    ("L", Register::L),
+    ("AF", Register::Af),
+    ("BC", Register::Bc),
+    ("DE", Register::De),
+    ("HL", Register::Hl),
+    ("SP", Register::Sp),
+    ("PC", Register::Pc),
+];
+
+impl Register {
+    pub fn value(self, regs: &Registers) -> u16 {
+        match self {
+            Register::A => regs.a as u16,
+            Register::F => regs.f as u16,
+            Register::B => regs.b as u16,
+            Register::C => regs.c as u16,
+            Register::D => regs.d as u16,
+            Register::E => regs.e as u16,
+            Register::H => regs.h as u16,
+            Register::L => regs.l as u16,
+            Register::Af => regs.af(),
+            Register::Bc => regs.bc(),
+            Register::De => regs.de(),
+            Register::Hl => regs.hl(),
+            Register::Sp => regs.sp,
+            Register::Pc => regs.pc,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = ExpressionError;
+
+    /// Case insensitive
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        REGISTER_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, register)| register)
+            .ok_or_else(|| ExpressionError::UnknownName(s.to_string()))
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, _) = REGISTER_NAMES.iter().find(|(_, r)| r == self).unwrap();
+        f.write_str(name)
+    }
+}
+
+/// A number written in decimal, or in hexadecimal with a `0x` or `$` prefix
+pub fn parse_number(text: &str) -> Result<i64, ExpressionError> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| ExpressionError::InvalidNumber(text.to_string()))
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+// From the loosest to the tightest binding. Unlike C, the bitwise operators
+// bind tighter than the comparisons: `F & 0x80 == 0` tests a flag.
+const OPERATORS: [(&str, Operator, u8); 13] = [
+    ("||", Operator::Or, 1),
+    ("&&", Operator::And, 2),
+    ("==", Operator::Equal, 3),
+    ("!=", Operator::NotEqual, 3),
+    ("<=", Operator::LessEqual, 4),
+    (">=", Operator::GreaterEqual, 4),
+    ("<", Operator::Less, 4),
+    (">", Operator::Greater, 4),
+    ("|", Operator::BitOr, 5),
+    ("^", Operator::BitXor, 6),
+    ("&", Operator::BitAnd, 7),
+    ("+", Operator::Add, 8),
+    ("-", Operator::Sub, 8),
+];
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    /// Byte at an address
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, regs: &Registers, mmu: &Mmu) -> i64 {
+        match self {
+            Node::Number(value) => *value,
+            Node::Register(register) => register.value(regs) as i64,
+            Node::Memory(addr) => mmu.peek(addr.eval(regs, mmu) as u16) as i64,
+            Node::Not(node) => (node.eval(regs, mmu) == 0) as i64,
+            Node::Negate(node) => node.eval(regs, mmu).wrapping_neg(),
+            Node::Binary(operator, left, right) => {
+                let left = left.eval(regs, mmu);
+                // Short-circuit, to skip the memory reads
+                match operator {
+                    Operator::Or if left != 0 => return 1,
+                    Operator::And if left == 0 => return 0,
+                    _ => (),
+                }
+                let right = right.eval(regs, mmu);
+                match operator {
+                    Operator::Or | Operator::And => (right != 0) as i64,
+                    Operator::Equal => (left == right) as i64,
+                    Operator::NotEqual => (left != right) as i64,
+                    Operator::Less => (left < right) as i64,
+                    Operator::LessEqual => (left <= right) as i64,
+                    Operator::Greater => (left > right) as i64,
+                    Operator::GreaterEqual => (left >= right) as i64,
+                    Operator::BitOr => left | right,
+                    Operator::BitXor => left ^ right,
+                    Operator::BitAnd => left & right,
+                    Operator::Add => left.wrapping_add(right),
+                    Operator::Sub => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expression {
+    text: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn eval(&self, regs: &Registers, mmu: &Mmu) -> i64 {
+        self.root.eval(regs, mmu)
+    }
+
+    /// The expression is not 0, the memory is read without side effects
+    pub fn is_true(&self, regs: &Registers, mmu: &Mmu) -> bool {
+        self.eval(regs, mmu) != 0
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let root = parser.expression(0)?;
+        match parser.tokens.get(parser.next) {
+            Some(token) => Err(ExpressionError::UnexpectedToken(token.clone())),
+            None => Ok(Self {
+                text: s.trim().to_string(),
+                root,
+            }),
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Numbers and names, operators and brackets
+fn tokenize(text: &str) -> Result<Vec<String>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '$' {
+            rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '$')
+                .unwrap_or(rest.len())
+        } else if let Some((op, _, _)) = OPERATORS.iter().find(|(op, _, _)| rest.starts_with(op)) {
+            op.len()
+        } else if "()[]!".contains(c) {
+            1
+        } else {
+            return Err(ExpressionError::UnexpectedToken(c.to_string()));
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// Precedence climbing over the tokens
+struct Parser {
+    tokens: Vec<String>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(String::as_str)
+    }
+
+    fn take(&mut self) -> Result<String, ExpressionError> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .ok_or(ExpressionError::UnexpectedEnd)?;
+        self.next += 1;
+        Ok(token.clone())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), ExpressionError> {
+        match self.take()? {
+            token if token == expected => Ok(()),
+            token => Err(ExpressionError::UnexpectedToken(token)),
+        }
+    }
+
+    /// Binary operators binding tighter than `min_precedence`
+    fn expression(&mut self, min_precedence: u8) -> Result<Node, ExpressionError> {
+        let mut left = self.operand()?;
+        while let Some(&(_, operator, precedence)) = self
+            .peek()
+            .and_then(|token| OPERATORS.iter().find(|(op, _, _)| *op == token))
+        {
+            if precedence <= min_precedence {
+                break;
+            }
+            self.next += 1;
+            let right = self.expression(precedence)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Node, ExpressionError> {
+        let token = self.take()?;
+        Ok(match token.as_str() {
+            "(" => {
+                let node = self.expression(0)?;
+                self.expect(")")?;
+                node
+            }
+            "[" => {
+                let node = self.expression(0)?;
+                self.expect("]")?;
+                Node::Memory(Box::new(node))
+            }
+            "!" => Node::Not(Box::new(self.operand()?)),
+            "-" => Node::Negate(Box::new(self.operand()?)),
+            _ if token.starts_with(|c: char| c.is_ascii_digit() || c == '$') => {
+                Node::Number(parse_number(&token)?)
+            }
+            _ if token.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                Node::Register(token.parse()?)
+            }
+            _ => return Err(ExpressionError::UnexpectedToken(token)),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExpressionError {
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    InvalidNumber(String),
+    UnknownName(String),
+}
+
+impl Error for ExpressionError {}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedToken(token) => write!(f, "Unexpected '{}'", token),
+            Self::UnexpectedEnd => f.write_str("Unexpected end of the expression"),
+            Self::InvalidNumber(text) => write!(f, "Invalid number {}", text),
+            Self::UnknownName(name) => write!(f, "Unknown register {}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    #[test]
+    fn test_expression() {
+        let mut regs = Registers::after_boot(Model::Dmg);
+        let mut mmu = Mmu::new(Vec::new(), Model::Dmg);
+        let eval = |text: &str, regs: &Registers, mmu: &Mmu| {
+            text.parse::<Expression>().unwrap().eval(regs, mmu)
+        };
+        assert_eq!(eval("1 + 2 - 4", &regs, &mmu), -1);
+        assert_eq!(eval("$10 + 0x10 + 10", &regs, &mmu), 42);
+        assert_eq!(eval("hl", &regs, &mmu), 0x014d);
+        assert_eq!(eval("2 + 3 == 5 && 1 < 2", &regs, &mmu), 1);
+        // Unlike C, the bitwise operators bind tighter than the comparisons
+        assert_eq!(eval("F & 0x80 != 0", &regs, &mmu), 1);
+        assert_eq!(eval("!(A == 1) || -1 == 0", &regs, &mmu), 0);
+
+        regs.a = 0x42;
+        regs.set_hl(0x8000);
+        let condition: Expression = "A==0x42 && HL>=0x8000".parse().unwrap();
+        assert!(condition.is_true(&regs, &mmu));
+        assert_eq!(condition.to_string(), "A==0x42 && HL>=0x8000");
+        regs.set_hl(0x7fff);
+        assert!(!condition.is_true(&regs, &mmu));
+
+        mmu.write(0xc000, 0x12);
+        assert_eq!(eval("[0xc000 + B] + 1", &regs, &mmu), 0x13);
+    }
+
+    #[test]
+    fn test_expression_invalid() {
+        let parse = |text: &str| text.parse::<Expression>().unwrap_err();
+        assert_eq!(parse("A =="), ExpressionError::UnexpectedEnd);
+        assert_eq!(
+            parse("A == 1)"),
+            ExpressionError::UnexpectedToken(")".into())
+        );
+        assert_eq!(parse("IX == 1"), ExpressionError::UnknownName("IX".into()));
+        assert_eq!(
+            parse("0x == 1"),
+            ExpressionError::InvalidNumber("0x".into())
+        );
+        assert_eq!(parse("A = 1"), ExpressionError::UnexpectedToken("=".into()));
+        assert_eq!(parse("(A"), ExpressionError::UnexpectedEnd);
+    }
+}
@@ -0,0 +1,141 @@
+//! Reads and writes of each address, counted by the MMU when a heatmap is
+//! set, to find the hot variables of a game and the RAM it never uses. The
+//! fetches of the instructions are not counted.
+
+use std::io::{self, Write};
+
+use crate::mmu;
+use crate::tiles::Image;
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    // Indexed by address
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![0; 0x10000],
+            writes: vec![0; 0x10000],
+        }
+    }
+
+    pub fn record_read(&mut self, addr: u16) {
+        let count = &mut self.reads[addr as usize];
+        *count = count.saturating_add(1);
+    }
+
+    pub fn record_write(&mut self, addr: u16) {
+        let count = &mut self.writes[addr as usize];
+        *count = count.saturating_add(1);
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.fill(0);
+        self.writes.fill(0);
+    }
+
+    pub fn reads(&self, addr: u16) -> u32 {
+        self.reads[addr as usize]
+    }
+
+    pub fn writes(&self, addr: u16) -> u32 {
+        self.writes[addr as usize]
+    }
+
+    /// Highest count of reads and of writes, to scale the colors
+    pub fn max(&self) -> (u32, u32) {
+        let max = |counts: &[u32]| counts.iter().copied().max().unwrap_or(0);
+        (max(&self.reads), max(&self.writes))
+    }
+
+    /// Red for the writes and green for the reads, on a logarithmic scale
+    /// from black for an address never accessed
+    pub fn color(&self, addr: u16, max: (u32, u32)) -> [u8; 4] {
+        let scale = |count: u32, max: u32| {
+            if count == 0 {
+                0
+            } else {
+                (64.0 + 191.0 * (count as f64).ln_1p() / (max as f64).ln_1p()) as u8
+            }
+        };
+        [
+            scale(self.writes(addr), max.1),
+            scale(self.reads(addr), max.0),
+            0,
+            0xff,
+        ]
+    }
+
+    /// One pixel per address, a row of 256 pixels per page of memory
+    pub fn image(&self) -> Image {
+        let max = self.max();
+        let mut image = Image::new(256, 256);
+        for addr in 0..=0xffff {
+            image.set_pixel(
+                addr as usize % 256,
+                addr as usize / 256,
+                self.color(addr, max),
+            );
+        }
+        image
+    }
+
+    /// A line per address accessed: `address,region,reads,writes`
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "address,region,reads,writes")?;
+        for addr in 0..=0xffff {
+            let (reads, writes) = (self.reads(addr), self.writes(addr));
+            if reads != 0 || writes != 0 {
+                writeln!(
+                    writer,
+                    "0x{:04x},{},{},{}",
+                    addr,
+                    mmu::region_name(addr),
+                    reads,
+                    writes
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap() {
+        let mut heatmap = Heatmap::new();
+        for _ in 0..3 {
+            heatmap.record_read(0xc000);
+        }
+        heatmap.record_write(0xc000);
+        heatmap.record_write(0xff80);
+        assert_eq!(heatmap.max(), (3, 1));
+        assert_eq!(heatmap.color(0xc000, heatmap.max()), [0xff, 0xff, 0, 0xff]);
+        assert_eq!(heatmap.color(0xc001, heatmap.max()), [0, 0, 0, 0xff]);
+        let image = heatmap.image();
+        assert_eq!(image.pixel(0x80, 0xff), [0xff, 0, 0, 0xff]);
+
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("0xc000,"));
+        assert!(lines[1].ends_with(",3,1"));
+
+        heatmap.clear();
+        assert_eq!(heatmap.max(), (0, 0));
+    }
+}
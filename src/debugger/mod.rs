@@ -0,0 +1,12 @@
+//! Debugging support in the core, shared by the frontends
+
+pub mod breakpoints;
+pub mod call_stack;
+pub mod coverage;
+pub mod doctor;
+pub mod expression;
+pub mod heatmap;
+pub mod monitor;
+pub mod profiler;
+pub mod trace;
+pub mod watches;
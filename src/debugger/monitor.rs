@@ -0,0 +1,429 @@
+//! A command line debugger over the emulator, in the style of gdb, for the
+//! `gb-mon` binary: `b 0x150`, `c`, `s`, `regs`, `x/16 0xff40`, `dis pc 10`...
+
+use std::error::Error;
+use std::fmt::{Display, Write};
+use std::str::FromStr;
+
+use crate::cpu::{FLAG_C, FLAG_H, FLAG_N, FLAG_Z};
+use crate::debugger::breakpoints::{Breakpoint, Hit};
+use crate::debugger::expression::{parse_number, Expression, ExpressionError};
+use crate::debugger::watches::Watch;
+use crate::decoder::{decode, instruction_len};
+use crate::emulator::Emulator;
+
+/// Frames run by `c` without reaching a breakpoint before giving back the
+/// control, a minute
+pub const CONTINUE_FRAMES: usize = 3600;
+
+pub const HELP: &str = "\
+b [ADDR|MNEMONIC|EXPR]  add a breakpoint, or list them
+d [ID]                  delete a breakpoint, or all of them
+w [REG|ADDR|IO]         watch a value, or list the watches and their changes
+s [N]                   execute N instructions, 1 by default
+n                       step over a call
+finish                  run up to the return of the current subroutine
+c [FRAMES]              continue up to a breakpoint, for at most 3600 frames by default
+regs                    show the registers
+bt                      show the call stack, the current call first
+x/N EXPR                show N bytes of memory from an address, 16 by default
+dis [EXPR [N]]          disassemble N instructions from an address, 10 from PC by default
+p EXPR                  print an expression: `p [hl] + 1`
+q                       quit
+An empty line repeats the last command.";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Break(Option<Breakpoint>),
+    Delete(Option<usize>),
+    Watch(Option<Watch>),
+    Step(usize),
+    Next,
+    Finish,
+    Continue(usize),
+    Registers,
+    Backtrace,
+    Examine {
+        addr: Expression,
+        count: usize,
+    },
+    Disassemble {
+        addr: Option<Expression>,
+        count: usize,
+    },
+    Print(Expression),
+    Help,
+    Quit,
+}
+
+fn parse_count(text: &str) -> Result<usize, MonitorError> {
+    parse_number(text)
+        .ok()
+        .and_then(|count| usize::try_from(count).ok())
+        .ok_or_else(|| MonitorError::InvalidCount(text.to_string()))
+}
+
+impl FromStr for Command {
+    type Err = MonitorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let args = args.trim();
+        let argument = |what| match args {
+            "" => Err(MonitorError::MissingArgument(what)),
+            args => Ok(args),
+        };
+        let optional = |args: &str| (!args.is_empty()).then_some(args.to_string());
+        Ok(match name {
+            "b" | "break" => Command::Break(optional(args).map(|b| b.parse()).transpose()?),
+            "d" | "delete" => Command::Delete(optional(args).map(|d| parse_count(&d)).transpose()?),
+            "w" | "watch" => Command::Watch(optional(args).map(|w| w.parse()).transpose()?),
+            "s" | "step" => Command::Step(match args {
+                "" => 1,
+                count => parse_count(count)?,
+            }),
+            "n" | "next" => Command::Next,
+            "finish" => Command::Finish,
+            "c" | "continue" => Command::Continue(match args {
+                "" => CONTINUE_FRAMES,
+                frames => parse_count(frames)?,
+            }),
+            "regs" | "r" => Command::Registers,
+            "bt" | "backtrace" => Command::Backtrace,
+            "p" | "print" => Command::Print(argument("an expression")?.parse()?),
+            "dis" => {
+                let mut args = args.split_whitespace();
+                let addr = args.next().map(str::parse).transpose()?;
+                let count = args.next().map(parse_count).transpose()?.unwrap_or(10);
+                Command::Disassemble { addr, count }
+            }
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ if name == "x" || name.starts_with("x/") => Command::Examine {
+                addr: argument("an address")?.parse()?,
+                count: match name.strip_prefix("x/") {
+                    Some(count) => parse_count(count)?,
+                    None => 16,
+                },
+            },
+            _ => return Err(MonitorError::UnknownCommand(name.to_string())),
+        })
+    }
+}
+
+/// Runs the commands over an emulator, returning their output
+pub struct Monitor {
+    emulator: Emulator,
+}
+
+impl Monitor {
+    pub fn new(emulator: Emulator) -> Self {
+        Self { emulator }
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    /// The output of the command, Quit is left to the caller
+    pub fn execute(&mut self, command: &Command) -> Result<String, MonitorError> {
+        let mut out = String::new();
+        match command {
+            Command::Break(Some(breakpoint)) => {
+                let breakpoints = self.emulator.cpu_mut().breakpoints_mut();
+                let id = breakpoints.add(breakpoint.clone());
+                writeln!(out, "Breakpoint #{}: {}", id, breakpoint).unwrap();
+            }
+            Command::Break(None) => {
+                for (id, breakpoint) in self.emulator.cpu().breakpoints().iter() {
+                    writeln!(out, "#{} {}", id, breakpoint).unwrap();
+                }
+            }
+            Command::Delete(Some(id)) => {
+                if !self.emulator.cpu_mut().breakpoints_mut().remove(*id) {
+                    return Err(MonitorError::UnknownBreakpoint(*id));
+                }
+            }
+            Command::Delete(None) => self.emulator.cpu_mut().breakpoints_mut().clear(),
+            Command::Watch(Some(watch)) => {
+                let id = self.emulator.cpu_mut().watches_mut().add(*watch);
+                writeln!(out, "Watch #{}: {}", id, watch).unwrap();
+            }
+            Command::Watch(None) => {
+                let (cpu, mmu) = (self.emulator.cpu(), self.emulator.mmu());
+                for (id, watch) in cpu.watches().iter() {
+                    let value = watch.value(cpu.registers(), mmu);
+                    writeln!(out, "#{} {} = {:02x}", id, watch, value).unwrap();
+                    let changes = cpu.watches().changes().filter(|change| change.id == id);
+                    for change in changes.rev().take(5) {
+                        let pc = change
+                            .pc
+                            .map_or("----".to_string(), |pc| format!("{:04x}", pc));
+                        writeln!(out, "  {} {:02x} -> {:02x}", pc, change.old, change.new).unwrap();
+                    }
+                }
+            }
+            Command::Step(count) => {
+                for _ in 0..*count {
+                    // A breakpoint on the next instruction does not stop it
+                    self.emulator.step();
+                    if self.emulator.cpu().hit().is_some() {
+                        self.emulator.step();
+                    }
+                }
+                out += &self.stopped(None);
+            }
+            Command::Next => {
+                self.emulator.step_over();
+                if self.emulator.cpu().hit().is_some() {
+                    self.emulator.step_over();
+                }
+                out += &self.run(CONTINUE_FRAMES);
+            }
+            Command::Finish => {
+                self.emulator.step_out();
+                out += &self.run(CONTINUE_FRAMES);
+            }
+            Command::Continue(frames) => out += &self.run(*frames),
+            Command::Registers => {
+                let regs = self.emulator.cpu().registers();
+                let flags: String = [(FLAG_Z, 'Z'), (FLAG_N, 'N'), (FLAG_H, 'H'), (FLAG_C, 'C')]
+                    .iter()
+                    .map(|&(flag, name)| if regs.flag(flag) { name } else { '-' })
+                    .collect();
+                writeln!(
+                    out,
+                    "AF {:04x}  BC {:04x}  DE {:04x}  HL {:04x}  SP {:04x}  PC {:04x}  {}  IME {}",
+                    regs.af(),
+                    regs.bc(),
+                    regs.de(),
+                    regs.hl(),
+                    regs.sp,
+                    regs.pc,
+                    flags,
+                    self.emulator.cpu().ime() as u8
+                )
+                .unwrap();
+            }
+            Command::Backtrace => {
+                let frames = self.emulator.cpu().call_stack().frames();
+                if frames.is_empty() {
+                    writeln!(out, "No call").unwrap();
+                }
+                for (depth, frame) in frames.iter().rev().enumerate() {
+                    writeln!(out, "#{:<3} {}", depth, frame).unwrap();
+                }
+            }
+            Command::Examine { addr, count } => {
+                let start = self.eval(addr);
+                let mmu = self.emulator.mmu();
+                for line in (0..*count).step_by(16) {
+                    let addr = start.wrapping_add(line as u16);
+                    let bytes: Vec<String> = (0..(*count - line).min(16))
+                        .map(|offset| format!("{:02x}", mmu.peek(addr.wrapping_add(offset as u16))))
+                        .collect();
+                    writeln!(out, "{:04x}  {}", addr, bytes.join(" ")).unwrap();
+                }
+            }
+            Command::Disassemble { addr, count } => {
+                let mut addr = match addr {
+                    Some(addr) => self.eval(addr),
+                    None => self.emulator.cpu().registers().pc,
+                };
+                for _ in 0..*count {
+                    let (line, len) = self.instruction(addr);
+                    writeln!(out, "{}", line).unwrap();
+                    addr = addr.wrapping_add(len);
+                }
+            }
+            Command::Print(expression) => {
+                let regs = self.emulator.cpu().registers();
+                let value = expression.eval(regs, self.emulator.mmu());
+                writeln!(out, "0x{:x} ({})", value, value).unwrap();
+            }
+            Command::Help => writeln!(out, "{}", HELP).unwrap(),
+            Command::Quit => {}
+        }
+        Ok(out)
+    }
+
+    fn eval(&self, expression: &Expression) -> u16 {
+        expression.eval(self.emulator.cpu().registers(), self.emulator.mmu()) as u16
+    }
+
+    // The instruction at `addr` as a line of the listing, and its size
+    fn instruction(&self, addr: u16) -> (String, u16) {
+        let mmu = self.emulator.mmu();
+        let len = instruction_len(mmu.peek(addr));
+        let bytes: Vec<u8> = (0..len)
+            .map(|offset| mmu.peek(addr.wrapping_add(offset)))
+            .collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let line = match decode(&mut bytes.into_iter()) {
+            Ok(opcode) => format!("{:04x}  {:<8}  {}", addr, hex.join(" "), opcode),
+            Err(_) => format!("{:04x}  {:<8}  ?", addr, hex.join(" ")),
+        };
+        (line, len)
+    }
+
+    // Run up to a breakpoint, for at most `frames` frames
+    fn run(&mut self, frames: usize) -> String {
+        for _ in 0..frames {
+            if let Some(hit) = self.emulator.run_frame() {
+                return self.stopped(Some(hit));
+            }
+        }
+        format!(
+            "No breakpoint after {} frames\n{}",
+            frames,
+            self.stopped(None)
+        )
+    }
+
+    // Where the emulation stopped, and the next instruction
+    fn stopped(&self, hit: Option<Hit>) -> String {
+        let breakpoints = self.emulator.cpu().breakpoints();
+        let mut out = String::new();
+        // The transient breakpoints of `n` and `finish` are gone once hit
+        if let Some((id, breakpoint)) =
+            hit.and_then(|hit| breakpoints.iter().find(|(id, _)| *id == hit.id))
+        {
+            writeln!(out, "Breakpoint #{}: {}", id, breakpoint).unwrap();
+        }
+        writeln!(
+            out,
+            "{}",
+            self.instruction(self.emulator.cpu().registers().pc).0
+        )
+        .unwrap();
+        out
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MonitorError {
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidCount(String),
+    UnknownBreakpoint(usize),
+    Expression(ExpressionError),
+}
+
+impl Error for MonitorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Expression(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExpressionError> for MonitorError {
+    fn from(value: ExpressionError) -> Self {
+        MonitorError::Expression(value)
+    }
+}
+
+impl Display for MonitorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownCommand(name) => write!(f, "Unknown command {}, try help", name),
+            Self::MissingArgument(what) => write!(f, "Missing {}", what),
+            Self::InvalidCount(text) => write!(f, "Invalid count {}", text),
+            Self::UnknownBreakpoint(id) => write!(f, "No breakpoint #{}", id),
+            Self::Expression(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CALL 0x0150; JR -5, the subroutine at 0x150 being INC A; RET
+    fn monitor() -> Monitor {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x105].copy_from_slice(&[0xcd, 0x50, 0x01, 0x18, 0xfb]);
+        rom[0x150..0x152].copy_from_slice(&[0x3c, 0xc9]);
+        Monitor::new(Emulator::new(rom, &Default::default()))
+    }
+
+    fn execute(monitor: &mut Monitor, line: &str) -> String {
+        monitor.execute(&line.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_command_parse() {
+        assert_eq!(
+            "b 0x150".parse(),
+            Ok(Command::Break(Some(Breakpoint::Pc(0x150))))
+        );
+        assert_eq!(
+            "x/4 0xff40".parse(),
+            Ok(Command::Examine {
+                addr: "0xff40".parse().unwrap(),
+                count: 4
+            })
+        );
+        assert_eq!(
+            "dis pc 10".parse(),
+            Ok(Command::Disassemble {
+                addr: Some("pc".parse().unwrap()),
+                count: 10
+            })
+        );
+        assert_eq!("  s  ".parse(), Ok(Command::Step(1)));
+        assert_eq!(
+            "jump".parse::<Command>(),
+            Err(MonitorError::UnknownCommand("jump".to_string()))
+        );
+        assert_eq!(
+            "p".parse::<Command>(),
+            Err(MonitorError::MissingArgument("an expression"))
+        );
+        assert_eq!(
+            "s two".parse::<Command>(),
+            Err(MonitorError::InvalidCount("two".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_monitor() {
+        let mut monitor = monitor();
+        assert_eq!(execute(&mut monitor, "b 0x0150"), "Breakpoint #0: 0x0150\n");
+        let stopped = execute(&mut monitor, "c");
+        assert!(stopped.starts_with("Breakpoint #0"), "{}", stopped);
+        assert_eq!(monitor.emulator().cpu().registers().pc, 0x150);
+        assert_eq!(
+            execute(&mut monitor, "bt"),
+            "#0   0150 call from 0100, returns to 0103\n"
+        );
+
+        // The breakpoint does not stop the step
+        execute(&mut monitor, "s");
+        assert_eq!(monitor.emulator().cpu().registers().pc, 0x151);
+        execute(&mut monitor, "finish");
+        assert_eq!(monitor.emulator().cpu().registers().pc, 0x103);
+        execute(&mut monitor, "s");
+        let a = monitor.emulator().cpu().registers().a;
+        execute(&mut monitor, "d 0");
+        execute(&mut monitor, "n");
+        assert_eq!(monitor.emulator().cpu().registers().pc, 0x103);
+        assert_eq!(monitor.emulator().cpu().registers().a, a.wrapping_add(1));
+
+        assert_eq!(execute(&mut monitor, "x/3 0x100"), "0100  cd 50 01\n");
+        assert_eq!(execute(&mut monitor, "p [pc] + 1"), "0x19 (25)\n");
+        assert_eq!(execute(&mut monitor, "dis 0x150 2").lines().count(), 2);
+        assert!(execute(&mut monitor, "regs").contains("PC 0103"));
+        assert_eq!(
+            monitor.execute(&Command::Delete(Some(0))),
+            Err(MonitorError::UnknownBreakpoint(0))
+        );
+        assert!(execute(&mut monitor, "c 2").starts_with("No breakpoint after 2 frames"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Cycles spent on each instruction, counted by `Cpu::step` when a profiler
+//! is set. The report lists the hottest addresses, or the hottest functions
+//! with the labels of the annotations.
+
+use std::collections::BTreeMap;
+
+use crate::annotations::{Annotation, Purpose};
+
+/// Time spent at an address
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Hotspot {
+    pub pc: u16,
+    pub executions: u64,
+    /// T-cycles, including those of the hardware running meanwhile
+    pub cycles: u64,
+}
+
+/// Time spent between a label and the next one
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionProfile {
+    /// None for the code before the first label or outside of the ROM
+    pub label: Option<String>,
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    // Executions and cycles, indexed by address
+    addresses: Vec<(u64, u64)>,
+    // Cycles halted or dispatching interrupts
+    other_cycles: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            addresses: vec![(0, 0); 0x10000],
+            other_cycles: 0,
+        }
+    }
+
+    /// Count a step of the CPU, `pc` is None when no instruction was
+    /// executed
+    pub fn record(&mut self, pc: Option<u16>, cycles: u32) {
+        match pc {
+            Some(pc) => {
+                let (executions, total) = &mut self.addresses[pc as usize];
+                *executions += 1;
+                *total += cycles as u64;
+            }
+            None => self.other_cycles += cycles as u64,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.fill((0, 0));
+        self.other_cycles = 0;
+    }
+
+    /// All the cycles counted, with those of `other_cycles`
+    pub fn total_cycles(&self) -> u64 {
+        self.addresses.iter().map(|(_, cycles)| cycles).sum::<u64>() + self.other_cycles
+    }
+
+    /// Cycles halted or dispatching interrupts
+    pub fn other_cycles(&self) -> u64 {
+        self.other_cycles
+    }
+
+    pub fn get(&self, pc: u16) -> Hotspot {
+        let (executions, cycles) = self.addresses[pc as usize];
+        Hotspot {
+            pc,
+            executions,
+            cycles,
+        }
+    }
+
+    /// The `count` addresses with the most cycles, from the hottest
+    pub fn hottest(&self, count: usize) -> Vec<Hotspot> {
+        let mut hotspots: Vec<Hotspot> = (0..=0xffff)
+            .map(|pc| self.get(pc))
+            .filter(|hotspot| hotspot.executions > 0)
+            .collect();
+        hotspots.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.pc.cmp(&b.pc)));
+        hotspots.truncate(count);
+        hotspots
+    }
+
+    /// Cycles grouped by the labels of `annotations`, from the hottest. The
+    /// ROM offsets are the addresses, without memory bank controller.
+    pub fn functions(
+        &self,
+        annotations: &BTreeMap<usize, Vec<Annotation>>,
+    ) -> Vec<FunctionProfile> {
+        let labels: Vec<(usize, &str)> = annotations
+            .iter()
+            .filter(|(&location, _)| location < 0x8000)
+            .filter_map(|(&location, annotations)| {
+                annotations
+                    .iter()
+                    .find(|annotation| annotation.purpose == Purpose::Label)
+                    .map(|annotation| (location, annotation.value.as_str()))
+            })
+            .collect();
+        let mut functions: BTreeMap<Option<&str>, (u64, u64)> = BTreeMap::new();
+        for (pc, &(executions, cycles)) in self.addresses.iter().enumerate() {
+            if executions == 0 {
+                continue;
+            }
+            let label = match labels.partition_point(|&(location, _)| location <= pc) {
+                index if index > 0 && pc < 0x8000 => Some(labels[index - 1].1),
+                _ => None,
+            };
+            let function = functions.entry(label).or_default();
+            function.0 += executions;
+            function.1 += cycles;
+        }
+        let mut functions: Vec<FunctionProfile> = functions
+            .into_iter()
+            .map(|(label, (executions, cycles))| FunctionProfile {
+                label: label.map(str::to_string),
+                executions,
+                cycles,
+            })
+            .collect();
+        functions.sort_by_key(|function| std::cmp::Reverse(function.cycles));
+        functions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiler() {
+        let mut profiler = Profiler::new();
+        for _ in 0..10 {
+            profiler.record(Some(0x150), 4);
+            profiler.record(Some(0x151), 12);
+        }
+        profiler.record(Some(0x200), 24);
+        profiler.record(Some(0xc000), 8);
+        profiler.record(None, 20);
+        assert_eq!(profiler.total_cycles(), 212);
+        assert_eq!(profiler.other_cycles(), 20);
+        assert_eq!(
+            profiler.hottest(2),
+            [
+                Hotspot {
+                    pc: 0x151,
+                    executions: 10,
+                    cycles: 120
+                },
+                Hotspot {
+                    pc: 0x150,
+                    executions: 10,
+                    cycles: 40
+                }
+            ]
+        );
+
+        let annotations = Annotation::parse("0x0150 L main\n0x0200 L vblank").unwrap();
+        let functions = profiler.functions(&annotations);
+        let summary: Vec<(Option<&str>, u64)> = functions
+            .iter()
+            .map(|function| (function.label.as_deref(), function.cycles))
+            .collect();
+        assert_eq!(
+            summary,
+            [(Some("main"), 160), (Some("vblank"), 24), (None, 8)]
+        );
+
+        profiler.clear();
+        assert_eq!(profiler.total_cycles(), 0);
+        assert!(profiler.hottest(10).is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+//! Traces of the instructions and the memory accesses, in a compact binary
+//! format: a header, then records compressed with deflate. An instruction
+//! takes 4 to 6 bytes before compression, an access 4 bytes. Filters on the
+//! addresses and the kinds of instructions keep the traces of long runs
+//! small, and `TraceReader` converts them back, to text for instance.
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufWriter;
+//! use gb::debugger::trace::{TraceFilter, Tracer};
+//!
+//! let rom = gb::rom::read("game.gb").unwrap();
+//! let mut emulator = gb::Emulator::new(rom, &Default::default());
+//! let filter = TraceFilter {
+//!     reads: false,
+//!     pc_ranges: vec![0x0150..=0x3fff],
+//!     ..Default::default()
+//! };
+//! let file = BufWriter::new(File::create("game.gbt").unwrap());
+//! let tracer = Tracer::new(file, filter).unwrap();
+//! emulator.add_observer(Box::new(tracer.clone()));
+//! emulator.frames().take(3600).for_each(drop);
+//! tracer.finish().unwrap();
+//! ```
+
+use std::error::Error;
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::debugger::expression::parse_number;
+use crate::decoder::{decode, instruction_len, Opcode, MNEMONICS};
+use crate::observer::{Frame, Observer};
+
+const MAGIC: &[u8; 4] = b"GBTR";
+const VERSION: u8 = 1;
+
+const INSTRUCTION: u8 = 0;
+const READ: u8 = 1;
+const WRITE: u8 = 2;
+const FRAME: u8 = 3;
+
+/// An event of the trace
+#[derive(Debug, PartialEq, Clone)]
+pub enum Record {
+    /// An instruction executed, with its bytes
+    Instruction {
+        pc: u16,
+        bytes: Vec<u8>,
+    },
+    /// A read of the CPU, other than the fetch of an instruction
+    Read {
+        addr: u16,
+        value: u8,
+    },
+    Write {
+        addr: u16,
+        value: u8,
+    },
+    /// The end of a frame, numbered like `Frame::number`
+    Frame(u32),
+}
+
+impl Record {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Record::Instruction { pc, bytes } => {
+                writer.write_all(&[INSTRUCTION])?;
+                writer.write_all(&pc.to_le_bytes())?;
+                writer.write_all(&[bytes.len() as u8])?;
+                writer.write_all(bytes)
+            }
+            Record::Read { addr, value } | Record::Write { addr, value } => {
+                let tag = if matches!(self, Record::Read { .. }) {
+                    READ
+                } else {
+                    WRITE
+                };
+                writer.write_all(&[tag])?;
+                writer.write_all(&addr.to_le_bytes())?;
+                writer.write_all(&[*value])
+            }
+            Record::Frame(number) => {
+                writer.write_all(&[FRAME])?;
+                writer.write_all(&number.to_le_bytes())
+            }
+        }
+    }
+
+    /// The next record, None at the end of the trace
+    pub fn read_from(reader: &mut impl Read) -> Result<Option<Self>, TraceError> {
+        let mut tag = [0];
+        if reader.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let read_u16 = |reader: &mut dyn Read| -> io::Result<u16> {
+            let mut bytes = [0; 2];
+            reader.read_exact(&mut bytes)?;
+            Ok(u16::from_le_bytes(bytes))
+        };
+        let mut byte = [0];
+        let record = match tag[0] {
+            INSTRUCTION => {
+                let pc = read_u16(reader)?;
+                reader.read_exact(&mut byte)?;
+                if !(1..=3).contains(&byte[0]) {
+                    return Err(TraceError::InvalidRecord(INSTRUCTION));
+                }
+                let mut bytes = vec![0; byte[0] as usize];
+                reader.read_exact(&mut bytes)?;
+                Record::Instruction { pc, bytes }
+            }
+            READ | WRITE => {
+                let addr = read_u16(reader)?;
+                reader.read_exact(&mut byte)?;
+                let value = byte[0];
+                if tag[0] == READ {
+                    Record::Read { addr, value }
+                } else {
+                    Record::Write { addr, value }
+                }
+            }
+            FRAME => {
+                let mut number = [0; 4];
+                reader.read_exact(&mut number)?;
+                Record::Frame(u32::from_le_bytes(number))
+            }
+            tag => return Err(TraceError::InvalidRecord(tag)),
+        };
+        Ok(Some(record))
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Record::Instruction { pc, bytes } => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                match decode(&mut bytes.iter().copied()) {
+                    Ok(opcode) => write!(f, "{:04x}  {:<8}  {}", pc, hex.join(" "), opcode),
+                    Err(_) => write!(f, "{:04x}  {:<8}  ?", pc, hex.join(" ")),
+                }
+            }
+            Record::Read { addr, value } => write!(f, "      read  {:04x} {:02x}", addr, value),
+            Record::Write { addr, value } => write!(f, "      write {:04x} {:02x}", addr, value),
+            Record::Frame(number) => write!(f, "-- end of frame {}", number),
+        }
+    }
+}
+
+/// What is traced. The accesses are those of the instructions traced.
+#[derive(Debug, Clone)]
+pub struct TraceFilter {
+    pub instructions: bool,
+    pub reads: bool,
+    pub writes: bool,
+    /// Addresses of the instructions traced, all of them when empty
+    pub pc_ranges: Vec<RangeInclusive<u16>>,
+    /// Addresses of the reads and writes traced, all of them when empty
+    pub address_ranges: Vec<RangeInclusive<u16>>,
+    /// Mnemonics of the instructions traced, all of them when empty
+    pub mnemonics: Vec<&'static str>,
+}
+
+impl Default for TraceFilter {
+    /// Everything
+    fn default() -> Self {
+        Self {
+            instructions: true,
+            reads: true,
+            writes: true,
+            pc_ranges: Vec::new(),
+            address_ranges: Vec::new(),
+            mnemonics: Vec::new(),
+        }
+    }
+}
+
+fn in_ranges(ranges: &[RangeInclusive<u16>], addr: u16) -> bool {
+    ranges.is_empty() || ranges.iter().any(|range| range.contains(&addr))
+}
+
+impl TraceFilter {
+    pub fn traces_instruction(&self, pc: u16, opcode: &Opcode) -> bool {
+        in_ranges(&self.pc_ranges, pc)
+            && (self.mnemonics.is_empty() || self.mnemonics.contains(&opcode.mnemonic()))
+    }
+
+    pub fn traces_address(&self, addr: u16) -> bool {
+        in_ranges(&self.address_ranges, addr)
+    }
+}
+
+/// A range of addresses, `0x0150-0x3fff`, or a single one
+pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, TraceError> {
+    let invalid = || TraceError::InvalidRange(text.to_string());
+    let address = |text: &str| {
+        parse_number(text.trim())
+            .ok()
+            .and_then(|number| u16::try_from(number).ok())
+            .ok_or_else(invalid)
+    };
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (address(start)?, address(end)?),
+        None => (address(text)?, address(text)?),
+    };
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..=end)
+}
+
+/// A mnemonic of `Opcode::mnemonic`, in any case
+pub fn parse_mnemonic(text: &str) -> Result<&'static str, TraceError> {
+    MNEMONICS
+        .iter()
+        .find(|mnemonic| mnemonic.eq_ignore_ascii_case(text))
+        .copied()
+        .ok_or_else(|| TraceError::UnknownMnemonic(text.to_string()))
+}
+
+struct State<W: Write> {
+    encoder: Option<DeflateEncoder<W>>,
+    filter: TraceFilter,
+    // Whether the current instruction is traced
+    traced: bool,
+    // Next byte of the current instruction to fetch, and the bytes fetched
+    fetch: Option<(u16, Vec<u8>)>,
+    // First error writing the trace, returned by `finish`
+    error: Option<io::Error>,
+}
+
+impl<W: Write> State<W> {
+    fn write(&mut self, record: Record) {
+        if let (Some(encoder), None) = (&mut self.encoder, &self.error) {
+            if let Err(err) = record.write_to(encoder) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+/// An observer writing the trace, shared with its clones to `finish` it once
+/// the emulation is over
+pub struct Tracer<W: Write>(Arc<Mutex<State<W>>>);
+
+impl<W: Write> Clone for Tracer<W> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut writer: W, filter: TraceFilter) -> Result<Self, TraceError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Self(Arc::new(Mutex::new(State {
+            encoder: Some(DeflateEncoder::new(writer, Compression::fast())),
+            filter,
+            traced: false,
+            fetch: None,
+            error: None,
+        }))))
+    }
+
+    /// End the trace, the records observed afterwards are dropped. Returns
+    /// the first error writing it.
+    pub fn finish(&self) -> Result<W, TraceError> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(err) = state.error.take() {
+            return Err(err.into());
+        }
+        let encoder = state.encoder.take().ok_or(TraceError::Finished)?;
+        let mut writer = encoder.finish()?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write + Send> Observer for Tracer<W> {
+    fn on_instruction(&mut self, pc: u16, opcode: &Opcode) {
+        let mut state = self.0.lock().unwrap();
+        state.traced = state.filter.traces_instruction(pc, opcode);
+        state.fetch = Some((pc, Vec::new()));
+    }
+
+    fn on_read(&mut self, addr: u16, value: u8) {
+        let mut state = self.0.lock().unwrap();
+        if let Some((next, mut bytes)) = state.fetch.take() {
+            if addr == next {
+                bytes.push(value);
+                if bytes.len() < instruction_len(bytes[0]) as usize {
+                    state.fetch = Some((next.wrapping_add(1), bytes));
+                } else if state.traced && state.filter.instructions {
+                    let pc = next.wrapping_sub(bytes.len() as u16 - 1);
+                    state.write(Record::Instruction { pc, bytes });
+                }
+                return;
+            }
+        }
+        if state.traced && state.filter.reads && state.filter.traces_address(addr) {
+            state.write(Record::Read { addr, value });
+        }
+    }
+
+    fn on_write(&mut self, addr: u16, value: u8) {
+        let mut state = self.0.lock().unwrap();
+        if state.traced && state.filter.writes && state.filter.traces_address(addr) {
+            state.write(Record::Write { addr, value });
+        }
+    }
+
+    fn on_frame(&mut self, frame: &Frame) {
+        self.0
+            .lock()
+            .unwrap()
+            .write(Record::Frame(frame.number as u32));
+    }
+}
+
+/// The records of a trace
+pub struct TraceReader<R: Read> {
+    decoder: DeflateDecoder<R>,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, TraceError> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(TraceError::InvalidHeader);
+        }
+        if header[4] != VERSION {
+            return Err(TraceError::UnsupportedVersion(header[4]));
+        }
+        Ok(Self {
+            decoder: DeflateDecoder::new(reader),
+        })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<Record, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Record::read_from(&mut self.decoder).transpose()
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    /// A record of an unknown type, or invalid
+    InvalidRecord(u8),
+    InvalidRange(String),
+    UnknownMnemonic(String),
+    /// `Tracer::finish` was already called
+    Finished,
+    IOError(io::Error),
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IOError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(value: io::Error) -> Self {
+        TraceError::IOError(value)
+    }
+}
+
+impl Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeader => f.write_str("Not a trace file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported trace version {}", version)
+            }
+            Self::InvalidRecord(tag) => write!(f, "Invalid record of type {}", tag),
+            Self::InvalidRange(range) => write!(f, "Invalid range of addresses: {}", range),
+            Self::UnknownMnemonic(name) => write!(f, "Unknown mnemonic: {}", name),
+            Self::Finished => f.write_str("The trace is already finished"),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    // LD A (0x0150); LD (0xc000) A; JR -8, with the data at 0x150
+    fn emulator() -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x108].copy_from_slice(&[0xfa, 0x50, 0x01, 0xea, 0x00, 0xc0, 0x18, 0xf8]);
+        rom[0x150] = 0x42;
+        Emulator::new(rom, &Default::default())
+    }
+
+    fn trace(filter: TraceFilter, instructions: usize) -> Vec<Record> {
+        let mut emulator = emulator();
+        let tracer = Tracer::new(Vec::new(), filter).unwrap();
+        emulator.add_observer(Box::new(tracer.clone()));
+        for _ in 0..instructions {
+            emulator.step();
+        }
+        let data = tracer.finish().unwrap();
+        TraceReader::new(data.as_slice())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_trace() {
+        let records = trace(Default::default(), 3);
+        assert_eq!(
+            records,
+            [
+                Record::Instruction {
+                    pc: 0x100,
+                    bytes: vec![0xfa, 0x50, 0x01]
+                },
+                Record::Read {
+                    addr: 0x150,
+                    value: 0x42
+                },
+                Record::Instruction {
+                    pc: 0x103,
+                    bytes: vec![0xea, 0x00, 0xc0]
+                },
+                Record::Write {
+                    addr: 0xc000,
+                    value: 0x42
+                },
+                Record::Instruction {
+                    pc: 0x106,
+                    bytes: vec![0x18, 0xf8]
+                },
+            ]
+        );
+        let opcode = decode(&mut [0xfa, 0x50, 0x01].into_iter()).unwrap();
+        assert_eq!(
+            records[0].to_string(),
+            format!("0100  fa 50 01  {}", opcode)
+        );
+        assert_eq!(records[3].to_string(), "      write c000 42");
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        let filter = TraceFilter {
+            instructions: false,
+            reads: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            trace(filter, 6),
+            vec![
+                Record::Write {
+                    addr: 0xc000,
+                    value: 0x42
+                };
+                2
+            ]
+        );
+
+        let filter = TraceFilter {
+            mnemonics: vec![parse_mnemonic("jr").unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(trace(filter, 3).len(), 1);
+
+        let filter = TraceFilter {
+            pc_ranges: vec![parse_range("0x100-0x102").unwrap()],
+            address_ranges: vec![parse_range("0xc000").unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(trace(filter, 3).len(), 1);
+    }
+
+    #[test]
+    fn test_trace_invalid() {
+        assert!(matches!(
+            parse_range("0x200-0x100"),
+            Err(TraceError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            parse_range("0x10000"),
+            Err(TraceError::InvalidRange(_))
+        ));
+        assert!(matches!(
+            parse_mnemonic("MOV"),
+            Err(TraceError::UnknownMnemonic(_))
+        ));
+        assert!(matches!(
+            TraceReader::new(&b"GBTR\x02"[..]),
+            Err(TraceError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            TraceReader::new(&b"MOVIE"[..]),
+            Err(TraceError::InvalidHeader)
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//! Watches on registers and addresses, updated by `Cpu::step` after each
+//! instruction. Every change is logged with the address of the instruction
+//! which made it, to find what sets LCDC to 0 without single-stepping.
+
+use std::collections::VecDeque;
+use std::{fmt::Display, str::FromStr};
+
+use crate::cpu::Registers;
+use crate::debugger::expression::{parse_number, ExpressionError, Register};
+use crate::disassembler::IoNames;
+use crate::mmu::Mmu;
+
+/// Changes kept by default, the oldest ones are dropped first
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Watch {
+    Register(Register),
+    /// Byte at an address, such as an IO register
+    Address(u16),
+}
+
+impl Watch {
+    pub fn value(&self, regs: &Registers, mmu: &Mmu) -> u16 {
+        match self {
+            Watch::Register(register) => register.value(regs),
+            Watch::Address(addr) => mmu.peek(*addr) as u16,
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = ExpressionError;
+
+    /// A register of the CPU such as `HL`, an address such as `0xff40` or
+    /// the name of an IO register such as `LCDC`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(addr) = parse_number(s) {
+            return u16::try_from(addr)
+                .map(Watch::Address)
+                .map_err(|_| ExpressionError::InvalidNumber(s.to_string()));
+        }
+        s.parse()
+            .map(Watch::Register)
+            .or_else(|err| IoNames::default().address(s).map(Watch::Address).ok_or(err))
+    }
+}
+
+impl Display for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Watch::Register(register) => write!(f, "{}", register),
+            Watch::Address(addr) => match IoNames::default().get(*addr) {
+                Some(name) => f.write_str(name),
+                None => write!(f, "0x{:04x}", addr),
+            },
+        }
+    }
+}
+
+/// A watched value which changed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Change {
+    /// As returned by `Watches::add`
+    pub id: usize,
+    /// Address of the instruction executed, None when the step dispatched an
+    /// interrupt or the CPU was halted
+    pub pc: Option<u16>,
+    pub old: u16,
+    pub new: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watches {
+    // With their last value, unknown until the next step
+    watches: Vec<(usize, Watch, Option<u16>)>,
+    next_id: usize,
+    changes: VecDeque<Change>,
+    capacity: usize,
+}
+
+impl Default for Watches {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Watches {
+    /// Keep the last `capacity` changes
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            watches: Vec::new(),
+            next_id: 0,
+            changes: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Returns the id of the watch, to remove it and to find its changes
+    pub fn add(&mut self, watch: Watch) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watches.push((id, watch, None));
+        id
+    }
+
+    /// False when there is no such watch, its changes are kept
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|(other, _, _)| *other != id);
+        self.watches.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// The watches and their ids, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Watch)> {
+        self.watches.iter().map(|(id, watch, _)| (*id, watch))
+    }
+
+    /// The changes logged, from the oldest
+    pub fn changes(&self) -> impl DoubleEndedIterator<Item = &Change> {
+        self.changes.iter()
+    }
+
+    pub fn clear_changes(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Log the values which changed since the last update, made by the
+    /// instruction at `pc`
+    pub fn update(&mut self, pc: Option<u16>, regs: &Registers, mmu: &Mmu) {
+        for (id, watch, last) in &mut self.watches {
+            let new = watch.value(regs, mmu);
+            match last.replace(new) {
+                Some(old) if old != new => {
+                    if self.changes.len() == self.capacity {
+                        self.changes.pop_front();
+                    }
+                    self.changes.push_back(Change {
+                        id: *id,
+                        pc,
+                        old,
+                        new,
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::ppu::LCDC;
+
+    #[test]
+    fn test_watch_parse() {
+        assert_eq!("hl".parse(), Ok(Watch::Register(Register::Hl)));
+        assert_eq!("0xc000".parse(), Ok(Watch::Address(0xc000)));
+        assert_eq!("lcdc".parse(), Ok(Watch::Address(LCDC)));
+        assert_eq!(Watch::Address(LCDC).to_string(), "LCDC");
+        assert_eq!(Watch::Address(0xc000).to_string(), "0xc000");
+        assert_eq!(
+            "IX".parse::<Watch>(),
+            Err(ExpressionError::UnknownName("IX".into()))
+        );
+    }
+
+    #[test]
+    fn test_watches() {
+        let mut mmu = Mmu::after_boot(Vec::new(), Model::Dmg);
+        let mut regs = Registers::after_boot(Model::Dmg);
+        let mut watches = Watches::with_capacity(2);
+        let a = watches.add(Watch::Register(Register::A));
+        let lcdc = watches.add("LCDC".parse().unwrap());
+        watches.update(None, &regs, &mmu);
+        assert_eq!(watches.changes().count(), 0);
+
+        regs.a = 0x42;
+        mmu.write(LCDC, 0x00);
+        watches.update(Some(0x150), &regs, &mmu);
+        assert_eq!(
+            watches.changes().collect::<Vec<_>>(),
+            [
+                &Change {
+                    id: a,
+                    pc: Some(0x150),
+                    old: 0x01,
+                    new: 0x42
+                },
+                &Change {
+                    id: lcdc,
+                    pc: Some(0x150),
+                    old: 0x91,
+                    new: 0x00
+                }
+            ]
+        );
+
+        // The oldest change is dropped
+        regs.a = 0x43;
+        watches.update(Some(0x152), &regs, &mmu);
+        assert_eq!(watches.changes().count(), 2);
+        assert_eq!(watches.changes().next().unwrap().id, lcdc);
+
+        assert!(watches.remove(a));
+        watches.clear_changes();
+        regs.a = 0x44;
+        watches.update(Some(0x153), &regs, &mmu);
+        assert_eq!(watches.changes().count(), 0);
+    }
+}
@@ -0,0 +1,736 @@
+use std::error::Error;
+use std::fmt::{Debug, Display};
+
+use crate::slots::{AddrRegister, Register16, Register16::*, Register8, Register8::*, Slot};
+
+/// Condition of the conditional jumps, calls and returns
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Opcode {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Ret,
+    Reti,
+    RetCond(Condition),
+    Ld(Slot, Slot),
+    // LD HL,SP+e
+    LdHlSpOffset(i8),
+    Call(Slot),
+    CallCond(Condition, Slot),
+    Rst(u8),
+    Inc(Slot),
+    Cp(Slot, Slot),
+    Dec(Slot),
+    Add(Slot, Slot),
+    // ADD SP,e
+    AddSpOffset(i8),
+    Adc(Slot),
+    Sub(Slot),
+    Sbc(Slot),
+    And(Slot),
+    Or(Slot),
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    LdToMemDec(Register16, Register8),
+    LdToMemInc(Register16, Register8),
+    LdFromMemDec(Register8, Register16),
+    LdFromMemInc(Register8, Register16),
+    // Rotations of A, they always clear the Z flag
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    RotLeft(Slot),
+    RotRight(Slot),
+    RotLeftCircular(Slot),
+    RotRightCircular(Slot),
+    ShiftLeftArith(Slot),
+    ShiftRightArith(Slot),
+    ShiftRightLogical(Slot),
+    Swap(Slot),
+    Push(Register16),
+    Pop(Register16),
+    Xor(Slot, Slot),
+    ComplBit(u8, Slot),
+    ResetBit(u8, Slot),
+    SetBit(u8, Slot),
+    Jump(i8),
+    JumpRZMemOffset(i8),
+    JumpRNZMemOffset(i8),
+    JumpRCMemOffset(i8),
+    JumpRNCMemOffset(i8),
+    JumpAbs(Slot),
+    JumpAbsCond(Condition, Slot),
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Opcode::Cp(to, from) => write!(f, "CP {:?} {:?}", to, from),
+            Opcode::Dec(from) => write!(f, "DEC {:?}", from),
+            Opcode::Inc(from) => write!(f, "INC {:?}", from),
+            Opcode::Push(from) => write!(f, "PUSH {:?}", from),
+            Opcode::Pop(to) => write!(f, "POP {:?}", to),
+            Opcode::Ld(to, from) => write!(f, "LD {:?} {:?}", to, from),
+            Opcode::Call(slot) => write!(f, "CALL {:?}", slot),
+            Opcode::LdToMemInc(to, from) => write!(f, "LD ({:?}++) {:?}", to, from),
+            Opcode::LdToMemDec(to, from) => write!(f, "LD ({:?}--) {:?}", to, from),
+            Opcode::LdFromMemInc(to, from) => write!(f, "LD {:?} ({:?}++)", to, from),
+            Opcode::LdFromMemDec(to, from) => write!(f, "LD {:?} ({:?}--)", to, from),
+            Opcode::Sub(from) => write!(f, "SUB A,{:?}", from),
+            Opcode::Add(to, from) => write!(f, "ADD {:?},{:?}", to, from),
+            Opcode::Adc(from) => write!(f, "ADC A,{:?}", from),
+            Opcode::Sbc(from) => write!(f, "SBC A,{:?}", from),
+            Opcode::And(from) => write!(f, "AND A,{:?}", from),
+            Opcode::Or(from) => write!(f, "OR A,{:?}", from),
+            Opcode::Rst(addr) => write!(f, "RST 0x{:02x}", addr),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Destination of a control flow instruction
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Target {
+    Jump(u16),
+    Call(u16),
+}
+
+impl Opcode {
+    /// Destination of the jumps, calls and restarts known without running
+    /// the code. `next` is the address of the following instruction.
+    pub fn target(&self, next: u16) -> Option<Target> {
+        let relative = |offset: i8| next.wrapping_add_signed(offset as i16);
+        match *self {
+            Opcode::Jump(offset)
+            | Opcode::JumpRZMemOffset(offset)
+            | Opcode::JumpRNZMemOffset(offset)
+            | Opcode::JumpRCMemOffset(offset)
+            | Opcode::JumpRNCMemOffset(offset) => Some(Target::Jump(relative(offset))),
+            Opcode::JumpAbs(Slot::Addr16(addr)) | Opcode::JumpAbsCond(_, Slot::Addr16(addr)) => {
+                Some(Target::Jump(addr))
+            }
+            Opcode::Call(Slot::Data16(addr)) | Opcode::CallCond(_, Slot::Data16(addr)) => {
+                Some(Target::Call(addr))
+            }
+            Opcode::Rst(addr) => Some(Target::Call(addr as u16)),
+            _ => None,
+        }
+    }
+
+    /// Name of the instruction, without its operands
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Nop => "NOP",
+            Opcode::Stop => "STOP",
+            Opcode::Halt => "HALT",
+            Opcode::Di => "DI",
+            Opcode::Ei => "EI",
+            Opcode::Ret | Opcode::RetCond(_) => "RET",
+            Opcode::Reti => "RETI",
+            Opcode::Ld(_, _)
+            | Opcode::LdHlSpOffset(_)
+            | Opcode::LdToMemDec(_, _)
+            | Opcode::LdToMemInc(_, _)
+            | Opcode::LdFromMemDec(_, _)
+            | Opcode::LdFromMemInc(_, _) => "LD",
+            Opcode::Call(_) | Opcode::CallCond(_, _) => "CALL",
+            Opcode::Rst(_) => "RST",
+            Opcode::Inc(_) => "INC",
+            Opcode::Cp(_, _) => "CP",
+            Opcode::Dec(_) => "DEC",
+            Opcode::Add(_, _) | Opcode::AddSpOffset(_) => "ADD",
+            Opcode::Adc(_) => "ADC",
+            Opcode::Sub(_) => "SUB",
+            Opcode::Sbc(_) => "SBC",
+            Opcode::And(_) => "AND",
+            Opcode::Or(_) => "OR",
+            Opcode::Daa => "DAA",
+            Opcode::Cpl => "CPL",
+            Opcode::Scf => "SCF",
+            Opcode::Ccf => "CCF",
+            Opcode::Rlca => "RLCA",
+            Opcode::Rrca => "RRCA",
+            Opcode::Rla => "RLA",
+            Opcode::Rra => "RRA",
+            Opcode::RotLeft(_) => "RL",
+            Opcode::RotRight(_) => "RR",
+            Opcode::RotLeftCircular(_) => "RLC",
+            Opcode::RotRightCircular(_) => "RRC",
+            Opcode::ShiftLeftArith(_) => "SLA",
+            Opcode::ShiftRightArith(_) => "SRA",
+            Opcode::ShiftRightLogical(_) => "SRL",
+            Opcode::Swap(_) => "SWAP",
+            Opcode::Push(_) => "PUSH",
+            Opcode::Pop(_) => "POP",
+            Opcode::Xor(_, _) => "XOR",
+            Opcode::ComplBit(_, _) => "BIT",
+            Opcode::ResetBit(_, _) => "RES",
+            Opcode::SetBit(_, _) => "SET",
+            Opcode::Jump(_)
+            | Opcode::JumpRZMemOffset(_)
+            | Opcode::JumpRNZMemOffset(_)
+            | Opcode::JumpRCMemOffset(_)
+            | Opcode::JumpRNCMemOffset(_) => "JR",
+            Opcode::JumpAbs(_) | Opcode::JumpAbsCond(_, _) => "JP",
+        }
+    }
+
+    /// False when the next instruction is never executed after this one:
+    /// unconditional jumps and returns
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Opcode::Jump(_) | Opcode::JumpAbs(_) | Opcode::Ret | Opcode::Reti
+        )
+    }
+}
+
+// 8-bit operands encoded on 3 bits in most opcodes
+const R8_SLOTS: [Slot; 8] = [
+    Slot::Register8(B),
+    Slot::Register8(C),
+    Slot::Register8(D),
+    Slot::Register8(E),
+    Slot::Register8(H),
+    Slot::Register8(L),
+    Slot::AddrRegister(AddrRegister::HL),
+    Slot::Register8(A),
+];
+
+/// Mnemonics of the SM83, as returned by `Opcode::mnemonic`
+pub const MNEMONICS: [&str; 43] = [
+    "NOP", "STOP", "HALT", "DI", "EI", "RET", "RETI", "LD", "CALL", "RST", "INC", "CP", "DEC",
+    "ADD", "ADC", "SUB", "SBC", "AND", "OR", "DAA", "CPL", "SCF", "CCF", "RLCA", "RRCA", "RLA",
+    "RRA", "RL", "RR", "RLC", "RRC", "SLA", "SRA", "SRL", "SWAP", "PUSH", "POP", "XOR", "BIT",
+    "RES", "SET", "JR", "JP",
+];
+
+/// Size of the instruction starting with `opcode`, 1 for an invalid one
+pub fn instruction_len(opcode: u8) -> u16 {
+    let mut len = 0;
+    let mut bytes = [opcode, 0, 0].into_iter().inspect(|_| len += 1);
+    match decode(&mut bytes) {
+        Ok(_) => len,
+        Err(_) => 1,
+    }
+}
+
+pub fn decode(data: &mut impl Iterator<Item = u8>) -> Result<Opcode, DecodeError> {
+    let opcode = data.next().ok_or(DecodeError::EndOfStream)?;
+    // Extended Opcodes
+    if opcode == 0xcb {
+        return decode_extended(data.next().ok_or(DecodeError::EndOfStream)?);
+    }
+
+    if (0x40..0x80).contains(&opcode) {
+        // Inside this range the arguments for the Ld Opcode
+        // repeat in a specific pattern: BB, BC, BD... CB, CC, CD... AB
+        // AC, AD, ...until AA. The first 3 bits represent the destination
+        // and the last 3 represent the source.
+
+        // Ld (HL), (HL) is a specific case replaced by Halt
+        if opcode == 0x76 {
+            return Ok(Opcode::Halt);
+        }
+
+        let address = (opcode - 0x40) as usize;
+        return Ok(Opcode::Ld(R8_SLOTS[address >> 3], R8_SLOTS[address & 0x7]));
+    }
+
+    if (0x80..0xc0).contains(&opcode) {
+        // Same pattern for the arithmetic operations with A: the first 3 bits
+        // select the operation and the last 3 the operand.
+        let slot = R8_SLOTS[(opcode & 0x7) as usize];
+        return Ok(match (opcode >> 3) & 0x7 {
+            0 => Opcode::Add(Slot::r8(A), slot),
+            1 => Opcode::Adc(slot),
+            2 => Opcode::Sub(slot),
+            3 => Opcode::Sbc(slot),
+            4 => Opcode::And(slot),
+            5 => Opcode::Xor(Slot::r8(A), slot),
+            6 => Opcode::Or(slot),
+            _ => Opcode::Cp(Slot::r8(A), slot),
+        });
+    }
+
+    Ok(match opcode {
+        0x00 => Opcode::Nop,
+        0x01 => Opcode::Ld(Slot::r16(BC), Slot::parse_d16(data)?),
+        0x02 => Opcode::Ld(Slot::addr(AddrRegister::BC), Slot::r8(A)),
+        0x03 => Opcode::Inc(Slot::r16(BC)),
+        0x04 => Opcode::Inc(Slot::r8(B)),
+        0x05 => Opcode::Dec(Slot::r8(B)),
+        0x06 => Opcode::Ld(Slot::r8(B), Slot::parse_d8(data)?),
+        0x07 => Opcode::Rlca,
+        0x08 => Opcode::Ld(Slot::parse_a16(data)?, Slot::r16(SP)),
+        0x09 => Opcode::Add(Slot::r16(HL), Slot::r16(BC)),
+        0x0a => Opcode::Ld(Slot::r8(A), Slot::addr(AddrRegister::BC)),
+        0x0b => Opcode::Dec(Slot::r16(BC)),
+        0x0c => Opcode::Inc(Slot::r8(C)),
+        0x0d => Opcode::Dec(Slot::r8(C)),
+        0x0e => Opcode::Ld(Slot::r8(C), Slot::parse_d8(data)?),
+        0x0f => Opcode::Rrca,
+        // STOP is followed by an ignored byte
+        0x10 => {
+            data.next().ok_or(DecodeError::EndOfStream)?;
+            Opcode::Stop
+        }
+        0x11 => Opcode::Ld(Slot::r16(DE), Slot::parse_d16(data)?),
+        0x12 => Opcode::Ld(Slot::addr(AddrRegister::DE), Slot::r8(A)),
+        0x13 => Opcode::Inc(Slot::r16(DE)),
+        0x14 => Opcode::Inc(Slot::r8(D)),
+        0x15 => Opcode::Dec(Slot::r8(D)),
+        0x16 => Opcode::Ld(Slot::r8(D), Slot::parse_d8(data)?),
+        0x17 => Opcode::Rla,
+        0x18 => Opcode::Jump(parse_offset(data)?),
+        0x19 => Opcode::Add(Slot::r16(HL), Slot::r16(DE)),
+        0x1a => Opcode::Ld(Slot::r8(A), Slot::addr(AddrRegister::DE)),
+        0x1b => Opcode::Dec(Slot::r16(DE)),
+        0x1c => Opcode::Inc(Slot::r8(E)),
+        0x1d => Opcode::Dec(Slot::r8(E)),
+        0x1e => Opcode::Ld(Slot::r8(E), Slot::parse_d8(data)?),
+        0x1f => Opcode::Rra,
+        0x20 => Opcode::JumpRNZMemOffset(parse_offset(data)?),
+        0x21 => Opcode::Ld(Slot::r16(HL), Slot::parse_d16(data)?),
+        0x22 => Opcode::LdToMemInc(HL, A),
+        0x23 => Opcode::Inc(Slot::r16(HL)),
+        0x24 => Opcode::Inc(Slot::r8(H)),
+        0x25 => Opcode::Dec(Slot::r8(H)),
+        0x26 => Opcode::Ld(Slot::r8(H), Slot::parse_d8(data)?),
+        0x27 => Opcode::Daa,
+        0x28 => Opcode::JumpRZMemOffset(parse_offset(data)?),
+        0x29 => Opcode::Add(Slot::r16(HL), Slot::r16(HL)),
+        0x2a => Opcode::LdFromMemInc(A, HL),
+        0x2b => Opcode::Dec(Slot::r16(HL)),
+        0x2c => Opcode::Inc(Slot::r8(L)),
+        0x2d => Opcode::Dec(Slot::r8(L)),
+        0x2e => Opcode::Ld(Slot::r8(L), Slot::parse_d8(data)?),
+        0x2f => Opcode::Cpl,
+        0x30 => Opcode::JumpRNCMemOffset(parse_offset(data)?),
+        0x31 => Opcode::Ld(Slot::r16(SP), Slot::parse_d16(data)?),
+        0x32 => Opcode::LdToMemDec(HL, A),
+        0x33 => Opcode::Inc(Slot::r16(SP)),
+        0x34 => Opcode::Inc(Slot::AddrRegister(AddrRegister::HL)),
+        0x35 => Opcode::Dec(Slot::AddrRegister(AddrRegister::HL)),
+        0x36 => Opcode::Ld(Slot::addr(AddrRegister::HL), Slot::parse_d8(data)?),
+        0x37 => Opcode::Scf,
+        0x38 => Opcode::JumpRCMemOffset(parse_offset(data)?),
+        0x39 => Opcode::Add(Slot::r16(HL), Slot::r16(SP)),
+        0x3a => Opcode::LdFromMemDec(A, HL),
+        0x3b => Opcode::Dec(Slot::r16(SP)),
+        0x3c => Opcode::Inc(Slot::r8(A)),
+        0x3d => Opcode::Dec(Slot::r8(A)),
+        0x3e => Opcode::Ld(Slot::r8(A), Slot::parse_d8(data)?),
+        0x3f => Opcode::Ccf,
+        0xc0 => Opcode::RetCond(Condition::NZ),
+        0xc1 => Opcode::Pop(BC),
+        0xc2 => Opcode::JumpAbsCond(Condition::NZ, Slot::parse_a16(data)?),
+        0xc3 => Opcode::JumpAbs(Slot::parse_a16(data)?),
+        0xc4 => Opcode::CallCond(Condition::NZ, Slot::parse_d16(data)?),
+        0xc5 => Opcode::Push(BC),
+        0xc6 => Opcode::Add(Slot::r8(A), Slot::parse_d8(data)?),
+        0xc8 => Opcode::RetCond(Condition::Z),
+        0xc9 => Opcode::Ret,
+        0xca => Opcode::JumpAbsCond(Condition::Z, Slot::parse_a16(data)?),
+        0xcc => Opcode::CallCond(Condition::Z, Slot::parse_d16(data)?),
+        0xcd => Opcode::Call(Slot::parse_d16(data)?),
+        0xce => Opcode::Adc(Slot::parse_d8(data)?),
+        0xd0 => Opcode::RetCond(Condition::NC),
+        0xd1 => Opcode::Pop(DE),
+        0xd2 => Opcode::JumpAbsCond(Condition::NC, Slot::parse_a16(data)?),
+        0xd4 => Opcode::CallCond(Condition::NC, Slot::parse_d16(data)?),
+        0xd5 => Opcode::Push(DE),
+        0xd6 => Opcode::Sub(Slot::parse_d8(data)?),
+        0xd8 => Opcode::RetCond(Condition::C),
+        0xd9 => Opcode::Reti,
+        0xda => Opcode::JumpAbsCond(Condition::C, Slot::parse_a16(data)?),
+        0xdc => Opcode::CallCond(Condition::C, Slot::parse_d16(data)?),
+        0xde => Opcode::Sbc(Slot::parse_d8(data)?),
+        0xe0 => Opcode::Ld(Slot::parse_a8(data)?, Slot::r8(A)),
+        0xe1 => Opcode::Pop(HL),
+        0xe2 => Opcode::Ld(Slot::addr(AddrRegister::C), Slot::r8(A)),
+        0xe5 => Opcode::Push(HL),
+        0xe6 => Opcode::And(Slot::parse_d8(data)?),
+        0xe8 => Opcode::AddSpOffset(parse_offset(data)?),
+        0xe9 => Opcode::JumpAbs(Slot::r16(HL)),
+        0xea => Opcode::Ld(Slot::parse_a16(data)?, Slot::r8(A)),
+        0xee => Opcode::Xor(Slot::r8(A), Slot::parse_d8(data)?),
+        0xf0 => Opcode::Ld(Slot::r8(A), Slot::parse_a8(data)?),
+        0xf1 => Opcode::Pop(AF),
+        0xf2 => Opcode::Ld(Slot::r8(A), Slot::addr(AddrRegister::C)),
+        0xf3 => Opcode::Di,
+        0xf5 => Opcode::Push(AF),
+        0xf6 => Opcode::Or(Slot::parse_d8(data)?),
+        0xf8 => Opcode::LdHlSpOffset(parse_offset(data)?),
+        0xf9 => Opcode::Ld(Slot::r16(SP), Slot::r16(HL)),
+        0xfa => Opcode::Ld(Slot::r8(A), Slot::parse_a16(data)?),
+        0xfb => Opcode::Ei,
+        0xfe => Opcode::Cp(Slot::r8(A), Slot::parse_d8(data)?),
+        0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => Opcode::Rst(opcode & 0x38),
+        _ => return Err(DecodeError::UnknownOpcode(opcode)),
+    })
+}
+
+/// Signed offset of the relative jumps and of the SP arithmetic
+fn parse_offset(data: &mut impl Iterator<Item = u8>) -> Result<i8, DecodeError> {
+    Ok(data.next().ok_or(DecodeError::EndOfStream)? as i8)
+}
+
+#[derive(PartialEq)]
+pub enum DecodeError {
+    EndOfStream,
+    UnknownOpcode(u8),
+    UnknownExtendedOpcode(u8),
+}
+
+impl Debug for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <DecodeError as Display>::fmt(self, f)
+    }
+}
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EndOfStream => write!(f, "End of stream detected during opcode decoding"),
+            Self::UnknownOpcode(opcode) => write!(f, "Unknown Opcode 0x{:x}", opcode),
+            Self::UnknownExtendedOpcode(opcode) => {
+                write!(f, "Unknown Extended opcode 0x{:x}", opcode)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+fn decode_extended(data: u8) -> Result<Opcode, DecodeError> {
+    // The first 2 bits select the group of operations, the next 3 the
+    // operation or the bit, and the last 3 the operand
+    let slot = R8_SLOTS[(data & 0x7) as usize];
+    let bit = (data >> 3) & 0x7;
+    Ok(match data >> 6 {
+        0 => match bit {
+            0 => Opcode::RotLeftCircular(slot),
+            1 => Opcode::RotRightCircular(slot),
+            2 => Opcode::RotLeft(slot),
+            3 => Opcode::RotRight(slot),
+            4 => Opcode::ShiftLeftArith(slot),
+            5 => Opcode::ShiftRightArith(slot),
+            6 => Opcode::Swap(slot),
+            _ => Opcode::ShiftRightLogical(slot),
+        },
+        1 => Opcode::ComplBit(bit, slot),
+        2 => Opcode::ResetBit(bit, slot),
+        _ => Opcode::SetBit(bit, slot),
+    })
+}
+
+/// Bytes of `opcode`, the inverse of `decode`. None for the operands that
+/// no instruction takes, as `LD (BC) B`.
+pub fn encode(opcode: &Opcode) -> Option<Vec<u8>> {
+    use Opcode::*;
+    let r8 = |slot: &Slot| R8_SLOTS.iter().position(|s| s == slot).map(|i| i as u8);
+    // BC, DE, HL and SP encoded on 2 bits
+    let r16 = |slot: &Slot| match slot {
+        Slot::Register16(BC) => Some(0),
+        Slot::Register16(DE) => Some(1),
+        Slot::Register16(HL) => Some(2),
+        Slot::Register16(SP) => Some(3),
+        _ => None,
+    };
+    let condition = |condition: &Condition| *condition as u8;
+    let a16 = |opcode: u8, value: u16| {
+        let [low, high] = value.to_le_bytes();
+        vec![opcode, low, high]
+    };
+    // The arithmetic operations with A, on a register or an immediate
+    let alu = |operation: u8, slot: &Slot| match slot {
+        Slot::Data8(value) => Some(vec![0xc6 | operation << 3, *value]),
+        slot => Some(vec![0x80 | operation << 3 | r8(slot)?]),
+    };
+    let extended = |operation: u8, slot: &Slot| Some(vec![0xcb, operation << 3 | r8(slot)?]);
+    let bit = |group: u8, bit: u8, slot: &Slot| {
+        (bit < 8).then_some(())?;
+        Some(vec![0xcb, group << 6 | bit << 3 | r8(slot)?])
+    };
+    let a = Slot::Register8(A);
+
+    Some(match opcode {
+        Nop => vec![0x00],
+        Stop => vec![0x10, 0x00],
+        Halt => vec![0x76],
+        Di => vec![0xf3],
+        Ei => vec![0xfb],
+        Ret => vec![0xc9],
+        Reti => vec![0xd9],
+        RetCond(c) => vec![0xc0 | condition(c) << 3],
+        Ld(Slot::AddrRegister(AddrRegister::HL), Slot::AddrRegister(AddrRegister::HL)) => {
+            return None
+        }
+        Ld(to, from) => match (to, from) {
+            (Slot::Register16(SP), Slot::Register16(HL)) => vec![0xf9],
+            (to, Slot::Data8(value)) => vec![0x06 | r8(to)? << 3, *value],
+            (to, Slot::Data16(value)) => a16(0x01 | r16(to)? << 4, *value),
+            (Slot::AddrRegister(AddrRegister::BC), from) if *from == a => vec![0x02],
+            (Slot::AddrRegister(AddrRegister::DE), from) if *from == a => vec![0x12],
+            (Slot::AddrRegister(AddrRegister::C), from) if *from == a => vec![0xe2],
+            (Slot::Addr8(addr), from) if *from == a => vec![0xe0, *addr],
+            (Slot::Addr16(addr), from) if *from == a => a16(0xea, *addr),
+            (Slot::Addr16(addr), Slot::Register16(SP)) => a16(0x08, *addr),
+            (to, Slot::AddrRegister(AddrRegister::BC)) if *to == a => vec![0x0a],
+            (to, Slot::AddrRegister(AddrRegister::DE)) if *to == a => vec![0x1a],
+            (to, Slot::AddrRegister(AddrRegister::C)) if *to == a => vec![0xf2],
+            (to, Slot::Addr8(addr)) if *to == a => vec![0xf0, *addr],
+            (to, Slot::Addr16(addr)) if *to == a => a16(0xfa, *addr),
+            (to, from) => vec![0x40 | r8(to)? << 3 | r8(from)?],
+        },
+        LdHlSpOffset(offset) => vec![0xf8, *offset as u8],
+        Call(Slot::Data16(addr)) => a16(0xcd, *addr),
+        CallCond(c, Slot::Data16(addr)) => a16(0xc4 | condition(c) << 3, *addr),
+        Rst(addr) if addr & !0x38 == 0 => vec![0xc7 | addr],
+        Inc(slot) => match r16(slot) {
+            Some(rp) => vec![0x03 | rp << 4],
+            None => vec![0x04 | r8(slot)? << 3],
+        },
+        Dec(slot) => match r16(slot) {
+            Some(rp) => vec![0x0b | rp << 4],
+            None => vec![0x05 | r8(slot)? << 3],
+        },
+        Add(Slot::Register16(HL), from) => vec![0x09 | r16(from)? << 4],
+        Add(to, from) if *to == a => alu(0, from)?,
+        AddSpOffset(offset) => vec![0xe8, *offset as u8],
+        Adc(from) => alu(1, from)?,
+        Sub(from) => alu(2, from)?,
+        Sbc(from) => alu(3, from)?,
+        And(from) => alu(4, from)?,
+        Xor(to, from) if *to == a => alu(5, from)?,
+        Or(from) => alu(6, from)?,
+        Cp(to, from) if *to == a => alu(7, from)?,
+        Daa => vec![0x27],
+        Cpl => vec![0x2f],
+        Scf => vec![0x37],
+        Ccf => vec![0x3f],
+        LdToMemInc(HL, A) => vec![0x22],
+        LdToMemDec(HL, A) => vec![0x32],
+        LdFromMemInc(A, HL) => vec![0x2a],
+        LdFromMemDec(A, HL) => vec![0x3a],
+        Rlca => vec![0x07],
+        Rrca => vec![0x0f],
+        Rla => vec![0x17],
+        Rra => vec![0x1f],
+        RotLeftCircular(slot) => extended(0, slot)?,
+        RotRightCircular(slot) => extended(1, slot)?,
+        RotLeft(slot) => extended(2, slot)?,
+        RotRight(slot) => extended(3, slot)?,
+        ShiftLeftArith(slot) => extended(4, slot)?,
+        ShiftRightArith(slot) => extended(5, slot)?,
+        Swap(slot) => extended(6, slot)?,
+        ShiftRightLogical(slot) => extended(7, slot)?,
+        ComplBit(b, slot) => bit(1, *b, slot)?,
+        ResetBit(b, slot) => bit(2, *b, slot)?,
+        SetBit(b, slot) => bit(3, *b, slot)?,
+        Push(rp) | Pop(rp) => {
+            let index = match rp {
+                BC => 0,
+                DE => 1,
+                HL => 2,
+                AF => 3,
+                _ => return None,
+            };
+            vec![
+                if matches!(opcode, Push(_)) {
+                    0xc5
+                } else {
+                    0xc1
+                } | index << 4,
+            ]
+        }
+        Jump(offset) => vec![0x18, *offset as u8],
+        JumpRNZMemOffset(offset) => vec![0x20, *offset as u8],
+        JumpRZMemOffset(offset) => vec![0x28, *offset as u8],
+        JumpRNCMemOffset(offset) => vec![0x30, *offset as u8],
+        JumpRCMemOffset(offset) => vec![0x38, *offset as u8],
+        JumpAbs(Slot::Addr16(addr)) => a16(0xc3, *addr),
+        JumpAbs(Slot::Register16(HL)) => vec![0xe9],
+        JumpAbsCond(c, Slot::Addr16(addr)) => a16(0xc2 | condition(c) << 3, *addr),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn decode_ld_band() {
+        assert_eq!(
+            decode(&mut [0x40u8].iter().copied()).unwrap(),
+            Opcode::Ld(Slot::Register8(B), Slot::Register8(B))
+        );
+        assert_eq!(
+            decode(&mut [0x5fu8].iter().copied()).unwrap(),
+            Opcode::Ld(Slot::Register8(E), Slot::Register8(A))
+        );
+        assert_eq!(
+            decode(&mut [0x66u8].iter().copied()).unwrap(),
+            Opcode::Ld(Slot::Register8(H), Slot::AddrRegister(AddrRegister::HL),)
+        );
+        assert_eq!(
+            decode(&mut [0x68u8].iter().copied()).unwrap(),
+            Opcode::Ld(Slot::Register8(L), Slot::Register8(B)),
+        );
+
+        assert_eq!(
+            decode(&mut [0x7du8].iter().copied()).unwrap(),
+            Opcode::Ld(Slot::Register8(A), Slot::Register8(L)),
+        );
+        assert_eq!(decode(&mut [0x76u8].iter().copied()).unwrap(), Opcode::Halt);
+    }
+
+    #[test]
+    fn decode_alu_band() {
+        assert_eq!(
+            decode(&mut [0x80u8].iter().copied()).unwrap(),
+            Opcode::Add(Slot::Register8(A), Slot::Register8(B))
+        );
+        assert_eq!(
+            decode(&mut [0xaeu8].iter().copied()).unwrap(),
+            Opcode::Xor(Slot::Register8(A), Slot::AddrRegister(AddrRegister::HL))
+        );
+        assert_eq!(
+            decode(&mut [0xbfu8].iter().copied()).unwrap(),
+            Opcode::Cp(Slot::Register8(A), Slot::Register8(A))
+        );
+    }
+
+    #[test]
+    fn test_mnemonic() {
+        let mnemonic = |data: &[u8]| decode(&mut data.iter().copied()).unwrap().mnemonic();
+        assert_eq!(mnemonic(&[0xcd, 0x50, 0x01]), "CALL");
+        assert_eq!(mnemonic(&[0x20, 0xfe]), "JR");
+        assert_eq!(mnemonic(&[0x22]), "LD");
+        assert_eq!(mnemonic(&[0xcb, 0x7c]), "BIT");
+    }
+
+    #[test]
+    fn test_instruction_len() {
+        assert_eq!(instruction_len(0x00), 1);
+        assert_eq!(instruction_len(0x3e), 2);
+        assert_eq!(instruction_len(0xcb), 2);
+        assert_eq!(instruction_len(0xcd), 3);
+        assert_eq!(instruction_len(0xd3), 1);
+    }
+
+    #[test]
+    fn decode_extended_opcodes() {
+        assert_eq!(
+            decode(&mut [0xcbu8, 0x11].iter().copied()).unwrap(),
+            Opcode::RotLeft(Slot::Register8(C))
+        );
+        assert_eq!(
+            decode(&mut [0xcbu8, 0x7c].iter().copied()).unwrap(),
+            Opcode::ComplBit(7, Slot::Register8(H))
+        );
+        assert_eq!(
+            decode(&mut [0xcbu8, 0xc6].iter().copied()).unwrap(),
+            Opcode::SetBit(0, Slot::AddrRegister(AddrRegister::HL))
+        );
+    }
+
+    #[test]
+    fn decode_all_opcodes() {
+        // Every opcode but the 11 unused ones is decoded, with its operands
+        let illegal = [
+            0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd,
+        ];
+        for opcode in 0..=0xffu8 {
+            let result = decode(&mut [opcode, 0x00, 0x00].iter().copied());
+            if illegal.contains(&opcode) {
+                assert_eq!(result, Err(DecodeError::UnknownOpcode(opcode)));
+            } else {
+                assert!(result.is_ok(), "0x{:02x}", opcode);
+            }
+        }
+        assert_eq!(
+            decode(&mut [0xc3u8, 0x50].iter().copied()),
+            Err(DecodeError::EndOfStream)
+        );
+    }
+
+    #[test]
+    fn decode_targets() {
+        let target = |bytes: &[u8]| decode(&mut bytes.iter().copied()).unwrap().target(0x102);
+        assert_eq!(target(&[0x18, 0xfe]), Some(Target::Jump(0x100)));
+        assert_eq!(target(&[0x38, 0x10]), Some(Target::Jump(0x112)));
+        assert_eq!(target(&[0xc3, 0x50, 0x01]), Some(Target::Jump(0x150)));
+        assert_eq!(target(&[0xdc, 0x00, 0x40]), Some(Target::Call(0x4000)));
+        assert_eq!(target(&[0xef]), Some(Target::Call(0x28)));
+        assert_eq!(target(&[0xe9]), None);
+        assert_eq!(target(&[0xc9]), None);
+        assert!(!decode(&mut [0xe9u8].iter().copied())
+            .unwrap()
+            .falls_through());
+        assert!(decode(&mut [0xc0u8].iter().copied())
+            .unwrap()
+            .falls_through());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(&Opcode::Nop), Some(vec![0x00]));
+        assert_eq!(
+            encode(&Opcode::Ld(Slot::r16(SP), Slot::Data16(0xfffe))),
+            Some(vec![0x31, 0xfe, 0xff])
+        );
+        assert_eq!(
+            encode(&Opcode::Ld(Slot::Addr8(0x40), Slot::r8(A))),
+            Some(vec![0xe0, 0x40])
+        );
+        assert_eq!(
+            encode(&Opcode::ComplBit(7, Slot::r8(H))),
+            Some(vec![0xcb, 0x7c])
+        );
+        assert_eq!(encode(&Opcode::Rst(0x28)), Some(vec![0xef]));
+        // No such instructions
+        assert_eq!(
+            encode(&Opcode::Ld(Slot::addr(AddrRegister::BC), Slot::r8(B))),
+            None
+        );
+        assert_eq!(encode(&Opcode::Rst(0x01)), None);
+        assert_eq!(encode(&Opcode::Push(SP)), None);
+        assert_eq!(encode(&Opcode::SetBit(8, Slot::r8(A))), None);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(4096))]
+
+        #[test]
+        fn test_decode_encode(bytes in any::<[u8; 3]>()) {
+            let mut data = bytes.iter().copied();
+            if let Ok(opcode) = decode(&mut data) {
+                let len = bytes.len() - data.len();
+                prop_assert_eq!(len, instruction_len(bytes[0]) as usize);
+                let mut encoded = encode(&opcode).unwrap();
+                // The byte after STOP is ignored
+                if opcode == Opcode::Stop {
+                    encoded[1] = bytes[1];
+                }
+                prop_assert_eq!(&encoded[..], &bytes[..len]);
+                prop_assert_eq!(decode(&mut encoded.into_iter()), Ok(opcode));
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! ROM banks: offsets in the file and addresses seen by the CPU. Bank 0 is
+//! always mapped at 0x0000, the MBC maps one of the others at 0x4000.
+
+pub const BANK_SIZE: usize = 0x4000;
+// Header byte giving the ROM size, 32 KiB << value
+const ROM_SIZE: usize = 0x148;
+
+/// Number of ROM banks of the disassembled data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Banks {
+    count: usize,
+}
+
+impl Banks {
+    /// Banks declared in the header, or covering the data if it is larger
+    pub fn from_rom(data: &[u8]) -> Self {
+        let from_size = data.len().div_ceil(BANK_SIZE).max(2);
+        let from_header = match data.get(ROM_SIZE) {
+            Some(&size) if size <= 8 => 2 << size,
+            _ => 0,
+        };
+        Self {
+            count: from_size.max(from_header),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// More banks than the 2 mapped without a MBC
+    pub fn banked(&self) -> bool {
+        self.count > 2
+    }
+
+    /// Address of the byte at `offset` when its bank is mapped
+    pub fn cpu_address(offset: usize) -> u16 {
+        if offset < BANK_SIZE {
+            offset as u16
+        } else {
+            (BANK_SIZE + offset % BANK_SIZE) as u16
+        }
+    }
+
+    /// Offset of the byte at `address` for the code at offset `from`, which
+    /// runs with its own bank mapped. None outside of the ROM, or for the
+    /// switchable bank seen from bank 0 of a banked ROM as the mapped bank
+    /// is not known.
+    pub fn resolve(&self, from: usize, address: u16) -> Option<usize> {
+        let address = address as usize;
+        match address {
+            0..BANK_SIZE => Some(address),
+            BANK_SIZE..0x8000 if from >= BANK_SIZE => {
+                Some(from / BANK_SIZE * BANK_SIZE + address - BANK_SIZE)
+            }
+            BANK_SIZE..0x8000 if !self.banked() => Some(address),
+            _ => None,
+        }
+    }
+
+    /// First offset after the code starting at `offset` can run without
+    /// switching banks
+    pub fn end(&self, offset: usize) -> usize {
+        if self.banked() {
+            (offset / BANK_SIZE + 1) * BANK_SIZE
+        } else {
+            self.count * BANK_SIZE
+        }
+    }
+
+    /// `0x1234`, or `bank:address` with a banked ROM: `02:4abc`
+    pub fn format(&self, offset: usize) -> String {
+        if self.banked() {
+            format!(
+                "{:02x}:{:04x}",
+                offset / BANK_SIZE,
+                Self::cpu_address(offset)
+            )
+        } else {
+            format!("0x{:04x}", offset)
+        }
+    }
+}
+
+impl Default for Banks {
+    fn default() -> Self {
+        Self { count: 2 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banks() {
+        let mut rom = vec![0; 0x8000];
+        assert!(!Banks::from_rom(&rom).banked());
+        assert_eq!(Banks::from_rom(&rom).resolve(0x100, 0x4000), Some(0x4000));
+        assert_eq!(Banks::from_rom(&rom).format(0x4abc), "0x4abc");
+
+        // 128 KiB declared in the header
+        rom[ROM_SIZE] = 2;
+        let banks = Banks::from_rom(&rom);
+        assert_eq!(banks.count(), 8);
+        assert_eq!(banks.resolve(0x100, 0x0150), Some(0x150));
+        assert_eq!(banks.resolve(0x100, 0x4000), None);
+        assert_eq!(banks.resolve(0xabcd, 0x4000), Some(0x8000));
+        assert_eq!(banks.resolve(0xabcd, 0x0038), Some(0x38));
+        assert_eq!(banks.resolve(0xabcd, 0xc000), None);
+        assert_eq!(banks.end(0xabcd), 0xc000);
+        assert_eq!(banks.format(0xabcd), "02:6bcd");
+        assert_eq!(Banks::cpu_address(0xabcd), 0x6bcd);
+    }
+}
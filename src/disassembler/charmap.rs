@@ -0,0 +1,150 @@
+//! Characters of the strings found in the data. Games often use their own
+//! encoding instead of ASCII, a file gives the characters of the bytes with
+//! one `byte characters` per line, the characters being those of the
+//! following bytes:
+//! ```text
+//! 0x80 ABCDEFGHIJKLMNOPQRSTUVWXYZ
+//! 0xe0 0123456789
+//! ```
+
+use std::{
+    collections::BTreeMap, error::Error, fmt::Display, fs::File, io::Read, num::ParseIntError,
+    path::Path,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Charmap {
+    chars: BTreeMap<u8, char>,
+}
+
+impl Charmap {
+    /// Table without any character
+    pub fn empty() -> Self {
+        Self {
+            chars: BTreeMap::new(),
+        }
+    }
+
+    /// The printable ASCII characters
+    pub fn ascii() -> Self {
+        Self {
+            chars: (0x20..=0x7e).map(|byte| (byte, byte as char)).collect(),
+        }
+    }
+
+    /// Add the characters of `data` to the table
+    pub fn parse(&mut self, data: &str) -> Result<(), CharmapError> {
+        for line in data
+            .lines()
+            .map(|l| l.trim_start().trim_end_matches('\r'))
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            // The characters may be spaces, only the first one is a separator
+            let (byte, chars) = line.split_once(' ').ok_or(CharmapError::MissingChar)?;
+            if chars.is_empty() {
+                return Err(CharmapError::MissingChar);
+            }
+            let first = u8::from_str_radix(byte.trim_start_matches("0x"), 16)?;
+            for (index, c) in chars.chars().enumerate() {
+                let byte = u8::try_from(first as usize + index)
+                    .map_err(|_| CharmapError::TooManyChars(first))?;
+                self.chars.insert(byte, c);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse_file(&mut self, path: impl AsRef<Path>) -> Result<(), CharmapError> {
+        let mut tmp = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut tmp))?;
+        self.parse(&tmp)
+    }
+
+    pub fn get(&self, byte: u8) -> Option<char> {
+        self.chars.get(&byte).copied()
+    }
+
+    /// Text of `bytes`, None if one of them is not in the table
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        bytes.iter().map(|&byte| self.get(byte)).collect()
+    }
+}
+
+impl Default for Charmap {
+    fn default() -> Self {
+        Self::ascii()
+    }
+}
+
+#[derive(Debug)]
+pub enum CharmapError {
+    MissingChar,
+    TooManyChars(u8),
+    IOError(std::io::Error),
+    ParseError(ParseIntError),
+}
+
+impl Error for CharmapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::MissingChar => None,
+            Self::TooManyChars(_) => None,
+            Self::IOError(err) => Some(err),
+            Self::ParseError(err) => Some(err),
+        }
+    }
+}
+
+impl From<ParseIntError> for CharmapError {
+    fn from(value: ParseIntError) -> Self {
+        CharmapError::ParseError(value)
+    }
+}
+
+impl From<std::io::Error> for CharmapError {
+    fn from(value: std::io::Error) -> Self {
+        CharmapError::IOError(value)
+    }
+}
+
+impl Display for CharmapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingChar => f.write_str("Missing character"),
+            Self::TooManyChars(byte) => {
+                write!(f, "The characters from 0x{:02x} go past 0xff", byte)
+            }
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+            Self::ParseError(err) => write!(f, "Parse error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charmap() {
+        assert_eq!(Charmap::ascii().decode(b"Hi!"), Some("Hi!".to_string()));
+        assert_eq!(Charmap::ascii().decode(&[0x48, 0x0a]), None);
+
+        let mut charmap = Charmap::empty();
+        charmap
+            .parse("# letters\n0x80 ABC\n7f  \n0xfe .!\n")
+            .unwrap();
+        assert_eq!(
+            charmap.decode(&[0x80, 0x82, 0x7f, 0x81, 0xff]),
+            Some("AC B!".to_string())
+        );
+        assert_eq!(charmap.get(0x41), None);
+        assert!(matches!(
+            charmap.parse("0xff ab"),
+            Err(CharmapError::TooManyChars(0xff))
+        ));
+        assert!(matches!(
+            charmap.parse("0x80"),
+            Err(CharmapError::MissingChar)
+        ));
+    }
+}
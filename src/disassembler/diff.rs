@@ -0,0 +1,218 @@
+//! Differences between the disassemblies of two versions of a ROM. The lines
+//! are compared without their address and the destinations of the jumps and
+//! calls are replaced by their label, so that the code moved by an insertion
+//! still matches:
+//! ```text
+//! @@ 0x0150 0x0152 @@
+//!  0x0150 ld a, $01
+//! -0x0152 call init
+//! +0x0154 call update
+//! +0x0157 call init
+//!  0x0155 ret
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use similar::{capture_diff_slices, group_diff_ops, Algorithm, DiffTag};
+
+use crate::annotations::{Annotation, DataKind};
+use crate::decoder::Opcode;
+use crate::error::Error;
+
+use super::rgbds::mnemonic;
+use super::{disassemble, Banks, Listing, Options};
+
+// Unchanged lines shown around the differences
+const CONTEXT: usize = 3;
+
+// Lines of a disassembly, with the address of each one
+#[derive(Default)]
+struct Lines {
+    banks: Banks,
+    // Address of the next instruction, for the sections and labels
+    next: usize,
+    addresses: Vec<usize>,
+    texts: Vec<String>,
+}
+
+impl Lines {
+    fn push(&mut self, address: usize, text: String) {
+        self.addresses.push(address);
+        self.texts.push(text);
+    }
+}
+
+fn suffix(goto: Option<&str>, comment: Option<&str>) -> String {
+    let mut text = String::new();
+    if let Some(goto) = goto {
+        text += &format!(" -> {}", goto);
+    }
+    if let Some(comment) = comment {
+        text += &format!(" ; {}", comment);
+    }
+    text
+}
+
+impl Listing for Lines {
+    fn start(&mut self, banks: Banks) -> io::Result<()> {
+        self.banks = banks;
+        Ok(())
+    }
+
+    fn section(&mut self, name: &str) -> io::Result<()> {
+        self.push(self.next, format!("-- {} --", name));
+        Ok(())
+    }
+
+    fn label(&mut self, address: usize, name: &str, _sources: &[usize]) -> io::Result<()> {
+        self.push(address, format!("{}:", name));
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        opcode: &Opcode,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        // rgbds syntax, with the label instead of the address of the destination
+        let mut text = mnemonic(opcode);
+        let mut goto = goto;
+        if let Some(label) = goto.filter(|_| opcode.target(0).is_some()) {
+            if let Some(index) = text.rfind(" $") {
+                text = format!("{} {}", &text[..index], label);
+                goto = None;
+            }
+        }
+        self.push(address, text + suffix(goto, comment).as_str());
+        self.next = address + bytes.len();
+        Ok(())
+    }
+
+    fn data(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        kind: DataKind,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.push(
+            address,
+            format!("{} {}{}", kind.name(), hex.join(" "), suffix(goto, comment)),
+        );
+        self.next = address + bytes.len();
+        Ok(())
+    }
+}
+
+/// Write the lines that differ between the disassemblies of `old` and `new`
+/// to `out`, with a few unchanged lines around them. Returns the number of
+/// differing lines.
+pub fn diff(
+    old: Vec<u8>,
+    old_annotations: &BTreeMap<usize, Vec<Annotation>>,
+    new: Vec<u8>,
+    new_annotations: &BTreeMap<usize, Vec<Annotation>>,
+    options: &Options,
+    out: &mut impl Write,
+) -> Result<usize, Error> {
+    let mut old_lines = Lines::default();
+    disassemble(old, old_annotations, options, &mut old_lines)?;
+    let mut new_lines = Lines::default();
+    disassemble(new, new_annotations, options, &mut new_lines)?;
+
+    let ops = capture_diff_slices(Algorithm::Myers, &old_lines.texts, &new_lines.texts);
+    let mut count = 0;
+    for group in group_diff_ops(ops, CONTEXT) {
+        let (_, old_range, new_range) = group[0].as_tag_tuple();
+        writeln!(
+            out,
+            "@@ {} {} @@",
+            position(&old_lines, old_range.start),
+            position(&new_lines, new_range.start)
+        )?;
+        for op in group {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                for index in old_range {
+                    write_line(out, ' ', &old_lines, index)?;
+                }
+                continue;
+            }
+            count += old_range.len() + new_range.len();
+            for index in old_range {
+                write_line(out, '-', &old_lines, index)?;
+            }
+            for index in new_range {
+                write_line(out, '+', &new_lines, index)?;
+            }
+        }
+    }
+    Ok(count)
+}
+
+// Address of a line, or of the end of the listing
+fn position(lines: &Lines, index: usize) -> String {
+    lines
+        .banks
+        .format(lines.addresses.get(index).copied().unwrap_or(lines.next))
+}
+
+fn write_line(out: &mut impl Write, prefix: char, lines: &Lines, index: usize) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}{} {}",
+        prefix,
+        lines.banks.format(lines.addresses[index]),
+        lines.texts[index]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        // A nop inserted before the call, which moves its destination
+        let old = vec![0x3e, 0x01, 0xcd, 0x06, 0x00, 0xc9, 0xaf, 0xc9];
+        let new = vec![0x3e, 0x01, 0x00, 0xcd, 0x07, 0x00, 0xc9, 0xaf, 0xc9];
+        let old_annotations = Annotation::parse("0x6 L init").unwrap();
+        let new_annotations = Annotation::parse("0x7 L init").unwrap();
+        let mut out = Vec::new();
+        let count = diff(
+            old,
+            &old_annotations,
+            new,
+            &new_annotations,
+            &Options::default(),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@@ 0x0000 0x0000 @@\n 0x0000 ld a, $01\n+0x0002 nop\n 0x0002 call init\n\
+             \x200x0005 ret\n 0x0006 init:\n"
+        );
+
+        let mut out = Vec::new();
+        let rom = vec![0x00, 0xc9];
+        let empty = BTreeMap::new();
+        let count = diff(
+            rom.clone(),
+            &empty,
+            rom,
+            &empty,
+            &Options::default(),
+            &mut out,
+        );
+        assert_eq!(count.unwrap(), 0);
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use std::io::{self, Write};
+
+use crate::annotations::DataKind;
+use crate::decoder::Opcode;
+
+use super::rgbds::mnemonic;
+use super::Listing;
+
+/// One JSON object per line for each instruction and data region, with the
+/// section and the label preceding it, and the addresses of the jumps and
+/// calls to this label when the cross references are enabled:
+/// ```text
+/// {"address":7,"bytes":"20fb","type":"instruction","mnemonic":"jr","operands":["nz","@ - 3"],"section":null,"label":"loop","xrefs":[2],"target":"0x4","comment":null}
+/// {"address":9,"bytes":"0102","type":"data","kind":"db","section":null,"label":null,"xrefs":[],"target":null,"comment":"table"}
+/// ```
+/// The instructions use the rgbds syntax.
+pub struct JsonListing<W: Write> {
+    out: W,
+    section: Option<String>,
+    label: Option<String>,
+    xrefs: Vec<usize>,
+}
+
+impl<W: Write> JsonListing<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            section: None,
+            label: None,
+            xrefs: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn record(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        fields: &str,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let xrefs: Vec<String> = self
+            .xrefs
+            .drain(..)
+            .map(|source| source.to_string())
+            .collect();
+        writeln!(
+            self.out,
+            "{{\"address\":{},\"bytes\":\"{}\",{},\"section\":{},\"label\":{},\"xrefs\":[{}],\"target\":{},\"comment\":{}}}",
+            address,
+            hex,
+            fields,
+            optional(self.section.take().as_deref()),
+            optional(self.label.take().as_deref()),
+            xrefs.join(","),
+            optional(goto),
+            optional(comment)
+        )
+    }
+}
+
+impl<W: Write> Listing for JsonListing<W> {
+    fn section(&mut self, name: &str) -> io::Result<()> {
+        self.section = Some(name.to_string());
+        Ok(())
+    }
+
+    fn label(&mut self, _address: usize, name: &str, sources: &[usize]) -> io::Result<()> {
+        self.label = Some(name.to_string());
+        self.xrefs = sources.to_vec();
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        opcode: &Opcode,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let text = mnemonic(opcode);
+        let (name, operands) = text.split_once(' ').unwrap_or((&text, ""));
+        let operands: Vec<String> = operands
+            .split(", ")
+            .filter(|operand| !operand.is_empty())
+            .map(string)
+            .collect();
+        let fields = format!(
+            "\"type\":\"instruction\",\"mnemonic\":{},\"operands\":[{}]",
+            string(name),
+            operands.join(",")
+        );
+        self.record(address, bytes, &fields, goto, comment)
+    }
+
+    fn data(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        kind: DataKind,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let fields = format!("\"type\":\"data\",\"kind\":\"{}\"", kind.name());
+        self.record(address, bytes, &fields, goto, comment)
+    }
+}
+
+/// JSON string literal
+fn string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => literal += "\\\"",
+            '\\' => literal += "\\\\",
+            '\n' => literal += "\\n",
+            c if (c as u32) < 0x20 => literal += &format!("\\u{:04x}", c as u32),
+            c => literal.push(c),
+        }
+    }
+    literal + "\""
+}
+
+fn optional(value: Option<&str>) -> String {
+    value.map(string).unwrap_or_else(|| "null".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_listing() {
+        let mut listing = JsonListing::new(Vec::new());
+        listing.section("Main").unwrap();
+        listing.label(7, "loop", &[2]).unwrap();
+        listing
+            .instruction(
+                7,
+                &[0x20, 0xfb],
+                &Opcode::JumpRNZMemOffset(-5),
+                Some("0x4"),
+                None,
+            )
+            .unwrap();
+        listing
+            .data(9, &[0x01], DataKind::Text, None, Some("say \"hi\""))
+            .unwrap();
+        listing
+            .instruction(10, &[0xc9], &Opcode::Ret, None, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(listing.into_inner()).unwrap(),
+            "{\"address\":7,\"bytes\":\"20fb\",\"type\":\"instruction\",\"mnemonic\":\"jr\",\
+             \"operands\":[\"nz\",\"@ - 3\"],\"section\":\"Main\",\"label\":\"loop\",\
+             \"xrefs\":[2],\"target\":\"0x4\",\"comment\":null}\n\
+             {\"address\":9,\"bytes\":\"01\",\"type\":\"data\",\"kind\":\"str\",\"section\":null,\
+             \"label\":null,\"xrefs\":[],\"target\":null,\"comment\":\"say \\\"hi\\\"\"}\n\
+             {\"address\":10,\"bytes\":\"c9\",\"type\":\"instruction\",\"mnemonic\":\"ret\",\
+             \"operands\":[],\"section\":null,\"label\":null,\"xrefs\":[],\"target\":null,\"comment\":null}\n"
+        );
+    }
+}
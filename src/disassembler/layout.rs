@@ -0,0 +1,157 @@
+/// Widths of the columns of an aligned text listing. The values are padded
+/// to the width of their column, a longer value pushes the next columns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub address: usize,
+    pub bytes: usize,
+    pub mnemonic: usize,
+    pub operands: usize,
+    /// The destinations of the jumps, 0 hides them
+    pub goto: usize,
+    /// Longer comments continue on the next lines, 0 never wraps them
+    pub comment: usize,
+}
+
+// Before the address, to set the lines apart from the labels
+const INDENT: &str = "    ";
+
+impl Layout {
+    /// Widths separated by commas, in the order of the fields
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let widths = text
+            .split(',')
+            .map(|width| width.trim().parse::<usize>().map_err(|err| err.to_string()))
+            .collect::<Result<Vec<usize>, String>>()?;
+        match widths[..] {
+            [address, bytes, mnemonic, operands, goto, comment] => Ok(Self {
+                address,
+                bytes,
+                mnemonic,
+                operands,
+                goto,
+                comment,
+            }),
+            _ => Err(format!("Expected 6 widths, got {}", widths.len())),
+        }
+    }
+
+    /// Spaces before the mnemonic column
+    pub fn indent(&self, bytes: bool) -> String {
+        let bytes = if bytes { self.bytes + 1 } else { 0 };
+        " ".repeat(INDENT.len() + self.address + 1 + bytes)
+    }
+
+    /// One line, or several when the comment is wrapped. `bytes` is None
+    /// when the bytes column is hidden.
+    pub fn line(
+        &self,
+        address: &str,
+        bytes: Option<&[u8]>,
+        mnemonic: &str,
+        operands: &str,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> String {
+        let mut line = format!("{}{:<width$} ", INDENT, address, width = self.address);
+        if let Some(bytes) = bytes {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            line += &format!("{:<width$} ", hex.join(" "), width = self.bytes);
+        }
+        line += &format!(
+            "{:<mnemonic$} {:<operands$} ",
+            mnemonic,
+            operands,
+            mnemonic = self.mnemonic,
+            operands = self.operands
+        );
+        if self.goto > 0 {
+            let goto = goto.map(|goto| format!("-> {}", goto)).unwrap_or_default();
+            line += &format!("{:<width$} ", goto, width = self.goto);
+        }
+
+        let Some(comment) = comment else {
+            return line.trim_end().to_string() + "\n";
+        };
+        let column = line.len();
+        let mut text = line;
+        for (index, part) in self.wrap(comment).iter().enumerate() {
+            if index > 0 {
+                text += &" ".repeat(column);
+            }
+            text += &format!("; {}\n", part);
+        }
+        text
+    }
+
+    // Words of the comment grouped in lines of the comment width
+    fn wrap(&self, comment: &str) -> Vec<String> {
+        if self.comment == 0 {
+            return vec![comment.to_string()];
+        }
+        let mut lines: Vec<String> = Vec::new();
+        for word in comment.split(' ') {
+            match lines.last_mut() {
+                Some(line) if line.len() + 1 + word.len() <= self.comment => {
+                    *line += " ";
+                    *line += word;
+                }
+                _ => lines.push(word.to_string()),
+            }
+        }
+        lines
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            address: 7,
+            bytes: 8,
+            mnemonic: 6,
+            operands: 16,
+            goto: 16,
+            comment: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout::default();
+        assert_eq!(
+            layout.line("0x0150", None, "LD", "A 0x01", None, None),
+            "    0x0150  LD     A 0x01\n"
+        );
+        assert_eq!(
+            layout.line(
+                "0x0152",
+                Some(&[0x18, 0xfe]),
+                "Jump(-2)",
+                "",
+                Some("loop"),
+                Some("forever")
+            ),
+            "    0x0152  18 fe    Jump(-2)                  -> loop          ; forever\n"
+        );
+
+        let layout = Layout::parse("6, 0, 2, 4, 0, 10").unwrap();
+        assert_eq!(
+            layout.line(
+                "0x0150",
+                None,
+                "LD",
+                "A B",
+                Some("hidden"),
+                Some("a long comment, wrapped")
+            ),
+            "    0x0150 LD A B  ; a long\n                   ; comment,\n                   ; wrapped\n"
+        );
+        assert_eq!(layout.indent(false).len(), 11);
+        assert!(Layout::parse("1,2,3").is_err());
+        assert!(Layout::parse("1,2,3,4,5,x").is_err());
+    }
+}
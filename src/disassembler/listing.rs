@@ -0,0 +1,351 @@
+use std::io::{self, Write};
+
+use crate::annotations::DataKind;
+use crate::decoder::Opcode;
+use crate::header::Header;
+use crate::tiles::{decode_tile, TILE_SIZE};
+
+use super::{Banks, CrossReference, Layout};
+
+// Values per line of the data regions
+const BYTES_PER_LINE: usize = 8;
+const WORDS_PER_LINE: usize = 8;
+const CHARS_PER_LINE: usize = 32;
+// Width of the bytes of the longest instruction, `cd 50 01`
+const BYTES_COLUMN: usize = 8;
+// Characters drawing the 4 colors of the tiles, from the lightest
+const TILE_CHARS: [char; 4] = ['.', ':', 'o', '#'];
+
+/// Receives the lines of the disassembly, in order. Implemented by each
+/// output format.
+pub trait Listing {
+    /// Called first, with the banks of the disassembled ROM
+    fn start(&mut self, _banks: Banks) -> io::Result<()> {
+        Ok(())
+    }
+    /// Cartridge header, before the listing when it is enabled
+    fn header(&mut self, _header: &Header) -> io::Result<()> {
+        Ok(())
+    }
+    /// Start of a section, from an S annotation
+    fn section(&mut self, name: &str) -> io::Result<()>;
+    /// Name of the instruction or data at `address`, with the addresses of
+    /// the jumps and calls to it when the cross references are enabled
+    fn label(&mut self, address: usize, name: &str, sources: &[usize]) -> io::Result<()>;
+    /// A decoded instruction. `goto` is the destination of the jump, either
+    /// from a G annotation or computed for the relative jumps.
+    fn instruction(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        opcode: &Opcode,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()>;
+    /// Bytes left undecoded because of a D annotation
+    fn data(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        kind: DataKind,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()>;
+    /// Called last when the cross references are enabled, with every label
+    fn cross_references(&mut self, _labels: &[CrossReference]) -> io::Result<()> {
+        Ok(())
+    }
+    /// Called at the end with the number of bytes that were not instructions
+    fn unknown_opcodes(&mut self, _count: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Human readable listing
+pub struct TextListing<W: Write> {
+    out: W,
+    // Print the bytes of each instruction in a column after the address
+    bytes: bool,
+    banks: Banks,
+    // Aligned columns instead of the values separated by spaces
+    layout: Option<Layout>,
+}
+
+impl<W: Write> TextListing<W> {
+    pub fn new(out: W, bytes: bool) -> Self {
+        Self {
+            out,
+            bytes,
+            banks: Banks::default(),
+            layout: None,
+        }
+    }
+
+    /// Align the columns of the instructions and data
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // Column of the bytes, blank for the data to keep the mnemonics aligned
+    fn bytes_column(&self, bytes: &[u8]) -> String {
+        if !self.bytes {
+            return String::new();
+        }
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{:<width$} ", hex.join(" "), width = BYTES_COLUMN)
+    }
+}
+
+fn goto_text(goto: Option<&str>) -> String {
+    goto.map(|goto| format!("-> {}", goto)).unwrap_or_default()
+}
+
+/// `0x0213, 0x0450`
+pub(super) fn address_list(banks: Banks, addresses: &[usize]) -> String {
+    let addresses: Vec<String> = addresses
+        .iter()
+        .map(|&address| banks.format(address))
+        .collect();
+    addresses.join(", ")
+}
+
+/// ` ; xref: 0x0213, 0x0450`, empty without any source
+pub(super) fn xref_text(banks: Banks, sources: &[usize]) -> String {
+    if sources.is_empty() {
+        String::new()
+    } else {
+        format!(" ; xref: {}", address_list(banks, sources))
+    }
+}
+
+fn comment_text(comment: Option<&str>) -> String {
+    comment
+        .map(|comment| format!(" ; {}", comment))
+        .unwrap_or_default()
+}
+
+impl<W: Write> Listing for TextListing<W> {
+    fn start(&mut self, banks: Banks) -> io::Result<()> {
+        self.banks = banks;
+        Ok(())
+    }
+
+    fn header(&mut self, header: &Header) -> io::Result<()> {
+        writeln!(self.out, "-- Cartridge header --\n{}\n", header)
+    }
+
+    fn section(&mut self, name: &str) -> io::Result<()> {
+        writeln!(self.out, "\n-- {} --", name)
+    }
+
+    fn label(&mut self, _address: usize, name: &str, sources: &[usize]) -> io::Result<()> {
+        writeln!(self.out, "{}:{}", name, xref_text(self.banks, sources))
+    }
+
+    fn instruction(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        opcode: &Opcode,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        if let Some(layout) = &self.layout {
+            let text = opcode.to_string();
+            let (mnemonic, operands) = text.split_once(' ').unwrap_or((&text, ""));
+            let line = layout.line(
+                &self.banks.format(address),
+                Some(bytes).filter(|_| self.bytes),
+                mnemonic,
+                operands,
+                goto,
+                comment,
+            );
+            return write!(self.out, "{}", line);
+        }
+        writeln!(
+            self.out,
+            "    {} {}{} {} {}",
+            self.banks.format(address),
+            self.bytes_column(bytes),
+            opcode,
+            goto_text(goto),
+            comment_text(comment)
+        )
+    }
+
+    fn data(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        kind: DataKind,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        // One line per group of values, the goto and the comment are on the first one
+        let column = self.bytes_column(&[]);
+        let indent = match &self.layout {
+            Some(layout) => layout.indent(self.bytes),
+            None => " ".repeat(self.banks.format(address).len() + 5 + column.len()),
+        };
+        let lines: Vec<(usize, String)> = match kind {
+            DataKind::Skip if self.layout.is_some() => {
+                vec![(
+                    bytes.len(),
+                    format!("skip to {}", self.banks.format(address + bytes.len() - 1)),
+                )]
+            }
+            DataKind::Skip => {
+                return writeln!(
+                    self.out,
+                    "Skip {}-{} {} {}",
+                    self.banks.format(address),
+                    self.banks.format(address + bytes.len() - 1),
+                    goto_text(goto),
+                    comment_text(comment)
+                )
+            }
+            DataKind::Bytes => bytes
+                .chunks(BYTES_PER_LINE)
+                .map(|chunk| (chunk.len(), format!("db {}", hex_list(chunk, 1))))
+                .collect(),
+            DataKind::Words => bytes
+                .chunks(WORDS_PER_LINE * 2)
+                .map(|chunk| {
+                    // An odd byte at the end is shown as a byte
+                    let (words, byte) = chunk.split_at(chunk.len() & !1);
+                    let mut text = format!("dw {}", hex_list(words, 2));
+                    if !byte.is_empty() {
+                        text += &format!(" db {}", hex_list(byte, 1));
+                    }
+                    (chunk.len(), text)
+                })
+                .collect(),
+            DataKind::Text => bytes
+                .chunks(CHARS_PER_LINE)
+                .map(|chunk| (chunk.len(), format!("str \"{}\"", escape(chunk))))
+                .collect(),
+            DataKind::Tiles => bytes
+                .chunks(TILE_SIZE)
+                .map(|chunk| {
+                    let rows = decode_tile(chunk).map(|row| {
+                        format!(
+                            "\n{}{}",
+                            indent,
+                            row.map(|color| TILE_CHARS[color as usize])
+                                .iter()
+                                .collect::<String>()
+                        )
+                    });
+                    (chunk.len(), format!("tile{}", rows.concat()))
+                })
+                .collect(),
+        };
+
+        if let Some(layout) = self.layout {
+            let mut offset = 0;
+            for (index, (len, text)) in lines.into_iter().enumerate() {
+                let (head, tail) = text.split_once('\n').unwrap_or((&text, ""));
+                let (mnemonic, operands) = head.split_once(' ').unwrap_or((head, ""));
+                let (goto, comment) = if index == 0 {
+                    (goto, comment)
+                } else {
+                    (None, None)
+                };
+                let line = layout.line(
+                    &self.banks.format(address + offset),
+                    Some(&[][..]).filter(|_| self.bytes),
+                    mnemonic,
+                    operands,
+                    goto,
+                    comment,
+                );
+                write!(self.out, "{}", line)?;
+                if !tail.is_empty() {
+                    writeln!(self.out, "{}", tail)?;
+                }
+                offset += len;
+            }
+            return Ok(());
+        }
+
+        let mut offset = 0;
+        for (index, (len, text)) in lines.into_iter().enumerate() {
+            if index == 0 {
+                // After the first line of the tiles
+                let (head, tail) = text.split_once('\n').unwrap_or((&text, ""));
+                write!(
+                    self.out,
+                    "    {} {}{} {} {}",
+                    self.banks.format(address),
+                    column,
+                    head,
+                    goto_text(goto),
+                    comment_text(comment)
+                )?;
+                if tail.is_empty() {
+                    writeln!(self.out)?;
+                } else {
+                    writeln!(self.out, "\n{}", tail)?;
+                }
+            } else {
+                writeln!(
+                    self.out,
+                    "    {} {}{}",
+                    self.banks.format(address + offset),
+                    column,
+                    text
+                )?;
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    fn unknown_opcodes(&mut self, count: usize) -> io::Result<()> {
+        writeln!(self.out, "\n{} unknown opcodes", count)
+    }
+
+    fn cross_references(&mut self, labels: &[CrossReference]) -> io::Result<()> {
+        writeln!(self.out, "\n-- Cross references --")?;
+        for label in labels {
+            writeln!(
+                self.out,
+                "{} {}: {}",
+                self.banks.format(label.address),
+                label.name,
+                address_list(self.banks, &label.sources)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Comma separated values of `size` bytes, little endian
+fn hex_list(bytes: &[u8], size: usize) -> String {
+    let values: Vec<String> = bytes
+        .chunks(size)
+        .map(|value| match value {
+            [low, high] => format!("0x{:02x}{:02x}", high, low),
+            _ => format!("0x{:02x}", value[0]),
+        })
+        .collect();
+    values.join(", ")
+}
+
+/// Printable ASCII, the other bytes as `\xNN`
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'"' | b'\\' => format!("\\{}", byte as char),
+            0x20..=0x7e => (byte as char).to_string(),
+            _ => format!("\\x{:02x}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,911 @@
+//! Disassembler guided by annotations, decoding the data linearly or following
+//! the control flow from the entry points
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::annotations::{Annotation, DataKind, Purpose};
+use crate::decoder::{decode, DecodeError, Opcode, Target};
+use crate::error::Error;
+use crate::header::{Header, CGB_FLAG, HEADER_END, LOGO, TITLE};
+
+mod banks;
+mod charmap;
+mod diff;
+mod json;
+mod layout;
+mod listing;
+mod names;
+mod rgbds;
+
+pub use banks::{Banks, BANK_SIZE};
+pub use charmap::{Charmap, CharmapError};
+pub use diff::diff;
+pub use json::JsonListing;
+pub use layout::Layout;
+pub use listing::{Listing, TextListing};
+pub use names::{IoNames, NamesError};
+pub use rgbds::RgbdsListing;
+
+/// Options of the disassembly
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Offset of the first byte to disassemble
+    pub start: usize,
+    /// Offset after the last byte to disassemble, the end of the data by default
+    pub end: Option<usize>,
+    /// Add `loc_` and `sub_` labels to the destinations of the jumps and calls
+    pub auto_labels: bool,
+    /// List the jumps and calls to each label next to it, and after the
+    /// listing
+    pub xrefs: bool,
+    pub mode: Mode,
+    /// Names of the IO registers and of the other addresses in 0xff00-0xffff,
+    /// added to the comments of the instructions accessing them
+    pub names: IoNames,
+    /// Show the runs of characters of the table in the undecoded bytes as
+    /// strings, with the annotation to add in their comment
+    pub strings: Option<Charmap>,
+    /// Print the cartridge header before the listing, and show the logo and
+    /// the fields after it as data unless they are annotated
+    pub header: bool,
+}
+
+// Note of the bytes that are not an instruction
+const UNKNOWN_OPCODE: &str = "unknown opcode";
+
+// Shortest run of characters shown as a string
+const MIN_STRING_LEN: usize = 5;
+
+/// A label and the addresses of the jumps and calls to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossReference {
+    pub address: usize,
+    pub name: String,
+    pub sources: Vec<usize>,
+}
+
+/// Entry point and interrupt vectors of a cartridge
+pub const ENTRY_POINTS: [usize; 6] = [0x100, 0x40, 0x48, 0x50, 0x58, 0x60];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Mode {
+    /// Decode every byte that is not annotated as data
+    #[default]
+    Linear,
+    /// Only decode the code reached from these addresses by following the
+    /// jumps, calls and fallthroughs. The other bytes are data.
+    Recursive(Vec<usize>),
+}
+
+// A decoded region of the data
+enum Item {
+    Instruction {
+        address: usize,
+        len: usize,
+        opcode: Opcode,
+    },
+    Data {
+        address: usize,
+        len: usize,
+        kind: DataKind,
+        // Replaces the comment of the annotations
+        note: Option<String>,
+    },
+}
+
+impl Item {
+    fn address(&self) -> usize {
+        match self {
+            Item::Instruction { address, .. } | Item::Data { address, .. } => *address,
+        }
+    }
+}
+
+/// Decode the region of `data` selected by `options`, sending the
+/// instructions and the annotated regions to `listing`.
+pub fn disassemble(
+    data: Vec<u8>,
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+    options: &Options,
+    listing: &mut impl Listing,
+) -> Result<(), Error> {
+    let end = options.end.unwrap_or(data.len()).min(data.len());
+    if options.start >= end {
+        return Ok(());
+    }
+    let banks = Banks::from_rom(&data);
+    listing.start(banks)?;
+    let annotations = match Header::parse(&data).filter(|_| options.header) {
+        Some(header) => {
+            if options.start < HEADER_END {
+                listing.header(&header)?;
+            }
+            header_annotations(annotations)
+        }
+        None => Cow::Borrowed(annotations),
+    };
+    let annotations = annotations.as_ref();
+    let items = match &options.mode {
+        Mode::Linear => sweep(&data, annotations, banks, options.start, end)?,
+        Mode::Recursive(entry_points) => {
+            descend(&data, annotations, banks, options.start, end, entry_points)?
+        }
+    };
+    let items = match &options.strings {
+        Some(charmap) => strings(&data, items, annotations, charmap),
+        None => items,
+    };
+    let references = references(&items, banks);
+    let mut labels = if options.auto_labels {
+        auto_labels(&items, annotations, &references, banks)
+    } else {
+        BTreeMap::new()
+    };
+    // The L annotations name the destinations even without the automatic labels
+    for (&address, annotations) in annotations {
+        for annotation in annotations {
+            if annotation.purpose == Purpose::Label {
+                labels.insert(address, annotation.value.clone());
+            }
+        }
+    }
+
+    let empty_vec = vec![];
+    let mut cross_references = Vec::new();
+    for item in &items {
+        let address = item.address();
+        let mut comment = None;
+        let mut goto = None;
+        let mut label = labels.get(&address).map(String::as_str);
+        for annotation in annotations.get(&address).unwrap_or(&empty_vec) {
+            match annotation.purpose {
+                Purpose::Comment => comment = Some(annotation.value.as_str()),
+                Purpose::Goto => goto = Some(annotation.value.clone()),
+                Purpose::Label => label = Some(annotation.value.as_str()),
+                Purpose::Section => listing.section(&annotation.value)?,
+                Purpose::Data => (),
+            }
+        }
+        if let Some(l) = label {
+            let sources: Vec<usize> = match references.get(&address) {
+                Some(sources) if options.xrefs => {
+                    sources.iter().map(|(source, _)| *source).collect()
+                }
+                _ => Vec::new(),
+            };
+            listing.label(address, l, &sources)?;
+            cross_references.push(CrossReference {
+                address,
+                name: l.to_string(),
+                sources,
+            });
+        }
+
+        match item {
+            Item::Data {
+                len, kind, note, ..
+            } => listing.data(
+                address,
+                &data[address..address + len],
+                *kind,
+                goto.as_deref().filter(|_| note.is_none()),
+                note.as_deref().or(comment),
+            )?,
+            Item::Instruction { len, opcode, .. } => {
+                // Display the destination of a jump if it has not been provided
+                if goto.is_none() {
+                    goto = match destination(banks, address, *len, opcode) {
+                        Some((_, target)) if labels.contains_key(&target) => {
+                            Some(labels[&target].clone())
+                        }
+                        Some((Target::Jump(_), target)) if relative_jump(opcode) => {
+                            Some(if banks.banked() {
+                                banks.format(target)
+                            } else {
+                                format!("0x{:x}", target)
+                            })
+                        }
+                        _ => None,
+                    };
+                }
+                let comment = match (options.names.accessed_by(opcode), comment) {
+                    (Some(name), Some(comment)) => Some(format!("{} {}", name, comment)),
+                    (name, comment) => name.or(comment).map(str::to_string),
+                };
+                listing.instruction(
+                    address,
+                    &data[address..address + len],
+                    opcode,
+                    goto.as_deref(),
+                    comment.as_deref(),
+                )?;
+            }
+        }
+    }
+    if options.xrefs {
+        listing.cross_references(&cross_references)?;
+    }
+    let unknown = items
+        .iter()
+        .filter(
+            |item| matches!(item, Item::Data { note: Some(note), .. } if note == UNKNOWN_OPCODE),
+        )
+        .count();
+    if unknown > 0 {
+        listing.unknown_opcodes(unknown)?;
+    }
+    Ok(())
+}
+
+// The logo, title and other fields of the header as data, when none of them
+// is annotated as data
+fn header_annotations(
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+) -> Cow<'_, BTreeMap<usize, Vec<Annotation>>> {
+    let annotated = annotations
+        .range(LOGO..HEADER_END)
+        .flat_map(|(_, annotations)| annotations)
+        .any(|annotation| annotation.purpose == Purpose::Data);
+    if annotated {
+        return Cow::Borrowed(annotations);
+    }
+    let mut annotations = annotations.clone();
+    let fields = [
+        (LOGO, format!("db 0x{:x}", TITLE - LOGO), "Nintendo logo"),
+        (TITLE, format!("str 0x{:x}", CGB_FLAG - TITLE), "title"),
+        (
+            CGB_FLAG,
+            format!("db 0x{:x}", HEADER_END - CGB_FLAG),
+            "header",
+        ),
+    ];
+    for (location, value, comment) in fields {
+        let field = annotations.entry(location).or_default();
+        if !field.iter().any(|a| a.purpose == Purpose::Comment) {
+            field.push(Annotation {
+                location,
+                purpose: Purpose::Comment,
+                value: comment.to_string(),
+            });
+        }
+        field.push(Annotation {
+            location,
+            purpose: Purpose::Data,
+            value,
+        });
+    }
+    Cow::Owned(annotations)
+}
+
+// Kind and offset of the destination of the instruction at `address`
+fn destination(
+    banks: Banks,
+    address: usize,
+    len: usize,
+    opcode: &Opcode,
+) -> Option<(Target, usize)> {
+    let next = Banks::cpu_address(address).wrapping_add(len as u16);
+    let target = opcode.target(next)?;
+    let (Target::Jump(cpu_address) | Target::Call(cpu_address)) = target;
+    Some((target, banks.resolve(address, cpu_address)?))
+}
+
+// Split the data between `start` and `end` in instructions and annotated data
+fn sweep(
+    data: &[u8],
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+    banks: Banks,
+    start: usize,
+    end: usize,
+) -> Result<Vec<Item>, Error> {
+    let mut items = Vec::new();
+    let mut address = start;
+    while address < end {
+        let mut data_kind = None;
+        for annotation in annotations.get(&address).into_iter().flatten() {
+            if annotation.purpose == Purpose::Data {
+                data_kind = Some(
+                    annotation
+                        .data()
+                        .map_err(|err| Error::from(err).at(address))?,
+                );
+            }
+        }
+
+        match data_kind {
+            Some((kind, len)) if len > 0 => {
+                let len = len.min(end - address);
+                items.push(Item::Data {
+                    address,
+                    len,
+                    kind,
+                    note: None,
+                });
+                address += len;
+            }
+            _ => {
+                // An instruction cannot continue in the next bank
+                let limit = end.min(banks.end(address));
+                let mut bytes = data[address..limit].iter().copied();
+                match decode(&mut bytes) {
+                    Ok(opcode) => {
+                        let len = limit - address - bytes.len();
+                        items.push(Item::Instruction {
+                            address,
+                            len,
+                            opcode,
+                        });
+                        address += len;
+                    }
+                    // The last instruction is cut by the end of the region
+                    Err(DecodeError::EndOfStream) => {
+                        items.push(Item::Data {
+                            address,
+                            len: limit - address,
+                            kind: DataKind::Bytes,
+                            note: Some("truncated instruction".to_string()),
+                        });
+                        address = limit;
+                    }
+                    // Decoding starts again at the next byte
+                    Err(_) => {
+                        items.push(Item::Data {
+                            address,
+                            len: 1,
+                            kind: DataKind::Bytes,
+                            note: Some(UNKNOWN_OPCODE.to_string()),
+                        });
+                        address += 1;
+                    }
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+// Decode the code reached from `entry_points`, the unreached bytes between
+// `start` and `end` become data
+fn descend(
+    data: &[u8],
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+    banks: Banks,
+    start: usize,
+    end: usize,
+    entry_points: &[usize],
+) -> Result<Vec<Item>, Error> {
+    let mut items = BTreeMap::new();
+    // Bytes already part of an item, offset from `start`
+    let mut covered = vec![false; end - start];
+
+    for (&address, annotations) in annotations.range(start..end) {
+        for annotation in annotations {
+            if annotation.purpose == Purpose::Data {
+                let (kind, len) = annotation
+                    .data()
+                    .map_err(|err| Error::from(err).at(address))?;
+                let len = len.min(end - address);
+                if len > 0 {
+                    covered[address - start..address - start + len].fill(true);
+                    items.insert(
+                        address,
+                        Item::Data {
+                            address,
+                            len,
+                            kind,
+                            note: None,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    let mut pending = entry_points.to_vec();
+    while let Some(mut address) = pending.pop() {
+        // Follow the instructions until the flow leaves the region or the
+        // bank, meets decoded bytes or stops
+        while (start..end).contains(&address) && !covered[address - start] {
+            let limit = end.min(banks.end(address));
+            let mut bytes = data[address..limit].iter().copied();
+            let Ok(opcode) = decode(&mut bytes) else {
+                break;
+            };
+            let len = limit - address - bytes.len();
+            let range = address - start..address - start + len;
+            if covered[range.clone()].contains(&true) {
+                break;
+            }
+            covered[range].fill(true);
+
+            if let Some((_, target)) = destination(banks, address, len, &opcode) {
+                pending.push(target);
+            }
+            let falls_through = opcode.falls_through();
+            items.insert(
+                address,
+                Item::Instruction {
+                    address,
+                    len,
+                    opcode,
+                },
+            );
+            if !falls_through {
+                break;
+            }
+            address += len;
+        }
+    }
+
+    // Group the bytes that were not reached
+    let mut address = start;
+    while address < end {
+        if covered[address - start] {
+            address += 1;
+            continue;
+        }
+        let len = covered[address - start..]
+            .iter()
+            .position(|&covered| covered)
+            .unwrap_or(end - address);
+        items.insert(
+            address,
+            Item::Data {
+                address,
+                len,
+                kind: DataKind::Bytes,
+                note: None,
+            },
+        );
+        address += len;
+    }
+    Ok(items.into_values().collect())
+}
+
+// Split the bytes left undecoded, without a D annotation, in strings of at
+// least MIN_STRING_LEN characters of `charmap` and in the bytes between them
+fn strings(
+    data: &[u8],
+    items: Vec<Item>,
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+    charmap: &Charmap,
+) -> Vec<Item> {
+    let mut split = Vec::new();
+    for item in items {
+        let (start, len) = match item {
+            Item::Data {
+                address,
+                len,
+                kind: DataKind::Bytes,
+                note: None,
+            } if !annotations
+                .get(&address)
+                .into_iter()
+                .flatten()
+                .any(|annotation| annotation.purpose == Purpose::Data) =>
+            {
+                (address, len)
+            }
+            item => {
+                split.push(item);
+                continue;
+            }
+        };
+
+        let mut bytes_start = start;
+        let mut address = start;
+        while address < start + len {
+            let run = data[address..start + len]
+                .iter()
+                .take_while(|&&byte| charmap.get(byte).is_some())
+                .count();
+            if run < MIN_STRING_LEN {
+                address += run.max(1);
+                continue;
+            }
+            if bytes_start < address {
+                split.push(Item::Data {
+                    address: bytes_start,
+                    len: address - bytes_start,
+                    kind: DataKind::Bytes,
+                    note: None,
+                });
+            }
+            // The bytes are shown as ASCII, the text is added if it differs
+            let bytes = &data[address..address + run];
+            let mut note = format!("0x{:04x} D str 0x{:x}", address, run);
+            let text = charmap.decode(bytes).unwrap_or_default();
+            if text.as_bytes() != bytes {
+                note += &format!(" \"{}\"", text);
+            }
+            split.push(Item::Data {
+                address,
+                len: run,
+                kind: DataKind::Text,
+                note: Some(note),
+            });
+            address += run;
+            bytes_start = address;
+        }
+        if bytes_start < start + len {
+            split.push(Item::Data {
+                address: bytes_start,
+                len: start + len - bytes_start,
+                kind: DataKind::Bytes,
+                note: None,
+            });
+        }
+    }
+    split
+}
+
+// Addresses of the jumps and calls to each destination, with their kind
+fn references(items: &[Item], banks: Banks) -> BTreeMap<usize, Vec<(usize, Target)>> {
+    let mut references: BTreeMap<usize, Vec<(usize, Target)>> = BTreeMap::new();
+    for item in items {
+        if let Item::Instruction {
+            address,
+            len,
+            opcode,
+        } = item
+        {
+            if let Some((kind, target)) = destination(banks, *address, *len, opcode) {
+                references.entry(target).or_default().push((*address, kind));
+            }
+        }
+    }
+    references
+}
+
+// Names of the destinations of the jumps and calls starting an item, unless
+// they already have a label. A called address is a `sub_` even if it is also
+// the destination of a jump.
+fn auto_labels(
+    items: &[Item],
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+    references: &BTreeMap<usize, Vec<(usize, Target)>>,
+    banks: Banks,
+) -> BTreeMap<usize, String> {
+    let mut labels = BTreeMap::new();
+    for item in items {
+        let address = item.address();
+        let Some(sources) = references.get(&address) else {
+            continue;
+        };
+        let prefix = if sources
+            .iter()
+            .any(|(_, kind)| matches!(kind, Target::Call(_)))
+        {
+            "sub"
+        } else {
+            "loc"
+        };
+        let explicit = annotations
+            .get(&address)
+            .into_iter()
+            .flatten()
+            .find(|annotation| annotation.purpose == Purpose::Label);
+        let name = match explicit {
+            Some(annotation) => annotation.value.clone(),
+            None => format!("{}_{}", prefix, banks.format(address).replace(':', "_")),
+        };
+        labels.insert(address, name);
+    }
+    labels
+}
+
+fn relative_jump(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Jump(_)
+            | Opcode::JumpRZMemOffset(_)
+            | Opcode::JumpRNZMemOffset(_)
+            | Opcode::JumpRCMemOffset(_)
+            | Opcode::JumpRNCMemOffset(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(data: &[u8], annotations: &str, options: &Options) -> String {
+        let annotations = Annotation::parse(annotations).unwrap();
+        let mut listing = TextListing::new(Vec::new(), false);
+        disassemble(data.to_vec(), &annotations, options, &mut listing).unwrap();
+        String::from_utf8(listing.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_disassemble_listing() {
+        let output = run(
+            &[0x00, 0x18, 0xfe, 0x12, 0x34, 0xaf],
+            "0x0 S Start\n0x1 L loop\n0x1 C forever\n0x3 D 0x2",
+            &Options::default(),
+        );
+        assert_eq!(
+            output,
+            "\n-- Start --\n    0x0000 Nop  \nloop:\n    0x0001 Jump(-2) -> loop  ; forever\n\
+             Skip 0x0003-0x0004  \n    0x0005 Xor(A, A)  \n"
+        );
+        // Name of the IO register, before the comment
+        assert_eq!(
+            run(&[0xe0, 0x40, 0xf0, 0x44], "0x2 C wait", &Options::default()),
+            "    0x0000 LD (0x40) A   ; LCDC\n    0x0002 LD A (0x44)   ; LY wait\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let data = [0x00, 0x3e, 0x12, 0xaf, 0x00, 0xc3, 0x50];
+        let options = Options {
+            start: 1,
+            end: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&data, "", &options),
+            "    0x0001 LD A 0x12  \n    0x0003 Xor(A, A)  \n"
+        );
+        // The jump at the end is missing its last byte
+        assert_eq!(
+            run(
+                &data[..7],
+                "",
+                &Options {
+                    start: 4,
+                    end: None,
+                    ..Default::default()
+                }
+            ),
+            "    0x0004 Nop  \n    0x0005 db 0xc3, 0x50   ; truncated instruction\n"
+        );
+        assert_eq!(
+            run(
+                &data,
+                "",
+                &Options {
+                    start: 8,
+                    end: None,
+                    ..Default::default()
+                }
+            ),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_disassemble_typed_data() {
+        let mut data = b"\x01\x02\x03\x04\x05Hi\"\n".to_vec();
+        data.extend([0x3c, 0x7e, 0x42, 0x42]);
+        let output = run(
+            &data,
+            "0x0 D db 0x2\n0x2 D dw 0x3\n0x2 C table\n0x5 D str 0x4\n0x9 D tiles 0x4",
+            &Options::default(),
+        );
+        assert_eq!(
+            output,
+            "    0x0000 db 0x01, 0x02  \n\
+             \x20   0x0002 dw 0x0403 db 0x05   ; table\n\
+             \x20   0x0005 str \"Hi\\\"\\x0a\"  \n\
+             \x20   0x0009 tile  \n\
+             \x20          .o####o.\n\
+             \x20          .#....#.\n\
+             \x20          ........\n\
+             \x20          ........\n\
+             \x20          ........\n\
+             \x20          ........\n\
+             \x20          ........\n\
+             \x20          ........\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_auto_labels() {
+        // call 0x0006, jr nz back to the call, jp 0x0006, ret
+        let data = [0x00, 0xcd, 0x06, 0x00, 0x20, 0xfb, 0xc3, 0x06, 0x00, 0xc9];
+        let options = Options {
+            auto_labels: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&data, "", &options),
+            "    0x0000 Nop  \nloc_0x0001:\n    0x0001 CALL 0x0006 -> sub_0x0006 \n\
+             \x20   0x0004 JumpRNZMemOffset(-5) -> loc_0x0001 \n\
+             sub_0x0006:\n    0x0006 JumpAbs((0x0006)) -> sub_0x0006 \n    0x0009 Ret  \n"
+        );
+        // An explicit label replaces the generated name
+        assert!(run(&data, "0x6 L init", &options).contains("JumpAbs((0x0006)) -> init"));
+    }
+
+    #[test]
+    fn test_disassemble_xrefs() {
+        // call 0x0004, jr 0x0004, ret
+        let data = [0x00, 0xcd, 0x04, 0x00, 0x18, 0xfe, 0xc9];
+        let options = Options {
+            auto_labels: true,
+            xrefs: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&data, "0x0 L start", &options),
+            "start:\n    0x0000 Nop  \n    0x0001 CALL 0x0004 -> sub_0x0004 \n\
+             sub_0x0004: ; xref: 0x0001, 0x0004\n    0x0004 Jump(-2) -> sub_0x0004 \n\
+             \x20   0x0006 Ret  \n\n-- Cross references --\n0x0000 start: \n\
+             0x0004 sub_0x0004: 0x0001, 0x0004\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_recursive() {
+        // jr +2 over two bytes of data, call 0x0008, ret, ld a 0x12, ret, then
+        // an unreached byte
+        let data = [
+            0x18, 0x02, 0xff, 0xcb, 0xcd, 0x08, 0x00, 0xc9, 0x3e, 0x12, 0xc9, 0xaa,
+        ];
+        let options = Options {
+            mode: Mode::Recursive(vec![0]),
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&data, "0xb C not reached", &options),
+            "    0x0000 Jump(2) -> 0x4 \n    0x0002 db 0xff, 0xcb  \n\
+             \x20   0x0004 CALL 0x0008  \n    0x0007 Ret  \n\
+             \x20   0x0008 LD A 0x12  \n    0x000a Ret  \n    0x000b db 0xaa   ; not reached\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_unknown_opcodes() {
+        assert_eq!(
+            run(&[0x00, 0xd3, 0xe4, 0xaf], "", &Options::default()),
+            "    0x0000 Nop  \n    0x0001 db 0xd3   ; unknown opcode\n\
+             \x20   0x0002 db 0xe4   ; unknown opcode\n    0x0003 Xor(A, A)  \n\
+             \n2 unknown opcodes\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_bytes() {
+        let annotations = Annotation::parse("0x4 D db 0x9").unwrap();
+        let mut listing = TextListing::new(Vec::new(), true);
+        let data = vec![0xcd, 0x50, 0x01, 0xaf, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        disassemble(data, &annotations, &Options::default(), &mut listing).unwrap();
+        assert_eq!(
+            String::from_utf8(listing.into_inner()).unwrap(),
+            "    0x0000 cd 50 01 CALL 0x0150  \n    0x0003 af       Xor(A, A)  \n\
+             \x20   0x0004          db 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08  \n\
+             \x20   0x000c          db 0x09\n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_layout() {
+        let annotations =
+            Annotation::parse("0x0 C start\n0x2 L loop\n0x4 D tiles 0x10\n0x14 D 0x2").unwrap();
+        let mut data = vec![0x3e, 0x01, 0x18, 0xfe];
+        data.extend([0xff, 0x00].repeat(8));
+        data.extend([0, 0]);
+        let layout = Layout {
+            goto: 0,
+            ..Default::default()
+        };
+        let mut listing = TextListing::new(Vec::new(), true).with_layout(layout);
+        disassemble(data, &annotations, &Options::default(), &mut listing).unwrap();
+        let tile = format!("{}::::::::\n", " ".repeat(21)).repeat(8);
+        assert_eq!(
+            String::from_utf8(listing.into_inner()).unwrap(),
+            format!(
+                "    0x0000  3e 01    LD     A 0x01           ; start\nloop:\n\
+                 \x20   0x0002  18 fe    Jump(-2)\n    0x0004           tile\n{}\
+                 \x20   0x0014           skip   to 0x0015\n",
+                tile
+            )
+        );
+    }
+
+    #[test]
+    fn test_disassemble_strings() {
+        // ret, then "Hello" and "Hi" between unreached bytes
+        let mut data = vec![0xc9, 0x00];
+        data.extend_from_slice(b"Hello\x00Hi\x00");
+        let options = Options {
+            mode: Mode::Recursive(vec![0]),
+            strings: Some(Charmap::ascii()),
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&data, "", &options),
+            "    0x0000 Ret  \n    0x0001 db 0x00  \n\
+             \x20   0x0002 str \"Hello\"   ; 0x0002 D str 0x5\n\
+             \x20   0x0007 db 0x00, 0x48, 0x69, 0x00  \n"
+        );
+
+        let mut charmap = Charmap::empty();
+        charmap.parse("0x80 HELO").unwrap();
+        let data = [0xc9, 0x80, 0x81, 0x82, 0x82, 0x83];
+        let options = Options {
+            strings: Some(charmap),
+            ..options
+        };
+        assert!(run(&data, "", &options)
+            .contains("0x0001 str \"\\x80\\x81\\x82\\x82\\x83\"   ; 0x0001 D str 0x5 \"HELLO\""));
+    }
+
+    #[test]
+    fn test_disassemble_banks() {
+        // 64 KiB: a jump from bank 2 to itself and a call to bank 0, then a
+        // call split between banks 2 and 3
+        let mut data = vec![0; 4 * BANK_SIZE];
+        data[0x8000..0x8006].copy_from_slice(&[0x18, 0xfe, 0xcd, 0x38, 0x00, 0xc9]);
+        data[0xbffe..0xc001].copy_from_slice(&[0xcd, 0x00, 0x40]);
+        let listing = |start, end| {
+            run(
+                &data,
+                "",
+                &Options {
+                    start,
+                    end: Some(end),
+                    auto_labels: true,
+                    ..Default::default()
+                },
+            )
+        };
+        assert_eq!(
+            listing(0x8000, 0x8006),
+            "loc_02_4000:\n    02:4000 Jump(-2) -> loc_02_4000 \n\
+             \x20   02:4002 CALL 0x0038  \n    02:4005 Ret  \n"
+        );
+        assert_eq!(
+            listing(0xbffe, 0xc001),
+            "    02:7ffe db 0xcd, 0x00   ; truncated instruction\n    03:4000 LD B B  \n"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_header() {
+        let mut data = vec![0; HEADER_END];
+        data[0x100..LOGO].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        data[LOGO..LOGO + 4].copy_from_slice(&[0xce, 0xed, 0x66, 0x66]);
+        data[TITLE..TITLE + 4].copy_from_slice(b"TEST");
+        let options = Options {
+            start: 0x100,
+            header: true,
+            ..Default::default()
+        };
+        let output = run(&data, "0x134 C name", &options);
+        assert!(output.starts_with("-- Cartridge header --\nTitle            TEST\n"));
+        assert!(output.contains(
+            "\n    0x0100 Nop  \n    0x0101 JumpAbs((0x0150))  \n    0x0104 db 0xce, 0xed, 0x66, 0x66"
+        ));
+        assert!(output.contains("   ; Nintendo logo\n"));
+        assert!(output.contains("\n    0x0134 str \"TEST"));
+        assert!(output.contains("   ; name\n    0x0143 db 0x00"));
+
+        // Annotated header
+        let output = run(
+            &data,
+            "0x104 D 0x30",
+            &Options {
+                start: 0x101,
+                ..options.clone()
+            },
+        );
+        assert!(output.contains("\nSkip 0x0104-0x0133  \n    0x0134 "));
+        assert!(!output.contains("str"));
+        // No header when starting after it
+        data.push(0xc9);
+        assert_eq!(
+            run(
+                &data,
+                "",
+                &Options {
+                    start: HEADER_END,
+                    ..options
+                }
+            ),
+            "    0x0150 Ret  \n"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Names of the addresses in 0xff00-0xffff, shown next to the instructions
+//! reading or writing them. The built-in table has the IO registers, a file
+//! adds or replaces names with one `address name` per line:
+//! ```text
+//! 0xff80 hFrameCounter
+//! 0xff81 hJoypad
+//! ```
+
+use std::{
+    collections::BTreeMap, error::Error, fmt::Display, fs::File, io::Read, num::ParseIntError,
+    path::Path,
+};
+
+use crate::apu::{
+    NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR31, NR32, NR33, NR34, NR41, NR42,
+    NR43, NR44, NR50, NR51, NR52,
+};
+use crate::decoder::Opcode;
+use crate::joypad::P1;
+use crate::mmu::{DMA, IE, IF};
+use crate::ppu::{
+    BCPD, BCPS, BGP, LCDC, LY, LYC, OBP0, OBP1, OCPD, OCPS, SCX, SCY, STAT, VBK, WX, WY,
+};
+use crate::slots::Slot;
+use crate::timer::{DIV, TAC, TIMA, TMA};
+
+// Registers without a constant in the emulator
+const OTHER_REGISTERS: [(u16, &str); 11] = [
+    (0xff01, "SB"),
+    (0xff02, "SC"),
+    (0xff4d, "KEY1"),
+    (0xff50, "BANK"),
+    (0xff51, "HDMA1"),
+    (0xff52, "HDMA2"),
+    (0xff53, "HDMA3"),
+    (0xff54, "HDMA4"),
+    (0xff55, "HDMA5"),
+    (0xff56, "RP"),
+    (0xff70, "SVBK"),
+];
+
+#[derive(Debug, Clone)]
+pub struct IoNames {
+    names: BTreeMap<u16, String>,
+}
+
+impl IoNames {
+    /// Table without any name
+    pub fn empty() -> Self {
+        Self {
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// Add the names of `data` to the table
+    pub fn parse(&mut self, data: &str) -> Result<(), NamesError> {
+        for line in data
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let (address, name) = line
+                .split_once(char::is_whitespace)
+                .ok_or(NamesError::MissingName)?;
+            let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)?;
+            if address < 0xff00 {
+                return Err(NamesError::InvalidAddress(address));
+            }
+            self.names.insert(address, name.trim().to_string());
+        }
+        Ok(())
+    }
+
+    pub fn parse_file(&mut self, path: impl AsRef<Path>) -> Result<(), NamesError> {
+        let mut tmp = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut tmp))?;
+        self.parse(&tmp)
+    }
+
+    pub fn get(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Address of a name, case insensitive
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names
+            .iter()
+            .find(|(_, other)| other.eq_ignore_ascii_case(name))
+            .map(|(&address, _)| address)
+    }
+
+    /// Name of the address in 0xff00-0xffff read or written by `opcode`
+    pub fn accessed_by(&self, opcode: &Opcode) -> Option<&str> {
+        let address = match opcode {
+            Opcode::Ld(Slot::Addr8(addr), _) | Opcode::Ld(_, Slot::Addr8(addr)) => {
+                0xff00 + *addr as u16
+            }
+            Opcode::Ld(Slot::Addr16(addr), _) | Opcode::Ld(_, Slot::Addr16(addr)) => *addr,
+            _ => return None,
+        };
+        self.get(address)
+    }
+}
+
+impl Default for IoNames {
+    /// The IO registers
+    fn default() -> Self {
+        let registers = [
+            (P1, "P1"),
+            (DIV, "DIV"),
+            (TIMA, "TIMA"),
+            (TMA, "TMA"),
+            (TAC, "TAC"),
+            (IF, "IF"),
+            (NR10, "NR10"),
+            (NR11, "NR11"),
+            (NR12, "NR12"),
+            (NR13, "NR13"),
+            (NR14, "NR14"),
+            (NR21, "NR21"),
+            (NR22, "NR22"),
+            (NR23, "NR23"),
+            (NR24, "NR24"),
+            (NR30, "NR30"),
+            (NR31, "NR31"),
+            (NR32, "NR32"),
+            (NR33, "NR33"),
+            (NR34, "NR34"),
+            (NR41, "NR41"),
+            (NR42, "NR42"),
+            (NR43, "NR43"),
+            (NR44, "NR44"),
+            (NR50, "NR50"),
+            (NR51, "NR51"),
+            (NR52, "NR52"),
+            (LCDC, "LCDC"),
+            (STAT, "STAT"),
+            (SCY, "SCY"),
+            (SCX, "SCX"),
+            (LY, "LY"),
+            (LYC, "LYC"),
+            (DMA, "DMA"),
+            (BGP, "BGP"),
+            (OBP0, "OBP0"),
+            (OBP1, "OBP1"),
+            (WY, "WY"),
+            (WX, "WX"),
+            (VBK, "VBK"),
+            (BCPS, "BCPS"),
+            (BCPD, "BCPD"),
+            (OCPS, "OCPS"),
+            (OCPD, "OCPD"),
+            (IE, "IE"),
+        ];
+        let mut names = Self::empty();
+        for (address, name) in registers.into_iter().chain(OTHER_REGISTERS) {
+            names.names.insert(address, name.to_string());
+        }
+        // Wave pattern RAM
+        for index in 0..16 {
+            names
+                .names
+                .insert(0xff30 + index, format!("WAVE{:X}", index));
+        }
+        names
+    }
+}
+
+#[derive(Debug)]
+pub enum NamesError {
+    MissingName,
+    InvalidAddress(u16),
+    IOError(std::io::Error),
+    ParseError(ParseIntError),
+}
+
+impl Error for NamesError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::MissingName => None,
+            Self::InvalidAddress(_) => None,
+            Self::IOError(err) => Some(err),
+            Self::ParseError(err) => Some(err),
+        }
+    }
+}
+
+impl From<ParseIntError> for NamesError {
+    fn from(value: ParseIntError) -> Self {
+        NamesError::ParseError(value)
+    }
+}
+
+impl From<std::io::Error> for NamesError {
+    fn from(value: std::io::Error) -> Self {
+        NamesError::IOError(value)
+    }
+}
+
+impl Display for NamesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingName => f.write_str("Missing name"),
+            Self::InvalidAddress(address) => {
+                write!(f, "Address 0x{:04x} is not in 0xff00-0xffff", address)
+            }
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+            Self::ParseError(err) => write!(f, "Parse error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode;
+
+    #[test]
+    fn test_io_names() {
+        let mut names = IoNames::default();
+        let accessed = |names: &IoNames, bytes: &[u8]| {
+            names
+                .accessed_by(&decode(&mut bytes.iter().copied()).unwrap())
+                .map(str::to_string)
+        };
+        assert_eq!(accessed(&names, &[0xe0, 0x40]), Some("LCDC".to_string()));
+        assert_eq!(
+            accessed(&names, &[0xfa, 0x26, 0xff]),
+            Some("NR52".to_string())
+        );
+        assert_eq!(accessed(&names, &[0xf0, 0x80]), None);
+        assert_eq!(accessed(&names, &[0x3e, 0x40]), None);
+
+        names.parse("# HRAM\n0xff80 hFrame\nff40 rLCDC").unwrap();
+        assert_eq!(accessed(&names, &[0xf0, 0x80]), Some("hFrame".to_string()));
+        assert_eq!(names.get(0xff40), Some("rLCDC"));
+        assert_eq!(names.get(0xff3a), Some("WAVEA"));
+        assert_eq!(names.address("rlcdc"), Some(0xff40));
+        assert_eq!(names.address("LCDC"), None);
+        assert!(matches!(
+            names.parse("0xc000 wram"),
+            Err(NamesError::InvalidAddress(0xc000))
+        ));
+        assert!(matches!(
+            names.parse("0xff81"),
+            Err(NamesError::MissingName)
+        ));
+    }
+}
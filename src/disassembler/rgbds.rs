@@ -0,0 +1,369 @@
+use std::io::{self, Write};
+
+use crate::annotations::DataKind;
+use crate::decoder::{Condition, Opcode};
+use crate::header::Header;
+use crate::slots::{AddrRegister, Register16, Slot};
+
+use super::listing::{address_list, xref_text};
+use super::{Banks, CrossReference, Listing, BANK_SIZE};
+
+const BYTES_PER_LINE: usize = 8;
+
+/// Source file for rgbasm, assembling back to the disassembled bytes
+pub struct RgbdsListing<W: Write> {
+    out: W,
+    // Bank of the current SECTION
+    bank: Option<usize>,
+    banks: Banks,
+}
+
+impl<W: Write> RgbdsListing<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            bank: None,
+            banks: Banks::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // Open a new SECTION when `address` is in another bank than the previous line
+    fn place(&mut self, address: usize) -> io::Result<()> {
+        let bank = address / BANK_SIZE;
+        if self.bank == Some(bank) {
+            return Ok(());
+        }
+        self.bank = Some(bank);
+        if bank == 0 {
+            writeln!(self.out, "\nSECTION \"ROM0\", ROM0[${:04x}]", address)
+        } else {
+            writeln!(
+                self.out,
+                "\nSECTION \"ROM Bank ${:02x}\", ROMX[${:04x}], BANK[${:02x}]",
+                bank,
+                BANK_SIZE + address % BANK_SIZE,
+                bank
+            )
+        }
+    }
+
+    // `db` lines, split at the bank boundaries
+    fn bytes(&mut self, address: usize, bytes: &[u8], comment: &str) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let current = address + offset;
+            self.place(current)?;
+            let bank_end = (current / BANK_SIZE + 1) * BANK_SIZE - address;
+            let len = BYTES_PER_LINE
+                .min(bytes.len() - offset)
+                .min(bank_end - offset);
+            let values: Vec<String> = bytes[offset..offset + len]
+                .iter()
+                .map(|byte| format!("${:02x}", byte))
+                .collect();
+            let comment = if offset == 0 { comment } else { "" };
+            writeln!(self.out, "    db {}{}", values.join(", "), comment)?;
+            offset += len;
+        }
+        Ok(())
+    }
+}
+
+fn comment_text(goto: Option<&str>, comment: Option<&str>) -> String {
+    let text: Vec<String> = goto
+        .map(|goto| format!("-> {}", goto))
+        .into_iter()
+        .chain(comment.map(str::to_string))
+        .collect();
+    if text.is_empty() {
+        String::new()
+    } else {
+        format!(" ; {}", text.join(" "))
+    }
+}
+
+impl<W: Write> Listing for RgbdsListing<W> {
+    fn start(&mut self, banks: Banks) -> io::Result<()> {
+        self.banks = banks;
+        Ok(())
+    }
+
+    fn section(&mut self, name: &str) -> io::Result<()> {
+        writeln!(self.out, "\n; -- {} --", name)
+    }
+
+    fn label(&mut self, address: usize, name: &str, sources: &[usize]) -> io::Result<()> {
+        self.place(address)?;
+        writeln!(
+            self.out,
+            "{}:{}",
+            label_name(name),
+            xref_text(self.banks, sources)
+        )
+    }
+
+    fn instruction(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        opcode: &Opcode,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let comment = comment_text(goto, comment);
+        // rgbasm always assembles STOP with a 0 and cannot split an
+        // instruction between two sections
+        let crosses_bank = address / BANK_SIZE != (address + bytes.len() - 1) / BANK_SIZE;
+        if crosses_bank || (*opcode == Opcode::Stop && bytes != [0x10, 0x00]) {
+            return self.bytes(address, bytes, &comment);
+        }
+        self.place(address)?;
+        writeln!(self.out, "    {}{}", mnemonic(opcode), comment)
+    }
+
+    fn data(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        kind: DataKind,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let comment = comment_text(goto, comment);
+        let words_fit = address / BANK_SIZE == (address + bytes.len() - 1) / BANK_SIZE;
+        if kind != DataKind::Words || !words_fit {
+            return self.bytes(address, bytes, &comment);
+        }
+        self.place(address)?;
+        let (words, byte) = bytes.split_at(bytes.len() & !1);
+        for (index, chunk) in words.chunks(BYTES_PER_LINE * 2).enumerate() {
+            let values: Vec<String> = chunk
+                .chunks(2)
+                .map(|word| format!("${:02x}{:02x}", word[1], word[0]))
+                .collect();
+            let comment = if index == 0 { comment.as_str() } else { "" };
+            writeln!(self.out, "    dw {}{}", values.join(", "), comment)?;
+        }
+        let comment = if words.is_empty() {
+            comment.as_str()
+        } else {
+            ""
+        };
+        self.bytes(address + words.len(), byte, comment)
+    }
+
+    fn header(&mut self, header: &Header) -> io::Result<()> {
+        for line in header.to_string().lines() {
+            writeln!(self.out, "; {}", line)?;
+        }
+        Ok(())
+    }
+
+    fn unknown_opcodes(&mut self, count: usize) -> io::Result<()> {
+        writeln!(self.out, "\n; {} unknown opcodes", count)
+    }
+
+    // Comments at the end of the file, rgbasm ignores them
+    fn cross_references(&mut self, labels: &[CrossReference]) -> io::Result<()> {
+        writeln!(self.out, "\n; -- Cross references --")?;
+        for label in labels {
+            writeln!(
+                self.out,
+                "; {}: {}",
+                label_name(&label.name),
+                address_list(self.banks, &label.sources)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Label usable by rgbasm: the characters other than letters, digits and
+/// `_` are replaced by `_`
+fn label_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+fn condition(condition: &Condition) -> &'static str {
+    match condition {
+        Condition::NZ => "nz",
+        Condition::Z => "z",
+        Condition::NC => "nc",
+        Condition::C => "c",
+    }
+}
+
+fn register16(register: &Register16) -> String {
+    format!("{:?}", register).to_lowercase()
+}
+
+/// Operand in the rgbds syntax
+fn operand(slot: &Slot) -> String {
+    match slot {
+        Slot::Register8(register) => format!("{:?}", register).to_lowercase(),
+        Slot::Register16(register) => register16(register),
+        Slot::AddrRegister(AddrRegister::C) => "[c]".to_string(),
+        Slot::AddrRegister(register) => format!("[{:?}]", register).to_lowercase(),
+        Slot::Addr8(addr) => format!("[$ff{:02x}]", addr),
+        Slot::Addr16(addr) => format!("[${:04x}]", addr),
+        Slot::Data8(value) => format!("${:02x}", value),
+        Slot::Data16(value) => format!("${:04x}", value),
+    }
+}
+
+// Destination of a jump or a call, without the brackets of the addresses
+fn destination(slot: &Slot) -> String {
+    match slot {
+        Slot::Addr16(addr) | Slot::Data16(addr) => format!("${:04x}", addr),
+        _ => operand(slot),
+    }
+}
+
+// Relative jump, from the address of the instruction
+fn relative(offset: i8) -> String {
+    let offset = offset as i16 + 2;
+    if offset < 0 {
+        format!("@ - {}", -offset)
+    } else {
+        format!("@ + {}", offset)
+    }
+}
+
+fn signed(offset: i8) -> String {
+    if offset < 0 {
+        format!("- {}", -(offset as i16))
+    } else {
+        format!("+ {}", offset)
+    }
+}
+
+/// Instruction in the rgbds syntax
+pub(super) fn mnemonic(opcode: &Opcode) -> String {
+    match opcode {
+        Opcode::Nop => "nop".to_string(),
+        Opcode::Stop => "stop".to_string(),
+        Opcode::Halt => "halt".to_string(),
+        Opcode::Di => "di".to_string(),
+        Opcode::Ei => "ei".to_string(),
+        Opcode::Ret => "ret".to_string(),
+        Opcode::Reti => "reti".to_string(),
+        Opcode::RetCond(cond) => format!("ret {}", condition(cond)),
+        // The accesses to 0xff00-0xffff with an 8-bit address
+        Opcode::Ld(to @ (Slot::Addr8(_) | Slot::AddrRegister(AddrRegister::C)), from)
+        | Opcode::Ld(to, from @ (Slot::Addr8(_) | Slot::AddrRegister(AddrRegister::C))) => {
+            format!("ldh {}, {}", operand(to), operand(from))
+        }
+        Opcode::Ld(to, from) => format!("ld {}, {}", operand(to), operand(from)),
+        Opcode::LdHlSpOffset(offset) => format!("ld hl, sp {}", signed(*offset)),
+        Opcode::Call(slot) => format!("call {}", destination(slot)),
+        Opcode::CallCond(cond, slot) => {
+            format!("call {}, {}", condition(cond), destination(slot))
+        }
+        Opcode::Rst(addr) => format!("rst ${:02x}", addr),
+        Opcode::Inc(slot) => format!("inc {}", operand(slot)),
+        Opcode::Dec(slot) => format!("dec {}", operand(slot)),
+        Opcode::Cp(to, from) => format!("cp {}, {}", operand(to), operand(from)),
+        Opcode::Add(to, from) => format!("add {}, {}", operand(to), operand(from)),
+        Opcode::AddSpOffset(offset) => format!("add sp, {}", offset),
+        Opcode::Adc(slot) => format!("adc a, {}", operand(slot)),
+        Opcode::Sub(slot) => format!("sub a, {}", operand(slot)),
+        Opcode::Sbc(slot) => format!("sbc a, {}", operand(slot)),
+        Opcode::And(slot) => format!("and a, {}", operand(slot)),
+        Opcode::Or(slot) => format!("or a, {}", operand(slot)),
+        Opcode::Xor(to, from) => format!("xor {}, {}", operand(to), operand(from)),
+        Opcode::Daa => "daa".to_string(),
+        Opcode::Cpl => "cpl".to_string(),
+        Opcode::Scf => "scf".to_string(),
+        Opcode::Ccf => "ccf".to_string(),
+        Opcode::LdToMemDec(to, from) => {
+            format!("ld [{}-], {}", register16(to), operand(&Slot::r8(*from)))
+        }
+        Opcode::LdToMemInc(to, from) => {
+            format!("ld [{}+], {}", register16(to), operand(&Slot::r8(*from)))
+        }
+        Opcode::LdFromMemDec(to, from) => {
+            format!("ld {}, [{}-]", operand(&Slot::r8(*to)), register16(from))
+        }
+        Opcode::LdFromMemInc(to, from) => {
+            format!("ld {}, [{}+]", operand(&Slot::r8(*to)), register16(from))
+        }
+        Opcode::Rlca => "rlca".to_string(),
+        Opcode::Rrca => "rrca".to_string(),
+        Opcode::Rla => "rla".to_string(),
+        Opcode::Rra => "rra".to_string(),
+        Opcode::RotLeft(slot) => format!("rl {}", operand(slot)),
+        Opcode::RotRight(slot) => format!("rr {}", operand(slot)),
+        Opcode::RotLeftCircular(slot) => format!("rlc {}", operand(slot)),
+        Opcode::RotRightCircular(slot) => format!("rrc {}", operand(slot)),
+        Opcode::ShiftLeftArith(slot) => format!("sla {}", operand(slot)),
+        Opcode::ShiftRightArith(slot) => format!("sra {}", operand(slot)),
+        Opcode::ShiftRightLogical(slot) => format!("srl {}", operand(slot)),
+        Opcode::Swap(slot) => format!("swap {}", operand(slot)),
+        Opcode::Push(register) => format!("push {}", register16(register)),
+        Opcode::Pop(register) => format!("pop {}", register16(register)),
+        Opcode::ComplBit(bit, slot) => format!("bit {}, {}", bit, operand(slot)),
+        Opcode::ResetBit(bit, slot) => format!("res {}, {}", bit, operand(slot)),
+        Opcode::SetBit(bit, slot) => format!("set {}, {}", bit, operand(slot)),
+        Opcode::Jump(offset) => format!("jr {}", relative(*offset)),
+        Opcode::JumpRZMemOffset(offset) => format!("jr z, {}", relative(*offset)),
+        Opcode::JumpRNZMemOffset(offset) => format!("jr nz, {}", relative(*offset)),
+        Opcode::JumpRCMemOffset(offset) => format!("jr c, {}", relative(*offset)),
+        Opcode::JumpRNCMemOffset(offset) => format!("jr nc, {}", relative(*offset)),
+        Opcode::JumpAbs(slot) => format!("jp {}", destination(slot)),
+        Opcode::JumpAbsCond(cond, slot) => {
+            format!("jp {}, {}", condition(cond), destination(slot))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode;
+
+    #[test]
+    fn test_rgbds_mnemonic() {
+        let mnemonic = |bytes: &[u8]| mnemonic(&decode(&mut bytes.iter().copied()).unwrap());
+        assert_eq!(mnemonic(&[0x3e, 0x12]), "ld a, $12");
+        assert_eq!(mnemonic(&[0xe0, 0x40]), "ldh [$ff40], a");
+        assert_eq!(mnemonic(&[0xf2]), "ldh a, [c]");
+        assert_eq!(mnemonic(&[0xea, 0x00, 0xc0]), "ld [$c000], a");
+        assert_eq!(mnemonic(&[0x22]), "ld [hl+], a");
+        assert_eq!(mnemonic(&[0x18, 0xfe]), "jr @ + 0");
+        assert_eq!(mnemonic(&[0x20, 0xfb]), "jr nz, @ - 3");
+        assert_eq!(mnemonic(&[0xc2, 0x50, 0x01]), "jp nz, $0150");
+        assert_eq!(mnemonic(&[0xe9]), "jp hl");
+        assert_eq!(mnemonic(&[0xf8, 0xfe]), "ld hl, sp - 2");
+        assert_eq!(mnemonic(&[0xcb, 0x7c]), "bit 7, h");
+        assert_eq!(mnemonic(&[0x96]), "sub a, [hl]");
+    }
+
+    #[test]
+    fn test_rgbds_listing() {
+        let mut listing = RgbdsListing::new(Vec::new());
+        listing.label(0x3ffe, "main loop", &[0x10]).unwrap();
+        listing
+            .instruction(0x3ffe, &[0x00], &Opcode::Nop, None, Some("wait"))
+            .unwrap();
+        listing
+            .data(0x3fff, &[0x01, 0x02, 0x03], DataKind::Words, None, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(listing.into_inner()).unwrap(),
+            "\nSECTION \"ROM0\", ROM0[$3ffe]\nmain_loop: ; xref: 0x0010\n    nop ; wait\n    db $01\n\
+             \nSECTION \"ROM Bank $01\", ROMX[$4000], BANK[$01]\n    db $02, $03\n"
+        );
+    }
+}
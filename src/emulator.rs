@@ -0,0 +1,940 @@
+//! The whole console behind a few calls, for the frontends embedding the
+//! emulator:
+//! ```no_run
+//! let rom = gb::rom::read("game.gb").unwrap();
+//! let mut emulator = gb::Emulator::new(rom, &Default::default());
+//! loop {
+//!     emulator.set_buttons(0);
+//!     emulator.run_frame();
+//!     let (pixels, samples) = (emulator.framebuffer(), emulator.audio_samples());
+//!     # break;
+//! }
+//! ```
+//! or as an iterator, for the headless tools:
+//! ```no_run
+//! # let rom = gb::rom::read("game.gb").unwrap();
+//! let mut emulator = gb::Emulator::new(rom, &Default::default());
+//! for frame in emulator.frames().take(600) {
+//!     let (pixels, samples) = (frame.framebuffer, frame.samples);
+//! }
+//! ```
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cpu::{Cpu, Registers};
+use crate::debugger::breakpoints::{Breakpoint, Hit};
+use crate::decoder::{decode, DecodeError, Opcode};
+use crate::error::Error;
+use crate::mbc::BatteryError;
+use crate::mmu::{Access, Mmu};
+use crate::model::Model;
+use crate::observer::{Frame, Observer, OwnedFrame};
+use crate::palette::DmgPalette;
+use crate::ppu::{DOTS_PER_FRAME, FRAMEBUFFER_SIZE};
+#[cfg(feature = "serde")]
+use crate::rewind::{Rewind, FRAME_RATE};
+#[cfg(feature = "serde")]
+use crate::state::{self, StateError};
+
+/// Settings of the emulated console. The emulation is deterministic: two
+/// runs with the same ROM, options and buttons give the same frames.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Hardware to emulate, from the header of the ROM by default
+    pub model: Option<Model>,
+    /// Colors of the games without CGB support
+    pub palette: DmgPalette,
+    /// Frequency of the audio samples, the default rate of the APU otherwise
+    pub sample_rate: Option<u32>,
+    /// Registers of the CPU at power on, those left by the boot ROM by default
+    pub registers: Option<Registers>,
+    /// Content of the RAM at power on
+    pub ram: RamInit,
+    /// Internal counter of the timer at power on, DIV being its upper byte
+    pub div_counter: u16,
+    /// Boot ROM run at power on, the state it leaves is set directly
+    /// otherwise
+    pub boot_rom: Option<Vec<u8>>,
+}
+
+/// Initial content of the work RAM, the high RAM and the cartridge RAM
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RamInit {
+    #[default]
+    Zero,
+    /// Pseudo-random bytes generated from a seed, like the garbage left in
+    /// the RAM of the hardware, to find the games depending on it
+    Random(u64),
+}
+
+// xorshift64*, the seed 0 is replaced as it would only generate zeros
+fn random_bytes(seed: u64) -> impl FnMut() -> u8 {
+    let mut state = if seed == 0 {
+        0x9e37_79b9_7f4a_7c15
+    } else {
+        seed
+    };
+    move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+}
+
+// Seconds since the UNIX epoch, for the clock of the battery saves
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+pub struct Emulator {
+    cpu: Cpu,
+    mmu: Mmu,
+    model: Model,
+    // Frames emulated since power on
+    frame: usize,
+    // Audio, serial output and T-cycles of the last frame
+    samples: Vec<f32>,
+    serial: Vec<u8>,
+    cycles: u32,
+    observers: Vec<Box<dyn Observer>>,
+    // Reads and writes of the last step, for the observers
+    accesses: Vec<Access>,
+    #[cfg(feature = "serde")]
+    rewind: Option<Rewind>,
+}
+
+impl Emulator {
+    /// Power on the console with the cartridge `rom`, from the state left by
+    /// the boot ROM unless it is in the options. An empty ROM reads 0xff
+    /// everywhere. The emulator does no IO, the frontends read the ROM from a
+    /// file, a download...
+    pub fn new(rom: impl Into<Vec<u8>>, options: &Options) -> Self {
+        let rom = rom.into();
+        let model = options.model.unwrap_or_else(|| Model::from_rom(&rom));
+        let mut mmu = match &options.boot_rom {
+            Some(boot_rom) => {
+                let mut mmu = Mmu::new(rom, model);
+                mmu.map_boot_rom(boot_rom.clone());
+                mmu
+            }
+            None => Mmu::after_boot(rom, model),
+        };
+        mmu.ppu_mut().set_dmg_palette(options.palette);
+        if let Some(sample_rate) = options.sample_rate {
+            mmu.apu_mut().set_sample_rate(sample_rate);
+        }
+        if let RamInit::Random(seed) = options.ram {
+            mmu.fill_ram(random_bytes(seed));
+        }
+        mmu.timer_mut().set_counter(options.div_counter);
+        let registers = match (options.registers, &options.boot_rom) {
+            (Some(registers), _) => registers,
+            // Zeroed at power on, the boot ROM starts at 0x0000
+            (None, Some(_)) => Registers::default(),
+            (None, None) => Registers::after_boot(model),
+        };
+        Self {
+            cpu: Cpu::new(registers),
+            mmu,
+            model,
+            frame: 0,
+            samples: Vec::new(),
+            serial: Vec::new(),
+            cycles: 0,
+            observers: Vec::new(),
+            accesses: Vec::new(),
+            #[cfg(feature = "serde")]
+            rewind: None,
+        }
+    }
+
+    /// Run the emulation up to the next VBlank, or for the duration of a
+    /// frame when the LCD is off. Stops early on a breakpoint of the CPU,
+    /// the next call resumes the frame.
+    pub fn run_frame(&mut self) -> Option<Hit> {
+        let frame_count = self.mmu.ppu().frame_count();
+        let mut cycles = 0;
+        while self.mmu.ppu().frame_count() == frame_count && cycles < DOTS_PER_FRAME {
+            cycles += self.step();
+            if let Some(hit) = self.cpu.hit() {
+                return Some(hit);
+            }
+        }
+        self.frame += 1;
+        self.cycles = cycles;
+        self.mmu.apply_cheats();
+        self.samples.clear();
+        self.mmu.apu_mut().drain_samples(&mut self.samples);
+        self.serial.clear();
+        self.mmu.serial_mut().drain_output(&mut self.serial);
+        #[cfg(feature = "serde")]
+        if self.rewind.as_ref().is_some_and(|r| r.due(self.frame)) {
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(self.frame, &state);
+            }
+        }
+
+        let mut observers = std::mem::take(&mut self.observers);
+        let frame = self.last_frame();
+        for observer in &mut observers {
+            observer.on_frame(&frame);
+        }
+        self.observers = observers;
+        None
+    }
+
+    /// Same as `run_frame`, failing once an illegal opcode froze the CPU
+    pub fn try_run_frame(&mut self) -> Result<Option<Hit>, Error> {
+        let hit = self.run_frame();
+        self.check()?;
+        Ok(hit)
+    }
+
+    /// The illegal opcode which froze the CPU, if any, with its address
+    pub fn check(&self) -> Result<(), Error> {
+        match self.cpu.locked() {
+            Some(pc) => {
+                let opcode = self.mmu.peek(pc);
+                Err(Error::from(DecodeError::UnknownOpcode(opcode)).emulation(pc, pc))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The frame completed by the last `run_frame`
+    pub fn last_frame(&self) -> Frame<'_> {
+        Frame {
+            number: self.frame,
+            framebuffer: self.mmu.ppu().framebuffer(),
+            samples: &self.samples,
+            serial: &self.serial,
+            buttons: self.mmu.joypad().state(),
+            cycles: self.cycles,
+            rumble: self.mmu.mbc().rumble(),
+        }
+    }
+
+    /// Run the emulation frame after frame, up to a breakpoint
+    pub fn frames(&mut self) -> Frames<'_> {
+        Frames { emulator: self }
+    }
+
+    /// Execute a single instruction, returns the T-cycles elapsed
+    pub fn step(&mut self) -> u32 {
+        if self.observers.is_empty() {
+            return self.cpu.step(&mut self.mmu);
+        }
+        // Decoded before it runs, in case it modifies itself
+        let pc = self.cpu.registers().pc;
+        let mmu = &self.mmu;
+        let opcode = decode(&mut (0..3).map(|offset| mmu.peek(pc.wrapping_add(offset))));
+
+        self.mmu.set_tracing(true);
+        let cycles = self.cpu.step(&mut self.mmu);
+        self.mmu.set_tracing(false);
+        self.mmu.drain_accesses(&mut self.accesses);
+        for observer in &mut self.observers {
+            if let (Some(executed), Ok(opcode)) = (self.cpu.executed(), &opcode) {
+                observer.on_instruction(executed, opcode);
+            }
+            for access in &self.accesses {
+                match *access {
+                    Access::Read(addr, value) => observer.on_read(addr, value),
+                    Access::Write(addr, value) => observer.on_write(addr, value),
+                }
+            }
+        }
+        self.accesses.clear();
+        cycles
+    }
+
+    /// Execute the instruction at PC, and the whole subroutine if it is a
+    /// call: a transient breakpoint stops the next `run_frame` once it
+    /// returns, or right away for the other instructions
+    pub fn step_over(&mut self) -> u32 {
+        let regs = *self.cpu.registers();
+        let mut len = 0;
+        let mmu = &self.mmu;
+        let mut bytes = std::iter::from_fn(|| {
+            let value = mmu.peek(regs.pc.wrapping_add(len));
+            len += 1;
+            Some(value)
+        });
+        let call = matches!(
+            decode(&mut bytes),
+            Ok(Opcode::Call(_) | Opcode::CallCond(_, _) | Opcode::Rst(_))
+        );
+        let cycles = self.step();
+        if self.cpu.hit().is_some() {
+            return cycles;
+        }
+        let after = self.cpu.registers();
+        // The call is taken when it pushed the return address
+        let breakpoint = if call
+            && self.cpu.executed() == Some(regs.pc)
+            && after.sp == regs.sp.wrapping_sub(2)
+        {
+            Breakpoint::Return {
+                pc: regs.pc.wrapping_add(len),
+                sp: regs.sp,
+            }
+        } else {
+            Breakpoint::Return {
+                pc: after.pc,
+                sp: after.sp,
+            }
+        };
+        self.cpu.breakpoints_mut().add_transient(breakpoint);
+        cycles
+    }
+
+    /// Stop the next `run_frame` once the subroutine running returns, with a
+    /// transient breakpoint
+    pub fn step_out(&mut self) {
+        let sp = self.cpu.registers().sp;
+        self.cpu
+            .breakpoints_mut()
+            .add_transient(Breakpoint::StepOut { sp });
+    }
+
+    /// Call `observer` on the events of the emulation from now on
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    /// The screen, in RGBA8
+    pub fn framebuffer(&self) -> &[u8; FRAMEBUFFER_SIZE] {
+        self.mmu.ppu().framebuffer()
+    }
+
+    /// Buttons held, in the format of `Joypad::state`
+    pub fn set_buttons(&mut self, state: u8) {
+        self.mmu.set_joypad_state(state);
+    }
+
+    /// Audio produced by the last frame, as interleaved left and right
+    /// values between -1.0 and 1.0
+    pub fn audio_samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Bytes sent on the serial port by the last frame, with either clock
+    pub fn serial_output(&self) -> &[u8] {
+        &self.serial
+    }
+
+    /// Frames emulated since power on
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        self.mmu.rom()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn mmu(&self) -> &Mmu {
+        &self.mmu
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut Mmu {
+        &mut self.mmu
+    }
+
+    /// Write the RAM and the clock of the cartridge to a file, as
+    /// `mbc::battery_path`. Nothing is written without a battery.
+    pub fn save_battery_file(&self, path: impl AsRef<Path>) -> Result<(), BatteryError> {
+        let mbc = self.mmu.mbc();
+        if mbc.has_battery() {
+            std::fs::write(path, mbc.save(unix_time()))?;
+        }
+        Ok(())
+    }
+
+    /// Restore the battery save of a file, the clock catches up with the time
+    /// elapsed since it was written
+    pub fn load_battery_file(&mut self, path: impl AsRef<Path>) -> Result<(), BatteryError> {
+        let data = std::fs::read(path)?;
+        self.mmu.mbc_mut().load(&data, unix_time())
+    }
+
+    /// The state of the machine, to restore with `load_state`
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Vec<u8> {
+        state::encode(self.rom(), &(&self.cpu, &self.mmu, self.model, self.frame))
+    }
+
+    /// Write the state to a file, as a slot of `state::slot_path`
+    #[cfg(feature = "serde")]
+    pub fn save_state_file(&self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        Ok(std::fs::write(path, self.save_state())?)
+    }
+
+    /// Restore the state of a file written by `save_state_file`
+    #[cfg(feature = "serde")]
+    pub fn load_state_file(&mut self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        self.load_state(&std::fs::read(path)?)
+    }
+
+    /// Keep save states while running the frames, for `rewind`. None
+    /// disables it and frees the states.
+    #[cfg(feature = "serde")]
+    pub fn set_rewind(&mut self, rewind: Option<Rewind>) {
+        self.rewind = rewind;
+    }
+
+    /// Go back in time by `seconds` at least, to the newest state kept
+    /// before that, or to the oldest one. Returns the number of frames
+    /// rewound, 0 when rewind is disabled or there is no state yet. The
+    /// emulation goes on from the current frame if the state cannot be
+    /// restored.
+    #[cfg(feature = "serde")]
+    pub fn rewind(&mut self, seconds: f32) -> Result<usize, StateError> {
+        let current = self.frame;
+        let target = current.saturating_sub((seconds * FRAME_RATE).ceil() as usize);
+        let Some((frame, state)) = self.rewind.as_mut().and_then(|r| r.pop_until(target)) else {
+            return Ok(0);
+        };
+        self.load_state(&state)?;
+        Ok(current.saturating_sub(frame))
+    }
+
+    /// Restore a state saved with the same ROM. The settings of the frontend
+    /// are kept.
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let (mut cpu, mut mmu, model, frame): (Cpu, Mmu, Model, usize) =
+            state::decode(self.rom(), data)?;
+        mmu.keep_settings(&mut self.mmu);
+        cpu.keep_debugging(&mut self.cpu);
+        mmu.keep_debugging(&mut self.mmu);
+        self.cpu = cpu;
+        self.mmu = mmu;
+        self.model = model;
+        self.frame = frame;
+        self.samples.clear();
+        self.serial.clear();
+        self.cycles = 0;
+        Ok(())
+    }
+}
+
+/// `Options` set one at a time, the others keep their default:
+/// ```no_run
+/// use gb::emulator::EmulatorBuilder;
+/// use gb::model::Model;
+///
+/// let emulator = EmulatorBuilder::new()
+///     .rom(gb::rom::read("game.gb").unwrap())
+///     .boot_rom(std::fs::read("cgb_boot.bin").unwrap())
+///     .model(Model::Cgb)
+///     .sample_rate(48000)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EmulatorBuilder {
+    rom: Vec<u8>,
+    options: Options,
+    skip_boot: bool,
+}
+
+impl EmulatorBuilder {
+    /// Without a cartridge, reading 0xff everywhere
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rom(mut self, rom: impl Into<Vec<u8>>) -> Self {
+        self.rom = rom.into();
+        self
+    }
+
+    pub fn boot_rom(mut self, boot_rom: impl Into<Vec<u8>>) -> Self {
+        self.options.boot_rom = Some(boot_rom.into());
+        self
+    }
+
+    /// Start from the state left by the boot ROM even if there is one
+    pub fn skip_boot(mut self, skip: bool) -> Self {
+        self.skip_boot = skip;
+        self
+    }
+
+    pub fn model(mut self, model: Model) -> Self {
+        self.options.model = Some(model);
+        self
+    }
+
+    pub fn palette(mut self, palette: DmgPalette) -> Self {
+        self.options.palette = palette;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.options.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn registers(mut self, registers: Registers) -> Self {
+        self.options.registers = Some(registers);
+        self
+    }
+
+    pub fn ram(mut self, ram: RamInit) -> Self {
+        self.options.ram = ram;
+        self
+    }
+
+    pub fn div_counter(mut self, div_counter: u16) -> Self {
+        self.options.div_counter = div_counter;
+        self
+    }
+
+    pub fn build(mut self) -> Emulator {
+        if self.skip_boot {
+            self.options.boot_rom = None;
+        }
+        Emulator::new(self.rom, &self.options)
+    }
+}
+
+/// Iterator of `Emulator::frames`
+pub struct Frames<'a> {
+    emulator: &'a mut Emulator,
+}
+
+impl Frames<'_> {
+    /// Buttons held from the next frame, in the format of `Joypad::state`
+    pub fn set_buttons(&mut self, state: u8) {
+        self.emulator.set_buttons(state);
+    }
+}
+
+impl Iterator for Frames<'_> {
+    type Item = OwnedFrame;
+
+    fn next(&mut self) -> Option<OwnedFrame> {
+        match self.emulator.run_frame() {
+            Some(_) => None,
+            None => Some(OwnedFrame::from(&self.emulator.last_frame())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Opcode;
+    use crate::joypad::Button;
+    use std::sync::{Arc, Mutex};
+
+    // Events as text, shared with the test
+    struct Tracer(Arc<Mutex<Vec<String>>>);
+
+    impl Observer for Tracer {
+        fn on_instruction(&mut self, pc: u16, opcode: &Opcode) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("0x{:04x} {}", pc, opcode));
+        }
+        fn on_read(&mut self, addr: u16, value: u8) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("read 0x{:04x} 0x{:02x}", addr, value));
+        }
+        fn on_write(&mut self, addr: u16, value: u8) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("write 0x{:04x} 0x{:02x}", addr, value));
+        }
+        fn on_frame(&mut self, frame: &Frame) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("frame {}", frame.number));
+        }
+    }
+
+    #[test]
+    fn test_emulator_locked() {
+        // NOP, then an illegal opcode
+        let mut rom = vec![0; 0x8000];
+        rom[0x101] = 0xd3;
+        let mut emulator = Emulator::new(rom, &Default::default());
+        assert!(emulator.check().is_ok());
+        let err = emulator.try_run_frame().unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Emulation { pc: 0x101, addr: 0x101, source }
+                if matches!(**source, Error::Decode(DecodeError::UnknownOpcode(0xd3)))
+        ));
+        assert_eq!(emulator.cpu().locked(), Some(0x101));
+        assert_eq!(emulator.frame(), 1);
+    }
+
+    #[test]
+    fn test_emulator() {
+        // JR -2 at the entry point
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        let options = Options {
+            sample_rate: Some(48000),
+            ..Default::default()
+        };
+        let mut emulator = Emulator::new(rom, &options);
+        assert_eq!(emulator.model(), Model::Dmg);
+        emulator.set_buttons(Button::Start.mask());
+        emulator.run_frame();
+        assert_eq!(emulator.frame(), 1);
+        assert_eq!(emulator.cpu().registers().pc, 0x100);
+        assert!(emulator.mmu().joypad().pressed(Button::Start));
+        // About 800 stereo samples per frame
+        assert!((1500..1700).contains(&emulator.audio_samples().len()));
+        assert_eq!(emulator.step(), 12);
+
+        let emulator = Emulator::new(
+            Vec::new(),
+            &Options {
+                model: Some(Model::Cgb),
+                ..options
+            },
+        );
+        assert_eq!(emulator.model(), Model::Cgb);
+        assert!(emulator.rom().is_empty());
+    }
+
+    #[test]
+    fn test_emulator_builder() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        // LD A 0x01, LDH (0x50) A: unmap the boot ROM and run the cartridge
+        let boot_rom = [0x3e, 0x01, 0xe0, 0x50];
+        let mut emulator = EmulatorBuilder::new()
+            .rom(rom.clone())
+            .boot_rom(boot_rom)
+            .model(Model::Cgb)
+            .sample_rate(48000)
+            .build();
+        assert_eq!(emulator.model(), Model::Cgb);
+        assert_eq!(emulator.cpu().registers().pc, 0x0000);
+        assert!(emulator.mmu().boot_rom_mapped());
+        emulator.run_frame();
+        assert!(!emulator.mmu().boot_rom_mapped());
+        assert_eq!(emulator.cpu().registers().pc, 0x100);
+
+        let emulator = EmulatorBuilder::new()
+            .rom(rom)
+            .boot_rom(boot_rom)
+            .skip_boot(true)
+            .build();
+        assert_eq!(emulator.model(), Model::Dmg);
+        assert_eq!(
+            emulator.cpu().registers(),
+            &Registers::after_boot(Model::Dmg)
+        );
+        assert!(!emulator.mmu().boot_rom_mapped());
+    }
+
+    #[test]
+    fn test_emulator_frames() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        let mut emulator = Emulator::new(rom.clone(), &Default::default());
+        let mut frames = emulator.frames();
+        frames.set_buttons(Button::A.mask());
+        let frames: Vec<OwnedFrame> = frames.take(3).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].number, 3);
+        assert_eq!(frames[2].buttons, Button::A.mask());
+        assert!((70000..71000).contains(&frames[2].cycles));
+
+        let mut other = Emulator::new(rom, &Default::default());
+        other.set_buttons(Button::A.mask());
+        other.run_frame();
+        other.run_frame();
+        other.run_frame();
+        assert_eq!(OwnedFrame::from(&other.last_frame()), frames[2]);
+    }
+
+    #[test]
+    fn test_emulator_breakpoints() {
+        // CALL 0x0110; JR -2 ... 0x0110: RET
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x105].copy_from_slice(&[0xcd, 0x10, 0x01, 0x18, 0xfe]);
+        rom[0x110] = 0xc9;
+        let mut emulator = Emulator::new(rom, &Default::default());
+        let id = emulator
+            .cpu_mut()
+            .breakpoints_mut()
+            .add("RET".parse().unwrap());
+        assert_eq!(emulator.run_frame(), Some(Hit { id, pc: 0x0110 }));
+        assert_eq!(emulator.frame(), 0);
+        assert_eq!(emulator.cpu().registers().pc, 0x0110);
+        // The frame is resumed and completed
+        assert_eq!(emulator.run_frame(), None);
+        assert_eq!(emulator.frame(), 1);
+        assert_eq!(emulator.cpu().registers().pc, 0x0103);
+    }
+
+    #[test]
+    fn test_emulator_step_over() {
+        // CALL 0x0110; NOP; JR -2 ... 0x0110: PUSH BC; CALL 0x0120; POP BC;
+        // RET ... 0x0120: RET
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[0xcd, 0x10, 0x01, 0x00, 0x18, 0xfe]);
+        rom[0x110..0x116].copy_from_slice(&[0xc5, 0xcd, 0x20, 0x01, 0xc1, 0xc9]);
+        rom[0x120] = 0xc9;
+        let mut emulator = Emulator::new(rom, &Default::default());
+        emulator.step_over();
+        assert_eq!(emulator.cpu().registers().pc, 0x0110);
+        let hit = emulator.run_frame().unwrap();
+        assert_eq!(hit.pc, 0x0103);
+        assert!(emulator.cpu().breakpoints().is_empty());
+
+        // Not a call: stops after the instruction
+        emulator.step_over();
+        assert_eq!(emulator.run_frame().unwrap().pc, 0x0104);
+
+        // Out of the subroutine, not at the POP BC after the inner call
+        let mut emulator = Emulator::new(emulator.rom().to_vec(), &Default::default());
+        emulator.step();
+        emulator.step();
+        emulator.step_out();
+        assert_eq!(emulator.run_frame().unwrap().pc, 0x0103);
+        assert_eq!(emulator.cpu().registers().sp, 0xfffe);
+        assert!(emulator.cpu().breakpoints().is_empty());
+        assert_eq!(emulator.run_frame(), None);
+    }
+
+    #[test]
+    fn test_emulator_initial_state() {
+        let options = Options {
+            registers: Some(Registers {
+                pc: 0x150,
+                ..Default::default()
+            }),
+            ram: RamInit::Random(42),
+            div_counter: 0xabcc,
+            ..Default::default()
+        };
+        let emulator = Emulator::new(Vec::new(), &options);
+        assert_eq!(emulator.cpu().registers().pc, 0x150);
+        assert_eq!(emulator.mmu().read(crate::timer::DIV), 0xab);
+        let ram: Vec<u8> = (0xc000..0xc100)
+            .map(|addr| emulator.mmu().read(addr))
+            .collect();
+        assert!(ram.iter().any(|&byte| byte != ram[0]));
+
+        // Same seed, same RAM
+        let same = Emulator::new(Vec::new(), &options);
+        assert!((0xc000..0xc100).all(|addr| same.mmu().read(addr) == emulator.mmu().read(addr)));
+        let other = Emulator::new(
+            Vec::new(),
+            &Options {
+                ram: RamInit::Random(43),
+                ..options
+            },
+        );
+        assert!((0xc000..0xc100).any(|addr| other.mmu().read(addr) != emulator.mmu().read(addr)));
+        let zero = Emulator::new(Vec::new(), &Default::default());
+        assert_eq!(zero.mmu().read(0xff80), 0);
+    }
+
+    #[test]
+    fn test_emulator_serial() {
+        // LD A 0x4f, LDH (SB) A, LD A 0x81, LDH (SC) A, JR -2
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x10a]
+            .copy_from_slice(&[0x3e, 0x4f, 0xe0, 0x01, 0x3e, 0x81, 0xe0, 0x02, 0x18, 0xfe]);
+        let mut emulator = Emulator::new(rom, &Default::default());
+        emulator.run_frame();
+        assert_eq!(emulator.serial_output(), b"O");
+        assert_eq!(emulator.last_frame().serial, b"O");
+        assert_eq!(emulator.mmu().read(crate::serial::SB), 0xff);
+        emulator.run_frame();
+        assert!(emulator.serial_output().is_empty());
+    }
+
+    #[test]
+    fn test_emulator_cheats() {
+        // LD A (0x0200), LD (0xc000) A, JR -8
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x108].copy_from_slice(&[0xfa, 0x00, 0x02, 0xea, 0x00, 0xc0, 0x18, 0xf8]);
+        rom[0x200] = 0x12;
+        let mut emulator = Emulator::new(rom, &Default::default());
+        let cheats = emulator.mmu_mut().cheats_mut();
+        cheats.add("342-00F-A02".parse().unwrap());
+        // Not applied, 0x0200 holds 0x12
+        cheats.add("563-00F-A02".parse().unwrap());
+        cheats.add("017801C0".parse().unwrap());
+        emulator.run_frame();
+        assert_eq!(emulator.mmu().read(0xc000), 0x34);
+        assert_eq!(emulator.mmu().read(0xc001), 0x78);
+        emulator.mmu_mut().write(0xc001, 0x00);
+        emulator.run_frame();
+        assert_eq!(emulator.mmu().read(0xc001), 0x78);
+    }
+
+    #[test]
+    fn test_emulator_observer() {
+        // LD A 0x42, LD (0xc000) A, JR -2
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x107].copy_from_slice(&[0x3e, 0x42, 0xea, 0x00, 0xc0, 0x18, 0xfe]);
+        let mut emulator = Emulator::new(rom, &Default::default());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        emulator.add_observer(Box::new(Tracer(events.clone())));
+        emulator.step();
+        emulator.step();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "0x0100 LD A 0x42",
+                "read 0x0100 0x3e",
+                "read 0x0101 0x42",
+                "0x0102 LD (0xc000) A",
+                "read 0x0102 0xea",
+                "read 0x0103 0x00",
+                "read 0x0104 0xc0",
+                "write 0xc000 0x42",
+            ]
+        );
+
+        emulator.run_frame();
+        assert_eq!(events.lock().unwrap().last().unwrap(), "frame 1");
+        emulator.clear_observers();
+        events.lock().unwrap().clear();
+        emulator.run_frame();
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_emulator_rewind() {
+        // INC (HL) with HL at 0xc000, JR -3: a counter in the RAM
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd]);
+        let mut emulator = Emulator::new(rom, &Default::default());
+        assert_eq!(emulator.rewind(1.0).unwrap(), 0);
+        emulator.set_rewind(Some(Rewind::new(10, 5)));
+        for _ in 0..100 {
+            emulator.run_frame();
+        }
+        let counter = emulator.mmu().read(0xc000);
+        // Back to frame 80, the newest state before frame 100 - 0.25 s
+        assert_eq!(emulator.rewind(0.25).unwrap(), 20);
+        assert_eq!(emulator.frame(), 80);
+        assert_ne!(emulator.mmu().read(0xc000), counter);
+        for _ in 0..20 {
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.mmu().read(0xc000), counter);
+        // Only 5 states kept, the oldest one is frame 60
+        assert_eq!(emulator.rewind(60.0).unwrap(), 40);
+        assert_eq!(emulator.frame(), 60);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_save_state() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        let mut emulator = Emulator::new(rom.clone(), &Default::default());
+        emulator.run_frame();
+        emulator.mmu_mut().write(0xc000, 0x42);
+        let state = emulator.save_state();
+
+        emulator
+            .mmu_mut()
+            .ppu_mut()
+            .set_dmg_palette(DmgPalette::GREEN);
+        emulator.mmu_mut().write(0xc000, 0x00);
+        emulator.run_frame();
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.frame(), 1);
+        assert_eq!(emulator.mmu().read(0xc000), 0x42);
+        assert_eq!(emulator.rom(), rom.as_slice());
+        emulator.run_frame();
+        assert_eq!(emulator.frame(), 2);
+
+        let mut other = Emulator::new(vec![0; 0x8000], &Default::default());
+        assert!(matches!(
+            other.load_state(&state),
+            Err(StateError::OtherRom)
+        ));
+        let mut invalid = state.clone();
+        invalid[4] = 0xff;
+        assert!(matches!(
+            emulator.load_state(&invalid),
+            Err(StateError::UnsupportedVersion(0xff))
+        ));
+        assert!(matches!(
+            emulator.load_state(&state[..20]),
+            Err(StateError::DecodeError(_))
+        ));
+
+        let rom_path = std::env::temp_dir().join(format!("gb-state-{}.gb", std::process::id()));
+        let path = state::slot_path(&rom_path, 3);
+        assert_eq!(path, rom_path.with_extension("ss3"));
+        assert!(matches!(
+            emulator.load_state_file(&path),
+            Err(StateError::IOError(_))
+        ));
+        emulator.mmu_mut().write(0xc000, 0x12);
+        emulator.save_state_file(&path).unwrap();
+        emulator.mmu_mut().write(0xc000, 0x00);
+        emulator.load_state_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(emulator.mmu().read(0xc000), 0x12);
+    }
+
+    #[test]
+    fn test_battery_file() {
+        let rom_path = std::env::temp_dir().join(format!("gb-battery-{}.gb", std::process::id()));
+        let path = crate::mbc::battery_path(&rom_path);
+        assert_eq!(path, rom_path.with_extension("sav"));
+        // Without battery
+        let emulator = Emulator::new(vec![0; 0x8000], &Default::default());
+        emulator.save_battery_file(&path).unwrap();
+        assert!(!path.exists());
+
+        // MBC3+RAM+BATTERY with 8 KiB
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x13;
+        rom[0x149] = 0x02;
+        let mut emulator = Emulator::new(rom.clone(), &Default::default());
+        emulator.mmu_mut().write(0x0000, 0x0a);
+        emulator.mmu_mut().write(0xa123, 0x12);
+        emulator.save_battery_file(&path).unwrap();
+        let mut emulator = Emulator::new(rom, &Default::default());
+        emulator.load_battery_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(emulator.mmu().mbc().ram()[0x123], 0x12);
+        assert!(matches!(
+            emulator.load_battery_file(&path),
+            Err(BatteryError::IOError(_))
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+//! Errors of the library, gathering those of each module
+
+use std::{fmt::Display, path::PathBuf};
+
+use crate::annotations::AnnotationError;
+#[cfg(feature = "audio")]
+use crate::audio::AudioError;
+use crate::cheats::CheatError;
+use crate::debugger::doctor::DoctorError;
+use crate::debugger::expression::ExpressionError;
+use crate::debugger::trace::TraceError;
+use crate::decoder::DecodeError;
+use crate::disassembler::{CharmapError, NamesError};
+#[cfg(feature = "input")]
+use crate::input::InputMapError;
+use crate::mbc::BatteryError;
+use crate::movie::MovieError;
+use crate::palette::PaletteError;
+#[cfg(feature = "serde")]
+use crate::replay::ReplayError;
+#[cfg(feature = "scripting")]
+use crate::script::ScriptError;
+use crate::settings::SettingsError;
+#[cfg(feature = "serde")]
+use crate::state::StateError;
+
+#[derive(Debug)]
+pub enum Error {
+    Decode(DecodeError),
+    Annotation(AnnotationError),
+    Charmap(CharmapError),
+    Names(NamesError),
+    Palette(PaletteError),
+    #[cfg(feature = "input")]
+    InputMap(InputMapError),
+    Settings(SettingsError),
+    Movie(MovieError),
+    Battery(BatteryError),
+    Expression(ExpressionError),
+    Cheat(CheatError),
+    Trace(TraceError),
+    Doctor(DoctorError),
+    #[cfg(feature = "serde")]
+    State(StateError),
+    #[cfg(feature = "serde")]
+    Replay(ReplayError),
+    #[cfg(feature = "audio")]
+    Audio(AudioError),
+    #[cfg(feature = "scripting")]
+    Script(ScriptError),
+    Png(png::EncodingError),
+    Gif(gif::EncodingError),
+    /// A region outside of the ROM
+    InvalidRegion(usize, usize),
+    IOError(std::io::Error),
+    /// Error at an offset of the ROM
+    At(usize, Box<Error>),
+    /// Error of the emulation at the instruction `pc`, accessing `addr`
+    Emulation {
+        pc: u16,
+        addr: u16,
+        source: Box<Error>,
+    },
+    /// Error in a file
+    File(PathBuf, Box<Error>),
+}
+
+impl Error {
+    /// Add the offset of the ROM where the error happened
+    pub fn at(self, offset: usize) -> Self {
+        Error::At(offset, Box::new(self))
+    }
+
+    /// Add the instruction running and the address it accessed when the
+    /// error happened
+    pub fn emulation(self, pc: u16, addr: u16) -> Self {
+        Error::Emulation {
+            pc,
+            addr,
+            source: Box::new(self),
+        }
+    }
+
+    /// Add the file where the error happened
+    pub fn in_file(self, path: impl Into<PathBuf>) -> Self {
+        Error::File(path.into(), Box::new(self))
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(err) => Some(err),
+            Self::Annotation(err) => Some(err),
+            Self::Charmap(err) => Some(err),
+            Self::Names(err) => Some(err),
+            Self::Palette(err) => Some(err),
+            #[cfg(feature = "input")]
+            Self::InputMap(err) => Some(err),
+            Self::Settings(err) => Some(err),
+            Self::Movie(err) => Some(err),
+            Self::Battery(err) => Some(err),
+            Self::Expression(err) => Some(err),
+            Self::Cheat(err) => Some(err),
+            Self::Trace(err) => Some(err),
+            Self::Doctor(err) => Some(err),
+            #[cfg(feature = "serde")]
+            Self::State(err) => Some(err),
+            #[cfg(feature = "serde")]
+            Self::Replay(err) => Some(err),
+            #[cfg(feature = "audio")]
+            Self::Audio(err) => Some(err),
+            #[cfg(feature = "scripting")]
+            Self::Script(err) => Some(err),
+            Self::Png(err) => Some(err),
+            Self::Gif(err) => Some(err),
+            Self::InvalidRegion(_, _) => None,
+            Self::IOError(err) => Some(err),
+            Self::At(_, err) => Some(err.as_ref()),
+            Self::Emulation { source, .. } => Some(source.as_ref()),
+            Self::File(_, err) => Some(err.as_ref()),
+        }
+    }
+}
+
+macro_rules! from_error {
+    ($($error:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(value: $error) -> Self {
+                    Error::$variant(value)
+                }
+            }
+        )*
+    };
+}
+
+from_error!(
+    DecodeError => Decode,
+    AnnotationError => Annotation,
+    CharmapError => Charmap,
+    NamesError => Names,
+    PaletteError => Palette,
+    SettingsError => Settings,
+    MovieError => Movie,
+    BatteryError => Battery,
+    ExpressionError => Expression,
+    CheatError => Cheat,
+    TraceError => Trace,
+    DoctorError => Doctor,
+    png::EncodingError => Png,
+    gif::EncodingError => Gif,
+    std::io::Error => IOError,
+);
+
+#[cfg(feature = "serde")]
+from_error!(StateError => State, ReplayError => Replay);
+#[cfg(feature = "input")]
+from_error!(InputMapError => InputMap);
+#[cfg(feature = "audio")]
+from_error!(AudioError => Audio);
+#[cfg(feature = "scripting")]
+from_error!(ScriptError => Script);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode(err) => write!(f, "{}", err),
+            Self::Annotation(err) => write!(f, "{}", err),
+            Self::Charmap(err) => write!(f, "{}", err),
+            Self::Names(err) => write!(f, "{}", err),
+            Self::Palette(err) => write!(f, "{}", err),
+            #[cfg(feature = "input")]
+            Self::InputMap(err) => write!(f, "{}", err),
+            Self::Settings(err) => write!(f, "{}", err),
+            Self::Movie(err) => write!(f, "{}", err),
+            Self::Battery(err) => write!(f, "{}", err),
+            Self::Expression(err) => write!(f, "{}", err),
+            Self::Cheat(err) => write!(f, "{}", err),
+            Self::Trace(err) => write!(f, "{}", err),
+            Self::Doctor(err) => write!(f, "{}", err),
+            #[cfg(feature = "serde")]
+            Self::State(err) => write!(f, "{}", err),
+            #[cfg(feature = "serde")]
+            Self::Replay(err) => write!(f, "{}", err),
+            #[cfg(feature = "audio")]
+            Self::Audio(err) => write!(f, "{}", err),
+            #[cfg(feature = "scripting")]
+            Self::Script(err) => write!(f, "{}", err),
+            Self::Png(err) => write!(f, "PNG error: {}", err),
+            Self::Gif(err) => write!(f, "GIF error: {}", err),
+            Self::InvalidRegion(start, end) => {
+                write!(f, "Invalid region 0x{:x}-0x{:x}", start, end)
+            }
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+            Self::At(offset, err) => write!(f, "At 0x{:04x}: {}", offset, err),
+            Self::Emulation { pc, addr, source } => {
+                write!(f, "PC 0x{:04x}, address 0x{:04x}: {}", pc, addr, source)
+            }
+            Self::File(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error() {
+        let err = Error::from(DecodeError::UnknownOpcode(0xd3)).at(0x150);
+        assert!(matches!(&err, Error::At(0x150, inner) if matches!(**inner, Error::Decode(_))));
+        let err = err.in_file("game.gb");
+        assert_eq!(
+            err.to_string(),
+            format!("game.gb: At 0x0150: {}", DecodeError::UnknownOpcode(0xd3))
+        );
+        assert!(std::error::Error::source(&err).is_some());
+
+        let err = Error::from(DecodeError::UnknownOpcode(0xd3)).emulation(0x4123, 0x4123);
+        assert!(matches!(
+            &err,
+            Error::Emulation { pc: 0x4123, addr: 0x4123, source } if matches!(**source, Error::Decode(_))
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "PC 0x4123, address 0x4123: {}",
+                DecodeError::UnknownOpcode(0xd3)
+            )
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use eframe::egui;
+
+use crate::input::{Action, InputMap};
+
+/// Keys bound to each action. The key pressed after `+` is bound to its
+/// action, clicking a key removes it.
+#[derive(Default)]
+pub struct BindingsPanel {
+    // Action waiting for a key press
+    capturing: Option<Action>,
+}
+
+impl BindingsPanel {
+    /// Bind `key` to the action waiting for a key, Escape cancels. Returns
+    /// true when the key was captured, instead of triggering its action.
+    pub fn capture(&mut self, key: egui::Key, input_map: &mut InputMap) -> bool {
+        let Some(action) = self.capturing.take() else {
+            return false;
+        };
+        if key != egui::Key::Escape {
+            input_map.bind(key.name(), action);
+        }
+        true
+    }
+
+    /// Returns true when the bindings changed, to save
+    pub fn show(&mut self, ui: &mut egui::Ui, input_map: &mut InputMap) -> bool {
+        let mut changed = false;
+        if ui.button("Reset to defaults").clicked() {
+            *input_map = InputMap::default();
+            self.capturing = None;
+            changed = true;
+        }
+        ui.separator();
+
+        let mut removed = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                for action in Action::all() {
+                    ui.monospace(action.to_string());
+                    ui.horizontal(|ui| {
+                        for input in input_map.inputs(action) {
+                            if ui.small_button(input).on_hover_text("Remove").clicked() {
+                                removed = Some(input.to_string());
+                            }
+                        }
+                        if self.capturing == Some(action) {
+                            ui.label("Press a key, Escape cancels");
+                        } else if ui.small_button("+").on_hover_text("Add a key").clicked() {
+                            self.capturing = Some(action);
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some(input) = removed {
+            input_map.unbind(&input);
+            changed = true;
+        }
+        changed
+    }
+}
@@ -0,0 +1,56 @@
+use eframe::egui;
+
+use crate::debugger::breakpoints::{Breakpoint, Breakpoints};
+
+/// List of the breakpoints, added as addresses, mnemonics or conditions
+#[derive(Default)]
+pub struct BreakpointsPanel {
+    input: String,
+    error: Option<String>,
+}
+
+impl BreakpointsPanel {
+    /// Returns true when the breakpoints changed, to send to the emulator
+    pub fn show(&mut self, ui: &mut egui::Ui, breakpoints: &mut Breakpoints) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.input)
+                    .hint_text("0x0150, CALL or A==0x42 && HL>=0x8000"),
+            );
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if entered || ui.button("Add").clicked() {
+                match self.input.parse::<Breakpoint>() {
+                    Ok(breakpoint) => {
+                        breakpoints.add(breakpoint);
+                        self.input.clear();
+                        self.error = None;
+                        changed = true;
+                    }
+                    Err(err) => self.error = Some(err.to_string()),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        ui.separator();
+
+        let mut removed = None;
+        egui::Grid::new("breakpoints").striped(true).show(ui, |ui| {
+            for (id, breakpoint) in breakpoints.iter() {
+                ui.monospace(id.to_string());
+                ui.monospace(breakpoint.to_string());
+                if ui.small_button("x").on_hover_text("Remove").clicked() {
+                    removed = Some(id);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(id) = removed {
+            breakpoints.remove(id);
+            changed = true;
+        }
+        changed
+    }
+}
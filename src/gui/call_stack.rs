@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+use eframe::egui;
+
+use crate::annotations::{Annotation, Purpose};
+use crate::debugger::call_stack::CallStack;
+
+/// Frames of the call stack inferred by the CPU, from a snapshot of the
+/// emulator, the current one first. The addresses called are shown with
+/// their label when the annotations have one.
+pub fn show(
+    ui: &mut egui::Ui,
+    call_stack: &CallStack,
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+) {
+    if call_stack.depth() == 0 {
+        ui.label("No call");
+        return;
+    }
+    let label = |addr: u16| {
+        annotations
+            .get(&(addr as usize))
+            .and_then(|annotations| {
+                annotations
+                    .iter()
+                    .find(|annotation| annotation.purpose == Purpose::Label)
+            })
+            .map(|annotation| format!("{:04x} {}", addr, annotation.value))
+            .unwrap_or_else(|| format!("{:04x}", addr))
+    };
+    egui::ScrollArea::vertical()
+        .auto_shrink(false)
+        .show(ui, |ui| {
+            egui::Grid::new("call_stack").striped(true).show(ui, |ui| {
+                for frame in call_stack.frames().iter().rev() {
+                    ui.monospace(label(frame.target));
+                    ui.monospace(frame.kind.to_string());
+                    ui.monospace(format!("from {}", label(frame.caller)))
+                        .on_hover_text(format!(
+                            "Returns to {:04x}, SP {:04x}",
+                            frame.return_address, frame.sp
+                        ));
+                    ui.end_row();
+                }
+            });
+        });
+}
@@ -0,0 +1,63 @@
+use eframe::egui;
+
+use crate::cheats::{Cheat, Cheats};
+
+/// List of the Game Genie and GameShark codes, enabled with their checkbox
+#[derive(Default)]
+pub struct CheatsPanel {
+    input: String,
+    error: Option<String>,
+}
+
+impl CheatsPanel {
+    /// Returns true when the cheats changed, to send to the emulator
+    pub fn show(&mut self, ui: &mut egui::Ui, cheats: &mut Cheats) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.input).hint_text("ABC-DEF-GHI or 01VVAAAA"),
+            );
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if entered || ui.button("Add").clicked() {
+                match self.input.parse::<Cheat>() {
+                    Ok(cheat) => {
+                        cheats.add(cheat);
+                        self.input.clear();
+                        self.error = None;
+                        changed = true;
+                    }
+                    Err(err) => self.error = Some(err.to_string()),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        ui.separator();
+
+        let mut toggled = None;
+        let mut removed = None;
+        egui::Grid::new("cheats").striped(true).show(ui, |ui| {
+            for (id, cheat, enabled) in cheats.iter() {
+                let mut checked = enabled;
+                if ui.checkbox(&mut checked, "").changed() {
+                    toggled = Some((id, checked));
+                }
+                ui.monospace(cheat.to_string());
+                if ui.small_button("x").on_hover_text("Remove").clicked() {
+                    removed = Some(id);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some((id, enabled)) = toggled {
+            cheats.set_enabled(id, enabled);
+            changed = true;
+        }
+        if let Some(id) = removed {
+            cheats.remove(id);
+            changed = true;
+        }
+        changed
+    }
+}
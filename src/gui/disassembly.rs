@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use eframe::egui;
+
+use crate::annotations::{Annotation, Purpose};
+use crate::decoder::{decode, Opcode};
+use crate::mmu::Mmu;
+
+// Instructions shown after PC
+const LINES: usize = 24;
+
+/// Instructions starting at PC, decoded from the memory as seen by the CPU.
+/// The annotations are indexed by ROM offset, which is also the address
+/// without a memory bank controller, and only apply to the ROM area.
+pub fn show(ui: &mut egui::Ui, mmu: &Mmu, pc: u16, annotations: &BTreeMap<usize, Vec<Annotation>>) {
+    let mut addr = pc;
+    egui::Grid::new("disassembly").show(ui, |ui| {
+        for line in 0..LINES {
+            let marker = if line == 0 { ">" } else { "" };
+            let mut comment = String::new();
+            let mut goto = String::new();
+            let mut skip = 0;
+            let rom_annotations = match addr {
+                0x0000..=0x7fff => annotations.get(&(addr as usize)),
+                _ => None,
+            };
+            for annotation in rom_annotations.into_iter().flatten() {
+                match annotation.purpose {
+                    Purpose::Comment => comment = format!("; {}", annotation.value),
+                    Purpose::Goto => goto = format!("-> {}", annotation.value),
+                    Purpose::Label => {
+                        ui.label("");
+                        ui.label("");
+                        ui.monospace(format!("{}:", annotation.value));
+                        ui.end_row();
+                    }
+                    Purpose::Section => {
+                        ui.label("");
+                        ui.label("");
+                        ui.strong(format!("-- {} --", annotation.value));
+                        ui.end_row();
+                    }
+                    Purpose::Data => skip = annotation.data_len().unwrap_or(0) as u16,
+                }
+            }
+
+            if skip > 0 {
+                ui.monospace(marker);
+                ui.monospace(format!("{:04x}", addr));
+                ui.monospace(format!(
+                    "Skip 0x{:04x}-0x{:04x}",
+                    addr,
+                    addr.wrapping_add(skip - 1)
+                ));
+                ui.monospace(goto);
+                ui.monospace(comment);
+                ui.end_row();
+                addr = addr.wrapping_add(skip);
+                continue;
+            }
+
+            let mut len = 0;
+            let mut bytes = std::iter::from_fn(|| {
+                let value = mmu.read(addr.wrapping_add(len));
+                len += 1;
+                Some(value)
+            });
+            let text = match decode(&mut bytes) {
+                Ok(opcode) => {
+                    // Destination of the relative jumps, when not annotated
+                    let next = addr.wrapping_add(len);
+                    if let Opcode::Jump(offset)
+                    | Opcode::JumpRZMemOffset(offset)
+                    | Opcode::JumpRNZMemOffset(offset)
+                    | Opcode::JumpRCMemOffset(offset)
+                    | Opcode::JumpRNCMemOffset(offset) = opcode
+                    {
+                        if goto.is_empty() {
+                            goto = format!("-> 0x{:04x}", next.wrapping_add_signed(offset as i16));
+                        }
+                    }
+                    opcode.to_string()
+                }
+                // Unused opcode, shown as data
+                Err(_) => {
+                    len = 1;
+                    format!("DB 0x{:02x}", mmu.read(addr))
+                }
+            };
+            let hex: Vec<String> = (0..len)
+                .map(|i| format!("{:02x}", mmu.read(addr.wrapping_add(i))))
+                .collect();
+
+            ui.monospace(marker);
+            ui.monospace(format!("{:04x}", addr));
+            ui.monospace(hex.join(" "));
+            ui.monospace(text);
+            ui.monospace(goto);
+            ui.monospace(comment);
+            ui.end_row();
+            addr = addr.wrapping_add(len);
+        }
+    });
+}
@@ -0,0 +1,126 @@
+use eframe::egui;
+
+use crate::debugger::heatmap::Heatmap;
+use crate::mmu::{self, Mmu};
+
+const BYTES_PER_ROW: usize = 16;
+const ROWS: usize = 0x10000 / BYTES_PER_ROW;
+
+/// Change requested by the user
+pub enum MemoryEdit {
+    Write(u16, u8),
+    EnableHeatmap(bool),
+    ClearHeatmap,
+}
+
+/// Hex view of the whole address space, from a snapshot of the emulator.
+/// Bytes can be edited while the emulation is paused, the writes go through
+/// the MMU so that writing to a register has the same effect as on the
+/// hardware. With the heatmap, the background of the bytes shades their
+/// writes in red and their reads in green.
+#[derive(Default)]
+pub struct MemoryViewer {
+    jump: String,
+    scroll_to: Option<u16>,
+    // Address being edited and the text typed so far
+    editing: Option<(u16, String)>,
+    edit: Option<MemoryEdit>,
+}
+
+impl MemoryViewer {
+    pub fn show(&mut self, ui: &mut egui::Ui, mmu: &Mmu, editable: bool) -> Option<MemoryEdit> {
+        let heatmap = mmu.heatmap();
+        ui.horizontal(|ui| {
+            ui.label("Go to");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.jump).desired_width(40.0));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                match u16::from_str_radix(self.jump.trim_start_matches("0x"), 16) {
+                    Ok(addr) => self.scroll_to = Some(addr),
+                    Err(_) => self.jump.clear(),
+                }
+            }
+            if let Some(addr) = self.scroll_to {
+                ui.label(mmu::region_name(addr));
+            }
+            let mut enabled = heatmap.is_some();
+            if ui.checkbox(&mut enabled, "Heatmap").changed() {
+                self.edit = Some(MemoryEdit::EnableHeatmap(enabled));
+            }
+            if heatmap.is_some() && ui.button("Clear").clicked() {
+                self.edit = Some(MemoryEdit::ClearHeatmap);
+            }
+        });
+        if !editable {
+            self.editing = None;
+            ui.label("Pause to edit");
+        }
+        ui.separator();
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll = egui::ScrollArea::vertical().auto_shrink(false);
+        if let Some(addr) = self.scroll_to.take() {
+            let row = addr as usize / BYTES_PER_ROW;
+            scroll = scroll
+                .vertical_scroll_offset(row as f32 * (row_height + ui.spacing().item_spacing.y));
+        }
+        let heatmap = heatmap.as_deref().map(|heatmap| (heatmap, heatmap.max()));
+        scroll.show_rows(ui, row_height, ROWS, |ui, rows| {
+            for row in rows {
+                ui.horizontal(|ui| self.show_row(ui, mmu, heatmap, row, editable));
+            }
+        });
+        self.edit.take()
+    }
+
+    fn show_row(
+        &mut self,
+        ui: &mut egui::Ui,
+        mmu: &Mmu,
+        heatmap: Option<(&Heatmap, (u32, u32))>,
+        row: usize,
+        editable: bool,
+    ) {
+        let start = (row * BYTES_PER_ROW) as u16;
+        ui.monospace(format!("{:04x}", start))
+            .on_hover_text(mmu::region_name(start));
+        for addr in (start..).take(BYTES_PER_ROW) {
+            match &mut self.editing {
+                Some((editing, text)) if *editing == addr => {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(text)
+                            .desired_width(16.0)
+                            .font(egui::TextStyle::Monospace),
+                    );
+                    response.request_focus();
+                    if response.lost_focus() {
+                        if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                            if let Ok(value) = u8::from_str_radix(text, 16) {
+                                self.edit = Some(MemoryEdit::Write(addr, value));
+                            }
+                        }
+                        self.editing = None;
+                    }
+                }
+                _ => {
+                    let value = mmu.peek(addr);
+                    let mut text = egui::RichText::new(format!("{:02x}", value)).monospace();
+                    if let Some((heatmap, max)) = heatmap {
+                        let [r, g, b, _] = heatmap.color(addr, max);
+                        text = text.background_color(egui::Color32::from_rgb(r, g, b));
+                    }
+                    let mut label = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                    if let Some((heatmap, _)) = heatmap {
+                        label = label.on_hover_text(format!(
+                            "{} reads, {} writes",
+                            heatmap.reads(addr),
+                            heatmap.writes(addr)
+                        ));
+                    }
+                    if editable && label.double_clicked() {
+                        self.editing = Some((addr, format!("{:02x}", value)));
+                    }
+                }
+            }
+        }
+    }
+}
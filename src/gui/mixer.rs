@@ -0,0 +1,87 @@
+use eframe::egui;
+
+use crate::apu::Apu;
+#[cfg(feature = "audio")]
+use crate::audio::AudioOutput;
+
+const CHANNELS: [&str; 4] = ["Pulse 1", "Pulse 2", "Wave", "Noise"];
+
+/// Channels muted or played alone, kept by the frontend across the resets
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Channels {
+    muted: [bool; 4],
+    solo: Option<usize>,
+}
+
+impl Channels {
+    pub fn apply(&self, apu: &mut Apu) {
+        for (channel, muted) in (1..=4).zip(self.muted) {
+            apu.set_muted(channel, muted);
+        }
+        apu.set_solo(self.solo);
+    }
+}
+
+/// Volume, channels and output device
+#[derive(Default)]
+pub struct Mixer {
+    // Listing the devices is slow, they are only listed on demand
+    #[cfg(feature = "audio")]
+    devices: Vec<String>,
+}
+
+impl Mixer {
+    /// Returns the name of the output device selected by the user, if it changed
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        channels: &mut Channels,
+        #[cfg(feature = "audio")] audio: Option<&mut AudioOutput>,
+    ) -> Option<String> {
+        #[allow(unused_mut)]
+        let mut device = None;
+
+        #[cfg(feature = "audio")]
+        match audio {
+            Some(audio) => {
+                let mut volume = audio.volume();
+                ui.add(egui::Slider::new(&mut volume, 0.0..=1.0).text("Volume"));
+                audio.set_volume(volume);
+
+                let current = audio.device_name().to_string();
+                egui::ComboBox::from_label("Device")
+                    .selected_text(&current)
+                    .show_ui(ui, |ui| {
+                        if self.devices.is_empty() {
+                            self.devices = AudioOutput::devices();
+                        }
+                        for name in &self.devices {
+                            if ui.selectable_label(*name == current, name).clicked() {
+                                device = Some(name.clone());
+                            }
+                        }
+                    });
+                ui.separator();
+            }
+            None => {
+                ui.label("No audio output");
+                ui.separator();
+            }
+        }
+
+        egui::Grid::new("channels").show(ui, |ui| {
+            for (index, name) in CHANNELS.iter().enumerate() {
+                let channel = index + 1;
+                ui.label(*name);
+                ui.toggle_value(&mut channels.muted[index], "Mute");
+                let mut solo = channels.solo == Some(channel);
+                if ui.toggle_value(&mut solo, "Solo").changed() {
+                    channels.solo = solo.then_some(channel);
+                }
+                ui.end_row();
+            }
+        });
+
+        device
+    }
+}
@@ -0,0 +1,1238 @@
+//! egui frontend
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "serde")]
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+
+use crate::annotations::Annotation;
+use crate::apu::CLOCK_RATE;
+#[cfg(feature = "audio")]
+use crate::audio::AudioOutput;
+use crate::audio::WavDump;
+use crate::cheats::Cheats;
+use crate::debugger::breakpoints::{Breakpoints, Hit};
+use crate::debugger::heatmap::Heatmap;
+use crate::debugger::profiler::Profiler;
+use crate::emulator::{self, Emulator};
+use crate::infrared::Transport;
+use crate::input::{Action, InputMap, Turbo};
+use crate::joypad::Button;
+use crate::link::Link;
+use crate::mbc;
+use crate::movie::{self, Movie};
+use crate::palette::DmgPalette;
+use crate::ppu::{Layers, DOTS_PER_FRAME, FRAMEBUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(feature = "serde")]
+use crate::replay::Recorder;
+#[cfg(feature = "serde")]
+use crate::rewind::Rewind;
+use crate::runner::{Command, Event, Runner, Snapshot};
+use crate::serial;
+use crate::settings::Settings;
+#[cfg(feature = "serde")]
+use crate::state;
+use crate::tiles::Image;
+use crate::video::GifRecorder;
+use bindings::BindingsPanel;
+use breakpoints::BreakpointsPanel;
+use cheats::CheatsPanel;
+use memory::{MemoryEdit, MemoryViewer};
+use mixer::{Channels, Mixer};
+use profiler::{ProfilerEdit, ProfilerPanel};
+use stats::FrameStats;
+use vram::VramViewer;
+use watches::{WatchEdit, WatchesPanel};
+
+mod bindings;
+mod breakpoints;
+mod call_stack;
+mod cheats;
+mod disassembly;
+mod memory;
+mod mixer;
+mod profiler;
+mod registers;
+mod screen;
+mod stats;
+mod vram;
+mod watches;
+
+pub use screen::{Scale, Screen};
+
+// About 59.7 frames per second
+const FRAME_DURATION: Duration =
+    Duration::from_nanos(DOTS_PER_FRAME as u64 * 1_000_000_000 / CLOCK_RATE as u64);
+// Lag, in frames at normal speed, after which the missed frames are skipped
+const MAX_LAG_FRAMES: u32 = 8;
+// Frames queued on the emulator thread when the speed is not limited,
+// enough to keep it busy between two repaints
+const MAX_QUEUED_FRAMES: usize = 32;
+// Steps of the speed hotkeys, above the last one the speed is not limited
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+pub enum MovieMode {
+    /// Record the joypad, the movie is saved to the path on exit
+    Record(Movie, PathBuf),
+    /// Replay the joypad, the keyboard takes over at the end of the movie
+    Play(Movie),
+}
+
+pub struct MyApp {
+    // The emulator runs on its own thread, the GUI only sees its frames and
+    // the snapshots taken for the debug panels
+    runner: Runner,
+    // Cartridge inserted, for the resets
+    rom: Vec<u8>,
+    // Last frame received and its number
+    framebuffer: Box<[u8; FRAMEBUFFER_SIZE]>,
+    frame: usize,
+    // Frames requested since power on, and those not received yet
+    frames_sent: usize,
+    frames_queued: usize,
+    snapshot: Option<Box<Snapshot>>,
+    // The emulator changed since the last snapshot
+    snapshot_stale: bool,
+    snapshot_requested: bool,
+    // Copy of the breakpoints of the emulator, sent on each change
+    breakpoints: Breakpoints,
+    // Breakpoint which paused the emulation
+    hit: Option<Hit>,
+    // Copy of the cheats of the emulator, sent on each change
+    cheats: Cheats,
+    sample_rate: u32,
+    // Settings of the PPU and the APU kept across the resets
+    layers: Layers,
+    channels: Channels,
+    screen: Screen,
+    input_map: InputMap,
+    // File the bindings edited are saved to
+    input_map_path: Option<PathBuf>,
+    bindings_panel: BindingsPanel,
+    show_bindings: bool,
+    turbo: Turbo,
+    // Buttons held on the keyboard, in the format of `Joypad::state`
+    held: u8,
+    paused: bool,
+    frame_advance: bool,
+    fast_forward: bool,
+    // Multiplier of the emulation speed, ignored when uncapped
+    speed: f32,
+    uncapped: bool,
+    // Motor of the rumble cartridge at the last frame, shown in the status bar
+    rumble: bool,
+    stats: FrameStats,
+    show_registers: bool,
+    show_memory: bool,
+    show_disassembly: bool,
+    show_vram: bool,
+    show_mixer: bool,
+    show_breakpoints: bool,
+    show_watches: bool,
+    show_call_stack: bool,
+    show_profiler: bool,
+    fullscreen: bool,
+    memory_viewer: MemoryViewer,
+    breakpoints_panel: BreakpointsPanel,
+    watches_panel: WatchesPanel,
+    profiler_panel: ProfilerPanel,
+    annotations: BTreeMap<usize, Vec<Annotation>>,
+    vram_viewer: VramViewer,
+    mixer: Mixer,
+    cheats_panel: CheatsPanel,
+    show_cheats: bool,
+    next_frame: Instant,
+    #[cfg(feature = "audio")]
+    audio: Option<AudioOutput>,
+    wav_dump: Option<WavDump>,
+    gif_recorder: Option<(PathBuf, GifRecorder<BufWriter<File>>)>,
+    movie: Option<MovieMode>,
+    screenshot_at_frame: Option<usize>,
+    // Serial output so far when it is echoed to the standard output
+    serial_echo: Option<Vec<u8>>,
+    // Replay recorded since the last power on, saved to the path on exit
+    #[cfg(feature = "serde")]
+    replay: Option<(PathBuf, Arc<Mutex<Option<Recorder>>>)>,
+    settings: Settings,
+    settings_path: Option<PathBuf>,
+    // Palette of the games without a palette override
+    palette: DmgPalette,
+    rom_hash: u64,
+    // File of the ROM, the save states are next to it
+    rom_path: Option<PathBuf>,
+    state_slot: u8,
+    // New title of the window, sent on the next update
+    title: Option<String>,
+}
+
+impl MyApp {
+    pub fn new(emulator: Emulator) -> Self {
+        Self {
+            rom: emulator.rom().to_vec(),
+            framebuffer: Box::new(*emulator.framebuffer()),
+            frame: emulator.frame(),
+            frames_sent: emulator.frame(),
+            frames_queued: 0,
+            snapshot: None,
+            snapshot_stale: true,
+            snapshot_requested: false,
+            breakpoints: emulator.cpu().breakpoints().clone(),
+            hit: None,
+            cheats: emulator.mmu().cheats().clone(),
+            sample_rate: emulator.mmu().apu().sample_rate(),
+            layers: emulator.mmu().ppu().layers(),
+            channels: Channels::default(),
+            runner: Runner::spawn(emulator),
+            screen: Screen::new(),
+            input_map: InputMap::default(),
+            input_map_path: None,
+            bindings_panel: Default::default(),
+            show_bindings: false,
+            turbo: Turbo::default(),
+            held: 0,
+            paused: false,
+            frame_advance: false,
+            fast_forward: false,
+            speed: 1.0,
+            uncapped: false,
+            rumble: false,
+            stats: FrameStats::new(),
+            show_registers: false,
+            show_memory: false,
+            show_disassembly: false,
+            show_vram: false,
+            show_mixer: false,
+            show_cheats: false,
+            show_breakpoints: false,
+            show_watches: false,
+            show_call_stack: false,
+            show_profiler: false,
+            fullscreen: false,
+            memory_viewer: Default::default(),
+            breakpoints_panel: Default::default(),
+            watches_panel: Default::default(),
+            profiler_panel: Default::default(),
+            annotations: BTreeMap::new(),
+            vram_viewer: Default::default(),
+            mixer: Default::default(),
+            cheats_panel: Default::default(),
+            next_frame: Instant::now(),
+            #[cfg(feature = "audio")]
+            audio: None,
+            wav_dump: None,
+            gif_recorder: None,
+            movie: None,
+            screenshot_at_frame: None,
+            serial_echo: None,
+            #[cfg(feature = "serde")]
+            replay: None,
+            settings: Settings::default(),
+            settings_path: None,
+            palette: DmgPalette::default(),
+            rom_hash: movie::rom_hash(&[]),
+            rom_path: None,
+            state_slot: 1,
+            title: None,
+        }
+    }
+
+    /// Settings saved to `path` when they change
+    pub fn set_settings(&mut self, settings: Settings, path: PathBuf) {
+        self.settings = settings;
+        self.settings_path = Some(path);
+    }
+
+    /// Palette of the games without a palette override, applied on the next
+    /// ROM load
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+    }
+
+    /// Bindings of the keys, saved to `path` when edited in the `Input` menu
+    pub fn set_input_map(&mut self, input_map: InputMap, path: PathBuf) {
+        self.input_map = input_map;
+        self.input_map_path = Some(path);
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn set_audio_output(&mut self, mut audio: AudioOutput) {
+        audio.set_input_rate(self.sample_rate);
+        self.audio = Some(audio);
+    }
+
+    /// Record the audio, at the sample rate of the APU
+    pub fn set_wav_dump(&mut self, wav_dump: WavDump) {
+        self.wav_dump = Some(wav_dump);
+    }
+
+    pub fn set_movie(&mut self, movie: MovieMode) {
+        self.movie = Some(movie);
+    }
+
+    /// Annotations of the ROM shown in the disassembly, in the format of
+    /// the disassembler
+    pub fn set_annotations(&mut self, annotations: BTreeMap<usize, Vec<Annotation>>) {
+        self.annotations = annotations;
+    }
+
+    /// Save a screenshot after emulating `frame` frames
+    pub fn set_screenshot_at_frame(&mut self, frame: usize) {
+        self.screenshot_at_frame = Some(frame);
+    }
+
+    /// Print the bytes sent on the serial port, and exit once they contain
+    /// the result of a test ROM: with the status 0 when it passed, 1 when
+    /// it failed
+    pub fn set_serial_echo(&mut self) {
+        self.serial_echo = Some(Vec::new());
+    }
+
+    /// Replace the cheats, kept on reset
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cheats = cheats.clone();
+        self.apply(move |emulator| *emulator.mmu_mut().cheats_mut() = cheats);
+    }
+
+    /// Plug the link cable, kept when another cartridge is loaded
+    pub fn set_link(&mut self, link: Box<dyn Link>) {
+        self.apply(move |emulator| emulator.mmu_mut().serial_mut().connect(link));
+    }
+
+    /// Put the transport of the light in front of the infrared port, kept
+    /// when another cartridge is loaded
+    pub fn set_infrared(&mut self, transport: Box<dyn Transport>) {
+        self.apply(move |emulator| emulator.mmu_mut().infrared_mut().connect(transport));
+    }
+
+    /// Record a replay of the emulation from now on, and again from each
+    /// power on. It is saved to `path` on exit.
+    #[cfg(feature = "serde")]
+    pub fn set_replay_recording(&mut self, path: PathBuf) {
+        self.replay = Some((path, Arc::new(Mutex::new(None))));
+        self.start_replay();
+    }
+
+    #[cfg(feature = "serde")]
+    fn start_replay(&mut self) {
+        if let Some((_, recorder)) = &self.replay {
+            let recorder = recorder.clone();
+            self.apply(move |emulator| {
+                *recorder.lock().unwrap() = Some(Recorder::start(emulator));
+            });
+        }
+    }
+
+    // Wait for the emulator thread to save the replay
+    #[cfg(feature = "serde")]
+    fn save_replay(&mut self) {
+        let Some((path, recorder)) = self.replay.take() else {
+            return;
+        };
+        let (done, saved) = mpsc::channel();
+        self.apply(move |emulator| {
+            if let Some(recorder) = recorder.lock().unwrap().take() {
+                if let Err(err) = recorder.finish(emulator).save(&path) {
+                    eprintln!("Error saving the replay {}: {}", path.display(), err);
+                }
+            }
+            let _ = done.send(());
+        });
+        let _ = saved.recv();
+    }
+
+    /// Replace the cartridge and restart the emulation. The movie is stopped,
+    /// the cheats of the previous game are removed.
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        self.stop_movie();
+        self.save_battery();
+        self.rom_path = None;
+        if !self.cheats.is_empty() {
+            self.set_cheats(Cheats::default());
+        }
+        self.power_on(rom);
+    }
+
+    /// Restart the emulation of the current cartridge, from the state left
+    /// by the boot ROM. A movie being recorded starts over.
+    pub fn reset(&mut self) {
+        self.save_battery();
+        self.power_on(self.rom.clone());
+        self.load_battery();
+        if let Some(MovieMode::Record(movie, _)) = &mut self.movie {
+            movie.truncate(0);
+        }
+    }
+
+    /// Add `path` to the recent ROMs and show its name in the title. The
+    /// save states and the battery save of the ROM loaded are kept next to
+    /// it.
+    pub fn add_recent(&mut self, path: &Path) {
+        self.rom_path = Some(path.to_path_buf());
+        self.load_battery();
+        self.settings.add_recent(path);
+        self.save_settings();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.title = Some(format!("gb - {}", name));
+    }
+
+    /// Write the RAM of the cartridge to its battery save
+    fn save_battery(&mut self) {
+        if let Some(path) = self.rom_path.as_ref().map(mbc::battery_path) {
+            self.apply(move |emulator| {
+                if let Err(err) = emulator.save_battery_file(&path) {
+                    eprintln!("Error saving {}: {}", path.display(), err);
+                }
+            });
+        }
+    }
+
+    // Restore the battery save of the ROM file, if there is one
+    fn load_battery(&mut self) {
+        let Some(path) = self.rom_path.as_ref().map(mbc::battery_path) else {
+            return;
+        };
+        if path.exists() {
+            self.apply(move |emulator| {
+                if let Err(err) = emulator.load_battery_file(&path) {
+                    eprintln!("Error loading {}: {}", path.display(), err);
+                }
+            });
+        }
+    }
+
+    // The debug settings of the PPU and the settings of the APU are kept
+    fn power_on(&mut self, rom: Vec<u8>) {
+        self.rom_hash = movie::rom_hash(&rom);
+        let game = self.settings.game(self.rom_hash);
+
+        let options = emulator::Options {
+            palette: game.palette.unwrap_or(self.palette),
+            sample_rate: Some(self.sample_rate),
+            ..Default::default()
+        };
+        self.send(Command::LoadRom(rom.clone(), options));
+        let (layers, channels) = (self.layers, self.channels);
+        self.apply(move |emulator| {
+            #[cfg(feature = "serde")]
+            emulator.set_rewind(Some(Rewind::default()));
+            let mmu = emulator.mmu_mut();
+            mmu.ppu_mut().set_layers(layers);
+            channels.apply(mmu.apu_mut());
+        });
+        #[cfg(feature = "serde")]
+        self.start_replay();
+        self.hit = None;
+        self.rom = rom;
+        self.frames_sent = 0;
+        self.speed = game.speed.unwrap_or(1.0).clamp(SPEEDS[0], SPEEDS[5]);
+        self.uncapped = false;
+        self.state_slot = game.slot.unwrap_or(1);
+    }
+
+    /// Execute a single instruction, for debugging while paused
+    fn step_instruction(&mut self) {
+        self.resume();
+        self.send(Command::Step);
+    }
+
+    /// Run up to the instruction after the current one, or after the
+    /// subroutine it calls, and pause
+    fn step_over(&mut self) {
+        self.resume();
+        self.apply(|emulator| {
+            emulator.step_over();
+        });
+        self.paused = false;
+    }
+
+    /// Run up to the return of the current subroutine and pause
+    fn step_out(&mut self) {
+        self.resume();
+        self.apply(Emulator::step_out);
+        self.paused = false;
+    }
+
+    /// File of the save state slot selected, None without a ROM file
+    #[cfg(feature = "serde")]
+    fn state_path(&self) -> Option<PathBuf> {
+        let path = self
+            .rom_path
+            .as_ref()
+            .map(|rom| state::slot_path(rom, self.state_slot));
+        if path.is_none() {
+            eprintln!("No save state slots without a ROM file");
+        }
+        path
+    }
+
+    #[cfg(feature = "serde")]
+    fn save_state_slot(&mut self) {
+        if let Some(path) = self.state_path() {
+            self.apply(move |emulator| {
+                if let Err(err) = emulator.save_state_file(&path) {
+                    eprintln!("Error saving {}: {}", path.display(), err);
+                }
+            });
+        }
+    }
+
+    /// Restore a state saved with the current ROM
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, data: Vec<u8>) {
+        self.resume();
+        self.apply(move |emulator| {
+            if let Err(err) = emulator.load_state(&data) {
+                eprintln!("Error loading the state: {}", err);
+            }
+        });
+        // Show the frame restored
+        self.frame_advance = self.paused;
+    }
+
+    /// Let the emulator thread run the frames again after a breakpoint
+    fn resume(&mut self) {
+        if self.hit.take().is_some() {
+            self.send(Command::Resume);
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        self.runner.send(command);
+        self.snapshot_stale = true;
+    }
+
+    /// Change the emulator, on its thread
+    fn apply(&mut self, change: impl FnOnce(&mut Emulator) + Send + 'static) {
+        self.send(Command::Apply(Box::new(change)));
+    }
+
+    fn open_rom(&mut self, path: &Path) {
+        match crate::rom::read(path) {
+            Ok(rom) => {
+                self.load_rom(rom);
+                self.add_recent(path);
+            }
+            Err(err) => eprintln!("Error opening {}: {}", path.display(), err),
+        }
+    }
+
+    /// Set the palette override of the current game, `None` to use the
+    /// default palette
+    fn set_game_palette(&mut self, palette: Option<DmgPalette>) {
+        let mut game = self.settings.game(self.rom_hash);
+        game.palette = palette;
+        self.settings.set_game(self.rom_hash, game);
+        let palette = palette.unwrap_or(self.palette);
+        self.apply(move |emulator| emulator.mmu_mut().ppu_mut().set_dmg_palette(palette));
+        self.save_settings();
+    }
+
+    fn save_input_map(&self) {
+        if let Some(path) = &self.input_map_path {
+            if let Err(err) = self.input_map.save_file(path) {
+                eprintln!("Error saving the key bindings {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    fn save_settings(&self) {
+        if let Some(path) = &self.settings_path {
+            if let Err(err) = self.settings.save_file(path) {
+                eprintln!("Error saving the settings {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Restore the display settings and the open panels saved by
+    /// `eframe::App::save`. eframe restores the geometry of the window and
+    /// egui the position and size of the panels.
+    pub fn restore(&mut self, ctx: &egui::Context, storage: &dyn eframe::Storage) {
+        if let Some(scale) = storage.get_string("scale").and_then(|s| Scale::parse(&s)) {
+            self.screen.set_scale(scale);
+        }
+        if storage.get_string("fullscreen").as_deref() == Some("true") {
+            self.set_fullscreen(ctx, true);
+        }
+        if let Some(open) = storage.get_string("panels") {
+            let open: Vec<&str> = open.split(',').collect();
+            for (name, shown) in self.panels() {
+                *shown = open.contains(&name);
+            }
+        }
+    }
+
+    /// Visibility of the panels, by name
+    fn panels(&mut self) -> [(&'static str, &mut bool); 11] {
+        [
+            ("registers", &mut self.show_registers),
+            ("memory", &mut self.show_memory),
+            ("disassembly", &mut self.show_disassembly),
+            ("vram", &mut self.show_vram),
+            ("mixer", &mut self.show_mixer),
+            ("cheats", &mut self.show_cheats),
+            ("bindings", &mut self.show_bindings),
+            ("breakpoints", &mut self.show_breakpoints),
+            ("watches", &mut self.show_watches),
+            ("call_stack", &mut self.show_call_stack),
+            ("profiler", &mut self.show_profiler),
+        ]
+    }
+
+    fn set_fullscreen(&mut self, ctx: &egui::Context, fullscreen: bool) {
+        self.fullscreen = fullscreen;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(fullscreen));
+    }
+
+    fn stop_movie(&mut self) {
+        if let Some(MovieMode::Record(movie, path)) = self.movie.take() {
+            if let Err(err) = movie.save(&path) {
+                eprintln!("Error saving the movie {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    fn handle_input(&mut self, ctx: &egui::Context) {
+        let dropped = ctx.input(|input| input.raw.dropped_files.clone());
+        if let Some(path) = dropped.iter().find_map(|file| file.path.as_ref()) {
+            self.open_rom(path);
+        }
+
+        let events = ctx.input(|input| input.events.clone());
+        for event in events {
+            let egui::Event::Key {
+                key,
+                pressed,
+                repeat: false,
+                ..
+            } = event
+            else {
+                continue;
+            };
+            if pressed && self.bindings_panel.capture(key, &mut self.input_map) {
+                self.save_input_map();
+                continue;
+            }
+            let Some(action) = self.input_map.action(key.name()) else {
+                continue;
+            };
+            match action {
+                Action::Joypad(button) if pressed => self.held |= button.mask(),
+                Action::Joypad(button) => self.held &= !button.mask(),
+                Action::FastForward => self.fast_forward = pressed,
+                Action::Pause if pressed => self.paused = !self.paused,
+                Action::FrameAdvance if pressed => {
+                    self.paused = true;
+                    self.frame_advance = true;
+                }
+                Action::Screenshot if pressed => self.save_screenshot(),
+                Action::Fullscreen if pressed => self.set_fullscreen(ctx, !self.fullscreen),
+                Action::SpeedUp if pressed => self.change_speed(true),
+                Action::SpeedDown if pressed => self.change_speed(false),
+                Action::StateSlot(slot) if pressed => self.set_state_slot(slot),
+                Action::Turbo(button) if pressed => self.turbo.toggle(button),
+                #[cfg(feature = "serde")]
+                Action::SaveState if pressed => self.save_state_slot(),
+                // The movies and the replays would not match the frames anymore
+                #[cfg(feature = "serde")]
+                Action::Rewind if pressed && self.movie.is_none() && self.replay.is_none() => {
+                    self.resume();
+                    self.apply(|emulator| {
+                        if let Err(err) = emulator.rewind(1.0) {
+                            eprintln!("Error rewinding: {}", err);
+                        }
+                    });
+                    // Show the frame rewound to
+                    self.frame_advance = self.paused;
+                }
+                #[cfg(feature = "serde")]
+                Action::LoadState if pressed && self.movie.is_none() && self.replay.is_none() => {
+                    if let Some(path) = self.state_path() {
+                        match std::fs::read(&path) {
+                            Ok(data) => self.load_state(data),
+                            Err(err) => eprintln!("Error loading {}: {}", path.display(), err),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Queue a frame on the emulator thread, with the buttons of the
+    /// keyboard or of the movie
+    fn run_frame(&mut self) {
+        let live = self.turbo.next_frame(self.held);
+        let state = match &mut self.movie {
+            Some(MovieMode::Play(movie)) => movie.frame(self.frames_sent).unwrap_or(live),
+            Some(MovieMode::Record(movie, _)) => {
+                movie.record(live);
+                live
+            }
+            None => live,
+        };
+        self.resume();
+        self.send(Command::RunFrame(state));
+        self.frames_sent += 1;
+        self.frames_queued += 1;
+    }
+
+    /// Play and show the frames sent by the emulator thread, returns their
+    /// number
+    fn receive_events(&mut self) -> u32 {
+        let events: Vec<Event> = self.runner.events().collect();
+        let mut frames = 0;
+        for event in events {
+            match event {
+                Event::Frame(frame) => {
+                    frames += 1;
+                    self.frames_queued = self.frames_queued.saturating_sub(1);
+                    #[cfg(feature = "audio")]
+                    if let Some(audio) = &mut self.audio {
+                        audio.push(&frame.samples);
+                    }
+                    if let Some(wav_dump) = &mut self.wav_dump {
+                        if let Err(err) = wav_dump.write(&frame.samples) {
+                            eprintln!("Stopping the audio dump: {}", err);
+                            self.wav_dump = None;
+                        }
+                    }
+                    if let Some((path, recorder)) = &mut self.gif_recorder {
+                        if let Err(err) = recorder.push(&frame.framebuffer) {
+                            eprintln!("Stopping the recording of {}: {}", path.display(), err);
+                            self.gif_recorder = None;
+                        }
+                    }
+                    self.framebuffer = frame.framebuffer;
+                    self.frame = frame.number;
+                    self.rumble = frame.rumble;
+                    if self.screenshot_at_frame == Some(frame.number) {
+                        self.save_screenshot();
+                    }
+                    if let Some(output) = &mut self.serial_echo {
+                        let mut stdout = std::io::stdout();
+                        let _ = stdout.write_all(&frame.serial);
+                        let _ = stdout.flush();
+                        output.extend(&frame.serial);
+                        if let Some(passed) = serial::test_result(output) {
+                            std::process::exit(if passed { 0 } else { 1 });
+                        }
+                    }
+                }
+                Event::Snapshot(snapshot) => {
+                    self.snapshot = Some(snapshot);
+                    self.snapshot_requested = false;
+                }
+                Event::Break(hit) => {
+                    // The frames queued after the breakpoint are dropped
+                    self.hit = Some(hit);
+                    self.paused = true;
+                    self.frames_queued = 0;
+                }
+            }
+        }
+        frames
+    }
+
+    /// A debug panel showing the snapshot is open
+    fn debugging(&self) -> bool {
+        self.show_registers
+            || self.show_memory
+            || self.show_disassembly
+            || self.show_vram
+            || self.show_watches
+            || self.show_call_stack
+            || self.show_profiler
+    }
+
+    fn save_screenshot(&self) {
+        let path = format!("screenshot-{}.png", self.frame);
+        let image = Image {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: self.framebuffer.to_vec(),
+        };
+        match image.save_png(&path) {
+            Ok(()) => println!("Saved {}", path),
+            Err(err) => eprintln!("Error saving {}: {}", path, err),
+        }
+    }
+
+    /// Record the screen to `path`, one frame out of 2
+    fn start_gif(&mut self, path: PathBuf) {
+        match GifRecorder::create(&path, 2) {
+            Ok(recorder) => self.gif_recorder = Some((path, recorder)),
+            Err(err) => eprintln!("Error creating {}: {}", path.display(), err),
+        }
+    }
+
+    fn stop_gif(&mut self) {
+        if let Some((path, recorder)) = self.gif_recorder.take() {
+            match recorder.finish().and_then(|mut file| Ok(file.flush()?)) {
+                Ok(()) => println!("Saved {}", path.display()),
+                Err(err) => eprintln!("Error saving {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    /// Move to the next or the previous step of `SPEEDS`
+    fn change_speed(&mut self, faster: bool) {
+        let speed = self.speed;
+        if faster {
+            match SPEEDS.iter().find(|&&speed| speed > self.speed) {
+                Some(&speed) => self.speed = speed,
+                None => self.uncapped = true,
+            }
+        } else if self.uncapped {
+            self.uncapped = false;
+        } else if let Some(&speed) = SPEEDS.iter().rev().find(|&&speed| speed < self.speed) {
+            self.speed = speed;
+        }
+        if self.speed != speed {
+            self.save_game_speed();
+        }
+    }
+
+    /// Keep the speed for the next runs of the current game
+    fn save_game_speed(&mut self) {
+        let mut game = self.settings.game(self.rom_hash);
+        game.speed = Some(self.speed);
+        self.settings.set_game(self.rom_hash, game);
+        self.save_settings();
+    }
+
+    /// Select the save state slot, kept for the next runs of the current game
+    fn set_state_slot(&mut self, slot: u8) {
+        self.state_slot = slot;
+        let mut game = self.settings.game(self.rom_hash);
+        game.slot = Some(slot);
+        self.settings.set_game(self.rom_hash, game);
+        self.save_settings();
+    }
+
+    fn status_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.monospace(format!(
+                "{:5.1} FPS {:5.1} ms",
+                self.stats.fps(),
+                self.stats.frame_time().as_secs_f32() * 1000.0
+            ));
+            ui.separator();
+            let response = ui.add_enabled(
+                !self.uncapped,
+                egui::Slider::new(&mut self.speed, SPEEDS[0]..=SPEEDS[5])
+                    .logarithmic(true)
+                    .suffix("x")
+                    .text("Speed"),
+            );
+            // Saved once the slider is released, or typed in
+            if response.drag_stopped() || response.changed() && !response.dragged() {
+                self.save_game_speed();
+            }
+            ui.checkbox(&mut self.uncapped, "Uncapped");
+            #[cfg(feature = "serde")]
+            {
+                ui.separator();
+                ui.monospace(format!("Slot {}", self.state_slot));
+            }
+            if self.gif_recorder.is_some() {
+                ui.separator();
+                ui.monospace("Recording");
+            }
+            if self.rumble {
+                ui.separator();
+                ui.monospace("Rumble");
+            }
+            if let Some(hit) = self.hit {
+                ui.separator();
+                ui.monospace(format!("Breakpoint {} at 0x{:04x}", hit.id, hit.pc));
+            }
+        });
+    }
+
+    fn show_mixer(&mut self, ui: &mut egui::Ui) {
+        let channels = self.channels;
+        #[cfg(feature = "audio")]
+        if let Some(name) = self.mixer.show(ui, &mut self.channels, self.audio.as_mut()) {
+            match AudioOutput::with_device(&name) {
+                Ok(mut audio) => {
+                    if let Some(previous) = &self.audio {
+                        audio.set_volume(previous.volume());
+                    }
+                    self.set_audio_output(audio);
+                }
+                Err(err) => eprintln!("Error opening {}: {}", name, err),
+            }
+        }
+        #[cfg(not(feature = "audio"))]
+        self.mixer.show(ui, &mut self.channels);
+        if self.channels != channels {
+            let channels = self.channels;
+            self.apply(move |emulator| channels.apply(emulator.mmu_mut().apu_mut()));
+        }
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .button(if self.paused { "Resume" } else { "Pause" })
+            .clicked()
+        {
+            self.paused = !self.paused;
+        }
+        if ui.button("Reset").clicked() {
+            self.reset();
+        }
+        if ui
+            .add_enabled(self.paused, egui::Button::new("Step"))
+            .on_hover_text("Execute one instruction")
+            .clicked()
+        {
+            self.step_instruction();
+        }
+        if ui
+            .add_enabled(self.paused, egui::Button::new("Over"))
+            .on_hover_text("Step over the subroutine called")
+            .clicked()
+        {
+            self.step_over();
+        }
+        if ui
+            .add_enabled(self.paused, egui::Button::new("Out"))
+            .on_hover_text("Run up to the return of the subroutine")
+            .clicked()
+        {
+            self.step_out();
+        }
+        if ui
+            .add_enabled(self.paused, egui::Button::new("Frame"))
+            .on_hover_text("Run up to the next frame")
+            .clicked()
+        {
+            self.frame_advance = true;
+            ui.ctx().request_repaint();
+        }
+    }
+}
+
+impl eframe::App for MyApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_input(ctx);
+        if let Some(title) = self.title.take() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title));
+        }
+
+        let frames = self.receive_events();
+        self.stats.update(frames);
+
+        let now = Instant::now();
+        if self.paused {
+            if std::mem::take(&mut self.frame_advance) {
+                self.run_frame();
+            }
+            self.next_frame = now;
+        } else if self.fast_forward || self.uncapped {
+            // As many frames as the emulator thread can run
+            while self.frames_queued < MAX_QUEUED_FRAMES {
+                self.run_frame();
+            }
+            self.next_frame = now;
+            ctx.request_repaint();
+        } else {
+            // Skip the frames missed when too far behind, for example while
+            // the window was minimized
+            if now.duration_since(self.next_frame) > FRAME_DURATION * MAX_LAG_FRAMES {
+                self.next_frame = now;
+            }
+            let frame_duration = FRAME_DURATION.div_f32(self.speed);
+            while self.next_frame <= now {
+                self.run_frame();
+                self.next_frame += frame_duration;
+            }
+            ctx.request_repaint_after(self.next_frame - now);
+        }
+        if self.debugging() && self.snapshot_stale && !self.snapshot_requested {
+            self.runner.send(Command::Snapshot);
+            self.snapshot_stale = false;
+            self.snapshot_requested = true;
+        }
+        // Show the results as soon as they are received
+        if self.frames_queued > 0 || self.snapshot_requested {
+            ctx.request_repaint();
+        }
+
+        self.screen.update(ctx, &self.framebuffer[..]);
+        egui::TopBottomPanel::top("menu").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Open ROM...").clicked() {
+                        ui.close_menu();
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Game Boy ROM", &["gb", "gbc", "zip", "gz"])
+                            .pick_file()
+                        {
+                            self.open_rom(&path);
+                        }
+                    }
+                    if ui.button("Load annotations...").clicked() {
+                        ui.close_menu();
+                        if let Some(path) = rfd::FileDialog::new().pick_file() {
+                            match Annotation::parse_file(&path) {
+                                Ok(annotations) => self.annotations = annotations,
+                                Err(err) => {
+                                    eprintln!("Error loading {}: {}", path.display(), err)
+                                }
+                            }
+                        }
+                    }
+                    if self.gif_recorder.is_some() {
+                        if ui.button("Stop recording").clicked() {
+                            ui.close_menu();
+                            self.stop_gif();
+                        }
+                    } else if ui.button("Record GIF...").clicked() {
+                        ui.close_menu();
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("GIF", &["gif"])
+                            .set_file_name("recording.gif")
+                            .save_file()
+                        {
+                            self.start_gif(path);
+                        }
+                    }
+                    ui.separator();
+                    let mut open = None;
+                    for path in self.settings.recent() {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        if ui
+                            .button(name)
+                            .on_hover_text(path.display().to_string())
+                            .clicked()
+                        {
+                            open = Some(path.clone());
+                        }
+                    }
+                    if let Some(path) = open {
+                        ui.close_menu();
+                        self.open_rom(&path);
+                    }
+                });
+                ui.menu_button("View", |ui| {
+                    let mut scale = self.screen.scale();
+                    for choice in Scale::ALL {
+                        ui.radio_value(&mut scale, choice, choice.to_string());
+                    }
+                    self.screen.set_scale(scale);
+                    ui.separator();
+                    // Palette override of the current game
+                    let current = self.settings.game(self.rom_hash).palette;
+                    let mut palette = current;
+                    ui.add_enabled_ui(!self.rom.is_empty(), |ui| {
+                        ui.radio_value(&mut palette, None, "Default palette");
+                        ui.radio_value(&mut palette, Some(DmgPalette::GREY), "Grey");
+                        ui.radio_value(&mut palette, Some(DmgPalette::GREEN), "Green");
+                    });
+                    if palette != current {
+                        self.set_game_palette(palette);
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut self.show_mixer, "Audio mixer");
+                    ui.checkbox(&mut self.show_cheats, "Cheats");
+                    let mut fullscreen = self.fullscreen;
+                    if ui.checkbox(&mut fullscreen, "Fullscreen").changed() {
+                        self.set_fullscreen(ctx, fullscreen);
+                    }
+                });
+                ui.menu_button("Input", |ui| {
+                    ui.checkbox(&mut self.show_bindings, "Key bindings");
+                    ui.separator();
+                    // Auto-fire of the buttons while held
+                    for button in Button::ALL {
+                        let mut enabled = self.turbo.enabled(button);
+                        let label = format!("Turbo {:?}", button);
+                        if ui.checkbox(&mut enabled, label).changed() {
+                            self.turbo.set_enabled(button, enabled);
+                        }
+                    }
+                });
+                ui.menu_button("Debug", |ui| {
+                    ui.checkbox(&mut self.show_registers, "Registers");
+                    ui.checkbox(&mut self.show_memory, "Memory");
+                    ui.checkbox(&mut self.show_disassembly, "Disassembly");
+                    ui.checkbox(&mut self.show_vram, "VRAM");
+                    ui.checkbox(&mut self.show_breakpoints, "Breakpoints");
+                    ui.checkbox(&mut self.show_watches, "Watches");
+                    ui.checkbox(&mut self.show_call_stack, "Call stack");
+                    ui.checkbox(&mut self.show_profiler, "Profiler");
+                    ui.separator();
+                    let layers = self.layers;
+                    ui.checkbox(&mut self.layers.background, "Background");
+                    ui.checkbox(&mut self.layers.window, "Window");
+                    ui.checkbox(&mut self.layers.objects, "Objects");
+                    if self.layers != layers {
+                        let layers = self.layers;
+                        self.apply(move |emulator| emulator.mmu_mut().ppu_mut().set_layers(layers));
+                    }
+                });
+                ui.separator();
+                self.toolbar(ui);
+            });
+        });
+        if let (true, Some(snapshot)) = (self.show_registers, &self.snapshot) {
+            egui::SidePanel::right("registers")
+                .show(ctx, |ui| registers::show(ui, &snapshot.cpu, &snapshot.mmu));
+        }
+        if let Some(snapshot) = &self.snapshot {
+            egui::Window::new("Disassembly")
+                .open(&mut self.show_disassembly)
+                .show(ctx, |ui| {
+                    disassembly::show(
+                        ui,
+                        &snapshot.mmu,
+                        snapshot.cpu.registers().pc,
+                        &self.annotations,
+                    )
+                });
+        }
+        let mut edit = None;
+        if let Some(snapshot) = &self.snapshot {
+            egui::Window::new("Memory")
+                .open(&mut self.show_memory)
+                .show(ctx, |ui| {
+                    edit = self.memory_viewer.show(ui, &snapshot.mmu, self.paused)
+                });
+        }
+        if let Some(edit) = edit {
+            self.apply(move |emulator| match edit {
+                MemoryEdit::Write(addr, value) => emulator.mmu_mut().write(addr, value),
+                MemoryEdit::EnableHeatmap(enabled) => {
+                    emulator.mmu_mut().set_heatmap(enabled.then(Heatmap::new))
+                }
+                MemoryEdit::ClearHeatmap => {
+                    if let Some(heatmap) = emulator.mmu_mut().heatmap_mut() {
+                        heatmap.clear();
+                    }
+                }
+            });
+        }
+        let mut show_mixer = self.show_mixer;
+        egui::Window::new("Audio mixer")
+            .open(&mut show_mixer)
+            .show(ctx, |ui| self.show_mixer(ui));
+        self.show_mixer = show_mixer;
+        if let Some(snapshot) = &self.snapshot {
+            egui::Window::new("VRAM")
+                .open(&mut self.show_vram)
+                .show(ctx, |ui| self.vram_viewer.show(ui, snapshot.mmu.ppu()));
+        }
+        let mut edit = None;
+        if let Some(snapshot) = &self.snapshot {
+            egui::Window::new("Watches")
+                .open(&mut self.show_watches)
+                .show(ctx, |ui| {
+                    edit = self.watches_panel.show(ui, &snapshot.cpu, &snapshot.mmu)
+                });
+        }
+        if let Some(edit) = edit {
+            self.apply(move |emulator| {
+                let watches = emulator.cpu_mut().watches_mut();
+                match edit {
+                    WatchEdit::Add(watch) => {
+                        watches.add(watch);
+                    }
+                    WatchEdit::Remove(id) => {
+                        watches.remove(id);
+                    }
+                    WatchEdit::ClearChanges => watches.clear_changes(),
+                }
+            });
+        }
+        if let Some(snapshot) = &self.snapshot {
+            egui::Window::new("Call stack")
+                .open(&mut self.show_call_stack)
+                .show(ctx, |ui| {
+                    call_stack::show(ui, snapshot.cpu.call_stack(), &self.annotations)
+                });
+        }
+        let mut edit = None;
+        if let Some(snapshot) = &self.snapshot {
+            egui::Window::new("Profiler")
+                .open(&mut self.show_profiler)
+                .show(ctx, |ui| {
+                    edit = self
+                        .profiler_panel
+                        .show(ui, snapshot.cpu.profiler(), &self.annotations)
+                });
+        }
+        if let Some(edit) = edit {
+            self.apply(move |emulator| match edit {
+                ProfilerEdit::Enable(enabled) => {
+                    emulator.cpu_mut().set_profiler(enabled.then(Profiler::new))
+                }
+                ProfilerEdit::Clear => {
+                    if let Some(profiler) = emulator.cpu_mut().profiler_mut() {
+                        profiler.clear();
+                    }
+                }
+            });
+        }
+        let mut cheats_changed = false;
+        egui::Window::new("Cheats")
+            .open(&mut self.show_cheats)
+            .show(ctx, |ui| {
+                cheats_changed = self.cheats_panel.show(ui, &mut self.cheats)
+            });
+        if cheats_changed {
+            self.set_cheats(self.cheats.clone());
+        }
+        let mut bindings_changed = false;
+        egui::Window::new("Key bindings")
+            .open(&mut self.show_bindings)
+            .show(ctx, |ui| {
+                bindings_changed = self.bindings_panel.show(ui, &mut self.input_map)
+            });
+        if bindings_changed {
+            self.save_input_map();
+        }
+        let mut breakpoints_changed = false;
+        egui::Window::new("Breakpoints")
+            .open(&mut self.show_breakpoints)
+            .show(ctx, |ui| {
+                breakpoints_changed = self.breakpoints_panel.show(ui, &mut self.breakpoints)
+            });
+        if breakpoints_changed {
+            let breakpoints = self.breakpoints.clone();
+            self.apply(move |emulator| *emulator.cpu_mut().breakpoints_mut() = breakpoints);
+        }
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| self.status_bar(ui));
+        egui::CentralPanel::default().show(ctx, |ui| self.screen.show(ui));
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string("scale", self.screen.scale().to_string());
+        storage.set_string("fullscreen", self.fullscreen.to_string());
+        let open: Vec<&str> = self
+            .panels()
+            .into_iter()
+            .filter(|(_, shown)| **shown)
+            .map(|(name, _)| name)
+            .collect();
+        storage.set_string("panels", open.join(","));
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some(wav_dump) = self.wav_dump.take() {
+            if let Err(err) = wav_dump.finalize() {
+                eprintln!("Error writing the audio dump: {}", err);
+            }
+        }
+        self.stop_gif();
+        self.stop_movie();
+        #[cfg(feature = "serde")]
+        self.save_replay();
+        self.save_battery();
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use eframe::egui;
+
+use crate::annotations::Annotation;
+use crate::debugger::profiler::Profiler;
+
+// Rows of the report
+const HOTTEST: usize = 32;
+
+/// Change of the profiler requested by the user
+pub enum ProfilerEdit {
+    Enable(bool),
+    Clear,
+}
+
+/// Hottest addresses, or functions when annotations are loaded, from a
+/// snapshot of the emulator
+#[derive(Default)]
+pub struct ProfilerPanel {
+    by_function: bool,
+}
+
+impl ProfilerPanel {
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        profiler: Option<&Profiler>,
+        annotations: &BTreeMap<usize, Vec<Annotation>>,
+    ) -> Option<ProfilerEdit> {
+        let mut edit = None;
+        ui.horizontal(|ui| {
+            let mut enabled = profiler.is_some();
+            if ui.checkbox(&mut enabled, "Enabled").changed() {
+                edit = Some(ProfilerEdit::Enable(enabled));
+            }
+            if ui.button("Clear").clicked() {
+                edit = Some(ProfilerEdit::Clear);
+            }
+            ui.add_enabled(
+                !annotations.is_empty(),
+                egui::Checkbox::new(&mut self.by_function, "By function"),
+            );
+        });
+        let Some(profiler) = profiler else {
+            return edit;
+        };
+        let total = profiler.total_cycles().max(1) as f64;
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .auto_shrink(false)
+            .show(ui, |ui| {
+                egui::Grid::new("profile").striped(true).show(ui, |ui| {
+                    if self.by_function && !annotations.is_empty() {
+                        for function in profiler.functions(annotations).iter().take(HOTTEST) {
+                            ui.monospace(function.label.as_deref().unwrap_or("?"));
+                            ui.monospace(format!(
+                                "{:5.1}%",
+                                function.cycles as f64 / total * 100.0
+                            ));
+                            ui.monospace(function.executions.to_string());
+                            ui.end_row();
+                        }
+                    } else {
+                        for hotspot in profiler.hottest(HOTTEST) {
+                            ui.monospace(format!("{:04x}", hotspot.pc));
+                            ui.monospace(format!("{:5.1}%", hotspot.cycles as f64 / total * 100.0));
+                            ui.monospace(hotspot.executions.to_string())
+                                .on_hover_text(format!(
+                                    "{:.1} cycles per execution",
+                                    hotspot.cycles as f64 / hotspot.executions as f64
+                                ));
+                            ui.end_row();
+                        }
+                    }
+                });
+            });
+        edit
+    }
+}
@@ -0,0 +1,58 @@
+use eframe::egui;
+
+use crate::cpu::{Cpu, FLAG_C, FLAG_H, FLAG_N, FLAG_Z};
+use crate::interrupts::Interrupt;
+use crate::mmu::Mmu;
+
+const INTERRUPTS: [(&str, Interrupt); 5] = [
+    ("VBlank", Interrupt::VBlank),
+    ("STAT", Interrupt::Stat),
+    ("Timer", Interrupt::Timer),
+    ("Serial", Interrupt::Serial),
+    ("Joypad", Interrupt::Joypad),
+];
+
+/// CPU registers, flags and interrupts
+pub fn show(ui: &mut egui::Ui, cpu: &Cpu, mmu: &Mmu) {
+    let regs = cpu.registers();
+    egui::Grid::new("registers").striped(true).show(ui, |ui| {
+        for (name, value) in [
+            ("AF", regs.af()),
+            ("BC", regs.bc()),
+            ("DE", regs.de()),
+            ("HL", regs.hl()),
+            ("SP", regs.sp),
+            ("PC", regs.pc),
+        ] {
+            ui.monospace(name);
+            ui.monospace(format!("{:04x}", value));
+            ui.end_row();
+        }
+    });
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        for (name, flag) in [("Z", FLAG_Z), ("N", FLAG_N), ("H", FLAG_H), ("C", FLAG_C)] {
+            let mut set = regs.flag(flag);
+            ui.add_enabled(false, egui::Checkbox::new(&mut set, name));
+        }
+    });
+    let state = if cpu.halted() { " (halted)" } else { "" };
+    ui.label(format!("IME {}{}", cpu.ime() as u8, state));
+
+    ui.separator();
+    egui::Grid::new("interrupts").show(ui, |ui| {
+        ui.label("");
+        ui.label("IE");
+        ui.label("IF");
+        ui.end_row();
+        for (name, interrupt) in INTERRUPTS {
+            ui.label(name);
+            for register in [mmu.interrupt_enable(), mmu.interrupt_flag()] {
+                let mut set = register & interrupt.mask() != 0;
+                ui.add_enabled(false, egui::Checkbox::without_text(&mut set));
+            }
+            ui.end_row();
+        }
+    });
+}
@@ -0,0 +1,104 @@
+use std::fmt::Display;
+
+use eframe::egui;
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// How the screen fills the space of the window
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Scale {
+    /// Largest integer factor that fits, all the pixels stay square
+    Auto,
+    /// Fixed integer factor, from 1 to 6
+    Fixed(u8),
+    /// All the space keeping the aspect ratio, the pixels may differ in size
+    Stretch,
+}
+
+impl Scale {
+    pub const ALL: [Self; 8] = [
+        Self::Auto,
+        Self::Fixed(1),
+        Self::Fixed(2),
+        Self::Fixed(3),
+        Self::Fixed(4),
+        Self::Fixed(5),
+        Self::Fixed(6),
+        Self::Stretch,
+    ];
+
+    /// Inverse of `to_string`
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|scale| scale.to_string() == name)
+    }
+}
+
+impl Display for Scale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::Fixed(factor) => write!(f, "{}x", factor),
+            Self::Stretch => f.write_str("stretch"),
+        }
+    }
+}
+
+/// Texture showing the framebuffer of the PPU
+pub struct Screen {
+    texture: Option<egui::TextureHandle>,
+    scale: Scale,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            texture: None,
+            scale: Scale::Auto,
+        }
+    }
+
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    /// Upload the last frame of the PPU
+    pub fn update(&mut self, ctx: &egui::Context, framebuffer: &[u8]) {
+        let image =
+            egui::ColorImage::from_rgba_unmultiplied([SCREEN_WIDTH, SCREEN_HEIGHT], framebuffer);
+        match &mut self.texture {
+            Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+            None => {
+                self.texture =
+                    Some(ctx.load_texture("screen", image, egui::TextureOptions::NEAREST))
+            }
+        }
+    }
+
+    /// Draw the screen centered in the available space
+    pub fn show(&self, ui: &mut egui::Ui) {
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        let available = ui.available_size();
+        let fit = (available.x / SCREEN_WIDTH as f32).min(available.y / SCREEN_HEIGHT as f32);
+        let scale = match self.scale {
+            Scale::Auto => fit.floor().max(1.0),
+            Scale::Fixed(factor) => factor as f32,
+            Scale::Stretch => fit,
+        };
+        let size = egui::vec2(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32) * scale;
+        ui.centered_and_justified(|ui| ui.image((texture.id(), size)));
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
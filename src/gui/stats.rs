@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+/// Emulated frames per second and time between two repaints, averaged
+/// over a second
+pub struct FrameStats {
+    start: Instant,
+    frames: u32,
+    updates: u32,
+    fps: f32,
+    frame_time: Duration,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames: 0,
+            updates: 0,
+            fps: 0.0,
+            frame_time: Duration::ZERO,
+        }
+    }
+
+    /// Count a repaint, after emulating `frames` frames
+    pub fn update(&mut self, frames: u32) {
+        self.frames += frames;
+        self.updates += 1;
+        let elapsed = self.start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frame_time = elapsed / self.updates;
+            self.start = Instant::now();
+            self.frames = 0;
+            self.updates = 0;
+        }
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// Host time between two repaints
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,199 @@
+use eframe::egui;
+
+use crate::model::Model;
+use crate::ppu::{Ppu, TileMap};
+use crate::tiles::Image;
+
+// The tile sheets of the PPU have 16 columns of 24 tiles
+const SHEET_COLUMNS: f32 = 16.0;
+const SHEET_ROWS: f32 = 24.0;
+// Zoom of the tile sheets and of the object previews
+const SCALE: f32 = 2.0;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Tab {
+    Tiles,
+    Maps,
+    Objects,
+}
+
+/// Tile data, tile maps and OAM, refreshed every frame while shown
+pub struct VramViewer {
+    tab: Tab,
+    tile_sheets: [Option<egui::TextureHandle>; 2],
+    maps: [Option<egui::TextureHandle>; 2],
+}
+
+impl VramViewer {
+    pub fn show(&mut self, ui: &mut egui::Ui, ppu: &Ppu) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tab, Tab::Tiles, "Tiles");
+            ui.selectable_value(&mut self.tab, Tab::Maps, "Maps");
+            ui.selectable_value(&mut self.tab, Tab::Objects, "Objects");
+        });
+        ui.separator();
+
+        // The objects are drawn from the tile sheets
+        let banks = match ppu.model() {
+            Model::Dmg => 1,
+            Model::Cgb => 2,
+        };
+        for bank in 0..banks {
+            upload(
+                ui.ctx(),
+                &mut self.tile_sheets[bank],
+                &format!("tiles{}", bank),
+                &ppu.tile_sheet(bank),
+            );
+        }
+
+        match self.tab {
+            Tab::Tiles => {
+                ui.horizontal(|ui| {
+                    for (bank, texture) in self.tile_sheets.iter().take(banks).enumerate() {
+                        ui.vertical(|ui| {
+                            ui.label(format!("Bank {}", bank));
+                            if let Some(texture) = texture {
+                                ui.image((texture.id(), texture.size_vec2() * SCALE));
+                            }
+                        });
+                    }
+                });
+            }
+            Tab::Maps => self.show_maps(ui, ppu),
+            Tab::Objects => self.show_objects(ui, ppu),
+        }
+    }
+
+    fn show_maps(&mut self, ui: &mut egui::Ui, ppu: &Ppu) {
+        let addressing = ppu.tile_addressing();
+        ui.horizontal(|ui| {
+            for (index, map) in [TileMap::Map9800, TileMap::Map9c00].into_iter().enumerate() {
+                upload(
+                    ui.ctx(),
+                    &mut self.maps[index],
+                    &format!("map{}", index),
+                    &ppu.bg_map(map, addressing),
+                );
+                ui.vertical(|ui| {
+                    let mut usage = Vec::new();
+                    if ppu.bg_tile_map() == map {
+                        usage.push("BG");
+                    }
+                    if ppu.window_tile_map() == map {
+                        usage.push("Window");
+                    }
+                    let name = match map {
+                        TileMap::Map9800 => "9800",
+                        TileMap::Map9c00 => "9c00",
+                    };
+                    ui.label(format!("{} {}", name, usage.join(" ")));
+                    if let Some(texture) = &self.maps[index] {
+                        ui.image((texture.id(), texture.size_vec2()));
+                    }
+                });
+            }
+        });
+    }
+
+    fn show_objects(&self, ui: &mut egui::Ui, ppu: &Ppu) {
+        let height = ppu.object_height();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("objects").striped(true).show(ui, |ui| {
+                for header in ["#", "", "X", "Y", "Tile", "Palette", "Flip", "Priority"] {
+                    ui.label(header);
+                }
+                ui.end_row();
+
+                for (index, object) in ppu.objects().iter().enumerate() {
+                    ui.monospace(format!("{:2}", index));
+                    let size = egui::vec2(8.0, height as f32) * SCALE;
+                    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+                    let bank = match ppu.model() {
+                        Model::Dmg => 0,
+                        Model::Cgb => object.bank,
+                    };
+                    if let Some(texture) = &self.tile_sheets[bank] {
+                        // In 8x16 mode the bit 0 of the tile index is ignored
+                        let first = if height == 16 {
+                            object.tile & 0xfe
+                        } else {
+                            object.tile
+                        };
+                        for i in 0..height / 8 {
+                            let top = rect.min + egui::vec2(0.0, i as f32 * 8.0 * SCALE);
+                            let half = egui::Rect::from_min_size(top, egui::vec2(8.0, 8.0) * SCALE);
+                            // A vertical flip also swaps the two tiles
+                            let tile = if object.y_flip && height == 16 {
+                                first + 1 - i as u8
+                            } else {
+                                first + i as u8
+                            };
+                            ui.painter().image(
+                                texture.id(),
+                                half,
+                                tile_uv(tile, object.x_flip, object.y_flip),
+                                egui::Color32::WHITE,
+                            );
+                        }
+                    }
+                    ui.monospace(format!("{:3}", object.x as i16 - 8));
+                    ui.monospace(format!("{:3}", object.y as i16 - 16));
+                    ui.monospace(format!("{:02x}", object.tile));
+                    ui.monospace(match ppu.model() {
+                        Model::Dmg => format!("OBP{}", object.dmg_palette),
+                        Model::Cgb => format!("{} (bank {})", object.cgb_palette, object.bank),
+                    });
+                    ui.monospace(match (object.x_flip, object.y_flip) {
+                        (false, false) => "",
+                        (true, false) => "X",
+                        (false, true) => "Y",
+                        (true, true) => "XY",
+                    });
+                    ui.monospace(if object.bg_priority { "BG" } else { "" });
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+impl Default for VramViewer {
+    fn default() -> Self {
+        Self {
+            tab: Tab::Tiles,
+            tile_sheets: [None, None],
+            maps: [None, None],
+        }
+    }
+}
+
+fn upload(
+    ctx: &egui::Context,
+    texture: &mut Option<egui::TextureHandle>,
+    name: &str,
+    image: &Image,
+) {
+    let image =
+        egui::ColorImage::from_rgba_unmultiplied([image.width, image.height], &image.pixels);
+    match texture {
+        Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+        None => *texture = Some(ctx.load_texture(name, image, egui::TextureOptions::NEAREST)),
+    }
+}
+
+/// Texture coordinates of a tile in a tile sheet, swapping the edges to
+/// flip it
+fn tile_uv(tile: u8, x_flip: bool, y_flip: bool) -> egui::Rect {
+    let column = (tile as usize % 16) as f32;
+    let row = (tile as usize / 16) as f32;
+    let (mut left, mut right) = (column / SHEET_COLUMNS, (column + 1.0) / SHEET_COLUMNS);
+    let (mut top, mut bottom) = (row / SHEET_ROWS, (row + 1.0) / SHEET_ROWS);
+    if x_flip {
+        std::mem::swap(&mut left, &mut right);
+    }
+    if y_flip {
+        std::mem::swap(&mut top, &mut bottom);
+    }
+    egui::Rect::from_min_max(egui::pos2(left, top), egui::pos2(right, bottom))
+}
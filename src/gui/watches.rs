@@ -0,0 +1,87 @@
+use eframe::egui;
+
+use crate::cpu::Cpu;
+use crate::debugger::watches::Watch;
+use crate::mmu::Mmu;
+
+// Changes shown, from the most recent
+const SHOWN_CHANGES: usize = 256;
+
+/// Change of the watches requested by the user
+pub enum WatchEdit {
+    Add(Watch),
+    Remove(usize),
+    ClearChanges,
+}
+
+/// Watched registers and addresses with their value, from a snapshot of the
+/// emulator, and the log of their changes
+#[derive(Default)]
+pub struct WatchesPanel {
+    input: String,
+    error: Option<String>,
+}
+
+impl WatchesPanel {
+    pub fn show(&mut self, ui: &mut egui::Ui, cpu: &Cpu, mmu: &Mmu) -> Option<WatchEdit> {
+        let mut edit = None;
+        ui.horizontal(|ui| {
+            let response =
+                ui.add(egui::TextEdit::singleline(&mut self.input).hint_text("HL, LCDC or 0xc000"));
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if entered || ui.button("Add").clicked() {
+                match self.input.parse::<Watch>() {
+                    Ok(watch) => {
+                        edit = Some(WatchEdit::Add(watch));
+                        self.input.clear();
+                        self.error = None;
+                    }
+                    Err(err) => self.error = Some(err.to_string()),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        let watches = cpu.watches();
+        egui::Grid::new("watches").striped(true).show(ui, |ui| {
+            for (id, watch) in watches.iter() {
+                ui.monospace(watch.to_string());
+                ui.monospace(format!("{:02x}", watch.value(cpu.registers(), mmu)));
+                if ui.small_button("x").on_hover_text("Remove").clicked() {
+                    edit = Some(WatchEdit::Remove(id));
+                }
+                ui.end_row();
+            }
+        });
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Changes");
+            if ui.button("Clear").clicked() {
+                edit = Some(WatchEdit::ClearChanges);
+            }
+        });
+        egui::ScrollArea::vertical()
+            .auto_shrink(false)
+            .show(ui, |ui| {
+                egui::Grid::new("changes").striped(true).show(ui, |ui| {
+                    for change in watches.changes().rev().take(SHOWN_CHANGES) {
+                        match change.pc {
+                            Some(pc) => ui.monospace(format!("{:04x}", pc)),
+                            None => ui.monospace("----").on_hover_text("Interrupt or HALT"),
+                        };
+                        let name = watches
+                            .iter()
+                            .find(|(id, _)| *id == change.id)
+                            .map_or_else(|| format!("#{}", change.id), |(_, w)| w.to_string());
+                        ui.monospace(name);
+                        ui.monospace(format!("{:02x} -> {:02x}", change.old, change.new));
+                        ui.end_row();
+                    }
+                });
+            });
+        edit
+    }
+}
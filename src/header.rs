@@ -0,0 +1,225 @@
+//! Cartridge header, in 0x0100-0x014f of the ROM
+
+use std::fmt::Display;
+
+/// First byte after the header
+pub const HEADER_END: usize = 0x150;
+pub const LOGO: usize = 0x104;
+pub const TITLE: usize = 0x134;
+pub const CGB_FLAG: usize = 0x143;
+pub const SGB_FLAG: usize = 0x146;
+pub const CARTRIDGE_TYPE: usize = 0x147;
+pub const ROM_SIZE: usize = 0x148;
+pub const RAM_SIZE: usize = 0x149;
+pub const HEADER_CHECKSUM: usize = 0x14d;
+pub const GLOBAL_CHECKSUM: usize = 0x14e;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    /// Checksums computed from the ROM
+    pub computed_header_checksum: u8,
+    pub computed_global_checksum: u16,
+}
+
+impl Header {
+    /// None if the ROM is too small to have a header
+    pub fn parse(rom: &[u8]) -> Option<Self> {
+        if rom.len() < HEADER_END {
+            return None;
+        }
+        // The last bytes of the title are the manufacturer code and the CGB
+        // flag in the recent cartridges
+        let title_end = if rom[CGB_FLAG] & 0x80 != 0 {
+            CGB_FLAG
+        } else {
+            CGB_FLAG + 1
+        };
+        let title = rom[TITLE..title_end]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '?',
+            })
+            .collect();
+        let computed_header_checksum = rom[TITLE..HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(address, _)| !(GLOBAL_CHECKSUM..HEADER_END).contains(address))
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        Some(Self {
+            title,
+            cgb_flag: rom[CGB_FLAG],
+            sgb_flag: rom[SGB_FLAG],
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size: rom[ROM_SIZE],
+            ram_size: rom[RAM_SIZE],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+            computed_header_checksum,
+            computed_global_checksum,
+        })
+    }
+
+    /// Memory bank controller and other hardware of the cartridge
+    pub fn cartridge_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "unknown",
+        }
+    }
+
+    /// Size of the ROM declared in the header, None for an invalid value
+    pub fn rom_bytes(&self) -> Option<usize> {
+        (self.rom_size <= 8).then(|| 0x8000 << self.rom_size)
+    }
+
+    /// Size of the external RAM, None for an invalid value
+    pub fn ram_bytes(&self) -> Option<usize> {
+        match self.ram_size {
+            0 => Some(0),
+            1 => Some(0x800),
+            2 => Some(0x2000),
+            3 => Some(0x8000),
+            4 => Some(0x20000),
+            5 => Some(0x10000),
+            _ => None,
+        }
+    }
+}
+
+fn size(bytes: Option<usize>, value: u8) -> String {
+    match bytes {
+        Some(0) => "none".to_string(),
+        Some(bytes) => format!("{} KiB", bytes / 1024),
+        None => format!("invalid (0x{:02x})", value),
+    }
+}
+
+fn check(valid: bool) -> &'static str {
+    if valid {
+        "valid"
+    } else {
+        "invalid"
+    }
+}
+
+/// One line per field
+impl Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cgb = match self.cgb_flag {
+            0xc0 => "CGB only",
+            flag if flag & 0x80 != 0 => "supported",
+            _ => "no",
+        };
+        writeln!(f, "Title            {}", self.title)?;
+        writeln!(f, "CGB              {}", cgb)?;
+        writeln!(
+            f,
+            "SGB              {}",
+            if self.sgb_flag == 0x03 { "yes" } else { "no" }
+        )?;
+        writeln!(
+            f,
+            "Cartridge type   0x{:02x} {}",
+            self.cartridge_type,
+            self.cartridge_name()
+        )?;
+        writeln!(
+            f,
+            "ROM size         {}",
+            size(self.rom_bytes(), self.rom_size)
+        )?;
+        writeln!(
+            f,
+            "RAM size         {}",
+            size(self.ram_bytes(), self.ram_size)
+        )?;
+        writeln!(
+            f,
+            "Header checksum  0x{:02x} {}",
+            self.header_checksum,
+            check(self.header_checksum == self.computed_header_checksum)
+        )?;
+        write!(
+            f,
+            "Global checksum  0x{:04x} {}",
+            self.global_checksum,
+            check(self.global_checksum == self.computed_global_checksum)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        assert_eq!(Header::parse(&[0; 0x14f]), None);
+
+        let mut rom = vec![0; 0x8000];
+        rom[TITLE..TITLE + 6].copy_from_slice(b"TETRIS");
+        rom[CARTRIDGE_TYPE] = 0x03;
+        rom[ROM_SIZE] = 0x02;
+        rom[RAM_SIZE] = 0x02;
+        rom[HEADER_CHECKSUM] = 0x05;
+        rom[GLOBAL_CHECKSUM..HEADER_END].copy_from_slice(&[0x01, 0xe7]);
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.rom_bytes(), Some(0x20000));
+        assert_eq!(header.computed_global_checksum, 0x1e7);
+        assert_eq!(
+            header.to_string(),
+            "Title            TETRIS\nCGB              no\nSGB              no\n\
+             Cartridge type   0x03 MBC1+RAM+BATTERY\nROM size         128 KiB\n\
+             RAM size         8 KiB\nHeader checksum  0x05 valid\n\
+             Global checksum  0x01e7 valid"
+        );
+
+        // The CGB flag is not part of the title
+        rom[TITLE..CGB_FLAG + 1].copy_from_slice(b"POKEMON CRYSTAL\xc0");
+        let header = Header::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON CRYSTAL");
+        assert!(header.to_string().contains("CGB              CGB only"));
+        assert!(header.to_string().ends_with("invalid"));
+    }
+}
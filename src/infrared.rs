@@ -0,0 +1,304 @@
+//! Infrared port of the CGB, an LED and a light sensor on the RP register.
+//! The light received comes from a `Transport`: nothing, the LED of this
+//! console reflected back, or the LED of another emulator over TCP. The
+//! games time the pulses in cycles, which the TCP transport does not keep:
+//! they see the light of the other console change, not its timing.
+//! See https://gbdev.io/pandocs/CGB_Registers.html#ff56--rp-cgb-mode-only-infrared-communications-port
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// Infrared communications port
+pub const RP: u16 = 0xff56;
+
+const RP_LED: u8 = 0x01;
+// Cleared while light is received
+const RP_NO_LIGHT: u8 = 0x02;
+// Both bits set to read the sensor
+const RP_READ_ENABLE: u8 = 0xc0;
+// Between the polls of the transport
+const POLL_CYCLES: u32 = 512;
+
+pub trait Transport: Send {
+    /// The LED of this console was switched on or off
+    fn set_led(&mut self, on: bool);
+    /// True while the sensor receives light
+    fn light(&mut self) -> bool;
+}
+
+/// The LED of this console seen by its own sensor, as with a mirror in front
+/// of the port
+#[derive(Debug, Default)]
+pub struct Loopback(bool);
+
+impl Transport for Loopback {
+    fn set_led(&mut self, on: bool) {
+        self.0 = on;
+    }
+
+    fn light(&mut self) -> bool {
+        self.0
+    }
+}
+
+/// The LED of another emulator over TCP. Each change of a LED is a byte, 1
+/// for on and 0 for off. Once the other emulator disconnects, its LED is
+/// off.
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: Option<TcpStream>,
+    // LED of the other emulator
+    remote: bool,
+    // Change of the LED of this console waiting for room in the send buffer
+    unsent: Option<bool>,
+}
+
+impl TcpTransport {
+    /// Wait for the other emulator to connect to `addr`
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Over a stream already connected to the other emulator
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream: Some(stream),
+            remote: false,
+            unsent: None,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Send the last change of the LED, unless the send buffer is still full
+    fn send(&mut self) {
+        let (Some(stream), Some(on)) = (&mut self.stream, self.unsent) else {
+            return;
+        };
+        loop {
+            match stream.write(&[on as u8]) {
+                Ok(0) => break,
+                Ok(_) => {
+                    self.unsent = None;
+                    return;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                // Sent by the next poll, once the other emulator read the
+                // previous changes
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => break,
+            }
+        }
+        self.stream = None;
+        self.remote = false;
+    }
+
+    /// Read the changes of the other LED sent so far, keeping the last one
+    fn receive(&mut self) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        let mut bytes = [0; 64];
+        loop {
+            match stream.read(&mut bytes) {
+                Ok(0) => break,
+                Ok(len) => self.remote = bytes[len - 1] != 0,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => break,
+            }
+        }
+        // Closed by the other emulator
+        self.stream = None;
+        self.remote = false;
+    }
+}
+
+impl Transport for TcpTransport {
+    fn set_led(&mut self, on: bool) {
+        self.unsent = Some(on);
+        self.send();
+    }
+
+    fn light(&mut self) -> bool {
+        self.send();
+        self.receive();
+        self.remote
+    }
+}
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Infrared {
+    // LED and read enable bits
+    rp: u8,
+    // Nothing in front of the port without one
+    #[cfg_attr(feature = "serde", serde(skip))]
+    transport: Option<Box<dyn Transport>>,
+    // Received at the last poll
+    #[cfg_attr(feature = "serde", serde(skip))]
+    light: bool,
+    // T-cycles before the next poll of the transport
+    #[cfg_attr(feature = "serde", serde(skip))]
+    poll_countdown: u32,
+}
+
+/// A copy is not connected
+impl Clone for Infrared {
+    fn clone(&self) -> Self {
+        Self {
+            rp: self.rp,
+            transport: None,
+            light: false,
+            poll_countdown: 0,
+        }
+    }
+}
+
+impl Infrared {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Replace the transport of the light, the LED is sent to the new one
+    pub fn connect(&mut self, mut transport: Box<dyn Transport>) {
+        transport.set_led(self.led());
+        self.light = transport.light();
+        self.transport = Some(transport);
+    }
+
+    /// Remove the transport, returns it
+    pub fn disconnect(&mut self) -> Option<Box<dyn Transport>> {
+        self.light = false;
+        self.transport.take()
+    }
+
+    /// Take the transport of `previous`, replaced by a reset or a save state
+    pub fn keep_transport(&mut self, previous: &mut Self) {
+        self.transport = previous.transport.take();
+    }
+
+    /// True while the LED of this console is on
+    pub fn led(&self) -> bool {
+        self.rp & RP_LED != 0
+    }
+
+    /// Advance by `cycles` T-cycles, polling the transport now and then
+    pub fn tick(&mut self, cycles: u32) {
+        let Some(transport) = &mut self.transport else {
+            return;
+        };
+        self.poll_countdown = self.poll_countdown.saturating_sub(cycles);
+        if self.poll_countdown == 0 {
+            self.poll_countdown = POLL_CYCLES;
+            self.light = transport.light();
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        let light = self.light && self.rp & RP_READ_ENABLE == RP_READ_ENABLE;
+        // Bits 2 to 5 are unused
+        0x3c | self.rp | if light { 0 } else { RP_NO_LIGHT }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let previous = self.led();
+        self.rp = value & (RP_LED | RP_READ_ENABLE);
+        let led = self.led();
+        if let Some(transport) = &mut self.transport {
+            if led != previous {
+                transport.set_led(led);
+                // Seen at once in loopback
+                self.light = transport.light();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (TcpTransport, TcpTransport) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (TcpTransport::new(stream).unwrap(), client)
+    }
+
+    #[test]
+    fn test_infrared() {
+        let mut infrared = Infrared::new();
+        assert_eq!(infrared.read(), 0x3e);
+        infrared.write(0xff);
+        assert_eq!(infrared.read(), 0xff);
+        assert!(infrared.led());
+
+        infrared.connect(Box::new(Loopback::default()));
+        assert_eq!(infrared.read(), 0xfd);
+        // Disabled reads
+        infrared.write(RP_LED);
+        assert_eq!(infrared.read(), 0x3f);
+        infrared.write(RP_READ_ENABLE);
+        assert_eq!(infrared.read(), 0xfe);
+        assert!(infrared.clone().transport.is_none());
+
+        // Polled by the ticks
+        let (mut other, transport) = pair();
+        infrared.connect(Box::new(transport));
+        other.set_led(true);
+        let mut cycles = 0;
+        while infrared.read() & RP_NO_LIGHT != 0 {
+            infrared.tick(4);
+            cycles += 4;
+            assert!(cycles < 100_000_000, "No light");
+        }
+        assert!(infrared.disconnect().is_some());
+        assert_eq!(infrared.read(), 0xfe);
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let (mut a, mut b) = pair();
+        assert!(!b.light());
+        a.set_led(true);
+        let mut tries = 0;
+        while !b.light() {
+            tries += 1;
+            assert!(tries < 1_000_000, "No light");
+        }
+        a.set_led(false);
+        while b.light() {}
+
+        // Changes faster than the other emulator reads them fill the send
+        // buffer, the last one waits for room
+        while a.unsent.is_none() {
+            a.set_led(false);
+        }
+        a.set_led(true);
+        assert!(a.is_connected());
+        let mut tries = 0;
+        while !b.light() {
+            a.light();
+            tries += 1;
+            assert!(tries < 1_000_000, "No light");
+        }
+        assert_eq!(a.unsent, None);
+
+        b.set_led(true);
+        drop(b);
+        while a.is_connected() {
+            a.light();
+        }
+        assert!(!a.light());
+    }
+}
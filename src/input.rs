@@ -0,0 +1,397 @@
+//! Bindings between the host inputs (keys or gamepad buttons, named by the
+//! frontend) and the actions of the emulator.
+//!
+//! The configuration file has one binding per line, the input name followed
+//! by the action name. Lines starting with `#` are ignored:
+//! ```text
+//! # Fast-forward while held
+//! Tab fast-forward
+//! X a
+//! # Toggle the auto-fire of A
+//! S turbo-a
+//! ```
+
+use std::{
+    collections::BTreeMap, error::Error, fmt::Display, fs::File, io::Read, path::Path, str::FromStr,
+};
+
+use crate::joypad::Button;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    Joypad(Button),
+    FastForward,
+    Pause,
+    /// Run one frame while paused
+    FrameAdvance,
+    /// Save and load the state of the slot selected
+    SaveState,
+    LoadState,
+    /// Select the slot of the save states
+    StateSlot(u8),
+    Screenshot,
+    Fullscreen,
+    SpeedUp,
+    SpeedDown,
+    /// Go back in time, with the save states kept while running
+    Rewind,
+    /// Enable or disable the auto-fire of a button
+    Turbo(Button),
+}
+
+const ACTION_NAMES: [(&str, Action); 36] = [
+    ("right", Action::Joypad(Button::Right)),
+    ("left", Action::Joypad(Button::Left)),
+    ("up", Action::Joypad(Button::Up)),
+    ("down", Action::Joypad(Button::Down)),
+    ("a", Action::Joypad(Button::A)),
+    ("b", Action::Joypad(Button::B)),
+    ("select", Action::Joypad(Button::Select)),
+    ("start", Action::Joypad(Button::Start)),
+    ("fast-forward", Action::FastForward),
+    ("pause", Action::Pause),
+    ("frame-advance", Action::FrameAdvance),
+    ("save-state", Action::SaveState),
+    ("load-state", Action::LoadState),
+    ("slot-0", Action::StateSlot(0)),
+    ("slot-1", Action::StateSlot(1)),
+    ("slot-2", Action::StateSlot(2)),
+    ("slot-3", Action::StateSlot(3)),
+    ("slot-4", Action::StateSlot(4)),
+    ("slot-5", Action::StateSlot(5)),
+    ("slot-6", Action::StateSlot(6)),
+    ("slot-7", Action::StateSlot(7)),
+    ("slot-8", Action::StateSlot(8)),
+    ("slot-9", Action::StateSlot(9)),
+    ("screenshot", Action::Screenshot),
+    ("fullscreen", Action::Fullscreen),
+    ("speed-up", Action::SpeedUp),
+    ("speed-down", Action::SpeedDown),
+    ("rewind", Action::Rewind),
+    ("turbo-right", Action::Turbo(Button::Right)),
+    ("turbo-left", Action::Turbo(Button::Left)),
+    ("turbo-up", Action::Turbo(Button::Up)),
+    ("turbo-down", Action::Turbo(Button::Down)),
+    ("turbo-a", Action::Turbo(Button::A)),
+    ("turbo-b", Action::Turbo(Button::B)),
+    ("turbo-select", Action::Turbo(Button::Select)),
+    ("turbo-start", Action::Turbo(Button::Start)),
+];
+
+impl Action {
+    /// Every action, in the order of the names
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTION_NAMES.iter().map(|&(_, action)| action)
+    }
+}
+
+impl FromStr for Action {
+    type Err = InputMapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTION_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, action)| action)
+            .ok_or_else(|| InputMapError::InvalidAction(s.to_string()))
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, _) = ACTION_NAMES
+            .iter()
+            .find(|(_, action)| action == self)
+            .unwrap();
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct InputMap {
+    bindings: BTreeMap<String, Action>,
+}
+
+impl Default for InputMap {
+    /// Keyboard layout, with the key names used by egui
+    fn default() -> Self {
+        let mut map = Self::empty();
+        for (input, action) in [
+            ("ArrowRight", Action::Joypad(Button::Right)),
+            ("ArrowLeft", Action::Joypad(Button::Left)),
+            ("ArrowUp", Action::Joypad(Button::Up)),
+            ("ArrowDown", Action::Joypad(Button::Down)),
+            ("X", Action::Joypad(Button::A)),
+            ("Z", Action::Joypad(Button::B)),
+            ("Backspace", Action::Joypad(Button::Select)),
+            ("Enter", Action::Joypad(Button::Start)),
+            ("Tab", Action::FastForward),
+            ("P", Action::Pause),
+            ("N", Action::FrameAdvance),
+            ("F5", Action::SaveState),
+            ("F7", Action::LoadState),
+            ("0", Action::StateSlot(0)),
+            ("1", Action::StateSlot(1)),
+            ("2", Action::StateSlot(2)),
+            ("3", Action::StateSlot(3)),
+            ("4", Action::StateSlot(4)),
+            ("5", Action::StateSlot(5)),
+            ("6", Action::StateSlot(6)),
+            ("7", Action::StateSlot(7)),
+            ("8", Action::StateSlot(8)),
+            ("9", Action::StateSlot(9)),
+            ("F12", Action::Screenshot),
+            ("F11", Action::Fullscreen),
+            ("Equals", Action::SpeedUp),
+            ("Plus", Action::SpeedUp),
+            ("Minus", Action::SpeedDown),
+            ("R", Action::Rewind),
+        ] {
+            map.bind(input, action);
+        }
+        map
+    }
+}
+
+impl InputMap {
+    pub fn empty() -> Self {
+        Self {
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(data: &str) -> Result<Self, InputMapError> {
+        let mut map = Self::empty();
+        for line in data
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let (input, action) = line
+                .split_once(char::is_whitespace)
+                .ok_or(InputMapError::MissingField)?;
+            map.bind(input, action.trim().parse()?);
+        }
+        Ok(map)
+    }
+
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Self, InputMapError> {
+        let mut tmp = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut tmp))?;
+        Self::parse(&tmp)
+    }
+
+    /// Configuration file content for the current bindings
+    pub fn to_config(&self) -> String {
+        self.bindings
+            .iter()
+            .map(|(input, action)| format!("{} {}\n", input, action))
+            .collect()
+    }
+
+    pub fn save_file(&self, path: impl AsRef<Path>) -> Result<(), InputMapError> {
+        Ok(std::fs::write(path, self.to_config())?)
+    }
+
+    /// Bind `input` to `action`, replacing the previous action of `input`.
+    /// An action can be bound to several inputs.
+    pub fn bind(&mut self, input: &str, action: Action) {
+        self.bindings.insert(input.to_string(), action);
+    }
+
+    pub fn unbind(&mut self, input: &str) {
+        self.bindings.remove(input);
+    }
+
+    pub fn action(&self, input: &str) -> Option<Action> {
+        self.bindings.get(input).copied()
+    }
+
+    pub fn inputs(&self, action: Action) -> impl Iterator<Item = &str> {
+        self.bindings
+            .iter()
+            .filter(move |(_, a)| **a == action)
+            .map(|(input, _)| input.as_str())
+    }
+}
+
+/// Auto-fire: while held, the buttons with turbo enabled are alternately
+/// pressed and released.
+pub struct Turbo {
+    // Buttons with turbo enabled, in the format of `Joypad::state`
+    buttons: u8,
+    // Frames spent pressed, then released
+    period: u32,
+    frame: u32,
+}
+
+impl Default for Turbo {
+    /// 15 presses per second
+    fn default() -> Self {
+        Self::new(2)
+    }
+}
+
+impl Turbo {
+    pub fn new(period: u32) -> Self {
+        assert!(period > 0, "The turbo period must not be 0");
+        Self {
+            buttons: 0,
+            period,
+            frame: 0,
+        }
+    }
+
+    pub fn enabled(&self, button: Button) -> bool {
+        self.buttons & button.mask() != 0
+    }
+
+    pub fn set_enabled(&mut self, button: Button, enabled: bool) {
+        if enabled {
+            self.buttons |= button.mask();
+        } else {
+            self.buttons &= !button.mask();
+        }
+    }
+
+    pub fn toggle(&mut self, button: Button) {
+        self.set_enabled(button, !self.enabled(button));
+    }
+
+    /// Joypad state to use for the next frame, given the buttons held
+    pub fn next_frame(&mut self, held: u8) -> u8 {
+        let released = (self.frame / self.period) % 2 == 1;
+        self.frame = (self.frame + 1) % (self.period * 2);
+        if released {
+            held & !self.buttons
+        } else {
+            held
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InputMapError {
+    MissingField,
+    InvalidAction(String),
+    IOError(std::io::Error),
+}
+
+impl Error for InputMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::MissingField => None,
+            Self::InvalidAction(_) => None,
+            Self::IOError(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for InputMapError {
+    fn from(value: std::io::Error) -> Self {
+        InputMapError::IOError(value)
+    }
+}
+
+impl Display for InputMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingField => f.write_str("Missing action in input binding"),
+            Self::InvalidAction(action) => write!(f, "Invalid action {}", action),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_map_parse() {
+        let map = InputMap::parse("# comment\nQ  a\n\nW Fast-Forward\nE a\n").unwrap();
+        assert_eq!(map.action("Q"), Some(Action::Joypad(Button::A)));
+        assert_eq!(map.action("W"), Some(Action::FastForward));
+        assert_eq!(map.action("R"), None);
+        let slots = InputMap::parse("F1 slot-3").unwrap();
+        assert_eq!(slots.action("F1"), Some(Action::StateSlot(3)));
+        assert_eq!(Action::StateSlot(3).to_string(), "slot-3");
+        assert_eq!(
+            map.inputs(Action::Joypad(Button::A)).collect::<Vec<_>>(),
+            ["E", "Q"]
+        );
+
+        assert!(matches!(
+            InputMap::parse("Q").unwrap_err(),
+            InputMapError::MissingField
+        ));
+        assert!(matches!(
+            InputMap::parse("Q jump").unwrap_err(),
+            InputMapError::InvalidAction(_)
+        ));
+    }
+
+    #[test]
+    fn test_input_map_edit() {
+        let mut map = InputMap::default();
+        assert_eq!(InputMap::parse(&map.to_config()).unwrap(), map);
+
+        map.bind("Enter", Action::Pause);
+        map.unbind("X");
+        assert_eq!(map.action("Enter"), Some(Action::Pause));
+        assert_eq!(map.action("X"), None);
+        assert_eq!(map.inputs(Action::Joypad(Button::A)).count(), 0);
+
+        for action in Action::all() {
+            assert_eq!(action.to_string().parse::<Action>().unwrap(), action);
+        }
+        assert_eq!(Action::all().count(), ACTION_NAMES.len());
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut turbo = Turbo::new(2);
+        turbo.set_enabled(Button::A, true);
+        assert!(turbo.enabled(Button::A));
+        assert!(!turbo.enabled(Button::B));
+
+        // A and B held
+        let states: Vec<u8> = (0..6).map(|_| turbo.next_frame(0x30)).collect();
+        assert_eq!(states, [0x30, 0x30, 0x20, 0x20, 0x30, 0x30]);
+
+        turbo.set_enabled(Button::A, false);
+        assert_eq!(turbo.next_frame(0x30), 0x30);
+        assert_eq!(turbo.next_frame(0x30), 0x30);
+    }
+
+    #[test]
+    fn test_turbo_action() {
+        let map = InputMap::parse("X a\nS turbo-a\n").unwrap();
+        assert_eq!(map.action("S"), Some(Action::Turbo(Button::A)));
+        assert_eq!(Action::Turbo(Button::Start).to_string(), "turbo-start");
+
+        let mut turbo = Turbo::new(3);
+        if let Some(Action::Turbo(button)) = map.action("S") {
+            turbo.toggle(button);
+        }
+        assert!(turbo.enabled(Button::A));
+
+        // A held, as seen by the game
+        let mut joypad = crate::joypad::Joypad::new();
+        let mut pressed = Vec::new();
+        for _ in 0..12 {
+            let state = turbo.next_frame(Button::A.mask());
+            if state & Button::A.mask() != 0 {
+                joypad.press(Button::A);
+            } else {
+                joypad.release(Button::A);
+            }
+            pressed.push(joypad.pressed(Button::A));
+        }
+        let expected: Vec<bool> = (0..12).map(|frame| (frame / 3) % 2 == 0).collect();
+        assert_eq!(pressed, expected);
+
+        turbo.toggle(Button::A);
+        assert_eq!(turbo.next_frame(Button::A.mask()), Button::A.mask());
+    }
+}
@@ -0,0 +1,30 @@
+/// Interrupt sources, listed by priority. The value of each variant is the bit
+/// used for it in the IE (0xffff) and IF (0xff0f) registers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Interrupt {
+    VBlank = 0x01,
+    Stat = 0x02,
+    Timer = 0x04,
+    Serial = 0x08,
+    Joypad = 0x10,
+}
+
+impl Interrupt {
+    pub fn mask(self) -> u8 {
+        self as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupt_mask() {
+        assert_eq!(Interrupt::VBlank.mask(), 0x01);
+        assert_eq!(Interrupt::Stat.mask(), 0x02);
+        assert_eq!(Interrupt::Timer.mask(), 0x04);
+        assert_eq!(Interrupt::Serial.mask(), 0x08);
+        assert_eq!(Interrupt::Joypad.mask(), 0x10);
+    }
+}
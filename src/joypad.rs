@@ -0,0 +1,143 @@
+//! Joypad register, the buttons are read through a 2x4 matrix
+//! See https://gbdev.io/pandocs/Joypad_Input.html
+
+/// P1/JOYP register
+pub const P1: u16 = 0xff00;
+
+// Bits of P1 selecting a row of the matrix when cleared
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Bit of the button in `Joypad::state`. Directions use the low nibble
+    /// and the other buttons the high one, in the order of the P1 bits.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+    // Bits 4-5 of P1
+    select: u8,
+    // One bit per button, set when pressed
+    pressed: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self {
+            select: SELECT_DIRECTIONS | SELECT_BUTTONS,
+            pressed: 0,
+        }
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Buttons of the selected rows, a pressed button reads as 0
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= self.pressed & 0x0f;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            lines |= self.pressed >> 4;
+        }
+        !lines & 0x0f
+    }
+
+    pub fn read(&self) -> u8 {
+        0xc0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.select = value & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        self.pressed & button.mask() != 0
+    }
+
+    /// Returns true when the joypad interrupt is requested, which happens
+    /// when one of the selected lines goes from high to low.
+    pub fn press(&mut self, button: Button) -> bool {
+        let lines = self.lines();
+        self.pressed |= button.mask();
+        lines & !self.lines() != 0
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    /// All the buttons, bit n is set when `Button::ALL[n]` is pressed
+    pub fn state(&self) -> u8 {
+        self.pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_joypad_matrix() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Down);
+        joypad.press(Button::A);
+        assert_eq!(joypad.read(), 0xff);
+
+        joypad.write(!SELECT_DIRECTIONS);
+        assert_eq!(joypad.read(), 0xe7);
+        joypad.write(!SELECT_BUTTONS);
+        assert_eq!(joypad.read(), 0xde);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xc6);
+
+        joypad.release(Button::Down);
+        assert!(!joypad.pressed(Button::Down));
+        assert!(joypad.pressed(Button::A));
+        assert_eq!(joypad.read(), 0xce);
+    }
+
+    #[test]
+    fn test_joypad_interrupt() {
+        let mut joypad = Joypad::new();
+        // No row selected
+        assert!(!joypad.press(Button::Start));
+
+        joypad.write(!SELECT_DIRECTIONS);
+        assert!(!joypad.press(Button::B));
+        assert!(joypad.press(Button::Up));
+        // Already pressed
+        assert!(!joypad.press(Button::Up));
+    }
+}
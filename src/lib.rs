@@ -0,0 +1,47 @@
+pub mod annotations;
+pub mod apu;
+pub mod audio;
+pub mod cheats;
+pub mod cpu;
+pub mod debugger;
+pub mod decoder;
+pub mod disassembler;
+pub mod emulator;
+pub mod error;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod header;
+pub mod infrared;
+#[cfg(feature = "input")]
+pub mod input;
+pub mod interrupts;
+pub mod joypad;
+pub mod link;
+pub mod mbc;
+pub mod mmu;
+pub mod model;
+pub mod movie;
+pub mod observer;
+pub mod palette;
+pub mod ppu;
+pub mod program;
+#[cfg(feature = "serde")]
+pub mod replay;
+pub mod rewind;
+pub mod rom;
+pub mod runner;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod serial;
+pub mod settings;
+pub mod slots;
+#[cfg(feature = "serde")]
+pub mod state;
+pub mod tiles;
+pub mod timer;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod video;
+
+pub use emulator::Emulator;
+pub use error::Error;
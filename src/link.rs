@@ -0,0 +1,192 @@
+//! The other end of the link cable of the serial port: the serial port of
+//! this console in loopback, or another emulator over TCP for trading and
+//! multiplayer games. A transfer exchanges the bytes of the two consoles,
+//! the one providing the clock sends its byte first and the other answers
+//! with its own.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Messages of 2 bytes, the kind and the byte
+const TRANSFER: u8 = 0;
+const ANSWER: u8 = 1;
+// Wait for the answer of the other emulator, paused or too slow after that
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub trait Link: Send {
+    /// Start a transfer with the clock of this console, sending `byte`
+    fn start(&mut self, byte: u8);
+    /// The byte received by the transfer started last, 0xff when nothing
+    /// answered
+    fn finish(&mut self) -> u8;
+    /// The byte of a transfer started by the other console with its clock,
+    /// answered with `byte`. None when there is none.
+    fn poll(&mut self, byte: u8) -> Option<u8>;
+}
+
+/// Output of the serial port wired to its input: the transfers with the
+/// internal clock receive the byte sent, the other console never starts one
+#[derive(Debug, Default)]
+pub struct Loopback(u8);
+
+impl Link for Loopback {
+    fn start(&mut self, byte: u8) {
+        self.0 = byte;
+    }
+
+    fn finish(&mut self) -> u8 {
+        self.0
+    }
+
+    fn poll(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Another emulator over TCP. The bytes are exchanged at the end of the
+/// transfers, without the timing of the bits. Once the other emulator
+/// disconnects, nothing answers anymore.
+#[derive(Debug)]
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    // Start of a message
+    partial: Option<u8>,
+}
+
+impl TcpLink {
+    /// Wait for the other emulator to connect to `addr`
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Over a stream already connected to the other emulator
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            stream: Some(stream),
+            partial: None,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) {
+        if let Some(stream) = &mut self.stream {
+            if stream.write_all(&[kind, byte]).is_err() {
+                self.stream = None;
+            }
+        }
+    }
+
+    /// The next message, waiting for it up to `TIMEOUT` when `wait`
+    fn receive(&mut self, wait: bool) -> Option<(u8, u8)> {
+        let stream = self.stream.as_mut()?;
+        if stream.set_nonblocking(!wait).is_err() {
+            self.stream = None;
+            return None;
+        }
+        loop {
+            let mut byte = [0];
+            match stream.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) => match self.partial.take() {
+                    Some(kind) => return Some((kind, byte[0])),
+                    None => self.partial = Some(byte[0]),
+                },
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return None
+                }
+                Err(_) => break,
+            }
+        }
+        // Closed by the other emulator
+        self.stream = None;
+        None
+    }
+}
+
+impl Link for TcpLink {
+    fn start(&mut self, byte: u8) {
+        self.send(TRANSFER, byte);
+    }
+
+    fn finish(&mut self) -> u8 {
+        loop {
+            match self.receive(true) {
+                Some((ANSWER, byte)) => return byte,
+                // Both consoles provide the clock, neither shifts the bits of
+                // the other
+                Some((TRANSFER, _)) => self.send(ANSWER, 0xff),
+                Some(_) => (),
+                None => return 0xff,
+            }
+        }
+    }
+
+    fn poll(&mut self, byte: u8) -> Option<u8> {
+        loop {
+            // The answers are too late for their transfers
+            if let (TRANSFER, received) = self.receive(false)? {
+                self.send(ANSWER, byte);
+                return Some(received);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpLink::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (TcpLink::new(stream).unwrap(), client)
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut link = Loopback::default();
+        link.start(0x42);
+        assert_eq!(link.poll(0x12), None);
+        assert_eq!(link.finish(), 0x42);
+    }
+
+    #[test]
+    fn test_tcp_link() {
+        let (mut a, mut b) = pair();
+        assert_eq!(b.poll(0x34), None);
+        a.start(0x12);
+        let received = loop {
+            if let Some(byte) = b.poll(0x34) {
+                break byte;
+            }
+        };
+        assert_eq!(received, 0x12);
+        assert_eq!(a.finish(), 0x34);
+
+        // Both with the internal clock
+        a.start(0x56);
+        b.start(0x78);
+        let other = std::thread::spawn(move || (b.finish(), b));
+        assert_eq!(a.finish(), 0xff);
+        let (answer, b) = other.join().unwrap();
+        assert_eq!(answer, 0xff);
+
+        drop(b);
+        a.start(0x9a);
+        assert_eq!(a.finish(), 0xff);
+        assert!(!a.is_connected());
+    }
+}
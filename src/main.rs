@@ -1,306 +1,764 @@
 use std::collections::BTreeMap;
-use std::fmt::{Debug, Display};
-use std::{error::Error, fs::File, io::Read};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::ParseIntError;
 
-use clap::{Arg, ArgAction, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 extern crate clap;
 
-mod slots;
-use indexediter::IndexedIter;
-use slots::{AddrRegister, Register16, Register16::*, Register8, Register8::*, Slot};
-
-use annotations::{Annotation, Purpose};
-
-mod annotations;
-mod indexediter;
+use gb::annotations::Annotation;
+use gb::audio::WavDump;
+use gb::debugger::coverage::Coverage;
+use gb::debugger::doctor::lockstep;
+use gb::debugger::heatmap::Heatmap;
+use gb::debugger::trace::{parse_mnemonic, parse_range, TraceFilter, TraceReader, Tracer};
+use gb::disassembler::{
+    diff, disassemble, Charmap, IoNames, JsonListing, Layout, Mode, Options, RgbdsListing,
+    TextListing, ENTRY_POINTS,
+};
+use gb::emulator::{Emulator, Options as EmulatorOptions};
+use gb::movie::Movie;
+use gb::palette::DmgPalette;
+#[cfg(feature = "serde")]
+use gb::replay::{Recorder, Replay};
+use gb::tiles;
+use gb::video::GifRecorder;
 
 fn main() {
-    let matches = Command::new("Disassembler")
+    let command = Command::new("Disassembler")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(Arg::new("file").required(true))
         .arg(Arg::new("annotation").required(true))
-        .arg(Arg::new("debug").short('d').action(ArgAction::SetTrue))
-        .get_matches();
+        .arg(
+            Arg::new("bytes")
+                .long("bytes")
+                .short('d')
+                .action(ArgAction::SetTrue)
+                .help("Show the bytes of each instruction in a column after the address"),
+        )
+        .arg(
+            Arg::new("align")
+                .long("align")
+                .action(ArgAction::SetTrue)
+                .help("Align the columns of the listing"),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("WIDTHS")
+                .value_parser(Layout::parse)
+                .help(
+                    "Widths of the aligned address, bytes, mnemonic, operands, goto and comment \
+                     columns, 7,8,6,16,16,0 by default. A goto of 0 hides it, a comment of 0 \
+                     never wraps",
+                ),
+        )
+        .arg(
+            Arg::new("no-goto")
+                .long("no-goto")
+                .action(ArgAction::SetTrue)
+                .help("Align the columns without the destinations of the jumps"),
+        )
+        .arg(
+            Arg::new("start")
+                .long("start")
+                .value_parser(parse_hex)
+                .help("Offset of the first byte to disassemble, in hex"),
+        )
+        .arg(
+            Arg::new("end")
+                .long("end")
+                .value_parser(parse_hex)
+                .help("Offset after the last byte to disassemble, in hex"),
+        )
+        .arg(
+            Arg::new("auto-labels")
+                .long("auto-labels")
+                .action(ArgAction::SetTrue)
+                .help("Label the destinations of the jumps and calls"),
+        )
+        .arg(
+            Arg::new("xrefs")
+                .long("xrefs")
+                .action(ArgAction::SetTrue)
+                .help("List the jumps and calls to each label, inline and after the listing"),
+        )
+        .arg(
+            Arg::new("recursive")
+                .long("recursive")
+                .action(ArgAction::SetTrue)
+                .help("Only decode the code reached from the entry points, the rest is data"),
+        )
+        .arg(
+            Arg::new("entry")
+                .long("entry")
+                .value_parser(parse_hex)
+                .action(ArgAction::Append)
+                .help(
+                    "Entry point of --recursive in hex, 0x100 and the interrupt vectors by default",
+                ),
+        )
+        .arg(
+            Arg::new("names")
+                .long("names")
+                .value_name("FILE")
+                .help("Names of the addresses in 0xff00-0xffff, one `address name` per line"),
+        )
+        .arg(
+            Arg::new("strings")
+                .long("strings")
+                .action(ArgAction::SetTrue)
+                .help("Show the runs of ASCII characters in the undecoded bytes as strings"),
+        )
+        .arg(
+            Arg::new("charmap").long("charmap").value_name("FILE").help(
+                "Characters of the --strings instead of ASCII, one `byte characters` per line",
+            ),
+        )
+        .arg(
+            Arg::new("no-header")
+                .long("no-header")
+                .action(ArgAction::SetTrue)
+                .help("Do not print the cartridge header, nor show it as data"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(["text", "rgbds", "json"])
+                .default_value("text")
+                .help("text listing, rgbasm source or one JSON object per line"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Write the listing to FILE instead of the standard output"),
+        )
+        .subcommand(
+            Command::new("tiles")
+                .about("Export the 2bpp tiles stored in a region of the file as a PNG tile sheet")
+                .arg(Arg::new("file").required(true))
+                .arg(Arg::new("output").required(true))
+                .arg(
+                    Arg::new("start")
+                        .long("start")
+                        .value_parser(parse_hex)
+                        .default_value("0"),
+                )
+                .arg(Arg::new("end").long("end").value_parser(parse_hex))
+                .arg(
+                    Arg::new("columns")
+                        .long("columns")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("16"),
+                )
+                .arg(
+                    Arg::new("palette")
+                        .long("palette")
+                        .help("grey, green or 4 comma separated RRGGBB colors")
+                        .value_parser(clap::value_parser!(DmgPalette))
+                        .default_value("grey"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the instructions that differ between two versions of a ROM")
+                .arg(Arg::new("old").required(true))
+                .arg(Arg::new("new").required(true))
+                .arg(
+                    Arg::new("old-annotations")
+                        .long("old-annotations")
+                        .value_name("FILE")
+                        .help("Annotations of the old ROM, their labels replace the addresses"),
+                )
+                .arg(
+                    Arg::new("new-annotations")
+                        .long("new-annotations")
+                        .value_name("FILE")
+                        .help("Annotations of the new ROM"),
+                ),
+        )
+        .subcommand(
+            Command::new("coverage")
+                .about(
+                    "Run a ROM and write the regions never executed as data annotations, for \
+                     the disassembler",
+                )
+                .args(run_args()),
+        )
+        .subcommand(
+            Command::new("heatmap")
+                .about(
+                    "Run a ROM and count the reads and writes of each address, in a CSV file \
+                     or in a PNG image for a .png output",
+                )
+                .args(run_args()),
+        )
+        .subcommand(
+            Command::new("trace")
+                .about("Run a ROM and trace its instructions and memory accesses to a binary file")
+                .args(run_args())
+                .arg(
+                    Arg::new("pc")
+                        .long("pc")
+                        .value_name("RANGE")
+                        .value_parser(parse_range)
+                        .action(ArgAction::Append)
+                        .help("Only trace the instructions in 0x0150-0x3fff, or at an address"),
+                )
+                .arg(
+                    Arg::new("addr")
+                        .long("addr")
+                        .value_name("RANGE")
+                        .value_parser(parse_range)
+                        .action(ArgAction::Append)
+                        .help("Only trace the reads and writes in this range"),
+                )
+                .arg(
+                    Arg::new("opcode")
+                        .long("opcode")
+                        .value_name("MNEMONIC")
+                        .value_parser(parse_mnemonic)
+                        .action(ArgAction::Append)
+                        .help("Only trace the instructions with this mnemonic, CALL for instance"),
+                )
+                .arg(
+                    Arg::new("no-instructions")
+                        .long("no-instructions")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("no-reads")
+                        .long("no-reads")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("no-writes")
+                        .long("no-writes")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("record")
+                .about("Run a ROM without a window and record the screen to an animated GIF")
+                .args(run_args())
+                .arg(
+                    Arg::new("every")
+                        .long("every")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("2")
+                        .help("Keep one frame out of N, the GIF players are slow below 2"),
+                )
+                .arg(
+                    Arg::new("wav")
+                        .long("wav")
+                        .value_name("FILE")
+                        .help("Record the sound to a .wav file, a GIF has none"),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about(
+                    "Run a ROM along a log of Gameboy Doctor and show the first instruction \
+                     which differs",
+                )
+                .arg(Arg::new("file").required(true))
+                .arg(
+                    Arg::new("log")
+                        .required(true)
+                        .help("Reference log, can be zipped or gzipped"),
+                ),
+        )
+        .subcommand(
+            Command::new("serial")
+                .about(
+                    "Run a test ROM without a window and print its serial output, up to Passed \
+                     or Failed",
+                )
+                .arg(Arg::new("file").required(true))
+                .arg(
+                    Arg::new("frames")
+                        .long("frames")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("7200")
+                        .help("Frames to run at most, two minutes by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("trace-text")
+                .about("Convert a binary trace to text")
+                .arg(Arg::new("trace").required(true))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .value_name("FILE")
+                        .help("Write the text to FILE instead of the standard output"),
+                ),
+        );
+    #[cfg(feature = "tui")]
+    let command = command.subcommand(
+        Command::new("tui")
+            .about("Browse the disassembly in the terminal and edit the annotations")
+            .arg(Arg::new("file").required(true))
+            .arg(Arg::new("annotation").required(true)),
+    );
+    #[cfg(feature = "serde")]
+    let command = command
+        .subcommand(
+            Command::new("record-replay")
+                .about(
+                    "Run a ROM without a window and record a replay, which reproduces the run \
+                     exactly",
+                )
+                .args(run_args()),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Run a replay and check that it ends in the state recorded")
+                .arg(Arg::new("file").required(true))
+                .arg(Arg::new("replay").required(true)),
+        );
+    #[cfg(feature = "scripting")]
+    let command = command.subcommand(
+        Command::new("script")
+            .about("Run a ROM without a window along a Rhai script")
+            .args(run_args())
+            .mut_arg("output", |arg| arg.value_name("SCRIPT")),
+    );
+    let matches = command.get_matches();
+
+    let result = match matches.subcommand() {
+        Some(("tiles", matches)) => export_tiles(matches),
+        Some(("coverage", matches)) => export_coverage(matches),
+        Some(("heatmap", matches)) => export_heatmap(matches),
+        Some(("trace", matches)) => trace(matches),
+        Some(("record", matches)) => record(matches),
+        Some(("trace-text", matches)) => trace_text(matches),
+        Some(("doctor", matches)) => match doctor(matches) {
+            Ok(true) => Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(err) => Err(err),
+        },
+        Some(("serial", matches)) => match serial(matches) {
+            // The status of a test: passed, failed or no result
+            Ok(Some(true)) => Ok(()),
+            Ok(Some(false)) => std::process::exit(1),
+            Ok(None) => std::process::exit(2),
+            Err(err) => Err(err),
+        },
+        Some(("diff", matches)) => match diff_roms(matches) {
+            // Like diff, the status tells whether the ROMs differ
+            Ok(count) => std::process::exit(if count > 0 { 1 } else { 0 }),
+            Err(err) => Err(err),
+        },
+        #[cfg(feature = "tui")]
+        Some(("tui", matches)) => tui(matches),
+        #[cfg(feature = "scripting")]
+        Some(("script", matches)) => script(matches),
+        #[cfg(feature = "serde")]
+        Some(("record-replay", matches)) => record_replay(matches),
+        #[cfg(feature = "serde")]
+        Some(("replay", matches)) => match replay(matches) {
+            Ok(true) => Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(err) => Err(err),
+        },
+        _ => disassemble_rom(&matches),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(2);
+    }
+}
+
+fn disassemble_rom(matches: &ArgMatches) -> Result<(), gb::Error> {
     let file_name: &String = matches.get_one("file").unwrap();
     let file_name_annotation: &String = matches.get_one("annotation").unwrap();
 
-    let annotations =
-        Annotation::parse_file(file_name_annotation).expect("Error loading the annotation file");
+    let annotations = load_annotations(file_name_annotation)?;
 
-    println!("{}", file_name);
+    let mut out: Box<dyn Write> = match matches.get_one::<String>("output") {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).map_err(|err| gb::Error::from(err).in_file(path))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
 
-    let mut buf = vec![];
-    File::open(file_name)
-        .and_then(|mut file| file.read_to_end(&mut buf))
-        .unwrap();
-    disassemble(buf, annotations, matches.get_flag("debug")).unwrap()
-}
+    let mut names = IoNames::default();
+    if let Some(path) = matches.get_one::<String>("names") {
+        names
+            .parse_file(path)
+            .map_err(|err| gb::Error::from(err).in_file(path))?;
+    }
 
-fn disassemble(
-    data: Vec<u8>,
-    annotations: BTreeMap<usize, Vec<Annotation>>,
-    debug: bool,
-) -> Result<(), Box<dyn Error + 'static>> {
-    let empty_vec = vec![];
-    let mut it = IndexedIter::from_vec(data.clone());
-
-    loop {
-        let mut comment = String::new();
-        let mut goto = String::new();
-        let mut label = None;
-        let mut skip = 0;
-        let annotations = annotations.get(&it.index()).unwrap_or(&empty_vec);
-
-        for annotation in annotations {
-            match annotation.purpose {
-                Purpose::Comment => comment = format!(" ; {}", &annotation.value),
-                Purpose::Goto => goto = format!("-> {}", &annotation.value),
-                Purpose::Label => label = Some(annotation.value.to_string()),
-                Purpose::Section => {
-                    println!("\n-- {} --", annotation.value)
-                }
-                Purpose::Data => {
-                    skip = usize::from_str_radix(annotation.value.trim_start_matches("0x"), 16)
-                        .unwrap();
-                }
-            }
+    let strings = match matches.get_one::<String>("charmap") {
+        Some(path) => {
+            let mut charmap = Charmap::empty();
+            charmap
+                .parse_file(path)
+                .map_err(|err| gb::Error::from(err).in_file(path))?;
+            Some(charmap)
         }
+        None if matches.get_flag("strings") => Some(Charmap::ascii()),
+        None => None,
+    };
 
-        if let Some(l) = label {
-            println!("{}:", l);
-        }
-        if skip > 0 {
-            println!(
-                "Skip 0x{:04x}-0x{:04x} {} {}",
-                it.index(),
-                it.index() + skip - 1,
-                goto,
-                comment
-            );
-            it.nth(skip - 1);
+    let options = Options {
+        start: matches.get_one("start").copied().unwrap_or(0),
+        end: matches.get_one("end").copied(),
+        auto_labels: matches.get_flag("auto-labels"),
+        xrefs: matches.get_flag("xrefs"),
+        mode: if matches.get_flag("recursive") {
+            let entry_points = match matches.get_many("entry") {
+                Some(entry_points) => entry_points.copied().collect(),
+                None => ENTRY_POINTS.to_vec(),
+            };
+            Mode::Recursive(entry_points)
         } else {
-            let current_index = it.index();
+            Mode::Linear
+        },
+        names,
+        strings,
+        header: !matches.get_flag("no-header"),
+    };
 
-            let opcode = decode(&mut it).unwrap();
-            if debug {
-                print!("{:02x} ", data[current_index]);
-            }
-            // Display the destination address of a jump if it has not been provided
-            goto = if goto.is_empty() {
-                let fmt_offset =
-                    |offset| format!("-> 0x{:x}", it.index() as isize + offset as isize);
-                match opcode {
-                    Opcode::Jump(offset) => fmt_offset(offset),
-                    Opcode::JumpRNZMemOffset(offset) => fmt_offset(offset),
-                    Opcode::JumpRZMemOffset(offset) => fmt_offset(offset),
-                    _ => String::new(),
+    let data = read_file(file_name)?;
+    match matches.get_one::<String>("format").unwrap().as_str() {
+        "rgbds" => {
+            writeln!(out, "; {}", file_name)?;
+            let mut listing = RgbdsListing::new(out);
+            disassemble(data, &annotations, &options, &mut listing)
+        }
+        "json" => {
+            let mut listing = JsonListing::new(out);
+            disassemble(data, &annotations, &options, &mut listing)
+        }
+        _ => {
+            writeln!(out, "{}", file_name)?;
+            let mut listing = TextListing::new(out, matches.get_flag("bytes"));
+            let layout = matches.get_one::<Layout>("columns").copied();
+            let aligned = matches.get_flag("align") || matches.get_flag("no-goto");
+            if let Some(mut layout) = layout.or(aligned.then(Layout::default)) {
+                if matches.get_flag("no-goto") {
+                    layout.goto = 0;
                 }
-            } else {
-                goto
-            };
-
-            println!(
-                "    0x{:04x} {} {} {}",
-                current_index, opcode, goto, comment
-            );
+                listing = listing.with_layout(layout);
+            }
+            disassemble(data, &annotations, &options, &mut listing)
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum Opcode {
-    Nop,
-    Halt,
-    Ret,
-    Ld(Slot, Slot),
-    Call(Slot),
-    Inc(Slot),
-    Cp(Slot, Slot),
-    Dec(Slot),
-    Sub(Slot),
-    LdToMemDec(Register16, Register8),
-    LdToMemInc(Register16, Register8),
-    RotLeft(Register8),
-    Push(Register16),
-    Pop(Register16),
-    Xor(Register8, Register8),
-    ComplBit(u8, Register8),
-    Jump(i8),
-    JumpRZMemOffset(i8),
-    JumpRNZMemOffset(i8),
+#[cfg(feature = "tui")]
+fn tui(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let annotation: &String = matches.get_one("annotation").unwrap();
+    gb::tui::run(rom, annotation.into())
+}
+
+fn load_annotations(path: &String) -> Result<BTreeMap<usize, Vec<Annotation>>, gb::Error> {
+    Annotation::parse_file(path).map_err(|err| gb::Error::from(err).in_file(path))
 }
 
-impl Display for Opcode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Opcode::Cp(to, from) => write!(f, "CP {:?} {:?}", to, from),
-            Opcode::Dec(from) => write!(f, "DEC {:?}", from),
-            Opcode::Inc(from) => write!(f, "INC {:?}", from),
-            Opcode::Push(from) => write!(f, "PUSH {:?}", from),
-            Opcode::Pop(to) => write!(f, "POP {:?}", to),
-            Opcode::Ld(to, from) => write!(f, "LD {:?} {:?}", to, from),
-            Opcode::Call(slot) => write!(f, "CALL {:?}", slot),
-            Opcode::LdToMemInc(to, from) => write!(f, "LD ({:?}++) {:?}", to, from),
-            Opcode::LdToMemDec(to, from) => write!(f, "LD ({:?}--) {:?}", to, from),
-            Opcode::Sub(from) => write!(f, "SUB A,{:?}", from),
-            _ => write!(f, "{:?}", self),
+fn diff_roms(matches: &ArgMatches) -> Result<usize, gb::Error> {
+    let annotations = |name| match matches.get_one::<String>(name) {
+        Some(path) => load_annotations(path),
+        None => Ok(BTreeMap::new()),
+    };
+    let count = diff(
+        read_file(matches.get_one("old").unwrap())?,
+        &annotations("old-annotations")?,
+        read_file(matches.get_one("new").unwrap())?,
+        &annotations("new-annotations")?,
+        &Options {
+            header: true,
+            ..Default::default()
+        },
+        &mut io::stdout().lock(),
+    )?;
+    println!("{} lines differ", count);
+    Ok(count)
+}
+
+fn read_file(file_name: &String) -> Result<Vec<u8>, gb::Error> {
+    gb::rom::read(file_name).map_err(|err| gb::Error::from(err).in_file(file_name))
+}
+
+fn parse_hex(value: &str) -> Result<usize, ParseIntError> {
+    usize::from_str_radix(value.trim_start_matches("0x"), 16)
+}
+
+fn export_tiles(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let data = read_file(matches.get_one("file").unwrap())?;
+    let start: usize = *matches.get_one("start").unwrap();
+    let end: usize = matches.get_one("end").copied().unwrap_or(data.len());
+    let region = data
+        .get(start..end)
+        .ok_or(gb::Error::InvalidRegion(start, end))?;
+
+    let sheet = tiles::tile_sheet(
+        region,
+        *matches.get_one("columns").unwrap(),
+        matches.get_one("palette").unwrap(),
+    );
+    sheet.save_png(matches.get_one::<String>("output").unwrap())?;
+    Ok(())
+}
+
+// The arguments of the subcommands running a ROM
+fn run_args() -> [Arg; 4] {
+    [
+        Arg::new("file").required(true),
+        Arg::new("output").required(true),
+        Arg::new("frames")
+            .long("frames")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("3600")
+            .help("Frames to run, a minute by default"),
+        Arg::new("play")
+            .long("play")
+            .value_name("FILE")
+            .help("Replay the joypad from a movie file, to reach more of the code"),
+    ]
+}
+
+// The movie of `--play`, and the emulator of the ROM to replay it
+fn movie_emulator(
+    matches: &ArgMatches,
+    rom: Vec<u8>,
+) -> Result<(Option<Movie>, Emulator), gb::Error> {
+    let movie = match matches.get_one::<String>("play") {
+        Some(path) => {
+            let movie = Movie::load(path).map_err(|err| gb::Error::from(err).in_file(path))?;
+            if !movie.matches_rom(&rom) {
+                eprintln!("Warning: {} was recorded with another ROM", path);
+            }
+            Some(movie)
         }
-    }
+        None => None,
+    };
+    let options = EmulatorOptions {
+        model: movie.as_ref().map(|movie| movie.model),
+        ..Default::default()
+    };
+    Ok((movie, Emulator::new(rom, &options)))
 }
 
-fn decode(data: &mut impl Iterator<Item = u8>) -> Result<Opcode, DecodeError> {
-    let opcode = data.next().ok_or(DecodeError::EndOfStream)?;
-    // Extended Opcodes
-    if opcode == 0xcb {
-        return decode_extended(data.next().ok_or(DecodeError::EndOfStream)?);
+// Run the ROM of `run_args` without a window, once `setup` added its
+// observers or debugging tools
+fn run_rom(
+    matches: &ArgMatches,
+    rom: Vec<u8>,
+    setup: impl FnOnce(&mut Emulator),
+) -> Result<Emulator, gb::Error> {
+    let (movie, mut emulator) = movie_emulator(matches, rom)?;
+    setup(&mut emulator);
+    for frame in 0..*matches.get_one::<usize>("frames").unwrap() {
+        if let Some(state) = movie.as_ref().and_then(|movie| movie.frame(frame)) {
+            emulator.set_buttons(state);
+        }
+        emulator.try_run_frame()?;
     }
+    Ok(emulator)
+}
 
-    if (0x40..0x80).contains(&opcode) {
-        // Inside this range the arguments for the Ld Opcode
-        // repeat in a specific pattern: BB, BC, BD... CB, CC, CD... AB
-        // AC, AD, ...until AA. The first 3 bits represent the destination
-        // and the last 3 represent the source.
+fn export_coverage(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let coverage = Coverage::new(rom.len());
+    run_rom(matches, rom, |emulator| {
+        emulator.add_observer(Box::new(coverage.clone()))
+    })?;
 
-        // Ld (HL), (HL) is a specific case replaced by Halt
-        if opcode == 0x76 {
-            return Ok(Opcode::Halt);
-        }
+    let (executed, read) = coverage.counts();
+    println!("{} bytes executed, {} read as data", executed, read);
+    let output: &String = matches.get_one("output").unwrap();
+    Annotation::save_file(&coverage.data_annotations(), output)
+        .map_err(|err| gb::Error::from(err).in_file(output))
+}
 
-        let address = (opcode - 0x40) as usize;
-        let mapping = [
-            Slot::r8(B),
-            Slot::r8(C),
-            Slot::r8(D),
-            Slot::r8(E),
-            Slot::r8(H),
-            Slot::r8(L),
-            Slot::AddrRegister(AddrRegister::HL),
-            Slot::r8(A),
-        ];
-        return Ok(Opcode::Ld(mapping[address >> 3], mapping[address & 0x7]));
+fn export_heatmap(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let emulator = run_rom(matches, rom, |emulator| {
+        emulator.mmu_mut().set_heatmap(Some(Heatmap::new()))
+    })?;
+    let heatmap = emulator.mmu().heatmap().unwrap();
+
+    let output: &String = matches.get_one("output").unwrap();
+    if output.ends_with(".png") {
+        heatmap.image().save_png(output)?;
+    } else {
+        let mut file = BufWriter::new(File::create(output)?);
+        heatmap.write_csv(&mut file)?;
+        file.flush()?;
     }
-    Ok(match opcode {
-        0x00 => Opcode::Nop,
-        0x01 => Opcode::Ld(Slot::r16(BC), Slot::parse_d16(data)?),
-        0x02 => Opcode::Ld(Slot::addr(AddrRegister::BC), Slot::r8(A)),
-        0x03 => Opcode::Inc(Slot::r16(BC)),
-        0x04 => Opcode::Inc(Slot::r8(B)),
-        0x05 => Opcode::Dec(Slot::r8(B)),
-        0x06 => Opcode::Ld(Slot::r8(B), Slot::parse_d8(data)?),
-        0x0c => Opcode::Inc(Slot::r8(C)),
-        0x0d => Opcode::Dec(Slot::r8(C)),
-        0x0e => Opcode::Ld(Slot::r8(C), Slot::parse_d8(data)?),
-        0x11 => Opcode::Ld(Slot::r16(DE), Slot::parse_d16(data)?),
-        0x13 => Opcode::Inc(Slot::r16(DE)),
-        0x14 => Opcode::Inc(Slot::r8(D)),
-        0x15 => Opcode::Dec(Slot::r8(D)),
-        0x16 => Opcode::Ld(Slot::r8(D), Slot::parse_d8(data)?),
-        0x17 => Opcode::RotLeft(A),
-        0x18 => Opcode::Jump(data.next().ok_or(DecodeError::EndOfStream)? as i8),
-        0x1a => Opcode::Ld(Slot::r8(A), Slot::addr(AddrRegister::DE)),
-        0x1b => Opcode::Dec(Slot::r16(DE)),
-        0x1c => Opcode::Inc(Slot::r8(E)),
-        0x1d => Opcode::Dec(Slot::r8(E)),
-        0x1e => Opcode::Ld(Slot::r8(E), Slot::parse_d8(data)?),
-        0x20 => Opcode::JumpRNZMemOffset(data.next().ok_or(DecodeError::EndOfStream)? as i8),
-        0x21 => Opcode::Ld(Slot::r16(HL), Slot::parse_d16(data)?),
-        0x22 => Opcode::LdToMemInc(HL, A),
-        0x23 => Opcode::Inc(Slot::r16(HL)),
-        0x24 => Opcode::Inc(Slot::r8(H)),
-        0x25 => Opcode::Dec(Slot::r8(H)),
-        0x28 => Opcode::JumpRZMemOffset(data.next().ok_or(DecodeError::EndOfStream)? as i8),
-        0x2e => Opcode::Ld(Slot::r8(L), Slot::parse_d8(data)?),
-        0x31 => Opcode::Ld(Slot::r16(SP), Slot::parse_d16(data)?),
-        0x32 => Opcode::LdToMemDec(HL, A),
-        0x34 => Opcode::Inc(Slot::AddrRegister(AddrRegister::HL)),
-        0x35 => Opcode::Dec(Slot::AddrRegister(AddrRegister::HL)),
-        0x3d => Opcode::Dec(Slot::r8(A)),
-        0x3e => Opcode::Ld(Slot::r8(A), Slot::parse_d8(data)?),
-        0x90 => Opcode::Sub(Slot::r8(B)),
-        0x91 => Opcode::Sub(Slot::r8(C)),
-        0x92 => Opcode::Sub(Slot::r8(D)),
-        0x93 => Opcode::Sub(Slot::r8(E)),
-        0x94 => Opcode::Sub(Slot::r8(H)),
-        0x95 => Opcode::Sub(Slot::r8(L)),
-        0x96 => Opcode::Sub(Slot::AddrRegister(AddrRegister::HL)),
-        0x97 => Opcode::Sub(Slot::r8(A)),
-        0xaf => Opcode::Xor(A, A),
-        0xc1 => Opcode::Pop(BC),
-        0xc5 => Opcode::Push(BC),
-        0xc9 => Opcode::Ret,
-        0xcd => Opcode::Call(Slot::parse_d16(data)?),
-        0xe0 => Opcode::Ld(Slot::parse_a8(data)?, Slot::r8(A)),
-        0xe2 => Opcode::Ld(Slot::addr(AddrRegister::C), Slot::r8(A)),
-        0xea => Opcode::Ld(Slot::parse_a16(data)?, Slot::r8(A)),
-        0xf0 => Opcode::Ld(Slot::r8(A), Slot::parse_a8(data)?),
-        0xf1 => Opcode::Pop(AF),
-        0xfe => Opcode::Cp(Slot::r8(A), Slot::parse_d8(data)?),
-        _ => return Err(DecodeError::UnknownOpcode(opcode)),
-    })
+    Ok(())
 }
 
-#[derive(PartialEq)]
-pub enum DecodeError {
-    EndOfStream,
-    UnknownOpcode(u8),
-    UnknownExtendedOpcode(u8),
+fn trace(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let ranges = |name| match matches.get_many(name) {
+        Some(ranges) => ranges.cloned().collect(),
+        None => Vec::new(),
+    };
+    let filter = TraceFilter {
+        instructions: !matches.get_flag("no-instructions"),
+        reads: !matches.get_flag("no-reads"),
+        writes: !matches.get_flag("no-writes"),
+        pc_ranges: ranges("pc"),
+        address_ranges: ranges("addr"),
+        mnemonics: match matches.get_many("opcode") {
+            Some(mnemonics) => mnemonics.copied().collect(),
+            None => Vec::new(),
+        },
+    };
+    let output: &String = matches.get_one("output").unwrap();
+    let file = File::create(output).map_err(|err| gb::Error::from(err).in_file(output))?;
+    let tracer = Tracer::new(BufWriter::new(file), filter)?;
+    run_rom(matches, rom, |emulator| {
+        emulator.add_observer(Box::new(tracer.clone()))
+    })?;
+    tracer
+        .finish()
+        .map_err(|err| gb::Error::from(err).in_file(output))?;
+    Ok(())
 }
 
-impl Debug for DecodeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        <DecodeError as Display>::fmt(self, f)
+fn trace_text(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let path: &String = matches.get_one("trace").unwrap();
+    let file = File::open(path).map_err(|err| gb::Error::from(err).in_file(path))?;
+    let mut out: Box<dyn Write> = match matches.get_one::<String>("output") {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).map_err(|err| gb::Error::from(err).in_file(path))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    for record in TraceReader::new(io::BufReader::new(file))? {
+        let record = record.map_err(|err| gb::Error::from(err).in_file(path))?;
+        writeln!(out, "{}", record)?;
     }
+    Ok(out.flush()?)
 }
-impl Display for DecodeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::EndOfStream => write!(f, "End of stream detected during opcode decoding"),
-            Self::UnknownOpcode(opcode) => write!(f, "Unknown Opcode 0x{:x}", opcode),
-            Self::UnknownExtendedOpcode(opcode) => {
-                write!(f, "Unknown Extended opcode 0x{:x}", opcode)
-            }
+
+fn record(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let (movie, mut emulator) = movie_emulator(matches, rom)?;
+    let output: &String = matches.get_one("output").unwrap();
+    let in_file = |err: gif::EncodingError| gb::Error::from(err).in_file(output);
+    let mut recorder =
+        GifRecorder::create(output, *matches.get_one("every").unwrap()).map_err(in_file)?;
+    let mut wav = match matches.get_one::<String>("wav") {
+        Some(path) => Some(
+            WavDump::create(path, emulator.mmu().apu().sample_rate())
+                .map_err(|err| gb::Error::from(io::Error::other(err)).in_file(path))?,
+        ),
+        None => None,
+    };
+    for frame in 0..*matches.get_one::<usize>("frames").unwrap() {
+        if let Some(state) = movie.as_ref().and_then(|movie| movie.frame(frame)) {
+            emulator.set_buttons(state);
+        }
+        emulator.try_run_frame()?;
+        recorder.push(emulator.framebuffer()).map_err(in_file)?;
+        if let Some(wav) = &mut wav {
+            wav.write(emulator.audio_samples())
+                .map_err(|err| gb::Error::from(io::Error::other(err)))?;
         }
     }
+    recorder.finish().map_err(in_file)?.flush()?;
+    if let Some(wav) = wav {
+        wav.finalize()
+            .map_err(|err| gb::Error::from(io::Error::other(err)))?;
+    }
+    Ok(())
 }
 
-impl Error for DecodeError {}
+// True when the whole log matches
+fn doctor(matches: &ArgMatches) -> Result<bool, gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let path: &String = matches.get_one("log").unwrap();
+    let log = read_file(path)?;
+    let mut emulator = Emulator::new(rom, &Default::default());
+    let comparison = lockstep(&mut emulator, log.as_slice())
+        .map_err(|err| gb::Error::from(err).in_file(path))?;
+    match comparison.divergence {
+        Some(divergence) => {
+            println!("{}", divergence);
+            Ok(false)
+        }
+        None => {
+            println!("{} lines match", comparison.lines);
+            Ok(true)
+        }
+    }
+}
 
-fn decode_extended(data: u8) -> Result<Opcode, DecodeError> {
-    Ok(match data {
-        0x11 => Opcode::RotLeft(C),
-        0x7c => Opcode::ComplBit(7, H),
-        0x4f => Opcode::ComplBit(1, A),
-        _ => return Err(DecodeError::UnknownExtendedOpcode(data)),
-    })
+fn serial(matches: &ArgMatches) -> Result<Option<bool>, gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let mut emulator = Emulator::new(rom, &Default::default());
+    let frames = *matches.get_one::<usize>("frames").unwrap();
+    let mut stdout = io::stdout();
+    let mut output = Vec::new();
+    for _ in 0..frames {
+        emulator.run_frame();
+        stdout.write_all(emulator.serial_output())?;
+        stdout.flush()?;
+        output.extend(emulator.serial_output());
+        if let Some(passed) = gb::serial::test_result(&output) {
+            return Ok(Some(passed));
+        }
+    }
+    eprintln!("No result after {} frames", frames);
+    Ok(None)
 }
 
-#[cfg(test)]
-mod test {
-    use super::decode;
-    use super::{slots::AddrRegister, slots::Slot, Opcode, Register8::*};
+#[cfg(feature = "serde")]
+fn record_replay(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let mut recorder = None;
+    let emulator = run_rom(matches, rom, |emulator| {
+        recorder = Some(Recorder::start(emulator))
+    })?;
+    let replay = recorder.unwrap().finish(&emulator);
+    let output: &String = matches.get_one("output").unwrap();
+    replay
+        .save(output)
+        .map_err(|err| gb::Error::from(err).in_file(output))?;
+    println!("{} frames recorded", replay.movie().len());
+    Ok(())
+}
 
-    #[test]
-    fn decode_ld_band() {
-        assert_eq!(
-            decode(&mut [0x40u8].iter().copied()).unwrap(),
-            Opcode::Ld(Slot::Register8(B), Slot::Register8(B))
-        );
-        assert_eq!(
-            decode(&mut [0x5fu8].iter().copied()).unwrap(),
-            Opcode::Ld(Slot::Register8(E), Slot::Register8(A))
-        );
-        assert_eq!(
-            decode(&mut [0x66u8].iter().copied()).unwrap(),
-            Opcode::Ld(Slot::Register8(H), Slot::AddrRegister(AddrRegister::HL),)
-        );
-        assert_eq!(
-            decode(&mut [0x68u8].iter().copied()).unwrap(),
-            Opcode::Ld(Slot::Register8(L), Slot::Register8(B)),
+#[cfg(feature = "serde")]
+fn replay(matches: &ArgMatches) -> Result<bool, gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let path: &String = matches.get_one("replay").unwrap();
+    let in_file = |err| gb::Error::from(err).in_file(path);
+    let replay = Replay::load(path).map_err(in_file)?;
+    let mut emulator = replay.emulator(rom).map_err(in_file)?;
+    let same = replay.play(&mut emulator);
+    if same {
+        println!("{} frames replayed, same final state", replay.movie().len());
+    } else {
+        println!(
+            "{} frames replayed, the final state differs from the recording",
+            replay.movie().len()
         );
+    }
+    Ok(same)
+}
 
-        assert_eq!(
-            decode(&mut [0x7du8].iter().copied()).unwrap(),
-            Opcode::Ld(Slot::Register8(A), Slot::Register8(L)),
-        );
-        assert_eq!(decode(&mut [0x76u8].iter().copied()).unwrap(), Opcode::Halt);
+#[cfg(feature = "scripting")]
+fn script(matches: &ArgMatches) -> Result<(), gb::Error> {
+    let rom = read_file(matches.get_one("file").unwrap())?;
+    let (movie, emulator) = movie_emulator(matches, rom)?;
+    let path: &String = matches.get_one("output").unwrap();
+    let mut script = gb::script::Script::load(path, emulator)
+        .map_err(|err| gb::Error::from(err).in_file(path))?;
+    for frame in 0..*matches.get_one::<usize>("frames").unwrap() {
+        let buttons = movie.as_ref().and_then(|movie| movie.frame(frame));
+        if let Some(hit) = script.run_frame(buttons.unwrap_or(0))? {
+            println!("Breakpoint #{} at {:04x}", hit.id, hit.pc);
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,422 @@
+//! Memory bank controllers of the cartridges, mapping the banks of the ROM
+//! and of the external RAM
+//! See https://gbdev.io/pandocs/MBCs.html
+//!
+//! The battery saves are the external RAM, followed by the clock of the
+//! MBC3 when it has one, as in VBA-M and BGB. The frontends keep them next to
+//! the ROM, `game.sav` for `game.gb`.
+
+pub mod rtc;
+
+use std::path::{Path, PathBuf};
+use std::{error::Error, fmt::Display, io};
+
+use crate::header::{Header, CARTRIDGE_TYPE};
+use rtc::{Rtc, RTC_DH, RTC_S};
+
+/// File of the battery save of the ROM at `rom`
+pub fn battery_path(rom: impl AsRef<Path>) -> PathBuf {
+    rom.as_ref().with_extension("sav")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Kind {
+    /// 32 KiB of ROM and 8 KiB of RAM, without banks
+    None,
+    Mbc3,
+    Mbc5,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mbc {
+    kind: Kind,
+    battery: bool,
+    // Banks of 16 KiB in the ROM
+    rom_banks: usize,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // Mapped at 0x4000-0x7fff
+    rom_bank: usize,
+    // Mapped at 0xa000-0xbfff, or the RTC register from 0x08
+    ram_bank: u8,
+    rtc: Option<Rtc>,
+    // Motor of the MBC5 rumble cartridges, driven by the RAM bank register
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc {
+    /// The controller declared in the header of `rom`. The unsupported ones
+    /// map the ROM without banks.
+    pub fn new(rom: &[u8]) -> Self {
+        let cartridge_type = rom.get(CARTRIDGE_TYPE).copied().unwrap_or(0);
+        let ram_bytes = Header::parse(rom)
+            .and_then(|header| header.ram_bytes())
+            .unwrap_or(0);
+        let (kind, ram_bytes) = match cartridge_type {
+            0x0f..=0x13 => (Kind::Mbc3, ram_bytes),
+            0x19..=0x1e => (Kind::Mbc5, ram_bytes),
+            _ => (Kind::None, 0x2000),
+        };
+        Self {
+            kind,
+            battery: matches!(cartridge_type, 0x09 | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e),
+            // The ROM is mapped as 32 KiB at least
+            rom_banks: rom.len().div_ceil(0x4000).max(2),
+            ram: vec![0; ram_bytes],
+            ram_enabled: kind == Kind::None,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: matches!(cartridge_type, 0x0f | 0x10).then(Rtc::new),
+            has_rumble: matches!(cartridge_type, 0x1c..=0x1e),
+            rumble: false,
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// True if the RAM and the clock are kept when the console is off
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// True while the motor of a rumble cartridge is on
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Offset in the ROM of `addr`, in 0x0000-0x7fff
+    pub fn rom_offset(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x3fff => addr as usize,
+            _ => self.rom_bank % self.rom_banks * 0x4000 + (addr - 0x4000) as usize,
+        }
+    }
+
+    /// Write to the registers, in 0x0000-0x7fff
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match (self.kind, addr) {
+            (Kind::None, _) => (),
+            (Kind::Mbc3, 0x0000..=0x1fff) => self.ram_enabled = value & 0x0f == 0x0a,
+            (Kind::Mbc3, 0x2000..=0x3fff) => self.rom_bank = (value as usize & 0x7f).max(1),
+            (Kind::Mbc3, 0x4000..=0x5fff) => self.ram_bank = value,
+            (Kind::Mbc3, _) => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+            (Kind::Mbc5, 0x0000..=0x1fff) => self.ram_enabled = value == 0x0a,
+            // 9 bits, the bank 0 can be mapped at 0x4000 too
+            (Kind::Mbc5, 0x2000..=0x2fff) => self.rom_bank = self.rom_bank & 0x100 | value as usize,
+            (Kind::Mbc5, 0x3000..=0x3fff) => {
+                self.rom_bank = self.rom_bank & 0xff | (value as usize & 0x01) << 8
+            }
+            (Kind::Mbc5, 0x4000..=0x5fff) if self.has_rumble => {
+                self.rumble = value & 0x08 != 0;
+                self.ram_bank = value & 0x07;
+            }
+            (Kind::Mbc5, 0x4000..=0x5fff) => self.ram_bank = value & 0x0f,
+            (Kind::Mbc5, _) => (),
+        }
+    }
+
+    // The selected RTC register, if any
+    fn rtc_register(&mut self) -> Option<(&mut Rtc, u8)> {
+        let register = self.ram_bank;
+        match &mut self.rtc {
+            Some(rtc) if (RTC_S..=RTC_DH).contains(&register) => Some((rtc, register)),
+            _ => None,
+        }
+    }
+
+    // Offset in the RAM of `addr`, in 0xa000-0xbfff
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = match self.kind {
+            Kind::Mbc3 if self.ram_bank > 0x07 => return None,
+            Kind::Mbc3 => self.ram_bank & 0x03,
+            _ => self.ram_bank,
+        } as usize;
+        Some((bank * 0x2000 + (addr - 0xa000) as usize) % self.ram.len())
+    }
+
+    /// Read the RAM or the RTC, in 0xa000-0xbfff
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if let Some(rtc) = &self.rtc {
+            if self.ram_enabled && (RTC_S..=RTC_DH).contains(&self.ram_bank) {
+                return rtc.read(self.ram_bank);
+            }
+        }
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram[offset],
+            None => 0xff,
+        }
+    }
+
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some((rtc, register)) = self.rtc_register() {
+            rtc.write(register, value);
+        } else if let Some(offset) = self.ram_offset(addr) {
+            self.ram[offset] = value;
+        }
+    }
+
+    /// Advance the clock by `cycles` T-cycles
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    /// The battery save, with the clock at the UNIX time `now`
+    pub fn save(&self, now: u64) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.save(now));
+        }
+        data
+    }
+
+    /// Restore a battery save, the clock catches up with the time elapsed
+    /// until the UNIX time `now`. A save without the clock keeps it.
+    pub fn load(&mut self, data: &[u8], now: u64) -> Result<(), BatteryError> {
+        let (ram, clock) = data.split_at(data.len().min(self.ram.len()));
+        if ram.len() != self.ram.len() {
+            return Err(BatteryError::InvalidSize(data.len()));
+        }
+        match (&mut self.rtc, clock.len()) {
+            (_, 0) => (),
+            (Some(rtc), rtc::SAVE_SIZE) => rtc.load(clock.try_into().unwrap(), now),
+            _ => return Err(BatteryError::InvalidSize(data.len())),
+        }
+        self.ram.copy_from_slice(ram);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum BatteryError {
+    /// Not the size of the RAM of the cartridge
+    InvalidSize(usize),
+    IOError(io::Error),
+}
+
+impl Error for BatteryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidSize(_) => None,
+            Self::IOError(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for BatteryError {
+    fn from(value: io::Error) -> Self {
+        BatteryError::IOError(value)
+    }
+}
+
+impl Display for BatteryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSize(size) => {
+                write!(
+                    f,
+                    "Invalid battery save of {} bytes for this cartridge",
+                    size
+                )
+            }
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rtc::{RTC_H, RTC_M};
+    use super::*;
+    use crate::apu::CLOCK_RATE;
+    use crate::header::RAM_SIZE;
+
+    fn rom(cartridge_type: u8, banks: usize) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..banks).flat_map(|bank| [bank as u8; 0x4000]).collect();
+        rom[CARTRIDGE_TYPE] = cartridge_type;
+        // 32 KiB
+        rom[RAM_SIZE] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn test_mbc_none() {
+        let mut mbc = Mbc::new(&rom(0x00, 2));
+        assert_eq!(mbc.kind(), Kind::None);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(mbc.rom_offset(0x4000), 0x4000);
+        mbc.write_ram(0xbfff, 0x12);
+        assert_eq!(mbc.read_ram(0xbfff), 0x12);
+        assert_eq!(mbc.ram().len(), 0x2000);
+        assert!(!mbc.has_battery());
+        assert_eq!(Mbc::new(&[]).kind(), Kind::None);
+    }
+
+    #[test]
+    fn test_mbc3() {
+        let mut mbc = Mbc::new(&rom(0x13, 8));
+        assert_eq!(mbc.kind(), Kind::Mbc3);
+        assert!(mbc.has_battery());
+        assert!(mbc.rtc().is_none());
+        assert_eq!(mbc.rom_offset(0x0123), 0x0123);
+        assert_eq!(mbc.rom_offset(0x4123), 0x4123);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(mbc.rom_offset(0x4123), 0x14123);
+        // Bank 0 maps bank 1, the banks past the end wrap
+        mbc.write(0x3fff, 0x00);
+        assert_eq!(mbc.rom_offset(0x4000), 0x4000);
+        mbc.write(0x2000, 0x0a);
+        assert_eq!(mbc.rom_offset(0x4000), 0x8000);
+
+        // Disabled RAM
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+        mbc.write(0x0000, 0x0a);
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.read_ram(0xa000), 0x12);
+        mbc.write(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0xa000), 0x00);
+        mbc.write_ram(0xa000, 0x34);
+        assert_eq!(mbc.ram()[0x6000], 0x34);
+        // No clock
+        mbc.write(0x4000, RTC_S);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+        mbc.write(0x1fff, 0x00);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn test_mbc5() {
+        let mut mbc = Mbc::new(&rom(0x1b, 0x200));
+        assert_eq!(mbc.kind(), Kind::Mbc5);
+        assert!(mbc.has_battery());
+        mbc.write(0x2000, 0xff);
+        assert_eq!(mbc.rom_offset(0x4000), 0xff * 0x4000);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.rom_offset(0x4123), 0x1ff * 0x4000 + 0x123);
+        mbc.write(0x2fff, 0x00);
+        assert_eq!(mbc.rom_offset(0x4000), 0x100 * 0x4000);
+        // Bank 0 at 0x4000
+        mbc.write(0x3fff, 0x00);
+        assert_eq!(mbc.rom_offset(0x4000), 0x0000);
+        // Wraps in a smaller ROM
+        let mut small = Mbc::new(&rom(0x19, 4));
+        small.write(0x2000, 0x05);
+        assert_eq!(small.rom_offset(0x4000), 0x4000);
+
+        // Only 0x0a enables the RAM
+        mbc.write(0x0000, 0x1a);
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.read_ram(0xa000), 0xff);
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x03);
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.ram()[0x6000], 0x12);
+        // 4 banks of 32 KiB, the upper ones wrap
+        mbc.write(0x4000, 0x07);
+        assert_eq!(mbc.read_ram(0xa000), 0x12);
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn test_mbc5_rumble() {
+        let mut mbc = Mbc::new(&rom(0x1e, 4));
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x09);
+        assert!(mbc.rumble());
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.ram()[0x2000], 0x12);
+        mbc.write(0x4000, 0x01);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read_ram(0xa000), 0x12);
+
+        // Bit 3 of the RAM bank without a motor
+        let mut mbc = Mbc::new(&rom(0x1b, 4));
+        mbc.write(0x4000, 0x08);
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn test_mbc3_rtc() {
+        let mut mbc = Mbc::new(&rom(0x10, 4));
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, RTC_M);
+        mbc.write_ram(0xa000, 59);
+        mbc.write(0x4000, RTC_S);
+        mbc.write_ram(0xa000, 59);
+        assert_eq!(mbc.read_ram(0xa000), 59);
+        mbc.tick(CLOCK_RATE);
+        assert_eq!(mbc.read_ram(0xa000), 59);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xa000), 0);
+        mbc.write(0x4000, RTC_H);
+        assert_eq!(mbc.read_ram(0xbfff), 1);
+        // The RAM is still there
+        mbc.write(0x4000, 0x00);
+        mbc.write_ram(0xa000, 0x12);
+        assert_eq!(mbc.read_ram(0xa000), 0x12);
+
+        // Halted
+        mbc.write(0x4000, RTC_DH);
+        mbc.write_ram(0xa000, 0x40);
+        mbc.tick(CLOCK_RATE * 2);
+        assert_eq!(mbc.rtc().unwrap().registers(), [0, 0, 1, 0, 0x40]);
+    }
+
+    #[test]
+    fn test_mbc_battery_save() {
+        let mut mbc = Mbc::new(&rom(0x10, 4));
+        mbc.write(0x0000, 0x0a);
+        mbc.write_ram(0xa000, 0x12);
+        mbc.write(0x4000, RTC_M);
+        mbc.write_ram(0xa000, 10);
+        let data = mbc.save(1000);
+        assert_eq!(data.len(), 0x8000 + rtc::SAVE_SIZE);
+
+        let mut loaded = Mbc::new(&rom(0x10, 4));
+        loaded.load(&data, 1000 + 3600 + 60).unwrap();
+        assert_eq!(loaded.ram()[0], 0x12);
+        assert_eq!(loaded.rtc().unwrap().registers(), [0, 11, 1, 0, 0]);
+        // Saved without the clock
+        loaded.load(&data[..0x8000], 0).unwrap();
+        assert_eq!(loaded.rtc().unwrap().registers(), [0, 11, 1, 0, 0]);
+        assert!(matches!(
+            loaded.load(&data[1..], 0),
+            Err(BatteryError::InvalidSize(_))
+        ));
+        assert!(matches!(
+            Mbc::new(&rom(0x13, 4)).load(&data, 0),
+            Err(BatteryError::InvalidSize(_))
+        ));
+    }
+}
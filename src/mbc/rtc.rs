@@ -0,0 +1,247 @@
+//! Real time clock of the MBC3. It counts the seconds of the emulation, so
+//! the runs stay deterministic, and catches up with the time spent off when
+//! its battery save is loaded.
+//! See https://gbdev.io/pandocs/MBC3.html#the-clock-counter-registers
+
+use crate::apu::CLOCK_RATE;
+
+/// Registers selected with the RAM bank register
+pub const RTC_S: u8 = 0x08;
+pub const RTC_M: u8 = 0x09;
+pub const RTC_H: u8 = 0x0a;
+/// Low 8 bits of the day counter
+pub const RTC_DL: u8 = 0x0b;
+/// Bit 8 of the day counter, halt and day counter carry
+pub const RTC_DH: u8 = 0x0c;
+
+const DH_DAY_HIGH: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+/// Size of the clock at the end of the battery saves, in the format of
+/// VBA-M and BGB: the registers and the latched ones as 32-bit words, and
+/// the UNIX time of the save as a 64-bit word
+pub const SAVE_SIZE: usize = 48;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    // 9 bits
+    days: u16,
+    halt: bool,
+    carry: bool,
+    // Copy read by the game, from S to DH
+    latched: [u8; 5],
+    // 0 was written to the latch register, 1 latches next
+    latch_armed: bool,
+    // T-cycles into the current second
+    cycles: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Advance by `cycles` T-cycles, unless halted
+    pub fn tick(&mut self, cycles: u32) {
+        if self.halt {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CLOCK_RATE {
+            self.cycles -= CLOCK_RATE;
+            self.step();
+        }
+    }
+
+    // One second. The counters set by the game past their range overflow
+    // at the size of their register without carrying.
+    fn step(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    /// Advance by `seconds`, unless halted
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halt {
+            return;
+        }
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.step();
+            seconds -= 1;
+        }
+        let total = seconds
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days >= 512 {
+            self.carry = true;
+        }
+        self.days = (days % 512) as u16;
+    }
+
+    /// The registers from S to DH
+    pub fn registers(&self) -> [u8; 5] {
+        let dh = (self.days >> 8) as u8 & DH_DAY_HIGH
+            | if self.halt { DH_HALT } else { 0 }
+            | if self.carry { DH_CARRY } else { 0 };
+        [self.seconds, self.minutes, self.hours, self.days as u8, dh]
+    }
+
+    /// The latched register `register`, from `RTC_S` to `RTC_DH`
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - RTC_S) as usize]
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            RTC_S => {
+                self.seconds = value & 0x3f;
+                self.cycles = 0;
+            }
+            RTC_M => self.minutes = value & 0x3f,
+            RTC_H => self.hours = value & 0x1f,
+            RTC_DL => self.days = self.days & 0x100 | value as u16,
+            RTC_DH => {
+                self.days = self.days & 0xff | ((value & DH_DAY_HIGH) as u16) << 8;
+                self.halt = value & DH_HALT != 0;
+                self.carry = value & DH_CARRY != 0;
+            }
+            _ => (),
+        }
+        // The game reads what it wrote
+        self.latched = self.registers();
+    }
+
+    /// Write to 0x6000-0x7fff, 0 then 1 copies the registers to the latched
+    /// ones
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 1 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0;
+    }
+
+    /// The clock for the end of the battery save, at the UNIX time `now`
+    pub fn save(&self, now: u64) -> [u8; SAVE_SIZE] {
+        let mut data = [0; SAVE_SIZE];
+        for (i, register) in self.registers().into_iter().chain(self.latched).enumerate() {
+            data[i * 4] = register;
+        }
+        data[40..].copy_from_slice(&now.to_le_bytes());
+        data
+    }
+
+    /// Restore the clock of a battery save, and add the time elapsed from
+    /// its save to the UNIX time `now`
+    pub fn load(&mut self, data: &[u8; SAVE_SIZE], now: u64) {
+        for (register, i) in (RTC_S..=RTC_DH).zip(0..) {
+            self.write(register, data[i * 4]);
+        }
+        for (i, latched) in self.latched.iter_mut().enumerate() {
+            *latched = data[(i + 5) * 4];
+        }
+        let saved = u64::from_le_bytes(data[40..].try_into().unwrap());
+        self.advance(now.saturating_sub(saved));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtc() {
+        let mut rtc = Rtc::new();
+        rtc.write(RTC_H, 23);
+        rtc.write(RTC_M, 59);
+        rtc.write(RTC_S, 58);
+        rtc.tick(CLOCK_RATE * 2 - 1);
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        assert_eq!(rtc.read(RTC_S), 59);
+        rtc.tick(1);
+        // Latched until the next latch
+        assert_eq!(rtc.read(RTC_S), 59);
+        rtc.write_latch(1);
+        assert_eq!(rtc.read(RTC_S), 59);
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        assert_eq!(rtc.registers(), [0, 0, 0, 1, 0]);
+        assert_eq!(rtc.read(RTC_DL), 1);
+
+        // Halted
+        rtc.write(RTC_DH, DH_HALT | DH_DAY_HIGH);
+        rtc.tick(CLOCK_RATE * 10);
+        rtc.advance(1000);
+        assert_eq!(rtc.registers(), [0, 0, 0, 1, 0x41]);
+
+        // Day 511 overflows to the carry, which stays set
+        rtc.write(RTC_DL, 0xff);
+        rtc.write(RTC_H, 23);
+        rtc.write(RTC_M, 59);
+        rtc.write(RTC_S, 59);
+        rtc.write(RTC_DH, DH_DAY_HIGH);
+        rtc.tick(CLOCK_RATE);
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, DH_CARRY]);
+        rtc.tick(CLOCK_RATE);
+        assert_eq!(rtc.registers(), [1, 0, 0, 0, DH_CARRY]);
+
+        // Out of range, wraps without carrying
+        rtc.write(RTC_S, 63);
+        rtc.tick(CLOCK_RATE);
+        assert_eq!(rtc.registers(), [0, 0, 0, 0, DH_CARRY]);
+    }
+
+    #[test]
+    fn test_rtc_save() {
+        let mut rtc = Rtc::new();
+        rtc.write(RTC_M, 30);
+        rtc.write_latch(0);
+        rtc.write_latch(1);
+        rtc.write(RTC_H, 5);
+        let data = rtc.save(1_000_000);
+        assert_eq!(data[4], 30);
+        assert_eq!(data[8], 5);
+        // Latched, with the hours written
+        assert_eq!(data[28], 5);
+
+        // Off for a day, an hour and 10 seconds
+        let mut loaded = Rtc::new();
+        loaded.load(&data, 1_000_000 + 86400 + 3600 + 10);
+        assert_eq!(loaded.registers(), [10, 30, 6, 1, 0]);
+        // Still latched at the save
+        assert_eq!(loaded.read(RTC_H), 5);
+
+        // Saved in the future
+        loaded.load(&data, 0);
+        assert_eq!(loaded.registers(), [0, 30, 5, 0, 0]);
+    }
+}
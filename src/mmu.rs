@@ -0,0 +1,588 @@
+//! Memory bus connecting the CPU to the cartridge, the RAM and the IO registers
+//! See https://gbdev.io/pandocs/Memory_Map.html
+
+use std::cell::{Ref, RefCell};
+
+use crate::apu::{Apu, NR50, NR51, NR52};
+use crate::cheats::Cheats;
+use crate::debugger::heatmap::Heatmap;
+use crate::infrared::{Infrared, RP};
+use crate::interrupts::Interrupt;
+use crate::joypad::{Button, Joypad, P1};
+use crate::mbc::Mbc;
+use crate::model::Model;
+use crate::ppu::{Ppu, BGP, LCDC, LY};
+use crate::serial::{Serial, SB, SC};
+use crate::timer::{Timer, DIV, TAC};
+
+/// Interrupt flags
+pub const IF: u16 = 0xff0f;
+/// Interrupt enable
+pub const IE: u16 = 0xffff;
+/// OAM DMA source address, divided by 0x100
+pub const DMA: u16 = 0xff46;
+/// Writing a non-zero value unmaps the boot ROM
+pub const BOOT: u16 = 0xff50;
+
+/// Name of the memory region containing `addr`
+pub fn region_name(addr: u16) -> &'static str {
+    match addr {
+        0x0000..=0x3fff => "ROM bank 0",
+        0x4000..=0x7fff => "ROM bank 1",
+        0x8000..=0x9fff => "VRAM",
+        0xa000..=0xbfff => "External RAM",
+        0xc000..=0xdfff => "Work RAM",
+        0xe000..=0xfdff => "Echo RAM",
+        0xfe00..=0xfe9f => "OAM",
+        0xfea0..=0xfeff => "Unusable",
+        0xff00..=0xff7f => "IO registers",
+        0xff80..=0xfffe => "High RAM",
+        IE => "Interrupt enable",
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmu {
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rom: Vec<u8>,
+    // Mapped over the cartridge at power on, until a write to BOOT
+    #[cfg_attr(feature = "serde", serde(skip))]
+    boot_rom: Vec<u8>,
+    boot_rom_mapped: bool,
+    // Banks of the ROM and cartridge RAM
+    mbc: Mbc,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::array"))]
+    work_ram: [u8; 0x2000],
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::array"))]
+    high_ram: [u8; 0x7f],
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    dma: u8,
+    joypad: Joypad,
+    timer: Timer,
+    serial: Serial,
+    infrared: Infrared,
+    ppu: Ppu,
+    apu: Apu,
+    // Record the reads and writes for the observers of the emulator
+    #[cfg_attr(feature = "serde", serde(skip))]
+    tracing: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    accesses: RefCell<Vec<Access>>,
+    // Value read from LY instead of the current line, for the test logs
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ly_override: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    heatmap: RefCell<Option<Box<Heatmap>>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    cheats: Cheats,
+    // The whole address space as RAM, instead of the hardware
+    #[cfg_attr(feature = "serde", serde(skip))]
+    flat: Option<Box<[u8]>>,
+}
+
+/// A read or a write on the bus, with its value
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+impl Mmu {
+    pub fn new(rom: Vec<u8>, model: Model) -> Self {
+        Self {
+            mbc: Mbc::new(&rom),
+            rom,
+            boot_rom: Vec::new(),
+            boot_rom_mapped: false,
+            work_ram: [0; 0x2000],
+            high_ram: [0; 0x7f],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            dma: 0xff,
+            joypad: Joypad::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            infrared: Infrared::new(),
+            ppu: Ppu::with_model(model),
+            apu: Apu::new(),
+            tracing: false,
+            accesses: RefCell::new(Vec::new()),
+            ly_override: None,
+            heatmap: RefCell::new(None),
+            cheats: Cheats::default(),
+            flat: None,
+        }
+    }
+
+    /// 64 KiB of RAM without any hardware, for the CPU test vectors setting
+    /// any address. Nothing raises an interrupt.
+    pub fn flat() -> Self {
+        Self {
+            flat: Some(vec![0; 0x10000].into_boxed_slice()),
+            ..Self::new(Vec::new(), Model::Dmg)
+        }
+    }
+
+    /// IO registers as left by the boot ROM, which is not emulated
+    pub fn after_boot(rom: Vec<u8>, model: Model) -> Self {
+        let mut mmu = Self::new(rom, model);
+        for (addr, value) in [
+            (NR52, 0xf1),
+            (NR50, 0x77),
+            (NR51, 0xf3),
+            (LCDC, 0x91),
+            (BGP, 0xfc),
+            (IF, 0xe1),
+        ] {
+            mmu.write(addr, value);
+        }
+        mmu
+    }
+
+    pub fn interrupt_enable(&self) -> u8 {
+        self.interrupt_enable
+    }
+
+    /// Pending interrupts, without the unused bits
+    pub fn interrupt_flag(&self) -> u8 {
+        self.interrupt_flag
+    }
+
+    /// Clear the flags of `mask` when the CPU dispatches the interrupt
+    pub(crate) fn acknowledge_interrupt(&mut self, mask: u8) {
+        self.interrupt_flag &= !mask;
+    }
+
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    pub fn press(&mut self, button: Button) {
+        if self.joypad.press(button) {
+            self.interrupt_flag |= Interrupt::Joypad.mask();
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    /// Press and release the buttons to match `state`, in the format of
+    /// `Joypad::state`
+    pub fn set_joypad_state(&mut self, state: u8) {
+        for (i, button) in Button::ALL.into_iter().enumerate() {
+            if state & (1 << i) != 0 {
+                self.press(button);
+            } else {
+                self.release(button);
+            }
+        }
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn mbc(&self) -> &Mbc {
+        &self.mbc
+    }
+
+    pub fn mbc_mut(&mut self) -> &mut Mbc {
+        &mut self.mbc
+    }
+
+    /// Map `boot_rom` over the cartridge, the CPU runs it from 0x0000. The
+    /// DMG boot ROM is 256 bytes, the CGB one 2304 bytes.
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = boot_rom;
+        self.boot_rom_mapped = true;
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    /// Take the cartridge, the cheats, the link cable, the infrared transport
+    /// and the settings of the frontend from `previous`, after loading a
+    /// save state
+    #[cfg(feature = "serde")]
+    pub(crate) fn keep_settings(&mut self, previous: &mut Self) {
+        self.rom = std::mem::take(&mut previous.rom);
+        self.boot_rom = std::mem::take(&mut previous.boot_rom);
+        self.ppu.keep_settings(&mut previous.ppu);
+        self.apu.keep_settings(&previous.apu);
+        self.cheats = std::mem::take(&mut previous.cheats);
+        self.serial.keep_link(&mut previous.serial);
+        self.infrared.keep_transport(&mut previous.infrared);
+    }
+
+    /// Take the heatmap of `previous`, replaced by a reset or a save state
+    pub fn keep_debugging(&mut self, previous: &mut Self) {
+        *self.heatmap.get_mut() = previous.heatmap.get_mut().take();
+    }
+
+    /// Count the reads and writes of each address from now on, None stops
+    pub fn set_heatmap(&mut self, heatmap: Option<Heatmap>) {
+        *self.heatmap.get_mut() = heatmap.map(Box::new);
+    }
+
+    pub fn heatmap(&self) -> Option<Ref<'_, Heatmap>> {
+        Ref::filter_map(self.heatmap.borrow(), |heatmap| heatmap.as_deref()).ok()
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut Heatmap> {
+        self.heatmap.get_mut().as_deref_mut()
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn timer_mut(&mut self) -> &mut Timer {
+        &mut self.timer
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    pub fn infrared(&self) -> &Infrared {
+        &self.infrared
+    }
+
+    pub fn infrared_mut(&mut self) -> &mut Infrared {
+        &mut self.infrared
+    }
+
+    /// Set each byte of the work RAM, the high RAM and the cartridge RAM to
+    /// the next value of `fill`
+    pub fn fill_ram(&mut self, mut fill: impl FnMut() -> u8) {
+        for byte in self
+            .work_ram
+            .iter_mut()
+            .chain(self.high_ram.iter_mut())
+            .chain(self.mbc.ram_mut().iter_mut())
+        {
+            *byte = fill();
+        }
+    }
+
+    /// Record the reads and writes from now on, or stop recording them
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    /// Move the recorded reads and writes to the end of `out`
+    pub fn drain_accesses(&mut self, out: &mut Vec<Access>) {
+        out.append(self.accesses.get_mut());
+    }
+
+    /// Advance the components on the bus by `cycles` T-cycles, and
+    /// request the interrupts they raise.
+    pub fn tick(&mut self, cycles: u32) {
+        if self.flat.is_some() {
+            return;
+        }
+        self.interrupt_flag |= self.ppu.tick(cycles);
+        self.interrupt_flag |= self.timer.tick(cycles);
+        self.interrupt_flag |= self.serial.tick(cycles);
+        self.infrared.tick(cycles);
+        self.apu.tick(cycles);
+        self.mbc.tick(cycles);
+    }
+
+    /// Copy 160 bytes from `source * 0x100` to the OAM. The copy is
+    /// instantaneous instead of taking 640 T-cycles.
+    fn start_dma(&mut self, source: u8) {
+        self.dma = source;
+        let start = (source as u16) << 8;
+        for i in 0..0xa0 {
+            let value = self.peek(start + i);
+            self.ppu.write(0xfe00 + i, value);
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        if let Some(heatmap) = self.heatmap.borrow_mut().as_mut() {
+            heatmap.record_read(addr);
+        }
+        self.fetch(addr)
+    }
+
+    /// Same as `read` for the CPU fetching an instruction, not counted by
+    /// the heatmap
+    pub(crate) fn fetch(&self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+        if self.tracing {
+            self.accesses.borrow_mut().push(Access::Read(addr, value));
+        }
+        value
+    }
+
+    /// Game Genie codes applied to the reads of the ROM, GameShark codes
+    /// written by `apply_cheats`
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Write the values of the GameShark codes, at each VBlank
+    pub fn apply_cheats(&mut self) {
+        let writes: Vec<(u16, u8)> = self.cheats.writes().collect();
+        for (addr, value) in writes {
+            self.write(addr, value);
+        }
+    }
+
+    /// Read LY as `value` whatever the line drawn, as the logs of Gameboy
+    /// Doctor expect with 0x90. None reads the line again.
+    pub fn set_ly_override(&mut self, value: Option<u8>) {
+        self.ly_override = value;
+    }
+
+    /// Same as `read`, without recording it for the observers
+    pub fn peek(&self, addr: u16) -> u8 {
+        if let Some(memory) = &self.flat {
+            return memory[addr as usize];
+        }
+        match addr {
+            0x0000..=0x7fff => match self.boot_rom.get(addr as usize) {
+                // The CGB boot ROM leaves the cartridge header visible
+                Some(&value) if self.boot_rom_mapped && !(0x100..0x200).contains(&addr) => value,
+                _ => {
+                    let offset = self.mbc.rom_offset(addr);
+                    let value = self.rom.get(offset).copied().unwrap_or(0xff);
+                    self.cheats.patch(addr, value)
+                }
+            },
+            0x8000..=0x9fff => self.ppu.read(addr),
+            0xa000..=0xbfff => self.mbc.read_ram(addr),
+            0xc000..=0xdfff => self.work_ram[(addr - 0xc000) as usize],
+            // Echo of the work RAM
+            0xe000..=0xfdff => self.work_ram[(addr - 0xe000) as usize],
+            0xfe00..=0xfe9f => self.ppu.read(addr),
+            P1 => self.joypad.read(),
+            SB | SC => self.serial.read(addr),
+            DIV..=TAC => self.timer.read(addr),
+            // The upper 3 bits are unused
+            IF => 0xe0 | self.interrupt_flag,
+            0xff10..=0xff3f => self.apu.read(addr),
+            DMA => self.dma,
+            BOOT => 0xff,
+            LY => self.ly_override.unwrap_or_else(|| self.ppu.read(LY)),
+            RP if self.ppu.model() == Model::Cgb => self.infrared.read(),
+            0xff40..=0xff7f => self.ppu.read(addr),
+            0xff80..=0xfffe => self.high_ram[(addr - 0xff80) as usize],
+            IE => self.interrupt_enable,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if let Some(heatmap) = self.heatmap.get_mut() {
+            heatmap.record_write(addr);
+        }
+        if self.tracing {
+            self.accesses.get_mut().push(Access::Write(addr, value));
+        }
+        if let Some(memory) = &mut self.flat {
+            memory[addr as usize] = value;
+            return;
+        }
+        match addr {
+            0x0000..=0x7fff => self.mbc.write(addr, value),
+            0x8000..=0x9fff => self.ppu.write(addr, value),
+            0xa000..=0xbfff => self.mbc.write_ram(addr, value),
+            0xc000..=0xdfff => self.work_ram[(addr - 0xc000) as usize] = value,
+            0xe000..=0xfdff => self.work_ram[(addr - 0xe000) as usize] = value,
+            0xfe00..=0xfe9f => self.ppu.write(addr, value),
+            P1 => self.joypad.write(value),
+            SB | SC => self.serial.write(addr, value),
+            DIV..=TAC => self.interrupt_flag |= self.timer.write(addr, value),
+            IF => self.interrupt_flag = value & 0x1f,
+            0xff10..=0xff3f => self.apu.write(addr, value),
+            DMA => self.start_dma(value),
+            BOOT => self.boot_rom_mapped &= value == 0,
+            RP if self.ppu.model() == Model::Cgb => self.infrared.write(value),
+            0xff40..=0xff7f => self.ppu.write(addr, value),
+            0xff80..=0xfffe => self.high_ram[(addr - 0xff80) as usize] = value,
+            IE => self.interrupt_enable = value,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::NR12;
+
+    #[test]
+    fn test_mmu_ram() {
+        let mut mmu = Mmu::new(vec![0x12, 0x34], Model::Dmg);
+        assert_eq!(mmu.read(0x0001), 0x34);
+        mmu.write(0x0001, 0x56);
+        assert_eq!(mmu.read(0x0001), 0x34);
+        assert_eq!(mmu.read(0x4000), 0xff);
+
+        mmu.write(0xc123, 0x56);
+        assert_eq!(mmu.read(0xe123), 0x56);
+        mmu.write(0xff80, 0x78);
+        assert_eq!(mmu.read(0xff80), 0x78);
+    }
+
+    #[test]
+    fn test_mmu_boot_rom() {
+        let mut mmu = Mmu::new(vec![0x12; 0x8000], Model::Cgb);
+        mmu.map_boot_rom(vec![0x34; 0x900]);
+        assert_eq!(mmu.read(0x0000), 0x34);
+        assert_eq!(mmu.read(0x0100), 0x12);
+        assert_eq!(mmu.read(0x0200), 0x34);
+        assert_eq!(mmu.read(0x0900), 0x12);
+        mmu.write(BOOT, 0x00);
+        assert!(mmu.boot_rom_mapped());
+        mmu.write(BOOT, 0x01);
+        assert!(!mmu.boot_rom_mapped());
+        assert_eq!(mmu.read(0x0000), 0x12);
+        // Only a reset maps it again
+        mmu.write(BOOT, 0x00);
+        assert_eq!(mmu.read(0x0000), 0x12);
+    }
+
+    #[test]
+    fn test_mmu_infrared() {
+        let mut mmu = Mmu::new(vec![], Model::Dmg);
+        mmu.write(RP, 0x01);
+        assert_eq!(mmu.read(RP), 0xff);
+        assert!(!mmu.infrared().led());
+
+        let mut mmu = Mmu::new(vec![], Model::Cgb);
+        assert_eq!(mmu.read(RP), 0x3e);
+        mmu.infrared_mut()
+            .connect(Box::new(crate::infrared::Loopback::default()));
+        mmu.write(RP, 0xc1);
+        assert_eq!(mmu.read(RP), 0xfd);
+    }
+
+    #[test]
+    fn test_mmu_io_registers() {
+        let mut mmu = Mmu::new(vec![], Model::Dmg);
+        mmu.write(NR52, 0x80);
+        mmu.write(NR12, 0xf3);
+        assert_eq!(mmu.read(NR12), 0xf3);
+        assert_eq!(mmu.apu().read(NR12), 0xf3);
+
+        mmu.write(LCDC, 0x80);
+        mmu.tick(456);
+        assert_eq!(mmu.read(LY), 1);
+        mmu.set_ly_override(Some(0x90));
+        assert_eq!(mmu.read(LY), 0x90);
+    }
+
+    #[test]
+    fn test_mmu_interrupts() {
+        let mut mmu = Mmu::new(vec![], Model::Dmg);
+        assert_eq!(mmu.read(IF), 0xe0);
+        mmu.write(LCDC, 0x80);
+        mmu.tick(456 * 144);
+        assert_eq!(mmu.read(IF), 0xe0 | Interrupt::VBlank.mask());
+        mmu.write(IF, 0);
+        assert_eq!(mmu.read(IF), 0xe0);
+    }
+
+    #[test]
+    fn test_mmu_joypad() {
+        let mut mmu = Mmu::new(vec![], Model::Dmg);
+        mmu.write(P1, 0x20);
+        mmu.press(Button::Left);
+        assert_eq!(mmu.read(P1), 0xed);
+        assert_eq!(mmu.read(IF), 0xe0 | Interrupt::Joypad.mask());
+        mmu.release(Button::Left);
+        assert_eq!(mmu.read(P1), 0xef);
+
+        mmu.set_joypad_state(0x82);
+        assert!(mmu.joypad().pressed(Button::Left));
+        assert!(mmu.joypad().pressed(Button::Start));
+        assert_eq!(mmu.joypad().state(), 0x82);
+    }
+
+    #[test]
+    fn test_mmu_timer_and_dma() {
+        let mut mmu = Mmu::new(vec![], Model::Dmg);
+        mmu.write(TAC, 0x05);
+        mmu.write(crate::timer::TIMA, 0xff);
+        mmu.tick(16);
+        assert_eq!(mmu.read(IF), 0xe0 | Interrupt::Timer.mask());
+
+        for i in 0..0xa0 {
+            mmu.write(0xc100 + i, i as u8);
+        }
+        mmu.write(DMA, 0xc1);
+        assert_eq!(mmu.read(DMA), 0xc1);
+        assert_eq!(mmu.read(0xfe00), 0x00);
+        assert_eq!(mmu.read(0xfe9f), 0x9f);
+    }
+
+    #[test]
+    fn test_mmu_heatmap() {
+        let mut mmu = Mmu::new(vec![0; 0x8000], Model::Dmg);
+        mmu.write(0xc000, 1);
+        mmu.set_heatmap(Some(Heatmap::new()));
+        mmu.write(0xc000, 2);
+        mmu.read(0xc000);
+        mmu.read(0xc000);
+        mmu.fetch(0x0100);
+        mmu.peek(0xc001);
+        let heatmap = mmu.heatmap().unwrap();
+        assert_eq!((heatmap.reads(0xc000), heatmap.writes(0xc000)), (2, 1));
+        assert_eq!(heatmap.max(), (2, 1));
+        drop(heatmap);
+
+        let mut reset = Mmu::new(vec![0; 0x8000], Model::Dmg);
+        reset.keep_debugging(&mut mmu);
+        assert!(mmu.heatmap().is_none());
+        assert_eq!(reset.heatmap_mut().unwrap().reads(0xc000), 2);
+    }
+
+    #[test]
+    fn test_mmu_flat() {
+        let mut mmu = Mmu::flat();
+        mmu.set_tracing(true);
+        mmu.write(0x0150, 0x12);
+        mmu.write(IF, 0x1f);
+        mmu.write(DMA, 0xc0);
+        mmu.tick(0x10000);
+        assert_eq!(mmu.read(0x0150), 0x12);
+        assert_eq!(mmu.read(DMA), 0xc0);
+        assert_eq!(mmu.interrupt_flag(), 0);
+        let mut accesses = Vec::new();
+        mmu.drain_accesses(&mut accesses);
+        assert_eq!(accesses.len(), 5);
+        assert_eq!(accesses[3], Access::Read(0x0150, 0x12));
+    }
+
+    #[test]
+    fn test_region_name() {
+        assert_eq!(region_name(0x0150), "ROM bank 0");
+        assert_eq!(region_name(0x9800), "VRAM");
+        assert_eq!(region_name(0xff44), "IO registers");
+        assert_eq!(region_name(IE), "Interrupt enable");
+    }
+}
@@ -0,0 +1,37 @@
+/// The hardware being emulated
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Model {
+    /// Original Game Boy
+    #[default]
+    Dmg,
+    /// Game Boy Color
+    Cgb,
+}
+
+impl Model {
+    /// Model for a cartridge, from the CGB flag of its header. The cartridges
+    /// working on both models run as CGB.
+    pub fn from_rom(rom: &[u8]) -> Self {
+        match rom.get(0x143) {
+            Some(flag) if flag & 0x80 != 0 => Self::Cgb,
+            _ => Self::Dmg,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_from_rom() {
+        let mut rom = vec![0; 0x150];
+        assert_eq!(Model::from_rom(&rom), Model::Dmg);
+        rom[0x143] = 0x80;
+        assert_eq!(Model::from_rom(&rom), Model::Cgb);
+        rom[0x143] = 0xc0;
+        assert_eq!(Model::from_rom(&rom), Model::Cgb);
+        assert_eq!(Model::from_rom(&[]), Model::Dmg);
+    }
+}
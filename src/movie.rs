@@ -0,0 +1,198 @@
+//! Recording of the joypad state of every frame, to replay a run
+//! deterministically from power-on.
+//!
+//! The core always powers on in the same state for a given model and ROM,
+//! so they are enough to describe the initial state. File layout:
+//! - magic "GBMV" and format version (1 byte)
+//! - model (1 byte, 0 for DMG and 1 for CGB)
+//! - FNV-1a hash of the ROM (8 bytes, little endian)
+//! - number of frames (4 bytes, little endian)
+//! - one byte per frame, in the format of `Joypad::state`
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::model::Model;
+
+const MAGIC: &[u8; 4] = b"GBMV";
+const VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Movie {
+    pub model: Model,
+    pub rom_hash: u64,
+    frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(model: Model, rom: &[u8]) -> Self {
+        Self {
+            model,
+            rom_hash: rom_hash(rom),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Whether the movie was recorded with this ROM
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == rom_hash(rom)
+    }
+
+    /// Append the joypad state of the next frame
+    pub fn record(&mut self, state: u8) {
+        self.frames.push(state);
+    }
+
+    /// Joypad state of `frame`, `None` after the end of the movie
+    pub fn frame(&self, frame: usize) -> Option<u8> {
+        self.frames.get(frame).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Drop the frames after `len`, to record again from there
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self, MovieError> {
+        let mut header = [0; 18];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(MovieError::InvalidHeader);
+        }
+        if header[4] != VERSION {
+            return Err(MovieError::UnsupportedVersion(header[4]));
+        }
+        let model = match header[5] {
+            0 => Model::Dmg,
+            1 => Model::Cgb,
+            _ => return Err(MovieError::InvalidHeader),
+        };
+        let rom_hash = u64::from_le_bytes(header[6..14].try_into().unwrap());
+        let len = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let mut frames = vec![0; len as usize];
+        reader.read_exact(&mut frames)?;
+        Ok(Self {
+            model,
+            rom_hash,
+            frames,
+        })
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), MovieError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, self.model as u8])?;
+        writer.write_all(&self.rom_hash.to_le_bytes())?;
+        writer.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.frames)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, MovieError> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), MovieError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// FNV-1a hash identifying a ROM
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    IOError(std::io::Error),
+}
+
+impl Error for MovieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidHeader => None,
+            Self::UnsupportedVersion(_) => None,
+            Self::IOError(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for MovieError {
+    fn from(value: std::io::Error) -> Self {
+        MovieError::IOError(value)
+    }
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeader => f.write_str("Not a movie file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported movie version {}", version)
+            }
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie_roundtrip() {
+        let rom = [0x00, 0xc3, 0x50, 0x01];
+        let mut movie = Movie::new(Model::Cgb, &rom);
+        for state in [0x00, 0x01, 0x81, 0x10] {
+            movie.record(state);
+        }
+        let mut data = vec![];
+        movie.write_to(&mut data).unwrap();
+        assert_eq!(data.len(), 18 + 4);
+
+        let loaded = Movie::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!(loaded, movie);
+        assert!(loaded.matches_rom(&rom));
+        assert!(!loaded.matches_rom(&rom[..3]));
+        assert_eq!(loaded.frame(2), Some(0x81));
+        assert_eq!(loaded.frame(4), None);
+    }
+
+    #[test]
+    fn test_movie_invalid() {
+        let mut data = vec![];
+        Movie::new(Model::Dmg, &[]).write_to(&mut data).unwrap();
+        data[4] = 2;
+        assert!(matches!(
+            Movie::read_from(&mut data.as_slice()).unwrap_err(),
+            MovieError::UnsupportedVersion(2)
+        ));
+        data[0] = b'X';
+        assert!(matches!(
+            Movie::read_from(&mut data.as_slice()).unwrap_err(),
+            MovieError::InvalidHeader
+        ));
+        assert!(matches!(
+            Movie::read_from(&mut &data[..10]).unwrap_err(),
+            MovieError::IOError(_)
+        ));
+    }
+}
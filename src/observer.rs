@@ -0,0 +1,64 @@
+//! Callbacks of the emulator, to build tracers, coverage tools or cheats on
+//! top of the core. Observing the instructions and the memory accesses slows
+//! down the emulation, the frames are free.
+
+use crate::decoder::Opcode;
+use crate::ppu::FRAMEBUFFER_SIZE;
+
+/// A frame completed by `Emulator::run_frame`
+pub struct Frame<'a> {
+    /// Frames emulated since power on, this one included
+    pub number: usize,
+    /// The screen, in RGBA8
+    pub framebuffer: &'a [u8; FRAMEBUFFER_SIZE],
+    /// Audio of the frame, interleaved left and right
+    pub samples: &'a [f32],
+    /// Bytes sent on the serial port during the frame
+    pub serial: &'a [u8],
+    /// Buttons held, in the format of `Joypad::state`
+    pub buttons: u8,
+    /// T-cycles emulated during the frame
+    pub cycles: u32,
+    /// Motor of a rumble cartridge on at the end of the frame
+    pub rumble: bool,
+}
+
+/// A copy of a `Frame`, to keep it after the next one, send it to another
+/// thread...
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedFrame {
+    pub number: usize,
+    pub framebuffer: Box<[u8; FRAMEBUFFER_SIZE]>,
+    pub samples: Vec<f32>,
+    pub serial: Vec<u8>,
+    pub buttons: u8,
+    pub cycles: u32,
+    pub rumble: bool,
+}
+
+impl From<&Frame<'_>> for OwnedFrame {
+    fn from(frame: &Frame) -> Self {
+        Self {
+            number: frame.number,
+            framebuffer: Box::new(*frame.framebuffer),
+            samples: frame.samples.to_vec(),
+            serial: frame.serial.to_vec(),
+            buttons: frame.buttons,
+            cycles: frame.cycles,
+            rumble: frame.rumble,
+        }
+    }
+}
+
+/// Receives the events of the emulator it is added to. All the methods do
+/// nothing by default. Observers are `Send` to run the emulator on another
+/// thread.
+pub trait Observer: Send {
+    /// Called before the reads and writes of the instruction at `pc`
+    fn on_instruction(&mut self, _pc: u16, _opcode: &Opcode) {}
+    /// A read of the CPU, including the fetches of the instructions
+    fn on_read(&mut self, _addr: u16, _value: u8) {}
+    /// A write of the CPU
+    fn on_write(&mut self, _addr: u16, _value: u8) {}
+    fn on_frame(&mut self, _frame: &Frame) {}
+}
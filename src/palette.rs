@@ -0,0 +1,157 @@
+use std::{error::Error, fmt::Display, num::ParseIntError, str::FromStr};
+
+/// RGBA colors used to display the 4 shades of the DMG, from the lightest (0)
+/// to the darkest (3)
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmgPalette {
+    pub shades: [[u8; 4]; 4],
+}
+
+impl DmgPalette {
+    pub const GREY: Self = Self {
+        shades: [
+            [0xff, 0xff, 0xff, 0xff],
+            [0xaa, 0xaa, 0xaa, 0xff],
+            [0x55, 0x55, 0x55, 0xff],
+            [0x00, 0x00, 0x00, 0xff],
+        ],
+    };
+
+    /// Colors of the original DMG screen
+    pub const GREEN: Self = Self {
+        shades: [
+            [0x9b, 0xbc, 0x0f, 0xff],
+            [0x8b, 0xac, 0x0f, 0xff],
+            [0x30, 0x62, 0x30, 0xff],
+            [0x0f, 0x38, 0x0f, 0xff],
+        ],
+    };
+
+    pub fn shade(&self, shade: u8) -> [u8; 4] {
+        self.shades[shade as usize]
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        Self::GREY
+    }
+}
+
+/// Either the name of a built-in palette (`grey`, `green`) or 4 comma
+/// separated RRGGBB hex colors, from the lightest to the darkest.
+impl FromStr for DmgPalette {
+    type Err = PaletteError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "grey" | "gray" => return Ok(Self::GREY),
+            "green" => return Ok(Self::GREEN),
+            _ => {}
+        }
+
+        let colors: Vec<&str> = value.split(',').map(str::trim).collect();
+        if colors.len() != 4 {
+            return Err(PaletteError::ColorCount(colors.len()));
+        }
+        let mut shades = [[0xff; 4]; 4];
+        for (shade, color) in shades.iter_mut().zip(colors) {
+            let color = color.trim_start_matches('#');
+            if color.len() != 6 {
+                return Err(PaletteError::InvalidColor(color.to_string()));
+            }
+            let rgb = u32::from_str_radix(color, 16)?;
+            shade[..3].copy_from_slice(&rgb.to_be_bytes()[1..]);
+        }
+        Ok(Self { shades })
+    }
+}
+
+/// The name of a built-in palette, or the colors in the format of `from_str`
+impl Display for DmgPalette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::GREY => f.write_str("grey"),
+            Self::GREEN => f.write_str("green"),
+            _ => {
+                let colors: Vec<String> = self
+                    .shades
+                    .iter()
+                    .map(|[r, g, b, _]| format!("{:02x}{:02x}{:02x}", r, g, b))
+                    .collect();
+                f.write_str(&colors.join(","))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    ColorCount(usize),
+    InvalidColor(String),
+    ParseError(ParseIntError),
+}
+
+impl Error for PaletteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ColorCount(_) => None,
+            Self::InvalidColor(_) => None,
+            Self::ParseError(err) => Some(err),
+        }
+    }
+}
+
+impl From<ParseIntError> for PaletteError {
+    fn from(value: ParseIntError) -> Self {
+        PaletteError::ParseError(value)
+    }
+}
+
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ColorCount(count) => write!(f, "Expected 4 colors, got {}", count),
+            Self::InvalidColor(color) => write!(f, "Invalid color {}", color),
+            Self::ParseError(err) => write!(f, "Parse error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_from_name() {
+        assert_eq!("grey".parse::<DmgPalette>().unwrap(), DmgPalette::GREY);
+        assert_eq!("green".parse::<DmgPalette>().unwrap(), DmgPalette::GREEN);
+    }
+
+    #[test]
+    fn test_palette_from_colors() {
+        let palette: DmgPalette = "ffffff, #c0c0c0,808080,102030".parse().unwrap();
+        assert_eq!(palette.shade(0), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(palette.shade(1), [0xc0, 0xc0, 0xc0, 0xff]);
+        assert_eq!(palette.shade(3), [0x10, 0x20, 0x30, 0xff]);
+        assert_eq!(palette.to_string(), "ffffff,c0c0c0,808080,102030");
+        assert_eq!(DmgPalette::GREEN.to_string(), "green");
+    }
+
+    #[test]
+    fn test_palette_errors() {
+        assert!(matches!(
+            "ffffff,000000".parse::<DmgPalette>(),
+            Err(PaletteError::ColorCount(2))
+        ));
+        assert!(matches!(
+            "fff,000000,000000,000000".parse::<DmgPalette>(),
+            Err(PaletteError::InvalidColor(_))
+        ));
+        assert!(matches!(
+            "gggggg,000000,000000,000000".parse::<DmgPalette>(),
+            Err(PaletteError::ParseError(_))
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+//! Color palettes of the Game Boy Color
+
+/// 8 palettes of 4 colors, each color stored as 2 bytes of little-endian
+/// RGB555. The palette RAM is accessed through an index register (BCPS/OCPS)
+/// and a data register (BCPD/OCPD).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct ColorPalettes {
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::array"))]
+    data: [u8; 64],
+    // Bits 0-5: address in the palette RAM, bit 7: auto-increment after writes
+    index: u8,
+}
+
+const AUTO_INCREMENT: u8 = 0x80;
+
+impl Default for ColorPalettes {
+    fn default() -> Self {
+        // Every color is white
+        Self {
+            data: [0xff; 64],
+            index: 0,
+        }
+    }
+}
+
+impl ColorPalettes {
+    pub fn read_index(&self) -> u8 {
+        // Bit 6 is unused
+        self.index | 0x40
+    }
+
+    pub fn write_index(&mut self, value: u8) {
+        self.index = value & 0xbf;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[(self.index & 0x3f) as usize]
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.data[(self.index & 0x3f) as usize] = value;
+        if self.index & AUTO_INCREMENT != 0 {
+            self.index = AUTO_INCREMENT | ((self.index + 1) & 0x3f);
+        }
+    }
+
+    /// RGB555 value of a color of one of the palettes
+    pub fn color(&self, palette: u8, color: u8) -> u16 {
+        let offset = (palette as usize * 4 + color as usize) * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]]) & 0x7fff
+    }
+}
+
+/// Convert a RGB555 color (red in the lowest bits) to RGBA8
+pub(super) fn rgb555_to_rgba(color: u16) -> [u8; 4] {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1f) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [channel(0), channel(5), channel(10), 0xff]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_palettes_auto_increment() {
+        let mut palettes = ColorPalettes::default();
+        palettes.write_index(AUTO_INCREMENT | 0x3e);
+        palettes.write_data(0x1f);
+        palettes.write_data(0x00);
+        // The address wraps around
+        assert_eq!(palettes.read_index(), 0x40 | AUTO_INCREMENT);
+        palettes.write_data(0xe0);
+        palettes.write_data(0x03);
+
+        assert_eq!(palettes.color(7, 3), 0x001f);
+        assert_eq!(palettes.color(0, 0), 0x03e0);
+    }
+
+    #[test]
+    fn test_color_palettes_no_auto_increment() {
+        let mut palettes = ColorPalettes::default();
+        palettes.write_index(0x02);
+        palettes.write_data(0x12);
+        palettes.write_data(0x34);
+        assert_eq!(palettes.read_index(), 0x42);
+        assert_eq!(palettes.read_data(), 0x34);
+        // The unused bit 15 is ignored
+        assert_eq!(palettes.color(0, 1), 0x7f34);
+    }
+
+    #[test]
+    fn test_rgb555_to_rgba() {
+        assert_eq!(rgb555_to_rgba(0x0000), [0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(rgb555_to_rgba(0x7fff), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(rgb555_to_rgba(0x001f), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(rgb555_to_rgba(0x03e0), [0x00, 0xff, 0x00, 0xff]);
+        assert_eq!(rgb555_to_rgba(0x7c00), [0x00, 0x00, 0xff, 0xff]);
+        assert_eq!(rgb555_to_rgba(0x0010), [0x84, 0x00, 0x00, 0xff]);
+    }
+}
@@ -0,0 +1,495 @@
+//! Dot by dot emulation of mode 3: the background fetcher feeds a FIFO of
+//! pixels which are shifted out to the LCD one per dot. Objects suspend the
+//! fetcher while their data is fetched and mixed into a second FIFO.
+//! See https://gbdev.io/pandocs/pixel_fifo.html
+
+use std::collections::VecDeque;
+
+use super::{BgPixel, Model, Ppu, LCDC_OBJ_ENABLE, LCDC_WINDOW_ENABLE};
+use super::{BG_BANK, BG_PALETTE, BG_PRIORITY, BG_X_FLIP, BG_Y_FLIP, SCREEN_WIDTH};
+
+// Number of dots to fetch a tile before its pixels can be pushed to the FIFO
+const FETCH_DOTS: u8 = 6;
+// The first tile of each line is fetched twice, and the first fetch is thrown away
+const LINE_START_DOTS: u8 = 6;
+// Minimum number of dots the fetcher is suspended for an object
+const OBJ_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ObjPixel {
+    color: u8,
+    attributes: u8,
+    // Position of the object in OAM, used for the priority on CGB
+    oam_order: usize,
+}
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct PixelFifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    // Dot of the current tile fetch: tile index, low byte, high byte, then push
+    fetcher_dot: u8,
+    // Tile being fetched, counted from the start of the line or of the window
+    fetcher_x: u8,
+    tile_index: u8,
+    tile_attributes: u8,
+    tile_low: u8,
+    tile_high: u8,
+    // Next pixel to output to the LCD
+    x: u8,
+    // Pixels thrown away at the start of the line for the fine scrolling (SCX % 8)
+    discard: u8,
+    // Dots during which nothing happens (line start, object fetches)
+    stall: u8,
+    in_window: bool,
+    // The window reached the screen during this line
+    window_drawn: bool,
+    // With WX=166, the window is triggered on the last dot of a line and
+    // covers the whole next line.
+    pub(super) window_next_line: bool,
+    // Objects of the line not fetched yet with their position in OAM, sorted by X
+    objects: VecDeque<(usize, [u8; 4])>,
+}
+
+impl Ppu {
+    /// Reset the FIFO at the beginning of mode 3
+    pub(super) fn fifo_start_line(&mut self) {
+        let window_next_line = self.fifo.window_next_line;
+        let mut objects: Vec<(usize, [u8; 4])> = if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.scan_objects(self.ly as usize)
+                .into_iter()
+                .enumerate()
+                .collect()
+        } else {
+            vec![]
+        };
+        // Objects are fetched from left to right, the first one in OAM first on equal X
+        objects.sort_by_key(|(_, obj)| obj[1]);
+        self.fifo = PixelFifo {
+            discard: self.scx % 8,
+            stall: LINE_START_DOTS,
+            objects: objects.into(),
+            ..Default::default()
+        };
+        if window_next_line && self.window_visible_on_line() {
+            self.fifo.in_window = true;
+            self.fifo.window_drawn = true;
+        }
+    }
+
+    /// Called at the beginning of HBlank
+    pub(super) fn fifo_end_line(&mut self) {
+        if self.fifo.window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    fn window_visible_on_line(&self) -> bool {
+        self.lcdc & LCDC_WINDOW_ENABLE != 0 && self.bg_enabled() && self.ly >= self.wy
+    }
+
+    /// Advance mode 3 by one dot. Returns true once the 160 pixels of the line
+    /// have been sent to the LCD.
+    pub(super) fn fifo_step(&mut self) -> bool {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return false;
+        }
+
+        if !self.fifo.in_window
+            && self.window_visible_on_line()
+            && self.fifo.discard == 0
+            && self.fifo.x as u16 + 7 >= self.wx as u16
+        {
+            if self.wx == 166 {
+                self.fifo.window_next_line = true;
+            } else {
+                // The fetcher restarts from the first tile of the window
+                self.fifo.in_window = true;
+                self.fifo.window_drawn = true;
+                self.fifo.bg.clear();
+                self.fifo.fetcher_dot = 0;
+                self.fifo.fetcher_x = 0;
+                return false;
+            }
+        }
+
+        self.fetcher_step();
+
+        if !self.fifo.bg.is_empty() && self.fetch_object() {
+            return false;
+        }
+
+        let Some(bg) = self.fifo.bg.pop_front() else {
+            return false;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+
+        let layer_visible = if self.fifo.in_window {
+            self.layers.window
+        } else {
+            self.layers.background
+        };
+        // When the background is disabled, it is white and objects always have priority
+        let (bg, mut value) = if self.bg_enabled() {
+            let bg = if layer_visible {
+                bg
+            } else {
+                BgPixel::default()
+            };
+            (bg, self.bg_output(bg))
+        } else {
+            (BgPixel::default(), 0)
+        };
+        if let Some(obj) = self.fifo.obj.pop_front() {
+            if obj.color != 0 && self.layers.objects && self.object_visible(bg, obj.attributes) {
+                value = self.obj_output(obj.attributes, obj.color);
+            }
+        }
+        let x = self.fifo.x as usize;
+        self.pixels[self.ly as usize * SCREEN_WIDTH + x] = value;
+        self.fifo.x += 1;
+        self.fifo.x as usize == SCREEN_WIDTH
+    }
+
+    /// If an object starts at the current pixel, mix it into the object FIFO
+    /// and suspend the fetcher. Returns true if an object was fetched.
+    fn fetch_object(&mut self) -> bool {
+        let x = self.fifo.x as usize;
+        let Some((oam_order, obj)) = self.fifo.objects.front().copied() else {
+            return false;
+        };
+        // Objects partially hidden on the left are fetched on the first pixel
+        if (obj[1] as usize).max(8) - 8 > x {
+            return false;
+        }
+        self.fifo.objects.pop_front();
+
+        // Only the pixels still to be displayed are mixed
+        let clipped = (x + 8).saturating_sub(obj[1] as usize).min(8);
+        while self.fifo.obj.len() < 8 - clipped {
+            self.fifo.obj.push_back(ObjPixel::default());
+        }
+        let colors = self.object_row(obj, self.ly as usize);
+        let cgb = self.model == Model::Cgb;
+        for (pixel, color) in self.fifo.obj.iter_mut().zip(&colors[clipped..]) {
+            // On DMG the objects already in the FIFO have priority as they have
+            // a smaller X. On CGB the first object in OAM wins.
+            if pixel.color == 0 || (cgb && *color != 0 && oam_order < pixel.oam_order) {
+                *pixel = ObjPixel {
+                    color: *color,
+                    attributes: obj[3],
+                    oam_order,
+                };
+            }
+        }
+
+        // The fetcher first finishes the background tile in progress
+        self.fifo.stall =
+            OBJ_FETCH_DOTS + (FETCH_DOTS - 1).saturating_sub(self.fifo.fetcher_dot) - 1;
+        true
+    }
+
+    fn fetcher_step(&mut self) {
+        let (map, tile_x, y) = if self.fifo.in_window {
+            (
+                self.window_tile_map(),
+                self.fifo.fetcher_x as usize,
+                self.window_line as usize,
+            )
+        } else {
+            // SCX is read for each tile, so writes in the middle of a line
+            // affect the following tiles.
+            (
+                self.bg_tile_map(),
+                (self.scx as usize / 8 + self.fifo.fetcher_x as usize) % 32,
+                (self.ly as usize + self.scy as usize) % 256,
+            )
+        };
+        let addressing = self.tile_addressing();
+        let fifo = &mut self.fifo;
+
+        match fifo.fetcher_dot {
+            1 => {
+                let map_addr = map.offset() + (y / 8) * 32 + tile_x;
+                fifo.tile_index = self.vram[map_addr];
+                fifo.tile_attributes = match self.model {
+                    Model::Dmg => 0,
+                    Model::Cgb => self.vram[0x2000 + map_addr],
+                };
+            }
+            3 | 5 => {
+                let attributes = fifo.tile_attributes;
+                let row = if attributes & BG_Y_FLIP != 0 {
+                    7 - y % 8
+                } else {
+                    y % 8
+                };
+                let bank = (attributes & BG_BANK != 0) as usize;
+                let addr = bank * 0x2000 + addressing.tile_addr(fifo.tile_index) + row * 2;
+                if fifo.fetcher_dot == 3 {
+                    fifo.tile_low = self.vram[addr];
+                } else {
+                    fifo.tile_high = self.vram[addr + 1];
+                }
+            }
+            _ => (),
+        }
+
+        if fifo.fetcher_dot < FETCH_DOTS {
+            fifo.fetcher_dot += 1;
+        } else if fifo.bg.is_empty() {
+            let attributes = fifo.tile_attributes;
+            for column in 0..8 {
+                let bit = if attributes & BG_X_FLIP != 0 {
+                    column
+                } else {
+                    7 - column
+                };
+                fifo.bg.push_back(BgPixel {
+                    color: (((fifo.tile_high >> bit) & 1) << 1) | ((fifo.tile_low >> bit) & 1),
+                    palette: attributes & BG_PALETTE,
+                    priority: attributes & BG_PRIORITY != 0,
+                });
+            }
+            fifo.fetcher_dot = 0;
+            fifo.fetcher_x = fifo.fetcher_x.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::super::*;
+
+    fn fifo_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::PixelFifo);
+        ppu
+    }
+
+    /// Number of dots spent in mode 3 on the next line
+    fn drawing_dots(ppu: &mut Ppu) -> u32 {
+        while ppu.mode() != Mode::OamScan {
+            ppu.tick(1);
+        }
+        while ppu.mode() != Mode::Drawing {
+            ppu.tick(1);
+        }
+        let mut dots = 0;
+        while ppu.mode() == Mode::Drawing {
+            ppu.tick(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_fifo_drawing_length() {
+        let mut ppu = fifo_ppu();
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_BG_ENABLE);
+        assert_eq!(drawing_dots(&mut ppu), 172);
+    }
+
+    #[test]
+    fn test_fifo_drawing_length_fine_scroll() {
+        let mut ppu = fifo_ppu();
+        ppu.write(SCX, 3);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_BG_ENABLE);
+        assert_eq!(drawing_dots(&mut ppu), 175);
+    }
+
+    #[test]
+    fn test_fifo_drawing_length_objects() {
+        let mut ppu = fifo_ppu();
+        for (i, value) in [16, 50, 0, 0, 16, 100, 0, 0].iter().enumerate() {
+            ppu.write(0xfe00 + i as u16, *value);
+        }
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_BG_ENABLE | LCDC_OBJ_ENABLE);
+        let dots = drawing_dots(&mut ppu);
+        assert!((172 + 2 * 6..=172 + 2 * 11).contains(&dots), "{}", dots);
+
+        // Objects not on the line do not slow down the PPU
+        for _ in 0..10 {
+            assert!(drawing_dots(&mut ppu) >= 172);
+        }
+        assert_eq!(drawing_dots(&mut ppu), 172);
+    }
+
+    /// Background, window and objects
+    fn draw_scene(ppu: &mut Ppu) {
+        fill_tile(ppu, 0x8010, 3);
+        for row in 0..8 {
+            ppu.write(0x8020 + row * 2, 0x3c);
+            ppu.write(0x8021 + row * 2, 0x0f);
+        }
+        for i in 0..0x400 {
+            ppu.write(0x9800 + i, (i % 3) as u8);
+            ppu.write(0x9c00 + i, 2);
+        }
+        let objects = [
+            [16, 4, 2, 0],
+            [20, 30, 1, OBJ_X_FLIP | OBJ_PALETTE],
+            [20, 34, 2, OBJ_BG_PRIORITY],
+            [100, 80, 2, OBJ_Y_FLIP],
+            [100, 80, 1, 0],
+        ];
+        for (i, obj) in objects.iter().enumerate() {
+            for (j, value) in obj.iter().enumerate() {
+                ppu.write(0xfe00 + (i * 4 + j) as u16, *value);
+            }
+        }
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(OBP0, IDENTITY_PALETTE);
+        ppu.write(OBP1, 0x1b);
+        ppu.write(SCX, 13);
+        ppu.write(SCY, 7);
+        ppu.write(WX, 90);
+        ppu.write(WY, 60);
+        ppu.write(
+            LCDC,
+            LCDC_ENABLE
+                | LCDC_TILE_DATA
+                | LCDC_BG_ENABLE
+                | LCDC_OBJ_ENABLE
+                | LCDC_WINDOW_ENABLE
+                | LCDC_WINDOW_TILE_MAP,
+        );
+    }
+
+    #[test]
+    fn test_fifo_same_output_as_scanline() {
+        let mut scanline = Ppu::new();
+        draw_scene(&mut scanline);
+        run_frame(&mut scanline);
+
+        let mut fifo = fifo_ppu();
+        draw_scene(&mut fifo);
+        run_frame(&mut fifo);
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                assert_eq!(pixel(&fifo, x, y), pixel(&scanline, x, y), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fifo_same_output_as_scanline_cgb() {
+        let cgb_scene = |ppu: &mut Ppu| {
+            draw_scene(ppu);
+            ppu.write(VBK, 1);
+            for i in 0..0x400 {
+                let attributes = [0, BG_X_FLIP | 1, BG_Y_FLIP | 1, BG_PRIORITY | 1];
+                ppu.write(0x9800 + i, attributes[i as usize % 4]);
+                ppu.write(0x9c00 + i, BG_BANK | 1);
+            }
+            fill_tile(ppu, 0x8020, 2);
+            for (i, value) in [24, 40, 1, 2, 24, 36, 2, OBJ_BANK | 2].iter().enumerate() {
+                ppu.write(0xfe20 + i as u16, *value);
+            }
+            ppu.write(VBK, 0);
+        };
+
+        let mut scanline = cgb_ppu();
+        cgb_scene(&mut scanline);
+        run_frame(&mut scanline);
+
+        let mut fifo = cgb_ppu();
+        fifo.set_renderer(Renderer::PixelFifo);
+        cgb_scene(&mut fifo);
+        run_frame(&mut fifo);
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                assert_eq!(pixel(&fifo, x, y), pixel(&scanline, x, y), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fifo_object_on_first_pixel() {
+        let scene = |ppu: &mut Ppu| {
+            fill_tile(ppu, 0x8010, 3);
+            for (i, value) in [16, 8, 1, 0].iter().enumerate() {
+                ppu.write(0xfe00 + i as u16, *value);
+            }
+            ppu.write(OBP0, 0x40);
+            ppu.write(LCDC, LCDC_ENABLE | LCDC_OBJ_ENABLE);
+        };
+        let mut scanline = Ppu::new();
+        scene(&mut scanline);
+        run_frame(&mut scanline);
+
+        let mut fifo = fifo_ppu();
+        scene(&mut fifo);
+        run_frame(&mut fifo);
+
+        for x in 0..8 {
+            assert_eq!(pixel(&scanline, x, 0), SHADES[1]);
+            assert_eq!(pixel(&fifo, x, 0), SHADES[1], "{}", x);
+        }
+    }
+
+    /// Write SCX in the middle of mode 3 of the first line
+    fn mid_scanline_scx(ppu: &mut Ppu) {
+        fill_tile(ppu, 0x8010, 3);
+        for i in 0..32 {
+            ppu.write(0x9800 + i, (i >= 16) as u8);
+        }
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        while ppu.mode() != Mode::Drawing {
+            ppu.tick(1);
+        }
+        ppu.tick(60);
+        ppu.write(SCX, 64);
+        run_frame(ppu);
+    }
+
+    #[test]
+    fn test_fifo_mid_scanline_scx() {
+        let mut ppu = fifo_ppu();
+        mid_scanline_scx(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), WHITE);
+        assert_eq!(pixel(&ppu, 159, 0), BLACK);
+        // The second line uses the new value from the start
+        assert_eq!(pixel(&ppu, 0, 1), WHITE);
+        assert_eq!(pixel(&ppu, 64, 1), BLACK);
+
+        // The scanline renderer only sees the value at the end of mode 3
+        let mut ppu = Ppu::new();
+        mid_scanline_scx(&mut ppu);
+        assert_eq!(pixel(&ppu, 64, 0), BLACK);
+    }
+
+    #[test]
+    fn test_fifo_wx_166() {
+        let mut ppu = fifo_ppu();
+        fill_tile(&mut ppu, 0x8010, 3);
+        for i in 0..0x400 {
+            ppu.write(0x9c00 + i, 1);
+        }
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(WX, 166);
+        ppu.write(WY, 10);
+        ppu.write(
+            LCDC,
+            LCDC_ENABLE
+                | LCDC_TILE_DATA
+                | LCDC_BG_ENABLE
+                | LCDC_WINDOW_ENABLE
+                | LCDC_WINDOW_TILE_MAP,
+        );
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 159, 10), WHITE);
+        assert_eq!(pixel(&ppu, 0, 11), BLACK);
+        assert_eq!(pixel(&ppu, 159, 11), BLACK);
+    }
+}
@@ -0,0 +1,106 @@
+//! Post-processing applied to each complete frame
+
+use super::FRAMEBUFFER_SIZE;
+
+/// A stage of the framebuffer pipeline, called once per frame on the RGBA8
+/// pixels after the conversion from the PPU output.
+pub trait Filter: Send {
+    fn apply(&mut self, frame: &mut [u8; FRAMEBUFFER_SIZE]);
+}
+
+/// The filters of a PPU. The copies of a PPU, taken for the debuggers, start
+/// without filters.
+#[derive(Default)]
+pub(super) struct Filters(pub(super) Vec<Box<dyn Filter>>);
+
+impl Clone for Filters {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// Blend each frame with the previous one to mimic the slow response of the
+/// DMG LCD. Games relying on it show objects every other frame to make them
+/// look transparent, which flickers without this filter.
+pub struct Ghosting {
+    strength: f32,
+    previous: Option<Box<[u8; FRAMEBUFFER_SIZE]>>,
+}
+
+impl Ghosting {
+    /// `strength` is the weight of the previous frame, from 0.0 (no effect)
+    /// to 1.0 (only the previous frame is shown)
+    pub fn new(strength: f32) -> Self {
+        Self {
+            strength: strength.clamp(0.0, 1.0),
+            previous: None,
+        }
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+    }
+}
+
+impl Default for Ghosting {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+impl Filter for Ghosting {
+    fn apply(&mut self, frame: &mut [u8; FRAMEBUFFER_SIZE]) {
+        let weight = (self.strength * 256.0) as u32;
+        match &mut self.previous {
+            Some(previous) => {
+                for (pixel, previous) in frame.iter_mut().zip(previous.iter_mut()) {
+                    let current = *pixel;
+                    *pixel =
+                        ((current as u32 * (256 - weight) + *previous as u32 * weight) / 256) as u8;
+                    // Blending with the unfiltered frame keeps a single frame of persistence
+                    *previous = current;
+                }
+            }
+            // Nothing to blend with on the first frame
+            None => self.previous = Some(Box::new(*frame)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ghosting_blends_previous_frame() {
+        let mut ghosting = Ghosting::new(0.25);
+
+        let mut frame = Box::new([0x00; FRAMEBUFFER_SIZE]);
+        ghosting.apply(&mut frame);
+        assert!(frame.iter().all(|&c| c == 0x00));
+
+        let mut frame = Box::new([0xff; FRAMEBUFFER_SIZE]);
+        ghosting.apply(&mut frame);
+        assert!(frame.iter().all(|&c| c == 0xbf));
+
+        let mut frame = Box::new([0xff; FRAMEBUFFER_SIZE]);
+        ghosting.apply(&mut frame);
+        assert!(frame.iter().all(|&c| c == 0xff));
+    }
+
+    #[test]
+    fn test_ghosting_strength_bounds() {
+        let mut ghosting = Ghosting::new(2.0);
+        assert_eq!(ghosting.strength(), 1.0);
+        ghosting.set_strength(0.0);
+
+        ghosting.apply(&mut Box::new([0x00; FRAMEBUFFER_SIZE]));
+        let mut frame = Box::new([0x80; FRAMEBUFFER_SIZE]);
+        ghosting.apply(&mut frame);
+        assert!(frame.iter().all(|&c| c == 0x80));
+    }
+}
@@ -0,0 +1,1455 @@
+use std::path::Path;
+
+use crate::interrupts::Interrupt;
+use crate::model::Model;
+use crate::palette::DmgPalette;
+use crate::tiles::{self, Image};
+
+use cgb::{rgb555_to_rgba, ColorPalettes};
+
+mod cgb;
+mod fifo;
+mod filter;
+
+pub use filter::{Filter, Ghosting};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+/// Size of the RGBA8 framebuffer
+pub const FRAMEBUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 4;
+
+// A scanline always lasts 456 dots (1 dot = 1 T-cycle of the CPU). The OAM
+// scan takes the first 80 dots, then drawing the pixels takes 172 dots in the
+// best case. The remaining dots are spent in HBlank.
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+// 144 visible lines followed by 10 lines of VBlank
+const LINES_PER_FRAME: u8 = 154;
+/// Length of a frame in T-cycles, about 59.7 frames per second
+pub const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+
+pub const LCDC: u16 = 0xff40;
+pub const STAT: u16 = 0xff41;
+pub const SCY: u16 = 0xff42;
+pub const SCX: u16 = 0xff43;
+pub const LY: u16 = 0xff44;
+pub const LYC: u16 = 0xff45;
+pub const BGP: u16 = 0xff47;
+pub const OBP0: u16 = 0xff48;
+pub const OBP1: u16 = 0xff49;
+pub const WY: u16 = 0xff4a;
+pub const WX: u16 = 0xff4b;
+// Game Boy Color only
+pub const VBK: u16 = 0xff4f;
+pub const BCPS: u16 = 0xff68;
+pub const BCPD: u16 = 0xff69;
+pub const OCPS: u16 = 0xff6a;
+pub const OCPD: u16 = 0xff6b;
+
+const LCDC_BG_ENABLE: u8 = 0x01;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_SIZE: u8 = 0x04;
+const LCDC_BG_TILE_MAP: u8 = 0x08;
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_TILE_MAP: u8 = 0x40;
+const LCDC_ENABLE: u8 = 0x80;
+
+const OBJ_BG_PRIORITY: u8 = 0x80;
+const OBJ_Y_FLIP: u8 = 0x40;
+const OBJ_X_FLIP: u8 = 0x20;
+const OBJ_PALETTE: u8 = 0x10;
+const OBJ_BANK: u8 = 0x08;
+const OBJ_CGB_PALETTE: u8 = 0x07;
+
+// Attributes of the background tiles, stored in the second VRAM bank (CGB only)
+const BG_PRIORITY: u8 = 0x80;
+const BG_Y_FLIP: u8 = 0x40;
+const BG_X_FLIP: u8 = 0x20;
+const BG_BANK: u8 = 0x08;
+const BG_PALETTE: u8 = 0x07;
+// The PPU can only display 10 objects per scanline
+const MAX_OBJ_PER_LINE: usize = 10;
+
+const STAT_LYC_EQUAL: u8 = 0x04;
+const STAT_HBLANK_INT: u8 = 0x08;
+const STAT_VBLANK_INT: u8 = 0x10;
+const STAT_OAM_INT: u8 = 0x20;
+const STAT_LYC_INT: u8 = 0x40;
+
+// Outline of the viewport in the debug views of the tile maps
+const VIEWPORT_COLOR: [u8; 4] = [0xff, 0x00, 0x00, 0xff];
+
+/// One of the two 32x32 tile maps used by the background and the window
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TileMap {
+    Map9800,
+    Map9c00,
+}
+
+impl TileMap {
+    /// Offset of the map inside VRAM
+    fn offset(self) -> usize {
+        match self {
+            Self::Map9800 => 0x1800,
+            Self::Map9c00 => 0x1c00,
+        }
+    }
+}
+
+/// How the background and window tile indexes are mapped to tile data
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TileAddressing {
+    /// Tiles 0 to 255 starting from 0x8000
+    Unsigned8000,
+    /// Tiles -128 to 127 relative to 0x9000
+    Signed8800,
+}
+
+impl TileAddressing {
+    /// Offset inside VRAM of a background or window tile
+    fn tile_addr(self, tile_index: u8) -> usize {
+        match self {
+            Self::Unsigned8000 => tile_index as usize * 16,
+            Self::Signed8800 => (0x1000 + tile_index as i8 as isize * 16) as usize,
+        }
+    }
+}
+
+/// How the pixels are drawn during mode 3
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Renderer {
+    /// Each line is drawn at once at the end of mode 3, which always lasts
+    /// 172 dots. Fast, but changes made to the registers during mode 3 are ignored.
+    Scanline,
+    /// Emulates the pixel FIFO dot by dot. Mid-scanline register writes, the
+    /// window quirks and the variable length of mode 3 (fine scrolling,
+    /// object fetches) are reproduced, at the cost of performance.
+    PixelFifo,
+}
+
+/// Debug switches to hide the layers drawn by the PPU. A hidden background or
+/// window is drawn with color 0, objects still appear over it. The timings are
+/// not affected.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub objects: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self {
+            background: true,
+            window: true,
+            objects: true,
+        }
+    }
+}
+
+/// A pixel of the background or the window, before the palette is applied
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct BgPixel {
+    color: u8,
+    // Color palette (CGB only)
+    palette: u8,
+    // Drawn over the objects (CGB only)
+    priority: bool,
+}
+
+/// An entry of OAM with its attributes decoded, for the debug views
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Object {
+    /// Position on the screen plus 16
+    pub y: u8,
+    /// Position on the screen plus 8
+    pub x: u8,
+    pub tile: u8,
+    /// Drawn behind the background colors 1-3
+    pub bg_priority: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    /// OBP0 or OBP1 (DMG only)
+    pub dmg_palette: u8,
+    /// Bank of VRAM of the tile (CGB only)
+    pub bank: usize,
+    /// Color palette (CGB only)
+    pub cgb_palette: u8,
+}
+
+impl Object {
+    fn from_oam(entry: &[u8]) -> Self {
+        let attributes = entry[3];
+        Self {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            bg_priority: attributes & OBJ_BG_PRIORITY != 0,
+            y_flip: attributes & OBJ_Y_FLIP != 0,
+            x_flip: attributes & OBJ_X_FLIP != 0,
+            dmg_palette: (attributes & OBJ_PALETTE != 0) as u8,
+            bank: (attributes & OBJ_BANK != 0) as usize,
+            cgb_palette: attributes & OBJ_CGB_PALETTE,
+        }
+    }
+}
+
+/// The mode of the PPU, as reported in the 2 lowest bits of STAT
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ppu {
+    model: Model,
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    // Position inside the current scanline
+    dot: u32,
+    // The STAT interrupt is only requested on a rising edge of the OR of
+    // all its enabled sources, so we need to remember the previous state.
+    stat_line: bool,
+    // 2 banks of 8KB, the second one is only used by the CGB
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::array"))]
+    vram: [u8; 0x4000],
+    // Bank of VRAM mapped at 0x8000
+    vram_bank: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::array"))]
+    oam: [u8; 0xa0],
+    bg_palettes: ColorPalettes,
+    obj_palettes: ColorPalettes,
+    // Line of the window being drawn, only incremented when the window is visible
+    window_line: u8,
+    // Pixels of the frame being drawn: the shade (0-3) on DMG, the RGB555 color on CGB
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
+    pixels: Box<[u16; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    // Last complete frame, updated at VBlank
+    #[cfg_attr(feature = "serde", serde(with = "crate::state::boxed_array"))]
+    framebuffer: Box<[u8; FRAMEBUFFER_SIZE]>,
+    frame_count: u64,
+    renderer: Renderer,
+    // Renderer used for the current line, changes only apply from the next line
+    line_renderer: Renderer,
+    fifo: fifo::PixelFifo,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    filters: filter::Filters,
+    // Colors of the 4 shades, only used on DMG
+    dmg_palette: DmgPalette,
+    layers: Layers,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        Self {
+            model,
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            stat_line: false,
+            vram: [0; 0x4000],
+            vram_bank: 0,
+            oam: [0; 0xa0],
+            bg_palettes: Default::default(),
+            obj_palettes: Default::default(),
+            window_line: 0,
+            pixels: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            framebuffer: Box::new([0xff; FRAMEBUFFER_SIZE]),
+            frame_count: 0,
+            renderer: Renderer::Scanline,
+            line_renderer: Renderer::Scanline,
+            fifo: Default::default(),
+            filters: Default::default(),
+            dmg_palette: DmgPalette::default(),
+            layers: Layers::default(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    /// The last complete frame as RGBA8 pixels, row by row. It is updated
+    /// at the beginning of each VBlank.
+    pub fn framebuffer(&self) -> &[u8; FRAMEBUFFER_SIZE] {
+        &self.framebuffer
+    }
+
+    /// Save the last complete frame as a PNG file
+    pub fn screenshot(&self, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+        let image = Image {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: self.framebuffer.to_vec(),
+        };
+        image.save_png(path)
+    }
+
+    /// Number of frames completed since power on
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Debug view of the 384 tiles stored in a bank of VRAM (0x8000-0x97ff),
+    /// 16 tiles per row. The second bank only exists on CGB.
+    pub fn tile_sheet(&self, bank: usize) -> Image {
+        let start = bank * 0x2000;
+        tiles::tile_sheet(&self.vram[start..start + 0x1800], 16, &self.dmg_palette)
+    }
+
+    pub fn dmg_palette(&self) -> DmgPalette {
+        self.dmg_palette
+    }
+
+    /// Change the colors used to display the 4 shades of the DMG. The
+    /// framebuffer is only updated at the end of the next frame.
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+    }
+
+    pub fn layers(&self) -> Layers {
+        self.layers
+    }
+
+    pub fn set_layers(&mut self, layers: Layers) {
+        self.layers = layers;
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    /// Append a stage to the post-processing of the framebuffer. Filters are
+    /// applied in the order they were added.
+    pub fn add_filter(&mut self, filter: Box<dyn Filter>) {
+        self.filters.0.push(filter);
+    }
+
+    pub fn clear_filters(&mut self) {
+        self.filters.0.clear();
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn keep_settings(&mut self, previous: &mut Self) {
+        self.filters = std::mem::take(&mut previous.filters);
+        self.dmg_palette = previous.dmg_palette;
+        self.layers = previous.layers;
+        self.renderer = previous.renderer;
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    /// Advance the PPU by `cycles` T-cycles. Returns the mask of the
+    /// interrupts requested during that time (see `Interrupt::mask`).
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        if !self.lcd_enabled() {
+            return interrupts;
+        }
+        for _ in 0..cycles {
+            interrupts |= self.step_dot();
+        }
+        interrupts
+    }
+
+    fn step_dot(&mut self) -> u8 {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+        }
+
+        let mode = if self.ly as usize >= SCREEN_HEIGHT {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else {
+            match self.mode {
+                Mode::OamScan => Mode::Drawing,
+                Mode::Drawing => {
+                    if self.drawing_done() {
+                        Mode::HBlank
+                    } else {
+                        Mode::Drawing
+                    }
+                }
+                mode => mode,
+            }
+        };
+
+        let mut interrupts = 0;
+        if mode != self.mode {
+            self.mode = mode;
+            match mode {
+                Mode::Drawing => {
+                    self.line_renderer = self.renderer;
+                    if self.line_renderer == Renderer::PixelFifo {
+                        self.fifo_start_line();
+                    }
+                }
+                Mode::HBlank => match self.line_renderer {
+                    Renderer::Scanline => self.render_line(),
+                    Renderer::PixelFifo => self.fifo_end_line(),
+                },
+                Mode::VBlank => {
+                    self.end_frame();
+                    interrupts |= Interrupt::VBlank.mask();
+                }
+                _ => (),
+            }
+        }
+        interrupts | self.update_stat_line()
+    }
+
+    /// Advance mode 3 by one dot, returns true when it is over
+    fn drawing_done(&mut self) -> bool {
+        match self.line_renderer {
+            Renderer::Scanline => self.dot >= OAM_SCAN_DOTS + DRAWING_DOTS,
+            Renderer::PixelFifo => self.fifo_step(),
+        }
+    }
+
+    fn lyc_equal(&self) -> bool {
+        self.ly == self.lyc
+    }
+
+    /// Refresh the internal STAT interrupt line, and return the STAT
+    /// interrupt mask if it went from low to high.
+    fn update_stat_line(&mut self) -> u8 {
+        let enabled = |bit: u8| self.stat & bit != 0;
+        let mode_source = match self.mode {
+            Mode::HBlank => enabled(STAT_HBLANK_INT),
+            Mode::VBlank => enabled(STAT_VBLANK_INT),
+            Mode::OamScan => enabled(STAT_OAM_INT),
+            Mode::Drawing => false,
+        };
+        let line = mode_source || (self.lyc_equal() && enabled(STAT_LYC_INT));
+        let rising_edge = line && !self.stat_line;
+        self.stat_line = line;
+        if rising_edge {
+            Interrupt::Stat.mask()
+        } else {
+            0
+        }
+    }
+
+    fn end_frame(&mut self) {
+        for (pixel, rgba) in self.pixels.iter().zip(self.framebuffer.chunks_exact_mut(4)) {
+            rgba.copy_from_slice(&pixel_rgba(self.model, &self.dmg_palette, *pixel));
+        }
+        for filter in &mut self.filters.0 {
+            filter.apply(&mut self.framebuffer);
+        }
+        self.window_line = 0;
+        self.fifo.window_next_line = false;
+        self.frame_count += 1;
+    }
+
+    /// Value of `pixels` for a background pixel
+    fn bg_output(&self, pixel: BgPixel) -> u16 {
+        match self.model {
+            Model::Dmg => palette_shade(self.bgp, pixel.color) as u16,
+            Model::Cgb => self.bg_palettes.color(pixel.palette, pixel.color),
+        }
+    }
+
+    /// Value of `pixels` for an object pixel
+    fn obj_output(&self, attributes: u8, color: u8) -> u16 {
+        match self.model {
+            Model::Dmg => palette_shade(self.object_palette(attributes), color) as u16,
+            Model::Cgb => self.obj_palettes.color(attributes & OBJ_CGB_PALETTE, color),
+        }
+    }
+
+    /// On DMG, LCDC bit 0 disables the background and the window. On CGB it
+    /// only removes their priority over the objects.
+    fn bg_enabled(&self) -> bool {
+        self.model == Model::Cgb || self.lcdc & LCDC_BG_ENABLE != 0
+    }
+
+    /// Whether a (non transparent) pixel of an object is drawn over the background
+    fn object_visible(&self, bg: BgPixel, attributes: u8) -> bool {
+        bg.color == 0
+            || (self.model == Model::Cgb && self.lcdc & LCDC_BG_ENABLE == 0)
+            || (attributes & OBJ_BG_PRIORITY == 0 && !bg.priority)
+    }
+
+    /// Color index (0-3) of a pixel of the tile at `tile_addr` (offset inside a VRAM bank)
+    fn tile_pixel(&self, bank: usize, tile_addr: usize, row: usize, column: usize) -> u8 {
+        // Each row is 2 bytes: the first one holds the low bit of each
+        // pixel and the second one the high bit, leftmost pixel in bit 7.
+        let addr = bank * 0x2000 + tile_addr + row * 2;
+        let low = self.vram[addr];
+        let high = self.vram[addr + 1];
+        let bit = 7 - column;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    pub fn bg_tile_map(&self) -> TileMap {
+        if self.lcdc & LCDC_BG_TILE_MAP != 0 {
+            TileMap::Map9c00
+        } else {
+            TileMap::Map9800
+        }
+    }
+
+    pub fn window_tile_map(&self) -> TileMap {
+        if self.lcdc & LCDC_WINDOW_TILE_MAP != 0 {
+            TileMap::Map9c00
+        } else {
+            TileMap::Map9800
+        }
+    }
+
+    pub fn tile_addressing(&self) -> TileAddressing {
+        if self.lcdc & LCDC_TILE_DATA != 0 {
+            TileAddressing::Unsigned8000
+        } else {
+            TileAddressing::Signed8800
+        }
+    }
+
+    /// Attributes of a tile of a map, always 0 on DMG
+    fn bg_attributes(&self, map_addr: usize) -> u8 {
+        match self.model {
+            Model::Dmg => 0,
+            Model::Cgb => self.vram[0x2000 + map_addr],
+        }
+    }
+
+    /// Pixel at (x, y) of a 256x256 tile map
+    fn map_pixel(&self, map: TileMap, addressing: TileAddressing, x: usize, y: usize) -> BgPixel {
+        let map_addr = map.offset() + (y / 8) * 32 + x / 8;
+        let tile_index = self.vram[map_addr];
+        let attributes = self.bg_attributes(map_addr);
+        let (mut row, mut column) = (y % 8, x % 8);
+        if attributes & BG_Y_FLIP != 0 {
+            row = 7 - row;
+        }
+        if attributes & BG_X_FLIP != 0 {
+            column = 7 - column;
+        }
+        let bank = (attributes & BG_BANK != 0) as usize;
+        BgPixel {
+            color: self.tile_pixel(bank, addressing.tile_addr(tile_index), row, column),
+            palette: attributes & BG_PALETTE,
+            priority: attributes & BG_PRIORITY != 0,
+        }
+    }
+
+    /// Debug view of a whole 256x256 tile map using the background palettes, with the
+    /// area currently displayed on the screen (SCX, SCY) outlined in red.
+    pub fn bg_map(&self, map: TileMap, addressing: TileAddressing) -> Image {
+        let mut image = Image::new(256, 256);
+        for y in 0..256 {
+            for x in 0..256 {
+                let pixel = self.map_pixel(map, addressing, x, y);
+                image.set_pixel(
+                    x,
+                    y,
+                    pixel_rgba(self.model, &self.dmg_palette, self.bg_output(pixel)),
+                );
+            }
+        }
+
+        // The viewport wraps around the edges of the map
+        let (left, top) = (self.scx as usize, self.scy as usize);
+        let right = (left + SCREEN_WIDTH - 1) % 256;
+        let bottom = (top + SCREEN_HEIGHT - 1) % 256;
+        for dx in 0..SCREEN_WIDTH {
+            image.set_pixel((left + dx) % 256, top, VIEWPORT_COLOR);
+            image.set_pixel((left + dx) % 256, bottom, VIEWPORT_COLOR);
+        }
+        for dy in 0..SCREEN_HEIGHT {
+            image.set_pixel(left, (top + dy) % 256, VIEWPORT_COLOR);
+            image.set_pixel(right, (top + dy) % 256, VIEWPORT_COLOR);
+        }
+        image
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly as usize;
+        let mut bg = [BgPixel::default(); SCREEN_WIDTH];
+
+        let bg_enabled = self.bg_enabled();
+        if bg_enabled {
+            let addressing = self.tile_addressing();
+            if self.layers.background {
+                let bg_map = self.bg_tile_map();
+                let y = (ly + self.scy as usize) % 256;
+                for (x, pixel) in bg.iter_mut().enumerate() {
+                    *pixel = self.map_pixel(bg_map, addressing, (x + self.scx as usize) % 256, y);
+                }
+            }
+
+            let window_x = self.wx as isize - 7;
+            if self.lcdc & LCDC_WINDOW_ENABLE != 0 && ly >= self.wy as usize && window_x < 160 {
+                let window_map = self.window_tile_map();
+                for (x, pixel) in bg.iter_mut().enumerate().skip(window_x.max(0) as usize) {
+                    let column = (x as isize - window_x) as usize;
+                    *pixel = if self.layers.window {
+                        self.map_pixel(window_map, addressing, column, self.window_line as usize)
+                    } else {
+                        BgPixel::default()
+                    };
+                }
+                self.window_line += 1;
+            }
+        }
+
+        for (x, pixel) in bg.iter().enumerate() {
+            // When the background is disabled, the screen is white whatever the palette
+            self.pixels[ly * SCREEN_WIDTH + x] = if bg_enabled {
+                self.bg_output(*pixel)
+            } else {
+                0
+            };
+        }
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 && self.layers.objects {
+            self.render_objects(ly, &bg);
+        }
+    }
+
+    /// Height of the objects in pixels, 8 or 16 depending on LCDC
+    pub fn object_height(&self) -> usize {
+        if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// The 40 entries of OAM, in order
+    pub fn objects(&self) -> Vec<Object> {
+        self.oam.chunks_exact(4).map(Object::from_oam).collect()
+    }
+
+    /// OAM scan: the first 10 objects overlapping the line, in OAM order
+    fn scan_objects(&self, ly: usize) -> Vec<[u8; 4]> {
+        let height = self.object_height() as isize;
+        self.oam
+            .chunks_exact(4)
+            .filter(|obj| {
+                let top = obj[0] as isize - 16;
+                (top..top + height).contains(&(ly as isize))
+            })
+            .take(MAX_OBJ_PER_LINE)
+            .map(|obj| obj.try_into().unwrap())
+            .collect()
+    }
+
+    /// Color indexes of the 8 pixels of an object on the line `ly`, from
+    /// left to right on the screen.
+    fn object_row(&self, obj: [u8; 4], ly: usize) -> [u8; 8] {
+        let (y, mut tile, attributes) = (obj[0], obj[2], obj[3]);
+        let height = self.object_height();
+        let mut row = (ly as isize - (y as isize - 16)) as usize;
+        if attributes & OBJ_Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+        if height == 16 {
+            tile &= 0xfe;
+        }
+        let mut colors = [0; 8];
+        for (column, color) in colors.iter_mut().enumerate() {
+            let column = if attributes & OBJ_X_FLIP != 0 {
+                7 - column
+            } else {
+                column
+            };
+            let bank = (self.model == Model::Cgb && attributes & OBJ_BANK != 0) as usize;
+            *color = self.tile_pixel(bank, tile as usize * 16, row, column);
+        }
+        colors
+    }
+
+    fn object_palette(&self, attributes: u8) -> u8 {
+        if attributes & OBJ_PALETTE != 0 {
+            self.obp1
+        } else {
+            self.obp0
+        }
+    }
+
+    fn render_objects(&mut self, ly: usize, bg: &[BgPixel; SCREEN_WIDTH]) {
+        let mut objects = self.scan_objects(ly);
+        // On DMG the object with the smallest X has priority, then the first one
+        // in OAM (the sort is stable). On CGB only the position in OAM matters.
+        if self.model == Model::Dmg {
+            objects.sort_by_key(|obj| obj[1]);
+        }
+
+        // Drawing the objects in reverse order lets the ones with priority overwrite the others.
+        let mut output = [None; SCREEN_WIDTH];
+        for obj in objects.iter().rev() {
+            let (x, attributes) = (obj[1], obj[3]);
+            for (column, color) in self.object_row(*obj, ly).into_iter().enumerate() {
+                let screen_x = x as isize - 8 + column as isize;
+                if !(0..SCREEN_WIDTH as isize).contains(&screen_x) {
+                    continue;
+                }
+                let screen_x = screen_x as usize;
+                // Color 0 is transparent
+                if color == 0 {
+                    continue;
+                }
+                output[screen_x] = if self.object_visible(bg[screen_x], attributes) {
+                    Some(self.obj_output(attributes, color))
+                } else {
+                    // Hidden behind the background, but still hides the objects with lower priority
+                    None
+                };
+            }
+        }
+
+        let line = &mut self.pixels[ly * SCREEN_WIDTH..(ly + 1) * SCREEN_WIDTH];
+        for (pixel, value) in line.iter_mut().zip(output) {
+            if let Some(value) = value {
+                *pixel = value;
+            }
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9fff => self.vram[self.vram_bank * 0x2000 + (addr - 0x8000) as usize],
+            0xfe00..=0xfe9f => self.oam[(addr - 0xfe00) as usize],
+            VBK | BCPS | BCPD | OCPS | OCPD if self.model == Model::Dmg => 0xff,
+            // Only bit 0 is used
+            VBK => 0xfe | self.vram_bank as u8,
+            BCPS => self.bg_palettes.read_index(),
+            BCPD => self.bg_palettes.read_data(),
+            OCPS => self.obj_palettes.read_index(),
+            OCPD => self.obj_palettes.read_data(),
+            LCDC => self.lcdc,
+            // Bit 7 is unused and always reads as 1
+            STAT => {
+                let lyc_equal = if self.lyc_equal() { STAT_LYC_EQUAL } else { 0 };
+                0x80 | (self.stat & 0x78) | lyc_equal | self.mode as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9fff => {
+                self.vram[self.vram_bank * 0x2000 + (addr - 0x8000) as usize] = value
+            }
+            0xfe00..=0xfe9f => self.oam[(addr - 0xfe00) as usize] = value,
+            VBK | BCPS | BCPD | OCPS | OCPD if self.model == Model::Dmg => (),
+            VBK => self.vram_bank = (value & 0x01) as usize,
+            BCPS => self.bg_palettes.write_index(value),
+            BCPD => self.bg_palettes.write_data(value),
+            OCPS => self.obj_palettes.write_index(value),
+            OCPD => self.obj_palettes.write_data(value),
+            LCDC => self.write_lcdc(value),
+            // The mode bits are read-only
+            STAT => self.stat = value & 0x78,
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            // LY is read-only
+            LY => (),
+            LYC => self.lyc = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            _ => (),
+        }
+    }
+
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+        if was_enabled && !self.lcd_enabled() {
+            // Turning the LCD off resets LY and leaves the PPU in HBlank
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::HBlank;
+        } else if !was_enabled && self.lcd_enabled() {
+            // The PPU starts again from the top of the screen
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::OamScan;
+        }
+    }
+}
+
+/// Convert a value of `pixels` to RGBA8
+fn pixel_rgba(model: Model, dmg_palette: &DmgPalette, pixel: u16) -> [u8; 4] {
+    match model {
+        Model::Dmg => dmg_palette.shade(pixel as u8),
+        Model::Cgb => rgb555_to_rgba(pixel),
+    }
+}
+
+/// Map a color index to a shade using one of the BGP, OBP0 and OBP1 palettes
+fn palette_shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) fn enabled_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(LCDC, LCDC_ENABLE);
+        ppu
+    }
+
+    #[test]
+    fn test_ppu_disabled_does_not_tick() {
+        let mut ppu = Ppu::new();
+        assert_eq!(ppu.tick(DOTS_PER_FRAME), 0);
+        assert_eq!(ppu.ly(), 0);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+    }
+
+    #[test]
+    fn test_ppu_scanline_modes() {
+        let mut ppu = enabled_ppu();
+        assert_eq!(ppu.mode(), Mode::OamScan);
+
+        ppu.tick(OAM_SCAN_DOTS - 1);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ppu.tick(1);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+
+        ppu.tick(DRAWING_DOTS - 1);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ppu.tick(1);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        assert_eq!(ppu.read(STAT) & 0x03, Mode::HBlank as u8);
+
+        ppu.tick(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        assert_eq!(ppu.ly(), 1);
+        assert_eq!(ppu.read(LY), 1);
+    }
+
+    #[test]
+    fn test_ppu_vblank_interrupt() {
+        let mut ppu = enabled_ppu();
+        let visible_dots = DOTS_PER_LINE * SCREEN_HEIGHT as u32;
+
+        assert_eq!(ppu.tick(visible_dots - 1), 0);
+        assert_eq!(ppu.tick(1), Interrupt::VBlank.mask());
+        assert_eq!(ppu.ly(), 144);
+        assert_eq!(ppu.mode(), Mode::VBlank);
+        assert_eq!(ppu.read(STAT) & 0x03, Mode::VBlank as u8);
+
+        // Only once per frame
+        assert_eq!(ppu.tick(DOTS_PER_FRAME - visible_dots - 1), 0);
+        assert_eq!(ppu.ly(), 153);
+        ppu.tick(1);
+        assert_eq!(ppu.ly(), 0);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        assert_eq!(ppu.tick(DOTS_PER_FRAME), Interrupt::VBlank.mask());
+    }
+
+    #[test]
+    fn test_ppu_lcd_off_resets_ly() {
+        let mut ppu = enabled_ppu();
+        ppu.tick(DOTS_PER_LINE * 10 + 100);
+        assert_eq!(ppu.ly(), 10);
+
+        ppu.write(LCDC, 0);
+        assert_eq!(ppu.ly(), 0);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+    }
+
+    #[test]
+    fn test_ppu_read_only_registers() {
+        let mut ppu = enabled_ppu();
+        ppu.write(LY, 0x42);
+        assert_eq!(ppu.read(LY), 0);
+        ppu.write(STAT, 0xff);
+        assert_eq!(ppu.read(STAT) & 0xfb, 0xf8 | Mode::OamScan as u8);
+    }
+
+    #[test]
+    fn test_ppu_lyc_compare_flag() {
+        let mut ppu = enabled_ppu();
+        ppu.write(LYC, 2);
+        assert_eq!(ppu.read(STAT) & STAT_LYC_EQUAL, 0);
+
+        ppu.tick(DOTS_PER_LINE * 2);
+        assert_eq!(ppu.ly(), 2);
+        assert_eq!(ppu.read(STAT) & STAT_LYC_EQUAL, STAT_LYC_EQUAL);
+
+        ppu.tick(DOTS_PER_LINE);
+        assert_eq!(ppu.read(STAT) & STAT_LYC_EQUAL, 0);
+    }
+
+    #[test]
+    fn test_ppu_stat_lyc_interrupt() {
+        let mut ppu = enabled_ppu();
+        ppu.write(LYC, 2);
+        ppu.write(STAT, STAT_LYC_INT);
+
+        assert_eq!(ppu.tick(DOTS_PER_LINE * 2 - 1), 0);
+        assert_eq!(ppu.tick(1), Interrupt::Stat.mask());
+        // The line stays high for the whole scanline
+        assert_eq!(ppu.tick(DOTS_PER_LINE - 1), 0);
+    }
+
+    #[test]
+    fn test_ppu_stat_mode_interrupts() {
+        let mut ppu = enabled_ppu();
+        ppu.write(STAT, STAT_HBLANK_INT);
+        assert_eq!(ppu.tick(OAM_SCAN_DOTS + DRAWING_DOTS - 1), 0);
+        assert_eq!(ppu.tick(1), Interrupt::Stat.mask());
+
+        let mut ppu = enabled_ppu();
+        ppu.write(STAT, STAT_OAM_INT);
+        // The PPU starts in OAM scan, the line rises on the first dot, and
+        // again when the next line starts its OAM scan
+        assert_eq!(ppu.tick(DOTS_PER_LINE - 1), Interrupt::Stat.mask());
+        assert_eq!(ppu.tick(1), Interrupt::Stat.mask());
+
+        let mut ppu = enabled_ppu();
+        ppu.write(STAT, STAT_VBLANK_INT);
+        assert_eq!(ppu.tick(DOTS_PER_LINE * 144 - 1), 0);
+        assert_eq!(
+            ppu.tick(1),
+            Interrupt::Stat.mask() | Interrupt::VBlank.mask()
+        );
+    }
+
+    #[test]
+    fn test_ppu_stat_blocking() {
+        // HBlank followed by the LYC match on the next line keeps the STAT
+        // line high during the transition: no new interrupt is requested.
+        let mut ppu = enabled_ppu();
+        ppu.write(LYC, 1);
+        ppu.write(STAT, STAT_HBLANK_INT | STAT_LYC_INT | STAT_OAM_INT);
+        ppu.tick(OAM_SCAN_DOTS + DRAWING_DOTS);
+        assert_eq!(ppu.tick(DOTS_PER_LINE - OAM_SCAN_DOTS - DRAWING_DOTS), 0);
+        assert_eq!(ppu.ly(), 1);
+    }
+
+    pub(super) const SHADES: [[u8; 4]; 4] = DmgPalette::GREY.shades;
+    pub(super) const BLACK: [u8; 4] = SHADES[3];
+    pub(super) const WHITE: [u8; 4] = SHADES[0];
+    // Identity palette: color index n is displayed with shade n
+    pub(super) const IDENTITY_PALETTE: u8 = 0xe4;
+
+    /// Fill the tile at `addr` with a single color index
+    pub(super) fn fill_tile(ppu: &mut Ppu, addr: u16, color: u8) {
+        let low = if color & 1 != 0 { 0xff } else { 0 };
+        let high = if color & 2 != 0 { 0xff } else { 0 };
+        for row in 0..8 {
+            ppu.write(addr + row * 2, low);
+            ppu.write(addr + row * 2 + 1, high);
+        }
+    }
+
+    pub(super) fn pixel(ppu: &Ppu, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * SCREEN_WIDTH + x) * 4;
+        ppu.framebuffer()[offset..offset + 4].try_into().unwrap()
+    }
+
+    pub(super) fn run_frame(ppu: &mut Ppu) {
+        while ppu.tick(1) & Interrupt::VBlank.mask() == 0 {}
+    }
+
+    #[test]
+    fn test_ppu_framebuffer_background() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0x8010, 3);
+        ppu.write(0x9800, 1);
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+
+        assert_eq!(ppu.frame_count(), 0);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.frame_count(), 1);
+        assert_eq!(pixel(&ppu, 0, 0), BLACK);
+        assert_eq!(pixel(&ppu, 7, 7), BLACK);
+        assert_eq!(pixel(&ppu, 8, 0), WHITE);
+        assert_eq!(pixel(&ppu, 0, 8), WHITE);
+
+        // Scrolling moves the tile up and to the left, wrapping around
+        ppu.write(SCX, 4);
+        ppu.write(SCY, 252);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 3, 4), BLACK);
+        assert_eq!(pixel(&ppu, 4, 4), WHITE);
+        assert_eq!(pixel(&ppu, 0, 3), WHITE);
+    }
+
+    #[test]
+    fn test_ppu_framebuffer_signed_tile_data() {
+        let mut ppu = Ppu::new();
+        // Tile -1 is just before 0x9000
+        fill_tile(&mut ppu, 0x8ff0, 1);
+        ppu.write(0x9800, 0xff);
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_BG_ENABLE);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), SHADES[1]);
+    }
+
+    #[test]
+    fn test_ppu_framebuffer_palette() {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, 0x03);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), BLACK);
+    }
+
+    #[test]
+    fn test_ppu_framebuffer_window() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0x8010, 3);
+        // The window uses the second map
+        for i in 0..0x400 {
+            ppu.write(0x9c00 + i, 1);
+        }
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(WX, 7 + 10);
+        ppu.write(WY, 20);
+        ppu.write(
+            LCDC,
+            LCDC_ENABLE
+                | LCDC_TILE_DATA
+                | LCDC_BG_ENABLE
+                | LCDC_WINDOW_ENABLE
+                | LCDC_WINDOW_TILE_MAP,
+        );
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 9, 20), WHITE);
+        assert_eq!(pixel(&ppu, 10, 20), BLACK);
+        assert_eq!(pixel(&ppu, 10, 19), WHITE);
+        assert_eq!(pixel(&ppu, 159, 143), BLACK);
+    }
+
+    #[test]
+    fn test_ppu_framebuffer_objects() {
+        let mut ppu = Ppu::new();
+        // Left half of the object is color 1, the right half is transparent
+        for row in 0..8 {
+            ppu.write(0x8020 + row * 2, 0xf0);
+        }
+        fill_tile(&mut ppu, 0x8010, 2);
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(OBP0, IDENTITY_PALETTE);
+        ppu.write(OBP1, 0x0c);
+
+        // Object 0 at the top left corner of the screen
+        for (i, value) in [16, 8, 2, 0].iter().enumerate() {
+            ppu.write(0xfe00 + i as u16, *value);
+        }
+        // Object 1 is flipped horizontally and uses OBP1
+        for (i, value) in [16, 20, 2, OBJ_X_FLIP | OBJ_PALETTE].iter().enumerate() {
+            ppu.write(0xfe04 + i as u16, *value);
+        }
+        // Object 2 is half over the background tile at (16, 8), and behind it
+        for (i, value) in [24, 20, 2, OBJ_BG_PRIORITY].iter().enumerate() {
+            ppu.write(0xfe08 + i as u16, *value);
+        }
+        ppu.write(0x9822, 1);
+        ppu.write(
+            LCDC,
+            LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE | LCDC_OBJ_ENABLE,
+        );
+        run_frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 3, 0), SHADES[1]);
+        assert_eq!(pixel(&ppu, 4, 0), WHITE);
+        assert_eq!(pixel(&ppu, 15, 0), WHITE);
+        assert_eq!(pixel(&ppu, 16, 0), BLACK);
+        // Behind the background, but visible where the background uses color 0
+        assert_eq!(pixel(&ppu, 12, 8), SHADES[1]);
+        assert_eq!(pixel(&ppu, 15, 15), SHADES[1]);
+        assert_eq!(pixel(&ppu, 16, 8), SHADES[2]);
+        assert_eq!(pixel(&ppu, 16, 15), SHADES[2]);
+    }
+
+    #[test]
+    fn test_ppu_objects() {
+        let mut ppu = Ppu::new();
+        for (i, value) in [16, 20, 2, OBJ_X_FLIP | OBJ_PALETTE | OBJ_BANK | 0x05]
+            .iter()
+            .enumerate()
+        {
+            ppu.write(0xfe04 + i as u16, *value);
+        }
+        let objects = ppu.objects();
+        assert_eq!(objects.len(), 40);
+        assert_eq!(
+            objects[1],
+            Object {
+                y: 16,
+                x: 20,
+                tile: 2,
+                bg_priority: false,
+                y_flip: false,
+                x_flip: true,
+                dmg_palette: 1,
+                bank: 1,
+                cgb_palette: 5,
+            }
+        );
+        assert_eq!(ppu.object_height(), 8);
+        ppu.write(LCDC, LCDC_OBJ_SIZE);
+        assert_eq!(ppu.object_height(), 16);
+    }
+
+    #[test]
+    fn test_ppu_screenshot() {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        fill_tile(&mut ppu, 0x8000, 3);
+        run_frame(&mut ppu);
+
+        let path = std::env::temp_dir().join(format!("gb-screenshot-{}.png", std::process::id()));
+        ppu.screenshot(&path).unwrap();
+        let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buffer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((reader.info().width, reader.info().height), (160, 144));
+        assert_eq!(&buffer[..], &ppu.framebuffer()[..]);
+    }
+
+    #[test]
+    fn test_ppu_filters() {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        ppu.add_filter(Box::new(Ghosting::new(0.5)));
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), WHITE);
+
+        fill_tile(&mut ppu, 0x8000, 3);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), [0x7f, 0x7f, 0x7f, 0xff]);
+
+        ppu.clear_filters();
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), BLACK);
+    }
+
+    #[test]
+    fn test_ppu_dmg_palette() {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        fill_tile(&mut ppu, 0x8010, 3);
+        ppu.write(0x9800, 1);
+
+        ppu.set_dmg_palette(DmgPalette::GREEN);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), DmgPalette::GREEN.shade(3));
+        assert_eq!(pixel(&ppu, 8, 0), DmgPalette::GREEN.shade(0));
+
+        // The CGB ignores it
+        let mut ppu = cgb_ppu();
+        ppu.set_dmg_palette(DmgPalette::GREEN);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), WHITE);
+    }
+
+    #[test]
+    fn test_ppu_layers() {
+        let mut ppu = Ppu::new();
+        ppu.write(BGP, IDENTITY_PALETTE);
+        ppu.write(OBP0, IDENTITY_PALETTE);
+        fill_tile(&mut ppu, 0x8010, 3);
+        fill_tile(&mut ppu, 0x8020, 2);
+        fill_tile(&mut ppu, 0x8030, 1);
+        // Background made of tile 1, window of tile 2 from (80, 0)
+        for addr in 0x9800..0x9c00 {
+            ppu.write(addr, 1);
+        }
+        for addr in 0x9c00..0xa000 {
+            ppu.write(addr, 2);
+        }
+        ppu.write(WX, 87);
+        // Object made of tile 3 at (0, 0) and (80, 0)
+        ppu.write(0xfe00, 16);
+        ppu.write(0xfe01, 8);
+        ppu.write(0xfe02, 3);
+        ppu.write(0xfe04, 16);
+        ppu.write(0xfe05, 88);
+        ppu.write(0xfe06, 3);
+        ppu.write(
+            LCDC,
+            LCDC_ENABLE
+                | LCDC_TILE_DATA
+                | LCDC_BG_ENABLE
+                | LCDC_WINDOW_ENABLE
+                | LCDC_WINDOW_TILE_MAP
+                | LCDC_OBJ_ENABLE,
+        );
+
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            ppu.set_renderer(renderer);
+            let mut layers = Layers::default();
+            run_frame(&mut ppu);
+            assert_eq!(pixel(&ppu, 0, 0), SHADES[1]);
+            assert_eq!(pixel(&ppu, 8, 0), BLACK);
+            assert_eq!(pixel(&ppu, 80, 0), SHADES[1]);
+            assert_eq!(pixel(&ppu, 88, 0), SHADES[2]);
+
+            layers.objects = false;
+            ppu.set_layers(layers);
+            run_frame(&mut ppu);
+            assert_eq!(pixel(&ppu, 0, 0), BLACK);
+            assert_eq!(pixel(&ppu, 80, 0), SHADES[2]);
+
+            layers.background = false;
+            ppu.set_layers(layers);
+            run_frame(&mut ppu);
+            assert_eq!(pixel(&ppu, 0, 0), WHITE);
+            assert_eq!(pixel(&ppu, 80, 0), SHADES[2]);
+
+            layers.window = false;
+            ppu.set_layers(layers);
+            run_frame(&mut ppu);
+            assert_eq!(pixel(&ppu, 0, 0), WHITE);
+            assert_eq!(pixel(&ppu, 80, 0), WHITE);
+
+            layers.objects = true;
+            ppu.set_layers(layers);
+            run_frame(&mut ppu);
+            assert_eq!(pixel(&ppu, 0, 0), SHADES[1]);
+            assert_eq!(pixel(&ppu, 80, 0), SHADES[1]);
+
+            ppu.set_layers(Layers::default());
+        }
+    }
+
+    #[test]
+    fn test_ppu_tile_sheet() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0x8010, 3);
+        fill_tile(&mut ppu, 0x97f0, 2);
+        let sheet = ppu.tile_sheet(0);
+        assert_eq!((sheet.width, sheet.height), (128, 192));
+        assert_eq!(sheet.pixel(0, 0), WHITE);
+        assert_eq!(sheet.pixel(8, 0), BLACK);
+        assert_eq!(sheet.pixel(127, 191), SHADES[2]);
+    }
+
+    #[test]
+    fn test_ppu_bg_map() {
+        let mut ppu = Ppu::new();
+        fill_tile(&mut ppu, 0x8010, 3);
+        fill_tile(&mut ppu, 0x9010, 2);
+        ppu.write(0x9c21, 1);
+        ppu.write(BGP, IDENTITY_PALETTE);
+
+        let map = ppu.bg_map(TileMap::Map9c00, TileAddressing::Unsigned8000);
+        assert_eq!((map.width, map.height), (256, 256));
+        assert_eq!(map.pixel(8, 8), BLACK);
+        assert_eq!(map.pixel(15, 15), BLACK);
+        assert_eq!(map.pixel(16, 16), WHITE);
+
+        let map = ppu.bg_map(TileMap::Map9c00, TileAddressing::Signed8800);
+        assert_eq!(map.pixel(8, 8), SHADES[2]);
+
+        let map = ppu.bg_map(TileMap::Map9800, TileAddressing::Unsigned8000);
+        assert_eq!(map.pixel(8, 8), WHITE);
+    }
+
+    #[test]
+    fn test_ppu_bg_map_viewport() {
+        let mut ppu = Ppu::new();
+        ppu.write(SCX, 200);
+        ppu.write(SCY, 10);
+        let map = ppu.bg_map(ppu.bg_tile_map(), ppu.tile_addressing());
+        assert_eq!(map.pixel(200, 10), VIEWPORT_COLOR);
+        assert_eq!(map.pixel(255, 10), VIEWPORT_COLOR);
+        // The viewport wraps around the right edge
+        assert_eq!(map.pixel(0, 10), VIEWPORT_COLOR);
+        assert_eq!(map.pixel(103, 10), VIEWPORT_COLOR);
+        assert_eq!(map.pixel(103, 153), VIEWPORT_COLOR);
+        assert_eq!(map.pixel(103, 154), WHITE);
+        assert_eq!(map.pixel(104, 10), WHITE);
+        assert_eq!(map.pixel(50, 50), WHITE);
+    }
+
+    pub(super) fn cgb_ppu() -> Ppu {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+        // BG palette 1 and OBJ palette 2: black, red, green, blue
+        for (index, data) in [(BCPS, BCPD), (OCPS, OCPD)] {
+            let palette = if index == BCPS { 1 } else { 2 };
+            ppu.write(index, 0x80 | (palette * 8));
+            for color in [0x0000u16, 0x001f, 0x03e0, 0x7c00] {
+                ppu.write(data, color as u8);
+                ppu.write(data, (color >> 8) as u8);
+            }
+        }
+        ppu
+    }
+
+    pub(super) const RED: [u8; 4] = [0xff, 0x00, 0x00, 0xff];
+    pub(super) const GREEN: [u8; 4] = [0x00, 0xff, 0x00, 0xff];
+    pub(super) const BLUE: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+    #[test]
+    fn test_ppu_vram_banks() {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+        ppu.write(0x8000, 0x12);
+        ppu.write(VBK, 0xff);
+        assert_eq!(ppu.read(VBK), 0xff);
+        assert_eq!(ppu.read(0x8000), 0x00);
+        ppu.write(0x8000, 0x34);
+        ppu.write(VBK, 0);
+        assert_eq!(ppu.read(VBK), 0xfe);
+        assert_eq!(ppu.read(0x8000), 0x12);
+
+        // There is no second bank on DMG
+        let mut ppu = Ppu::new();
+        ppu.write(0x8000, 0x12);
+        ppu.write(VBK, 1);
+        assert_eq!(ppu.read(VBK), 0xff);
+        assert_eq!(ppu.read(0x8000), 0x12);
+    }
+
+    #[test]
+    fn test_ppu_cgb_palette_registers() {
+        let mut ppu = cgb_ppu();
+        ppu.write(BCPS, 8 + 2);
+        assert_eq!(ppu.read(BCPD), 0x1f);
+        ppu.write(OCPS, 16 + 5);
+        assert_eq!(ppu.read(OCPD), 0x03);
+        assert_eq!(ppu.read(OCPS), 0x40 | (16 + 5));
+
+        let ppu = Ppu::new();
+        assert_eq!(ppu.read(BCPS), 0xff);
+    }
+
+    #[test]
+    fn test_ppu_cgb_bg_attributes() {
+        let mut ppu = cgb_ppu();
+        // Tile 1 of bank 1: left half color 1, right half color 2
+        ppu.write(VBK, 1);
+        for row in 0..8 {
+            ppu.write(0x8010 + row * 2, 0xf0);
+            ppu.write(0x8011 + row * 2, 0x0f);
+        }
+        // Attributes of the first two tiles of the map: palette 1, bank 1, then also X flip
+        ppu.write(0x9800, BG_BANK | 1);
+        ppu.write(0x9801, BG_BANK | BG_X_FLIP | 1);
+        ppu.write(VBK, 0);
+        ppu.write(0x9800, 1);
+        ppu.write(0x9801, 1);
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE);
+        run_frame(&mut ppu);
+
+        assert_eq!(pixel(&ppu, 0, 0), RED);
+        assert_eq!(pixel(&ppu, 4, 0), GREEN);
+        assert_eq!(pixel(&ppu, 8, 0), GREEN);
+        assert_eq!(pixel(&ppu, 12, 0), RED);
+        // Palette 0 is white by default
+        assert_eq!(pixel(&ppu, 16, 0), WHITE);
+    }
+
+    #[test]
+    fn test_ppu_cgb_object_priority() {
+        let mut ppu = cgb_ppu();
+        fill_tile(&mut ppu, 0x8010, 1);
+        fill_tile(&mut ppu, 0x8020, 3);
+        // Background tile with priority over the objects
+        ppu.write(0x9802, 1);
+        ppu.write(VBK, 1);
+        ppu.write(0x9802, BG_PRIORITY | 1);
+        ppu.write(VBK, 0);
+
+        // On CGB the first object in OAM wins even with a greater X
+        let objects = [[16, 12, 2, 2], [16, 8, 1, 2], [16, 24, 2, 2]];
+        for (i, obj) in objects.iter().enumerate() {
+            for (j, value) in obj.iter().enumerate() {
+                ppu.write(0xfe00 + (i * 4 + j) as u16, *value);
+            }
+        }
+        ppu.write(
+            LCDC,
+            LCDC_ENABLE | LCDC_TILE_DATA | LCDC_BG_ENABLE | LCDC_OBJ_ENABLE,
+        );
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), RED);
+        assert_eq!(pixel(&ppu, 4, 0), BLUE);
+        assert_eq!(pixel(&ppu, 16, 0), RED);
+
+        // Without LCDC bit 0, the objects are always on top
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_TILE_DATA | LCDC_OBJ_ENABLE);
+        run_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 16, 0), BLUE);
+    }
+}
@@ -0,0 +1,154 @@
+//! Tiny programs assembled from opcodes with the encoder of the decoder, so
+//! the tests of the CPU read like listings instead of bytes. They run on a
+//! flat memory, or from a ROM with the rest of the hardware.
+
+use crate::cpu::{Cpu, Registers};
+use crate::decoder::{encode, Opcode};
+use crate::mmu::Mmu;
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    origin: u16,
+    bytes: Vec<u8>,
+}
+
+impl Program {
+    /// Empty program starting at `origin`
+    pub fn new(origin: u16) -> Self {
+        Self {
+            origin,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Append `opcode`, panics when no instruction encodes it
+    pub fn op(mut self, opcode: Opcode) -> Self {
+        match encode(&opcode) {
+            Some(bytes) => self.bytes.extend(bytes),
+            None => panic!("No instruction encodes {}", opcode),
+        }
+        self
+    }
+
+    /// Append the opcodes one after the other
+    pub fn ops(self, opcodes: impl IntoIterator<Item = Opcode>) -> Self {
+        opcodes.into_iter().fold(self, Self::op)
+    }
+
+    /// Append raw bytes: data, or the invalid opcodes
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.bytes.extend(bytes);
+        self
+    }
+
+    /// Continue at `addr`, after NOPs up to it
+    pub fn at(mut self, addr: u16) -> Self {
+        assert!(addr >= self.here(), "0x{:04x} is before the end", addr);
+        self.bytes.resize((addr - self.origin) as usize, 0x00);
+        self
+    }
+
+    /// Address of the next opcode appended
+    pub fn here(&self) -> u16 {
+        self.origin + self.bytes.len() as u16
+    }
+
+    /// Append the relative jump `jump` to `target`, as
+    /// `.jr(Opcode::JumpRNZMemOffset, start)`
+    pub fn jr(self, jump: impl FnOnce(i8) -> Opcode, target: u16) -> Self {
+        let offset = target.wrapping_sub(self.here().wrapping_add(2)) as i16;
+        assert!(
+            i8::try_from(offset).is_ok(),
+            "0x{:04x} is out of reach of JR",
+            target
+        );
+        self.op(jump(offset as i8))
+    }
+
+    pub fn origin(&self) -> u16 {
+        self.origin
+    }
+
+    /// The assembled bytes, from the origin
+    pub fn assembled(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// ROM of 32 KiB with the program, which must fit below 0x8000
+    pub fn rom(&self) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let start = self.origin as usize;
+        rom[start..start + self.bytes.len()].copy_from_slice(&self.bytes);
+        rom
+    }
+
+    /// 64 KiB of RAM without any hardware, with the program
+    pub fn memory(&self) -> Mmu {
+        let mut mmu = Mmu::flat();
+        for (addr, &byte) in (self.origin..).zip(&self.bytes) {
+            mmu.write(addr, byte);
+        }
+        mmu
+    }
+
+    /// Execute `steps` instructions on a flat memory from the origin, the
+    /// registers cleared and SP at 0xfffe
+    pub fn run(&self, steps: usize) -> (Cpu, Mmu) {
+        let regs = Registers {
+            sp: 0xfffe,
+            ..Default::default()
+        };
+        self.run_with(regs, steps)
+    }
+
+    /// Same as `run` from `regs`, PC excepted
+    pub fn run_with(&self, regs: Registers, steps: usize) -> (Cpu, Mmu) {
+        let mut mmu = self.memory();
+        let mut cpu = Cpu::new(Registers {
+            pc: self.origin,
+            ..regs
+        });
+        for _ in 0..steps {
+            cpu.step(&mut mmu);
+        }
+        (cpu, mmu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Condition;
+    use crate::slots::{Register16::*, Register8::*, Slot};
+
+    #[test]
+    fn test_program() {
+        // Count down from 3 in B
+        let program = Program::new(0xc000).op(Opcode::Ld(Slot::r8(B), Slot::Data8(3)));
+        let start = program.here();
+        let program = program
+            .ops([Opcode::Inc(Slot::r8(C)), Opcode::Dec(Slot::r8(B))])
+            .jr(Opcode::JumpRNZMemOffset, start)
+            .op(Opcode::Ld(Slot::r16(HL), Slot::Data16(0x1234)))
+            .bytes(&[0xd3]);
+        assert_eq!(
+            program.assembled(),
+            [0x06, 0x03, 0x0c, 0x05, 0x20, 0xfc, 0x21, 0x34, 0x12, 0xd3]
+        );
+        let (cpu, _) = program.run(1 + 3 * 3 + 1);
+        let regs = cpu.registers();
+        assert_eq!((regs.b, regs.c, regs.hl(), regs.pc), (0, 3, 0x1234, 0xc009));
+
+        let program = Program::new(0x0100)
+            .op(Opcode::CallCond(Condition::Z, Slot::Data16(0x0110)))
+            .at(0x0110)
+            .op(Opcode::Ret);
+        assert_eq!(program.rom()[0x100..0x111], {
+            let mut bytes = [0; 0x11];
+            bytes[..3].copy_from_slice(&[0xcc, 0x10, 0x01]);
+            bytes[0x10] = 0xc9;
+            bytes
+        });
+        assert_eq!(program.memory().read(0x0110), 0xc9);
+    }
+}
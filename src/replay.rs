@@ -0,0 +1,317 @@
+//! Replays, to reproduce a run exactly from a small file attached to a bug
+//! report: the state of the machine when the recording started and the
+//! joypad of every frame. The inputs only change between frames, so they
+//! also give the timing of the joypad interrupts. A fingerprint of the
+//! machine after the last frame tells whether the replay went the same way.
+//! File layout:
+//! - magic "GBRP" and format version (1 byte)
+//! - compressed with deflate:
+//!   - the joypad of each frame, in the format of the movies
+//!   - size of the initial state (4 bytes, little endian) and the save state
+//!   - fingerprint of the machine after the last frame (8 bytes, little
+//!     endian)
+
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::emulator::{Emulator, Options};
+use crate::movie::{rom_hash, Movie, MovieError};
+use crate::observer::{Frame, Observer};
+use crate::state::StateError;
+
+const MAGIC: &[u8; 4] = b"GBRP";
+const VERSION: u8 = 1;
+
+/// FNV-1a hash of the registers and of the memory from 0x8000, the settings
+/// of the frontend in the save states do not change it
+pub fn fingerprint(emulator: &Emulator) -> u64 {
+    let regs = emulator.cpu().registers();
+    let mmu = emulator.mmu();
+    let mut data = vec![
+        regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l,
+    ];
+    data.extend(regs.sp.to_le_bytes());
+    data.extend(regs.pc.to_le_bytes());
+    data.extend((0x8000..=0xffff).map(|addr| mmu.peek(addr)));
+    rom_hash(&data)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Replay {
+    movie: Movie,
+    state: Vec<u8>,
+    fingerprint: u64,
+}
+
+impl Replay {
+    /// Joypad of each frame, with the model and the hash of the ROM
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Whether the replay was recorded with this ROM
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.movie.matches_rom(rom)
+    }
+
+    /// The emulator in the state where the recording started
+    pub fn emulator(&self, rom: Vec<u8>) -> Result<Emulator, ReplayError> {
+        if !self.matches_rom(&rom) {
+            return Err(ReplayError::OtherRom);
+        }
+        let options = Options {
+            model: Some(self.movie.model),
+            ..Default::default()
+        };
+        let mut emulator = Emulator::new(rom, &options);
+        emulator.load_state(&self.state)?;
+        Ok(emulator)
+    }
+
+    /// Run the frames of the replay from the state of `emulator`, returns
+    /// whether they end as when they were recorded. The breakpoints are
+    /// resumed.
+    pub fn play(&self, emulator: &mut Emulator) -> bool {
+        for frame in 0..self.movie.len() {
+            emulator.set_buttons(self.movie.frame(frame).unwrap());
+            while emulator.run_frame().is_some() {}
+        }
+        fingerprint(emulator) == self.fingerprint
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> Result<Self, ReplayError> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(ReplayError::InvalidHeader);
+        }
+        if header[4] != VERSION {
+            return Err(ReplayError::UnsupportedVersion(header[4]));
+        }
+        let mut decoder = DeflateDecoder::new(reader);
+        let movie = Movie::read_from(&mut decoder)?;
+        let mut len = [0; 4];
+        decoder.read_exact(&mut len)?;
+        // The length is not trusted, the state is read up to it
+        let len = u32::from_le_bytes(len) as usize;
+        let mut state = Vec::new();
+        decoder.by_ref().take(len as u64).read_to_end(&mut state)?;
+        if state.len() != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let mut fingerprint = [0; 8];
+        decoder.read_exact(&mut fingerprint)?;
+        Ok(Self {
+            movie,
+            state,
+            fingerprint: u64::from_le_bytes(fingerprint),
+        })
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), ReplayError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        let mut encoder = DeflateEncoder::new(writer, Compression::best());
+        self.movie.write_to(&mut encoder)?;
+        encoder.write_all(&(self.state.len() as u32).to_le_bytes())?;
+        encoder.write_all(&self.state)?;
+        encoder.write_all(&self.fingerprint.to_le_bytes())?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// An observer recording the frames of the emulator it is added to, shared
+/// with its clones
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<Replay>>);
+
+impl Recorder {
+    /// Record `emulator` from its current state, between two frames
+    pub fn start(emulator: &mut Emulator) -> Self {
+        let recorder = Self(Arc::new(Mutex::new(Replay {
+            movie: Movie::new(emulator.model(), emulator.rom()),
+            state: emulator.save_state(),
+            fingerprint: 0,
+        })));
+        emulator.add_observer(Box::new(recorder.clone()));
+        recorder
+    }
+
+    /// The replay of the frames recorded, ending with the state of
+    /// `emulator` after the last one
+    pub fn finish(&self, emulator: &Emulator) -> Replay {
+        let mut replay = self.0.lock().unwrap().clone();
+        replay.fingerprint = fingerprint(emulator);
+        replay
+    }
+}
+
+impl Observer for Recorder {
+    fn on_frame(&mut self, frame: &Frame) {
+        self.0.lock().unwrap().movie.record(frame.buttons);
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    OtherRom,
+    Movie(MovieError),
+    State(StateError),
+    IOError(io::Error),
+}
+
+impl Error for ReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Movie(err) => Some(err),
+            Self::State(err) => Some(err),
+            Self::IOError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<MovieError> for ReplayError {
+    fn from(value: MovieError) -> Self {
+        ReplayError::Movie(value)
+    }
+}
+
+impl From<StateError> for ReplayError {
+    fn from(value: StateError) -> Self {
+        ReplayError::State(value)
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(value: io::Error) -> Self {
+        ReplayError::IOError(value)
+    }
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeader => f.write_str("Not a replay file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported replay version {}", version)
+            }
+            Self::OtherRom => f.write_str("The replay was recorded with another ROM"),
+            Self::Movie(err) => write!(f, "{}", err),
+            Self::State(err) => write!(f, "{}", err),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+
+    // Counts the presses of A in 0xc000: LD HL 0xc000; LD A 0x10; LDH (P1) A;
+    // LDH A (P1); BIT 0 A; JR NZ -6; INC (HL); LDH A (P1); BIT 0 A; JR Z -6;
+    // JR -15
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x117].copy_from_slice(&[
+            0x21, 0x00, 0xc0, 0x3e, 0x10, 0xe0, 0x00, 0xf0, 0x00, 0xcb, 0x47, 0x20, 0xfa, 0x34,
+            0xf0, 0x00, 0xcb, 0x47, 0x28, 0xfa, 0x18, 0xf1, 0x00,
+        ]);
+        rom
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut emulator = Emulator::new(rom(), &Default::default());
+        emulator.run_frame();
+        let recorder = Recorder::start(&mut emulator);
+        for buttons in [
+            Button::A.mask(),
+            0,
+            0,
+            Button::A.mask(),
+            Button::A.mask(),
+            0,
+        ] {
+            emulator.set_buttons(buttons);
+            emulator.run_frame();
+        }
+        assert_eq!(emulator.mmu().peek(0xc000), 2);
+        let replay = recorder.finish(&emulator);
+        assert_eq!(replay.movie().len(), 6);
+
+        let mut data = Vec::new();
+        replay.write_to(&mut data).unwrap();
+        let loaded = Replay::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!(loaded, replay);
+
+        let mut replayed = loaded.emulator(rom()).unwrap();
+        assert_eq!(replayed.frame(), 1);
+        assert!(loaded.play(&mut replayed));
+        assert_eq!(replayed.mmu().peek(0xc000), 2);
+
+        // Another emulator with a different history
+        let mut other = loaded.emulator(rom()).unwrap();
+        other.mmu_mut().write(0xc000, 5);
+        assert!(!loaded.play(&mut other));
+    }
+
+    #[test]
+    fn test_replay_invalid() {
+        let mut emulator = Emulator::new(rom(), &Default::default());
+        let replay = Recorder::start(&mut emulator).finish(&emulator);
+        assert!(matches!(
+            replay.emulator(vec![0; 0x8000]),
+            Err(ReplayError::OtherRom)
+        ));
+
+        let mut data = Vec::new();
+        replay.write_to(&mut data).unwrap();
+        data[4] = 2;
+        assert!(matches!(
+            Replay::read_from(&mut data.as_slice()),
+            Err(ReplayError::UnsupportedVersion(2))
+        ));
+        data[0] = b'X';
+        assert!(matches!(
+            Replay::read_from(&mut data.as_slice()),
+            Err(ReplayError::InvalidHeader)
+        ));
+
+        // A state of 4 GiB, truncated
+        let mut data = Vec::new();
+        data.extend(MAGIC);
+        data.push(VERSION);
+        let mut encoder = DeflateEncoder::new(&mut data, Compression::default());
+        replay.movie().write_to(&mut encoder).unwrap();
+        encoder.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        encoder.write_all(&[0; 16]).unwrap();
+        encoder.finish().unwrap();
+        assert!(matches!(
+            Replay::read_from(&mut data.as_slice()),
+            Err(ReplayError::IOError(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
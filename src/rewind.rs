@@ -0,0 +1,119 @@
+//! Rewind, with a ring buffer of save states compressed with deflate: one
+//! state per second for the last minute by default, about 1 MB.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::apu::CLOCK_RATE;
+use crate::ppu::DOTS_PER_FRAME;
+
+/// Frames emulated per second, about 59.7
+pub const FRAME_RATE: f32 = CLOCK_RATE as f32 / DOTS_PER_FRAME as f32;
+
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    // Frames between two states
+    interval: usize,
+    capacity: usize,
+    // Frame numbers and states, from the oldest
+    states: VecDeque<(usize, Vec<u8>)>,
+}
+
+impl Default for Rewind {
+    /// One state per second for the last 60 seconds
+    fn default() -> Self {
+        Self::new(FRAME_RATE.round() as usize, 60)
+    }
+}
+
+impl Rewind {
+    /// A state every `interval` frames, keeping the last `capacity` ones
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity,
+            states: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// True when the state of `frame` should be pushed
+    pub fn due(&self, frame: usize) -> bool {
+        frame.is_multiple_of(self.interval)
+    }
+
+    /// Add the state of `frame`, dropping the oldest one when full
+    pub fn push(&mut self, frame: usize, state: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder
+            .write_all(state)
+            .expect("Writing to a Vec does not fail");
+        let compressed = encoder.finish().expect("Writing to a Vec does not fail");
+        self.states.push_back((frame, compressed));
+    }
+
+    /// Remove the states after `frame` and return the newest one left, with
+    /// its frame. The oldest state is returned when they are all after it.
+    pub fn pop_until(&mut self, frame: usize) -> Option<(usize, Vec<u8>)> {
+        while self.states.len() > 1 && self.states.back().is_some_and(|(f, _)| *f > frame) {
+            self.states.pop_back();
+        }
+        let (frame, compressed) = self.states.back()?;
+        let mut state = Vec::new();
+        DeflateDecoder::new(compressed.as_slice())
+            .read_to_end(&mut state)
+            .expect("The rewind states are valid deflate streams");
+        Some((*frame, state))
+    }
+
+    /// Number of states kept
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Memory used by the compressed states, in bytes
+    pub fn size(&self) -> usize {
+        self.states.iter().map(|(_, state)| state.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewind() {
+        let mut rewind = Rewind::new(10, 3);
+        assert!(rewind.pop_until(100).is_none());
+        assert!(rewind.due(20));
+        assert!(!rewind.due(25));
+        for frame in [10, 20, 30, 40] {
+            rewind.push(frame, &[frame as u8; 1000]);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert!(rewind.size() < 3000);
+
+        assert_eq!(rewind.pop_until(35), Some((30, vec![30; 1000])));
+        assert_eq!(rewind.len(), 2);
+        // Only the oldest state is left
+        assert_eq!(rewind.pop_until(0), Some((20, vec![20; 1000])));
+        assert_eq!(rewind.len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+//! Loading of the ROM files. `-` reads the standard input, and the ROMs
+//! compressed with gzip or in a zip archive are extracted, whatever their
+//! extension.
+
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+// Extensions of the ROM when an archive has several files
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "cgb"];
+
+/// Content of the ROM at `path`, or of the standard input for `-`
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let data = if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        std::fs::read(path)?
+    };
+    decompress(data)
+}
+
+/// Extract `data` if it is compressed, or return it unchanged
+pub fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if data.starts_with(&GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+        Ok(rom)
+    } else if data.starts_with(&ZIP_MAGIC) {
+        unzip(data)
+    } else {
+        Ok(data)
+    }
+}
+
+// The only file of the archive, or its only ROM
+fn unzip(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    let roms: Vec<String> = files
+        .iter()
+        .filter(|name| {
+            Path::new(name)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    ROM_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                })
+        })
+        .cloned()
+        .collect();
+    let name = match (files.as_slice(), roms.as_slice()) {
+        ([name], _) | (_, [name]) => name.clone(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected a single ROM in the archive, found {:?}", files),
+            ))
+        }
+    };
+    let mut rom = Vec::new();
+    archive.by_name(&name)?.read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_decompress() {
+        let rom = vec![0x00, 0xc3, 0x50, 0x01];
+        assert_eq!(decompress(rom.clone()).unwrap(), rom);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&rom).unwrap();
+        assert_eq!(decompress(encoder.finish().unwrap()).unwrap(), rom);
+
+        assert_eq!(decompress(zip(&[("game.bin", &rom)])).unwrap(), rom);
+        let archive = zip(&[("readme.txt", b"hello"), ("Game.GBC", &rom)]);
+        assert_eq!(decompress(archive).unwrap(), rom);
+        let archive = zip(&[("a.gb", &rom), ("b.gb", &rom)]);
+        assert_eq!(
+            decompress(archive).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
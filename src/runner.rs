@@ -0,0 +1,243 @@
+//! The emulator on a thread of its own, driven through channels. The
+//! frontend queues commands and polls the events, it stays responsive while
+//! the emulation is busy, when fast-forwarding for example:
+//! ```no_run
+//! use gb::runner::{Command, Event, Runner};
+//!
+//! let rom = gb::rom::read("game.gb").unwrap();
+//! let mut runner = Runner::spawn(gb::Emulator::new(rom, &Default::default()));
+//! runner.send(Command::RunFrame(0));
+//! for event in runner.events() {
+//!     if let Event::Frame(frame) = event {
+//!         // Show and play the frame
+//!     }
+//! }
+//! ```
+
+use std::panic;
+use std::sync::mpsc::{self, Receiver, Sender, TryIter};
+use std::thread::{self, JoinHandle};
+
+use crate::cpu::Cpu;
+use crate::debugger::breakpoints::Hit;
+use crate::emulator::{Emulator, Options};
+use crate::mmu::Mmu;
+use crate::observer::OwnedFrame;
+
+pub enum Command {
+    /// Power on with another cartridge, or the same one to reset. The
+    /// breakpoints, the watches, the cheats and the link cable are kept.
+    LoadRom(Vec<u8>, Options),
+    /// Run up to the next frame with the buttons held, in the format of
+    /// `Joypad::state`
+    RunFrame(u8),
+    /// Execute a single instruction, even after a breakpoint
+    Step,
+    /// Run the frames again after a breakpoint
+    Resume,
+    /// Send a copy of the machine, for the debuggers
+    Snapshot,
+    /// Change the settings, edit the memory...
+    Apply(Box<dyn FnOnce(&mut Emulator) + Send>),
+}
+
+pub enum Event {
+    /// Sent after each `Command::RunFrame`
+    Frame(OwnedFrame),
+    /// A breakpoint stopped a `Command::RunFrame` or a `Command::Step`, the
+    /// next `RunFrame` are ignored until a `Command::Resume`
+    Break(Hit),
+    Snapshot(Box<Snapshot>),
+}
+
+/// The machine at the time of a `Command::Snapshot`. The post-processing
+/// filters of the PPU are not copied.
+pub struct Snapshot {
+    pub cpu: Cpu,
+    pub mmu: Mmu,
+}
+
+pub struct Runner {
+    commands: Option<Sender<Command>>,
+    events: Receiver<Event>,
+    thread: Option<JoinHandle<Emulator>>,
+}
+
+impl Runner {
+    /// Move `emulator` to a new thread, it runs until the runner is dropped
+    pub fn spawn(emulator: Emulator) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let (sender, events) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("emulator".to_string())
+            .spawn(move || run(emulator, receiver, sender))
+            .expect("Cannot start the emulator thread");
+        Self {
+            commands: Some(commands),
+            events,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue a command, executed after the previous ones. A panic of the
+    /// emulator is forwarded to the caller.
+    pub fn send(&mut self, command: Command) {
+        let sent = self.commands.as_ref().unwrap().send(command);
+        if sent.is_err() {
+            // The thread only stops early when it panics
+            if let Err(err) = self.thread.take().unwrap().join() {
+                panic::resume_unwind(err);
+            }
+        }
+    }
+
+    pub fn apply(&mut self, change: impl FnOnce(&mut Emulator) + Send + 'static) {
+        self.send(Command::Apply(Box::new(change)));
+    }
+
+    /// Events received so far, without waiting
+    pub fn events(&self) -> TryIter<'_, Event> {
+        self.events.try_iter()
+    }
+
+    /// Execute the commands queued and return the emulator
+    pub fn stop(mut self) -> Emulator {
+        self.commands = None;
+        match self.thread.take().unwrap().join() {
+            Ok(emulator) => emulator,
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        self.commands = None;
+        if let Some(thread) = self.thread.take() {
+            // A panic was already reported by the thread
+            let _ = thread.join();
+        }
+    }
+}
+
+// Execute the commands until the runner is stopped or dropped
+fn run(mut emulator: Emulator, commands: Receiver<Command>, events: Sender<Event>) -> Emulator {
+    // Stopped on a breakpoint, the frames queued before it are dropped
+    let mut stopped = false;
+    for command in commands {
+        let event = match command {
+            Command::LoadRom(rom, options) => {
+                let mut previous = std::mem::replace(&mut emulator, Emulator::new(rom, &options));
+                emulator.cpu_mut().keep_debugging(previous.cpu_mut());
+                emulator.mmu_mut().keep_debugging(previous.mmu_mut());
+                *emulator.mmu_mut().cheats_mut() = previous.mmu().cheats().clone();
+                let serial = previous.mmu_mut().serial_mut();
+                emulator.mmu_mut().serial_mut().keep_link(serial);
+                let infrared = previous.mmu_mut().infrared_mut();
+                emulator.mmu_mut().infrared_mut().keep_transport(infrared);
+                stopped = false;
+                None
+            }
+            Command::RunFrame(_) if stopped => None,
+            Command::RunFrame(buttons) => {
+                emulator.set_buttons(buttons);
+                match emulator.run_frame() {
+                    Some(hit) => {
+                        stopped = true;
+                        Some(Event::Break(hit))
+                    }
+                    None => Some(Event::Frame(OwnedFrame::from(&emulator.last_frame()))),
+                }
+            }
+            Command::Step => {
+                emulator.step();
+                emulator.cpu().hit().map(|hit| {
+                    stopped = true;
+                    Event::Break(hit)
+                })
+            }
+            Command::Resume => {
+                stopped = false;
+                None
+            }
+            Command::Snapshot => Some(Event::Snapshot(Box::new(Snapshot {
+                cpu: emulator.cpu().clone(),
+                mmu: emulator.mmu().clone(),
+            }))),
+            Command::Apply(change) => {
+                change(&mut emulator);
+                None
+            }
+        };
+        if let Some(event) = event {
+            let _ = events.send(event);
+        }
+    }
+    emulator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+
+    #[test]
+    fn test_runner() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        let mut runner = Runner::spawn(Emulator::new(Vec::new(), &Default::default()));
+        runner.send(Command::LoadRom(rom, Default::default()));
+        runner.send(Command::RunFrame(Button::A.mask()));
+        runner.send(Command::RunFrame(0));
+        runner.send(Command::Step);
+        runner.apply(|emulator| emulator.mmu_mut().write(0xc000, 0x42));
+        runner.send(Command::Snapshot);
+        let emulator = runner.stop();
+        assert_eq!(emulator.frame(), 2);
+        assert_eq!(emulator.mmu().read(0xc000), 0x42);
+    }
+
+    #[test]
+    fn test_runner_events() {
+        let mut runner = Runner::spawn(Emulator::new(vec![0; 0x8000], &Default::default()));
+        runner.send(Command::Snapshot);
+        runner.send(Command::RunFrame(0));
+        let mut events = runner.events.iter();
+        match events.next() {
+            Some(Event::Snapshot(snapshot)) => assert_eq!(snapshot.cpu.registers().pc, 0x100),
+            _ => panic!("Expected a snapshot"),
+        }
+        assert!(matches!(
+            events.next(),
+            Some(Event::Frame(frame)) if frame.number == 1
+        ));
+    }
+
+    #[test]
+    fn test_runner_break() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        let mut runner = Runner::spawn(Emulator::new(rom, &Default::default()));
+        runner.apply(|emulator| {
+            emulator
+                .cpu_mut()
+                .breakpoints_mut()
+                .add("0x0100".parse().unwrap());
+        });
+        runner.send(Command::RunFrame(0));
+        // Ignored after the breakpoint
+        runner.send(Command::RunFrame(0));
+        runner.send(Command::Resume);
+        runner.apply(|emulator| emulator.cpu_mut().breakpoints_mut().clear());
+        runner.send(Command::RunFrame(0));
+        let mut events = runner.events.iter();
+        assert!(matches!(
+            events.next(),
+            Some(Event::Break(hit)) if hit.pc == 0x100
+        ));
+        assert!(matches!(
+            events.next(),
+            Some(Event::Frame(frame)) if frame.number == 1
+        ));
+    }
+}
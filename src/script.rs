@@ -0,0 +1,317 @@
+//! Scripts in Rhai (https://rhai.rs) running along the emulation, to build
+//! the tools of a game without recompiling: auto-splitters, checks of a
+//! randomizer, bots... The script registers its callbacks when it is loaded:
+//! ```text
+//! on_frame(|frame| if read(0xc0a0) == 3 { print(`level 3 at frame ${frame}`) });
+//! on_write(0xc000, 0xc0ff, |addr, value| print(`${addr} = ${value}`));
+//! on_breakpoint(0x0150, |pc| press("start"));
+//! ```
+//! `read(addr)`, `write(addr, value)`, `register("hl")` and `frame()` access
+//! the emulator, `press(button)` and `release(button)` hold the buttons of
+//! the joypad from the next frame.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, ParseError, AST, INT};
+
+use crate::debugger::breakpoints::{Breakpoint, Hit};
+use crate::debugger::expression::Register;
+use crate::emulator::Emulator;
+use crate::input::Action;
+use crate::observer::Observer;
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    write: Vec<(RangeInclusive<u16>, FnPtr)>,
+    // By id of the breakpoint
+    breakpoint: Vec<(usize, FnPtr)>,
+    // Writes in the ranges of `write` not passed to the script yet
+    writes: Vec<(u16, u8)>,
+    // Whether the observer of the writes is added to the emulator
+    observing: bool,
+    // Buttons held by the script
+    held: u8,
+}
+
+// Records the writes for the `on_write` callbacks
+struct WriteHooks(Arc<Mutex<Hooks>>);
+
+impl Observer for WriteHooks {
+    fn on_write(&mut self, addr: u16, value: u8) {
+        let mut hooks = self.0.lock().unwrap();
+        if hooks.write.iter().any(|(range, _)| range.contains(&addr)) {
+            hooks.writes.push((addr, value));
+        }
+    }
+}
+
+fn button(name: &str) -> Result<u8, Box<EvalAltResult>> {
+    match name.parse() {
+        Ok(Action::Joypad(button)) => Ok(button.mask()),
+        _ => Err(format!("Unknown button {}", name).into()),
+    }
+}
+
+/// A script and the emulator it runs along
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    emulator: Arc<Mutex<Emulator>>,
+    hooks: Arc<Mutex<Hooks>>,
+}
+
+impl Script {
+    /// Run the script once, to register its callbacks
+    pub fn new(source: &str, emulator: Emulator) -> Result<Self, ScriptError> {
+        let emulator = Arc::new(Mutex::new(emulator));
+        let hooks = Arc::new(Mutex::new(Hooks::default()));
+        let mut engine = Engine::new();
+
+        let e = emulator.clone();
+        engine.register_fn("read", move |addr: INT| {
+            e.lock().unwrap().mmu().peek(addr as u16) as INT
+        });
+        let e = emulator.clone();
+        engine.register_fn("write", move |addr: INT, value: INT| {
+            e.lock().unwrap().mmu_mut().write(addr as u16, value as u8)
+        });
+        let e = emulator.clone();
+        engine.register_fn(
+            "register",
+            move |name: &str| -> Result<INT, Box<EvalAltResult>> {
+                let register: Register = name
+                    .parse()
+                    .map_err(|_| format!("Unknown register {}", name))?;
+                Ok(register.value(e.lock().unwrap().cpu().registers()) as INT)
+            },
+        );
+        let e = emulator.clone();
+        engine.register_fn("frame", move || e.lock().unwrap().frame() as INT);
+        let h = hooks.clone();
+        engine.register_fn(
+            "press",
+            move |name: &str| -> Result<(), Box<EvalAltResult>> {
+                h.lock().unwrap().held |= button(name)?;
+                Ok(())
+            },
+        );
+        let h = hooks.clone();
+        engine.register_fn(
+            "release",
+            move |name: &str| -> Result<(), Box<EvalAltResult>> {
+                h.lock().unwrap().held &= !button(name)?;
+                Ok(())
+            },
+        );
+
+        let h = hooks.clone();
+        engine.register_fn("on_frame", move |callback: FnPtr| {
+            h.lock().unwrap().frame.push(callback)
+        });
+        let (h, e) = (hooks.clone(), emulator.clone());
+        engine.register_fn("on_write", move |start: INT, end: INT, callback: FnPtr| {
+            let mut hooks = h.lock().unwrap();
+            hooks.write.push((start as u16..=end as u16, callback));
+            if !hooks.observing {
+                hooks.observing = true;
+                e.lock()
+                    .unwrap()
+                    .add_observer(Box::new(WriteHooks(h.clone())));
+            }
+        });
+        let (h, e) = (hooks.clone(), emulator.clone());
+        engine.register_fn("on_breakpoint", move |addr: INT, callback: FnPtr| {
+            let mut emulator = e.lock().unwrap();
+            let id = emulator
+                .cpu_mut()
+                .breakpoints_mut()
+                .add(Breakpoint::Pc(addr as u16));
+            h.lock().unwrap().breakpoint.push((id, callback));
+        });
+
+        let ast = engine.compile(source)?;
+        engine.run_ast(&ast)?;
+        Ok(Self {
+            engine,
+            ast,
+            emulator,
+            hooks,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>, emulator: Emulator) -> Result<Self, ScriptError> {
+        let source = std::fs::read_to_string(path)?;
+        Self::new(&source, emulator)
+    }
+
+    pub fn emulator(&self) -> MutexGuard<'_, Emulator> {
+        self.emulator.lock().unwrap()
+    }
+
+    pub fn into_emulator(self) -> Emulator {
+        // The functions of the engine share the emulator
+        drop(self.engine);
+        match Arc::try_unwrap(self.emulator) {
+            Ok(emulator) => emulator.into_inner().unwrap(),
+            Err(_) => unreachable!("Only the script has the emulator"),
+        }
+    }
+
+    /// Run a frame with the `buttons` of the player and those held by the
+    /// script, calling the callbacks. The writes are passed once the frame
+    /// or a breakpoint is reached. Returns the breakpoints hit which are not
+    /// those of the script, as `Emulator::run_frame`.
+    pub fn run_frame(&mut self, buttons: u8) -> Result<Option<Hit>, ScriptError> {
+        loop {
+            let held = self.hooks.lock().unwrap().held;
+            let hit = {
+                let mut emulator = self.emulator.lock().unwrap();
+                emulator.set_buttons(buttons | held);
+                emulator.run_frame()
+            };
+            self.call_write_hooks()?;
+            let Some(hit) = hit else {
+                break;
+            };
+            let callbacks: Vec<FnPtr> = self
+                .hooks
+                .lock()
+                .unwrap()
+                .breakpoint
+                .iter()
+                .filter(|(id, _)| *id == hit.id)
+                .map(|(_, callback)| callback.clone())
+                .collect();
+            if callbacks.is_empty() {
+                return Ok(Some(hit));
+            }
+            for callback in callbacks {
+                self.call(&callback, (hit.pc as INT,))?;
+            }
+        }
+        let frame = self.emulator().frame() as INT;
+        let callbacks = self.hooks.lock().unwrap().frame.clone();
+        for callback in callbacks {
+            self.call(&callback, (frame,))?;
+        }
+        Ok(None)
+    }
+
+    fn call_write_hooks(&mut self) -> Result<(), ScriptError> {
+        let (writes, hooks) = {
+            let mut hooks = self.hooks.lock().unwrap();
+            (std::mem::take(&mut hooks.writes), hooks.write.clone())
+        };
+        for (addr, value) in writes {
+            for (range, callback) in &hooks {
+                if range.contains(&addr) {
+                    self.call(callback, (addr as INT, value as INT))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&self, callback: &FnPtr, args: impl rhai::FuncArgs) -> Result<(), ScriptError> {
+        let _: Dynamic = callback.call(&self.engine, &self.ast, args)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Parse(ParseError),
+    Runtime(Box<EvalAltResult>),
+    IOError(std::io::Error),
+}
+
+impl Error for ScriptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Parse(err) => Some(err),
+            Self::Runtime(err) => Some(err.as_ref()),
+            Self::IOError(err) => Some(err),
+        }
+    }
+}
+
+impl From<ParseError> for ScriptError {
+    fn from(value: ParseError) -> Self {
+        ScriptError::Parse(value)
+    }
+}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(value: Box<EvalAltResult>) -> Self {
+        ScriptError::Runtime(value)
+    }
+}
+
+impl From<std::io::Error> for ScriptError {
+    fn from(value: std::io::Error) -> Self {
+        ScriptError::IOError(value)
+    }
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "Script error: {}", err),
+            Self::Runtime(err) => write!(f, "Script error: {}", err),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Button;
+
+    // INC A; LD (0xc000) A; JR -6
+    fn emulator() -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[0x3c, 0xea, 0x00, 0xc0, 0x18, 0xfa]);
+        Emulator::new(rom, &Default::default())
+    }
+
+    #[test]
+    fn test_script() {
+        let source = r#"
+            let frames = [];
+            on_frame(|frame| { frames.push(frame); write(0xc100, frames.len()) });
+            on_write(0xc000, 0xc000, |addr, value| if value == 0x10 { press("start") });
+            on_breakpoint(0x0104, |pc| write(0xc101, read(0xc101) + 1));
+        "#;
+        let mut script = Script::new(source, emulator()).unwrap();
+        script.run_frame(0).unwrap();
+        script.run_frame(Button::A.mask()).unwrap();
+
+        let emulator = script.into_emulator();
+        assert_eq!(emulator.mmu().peek(0xc100), 2);
+        assert!(emulator.mmu().peek(0xc101) > 100);
+        assert_eq!(
+            emulator.mmu().joypad().state(),
+            Button::A.mask() | Button::Start.mask()
+        );
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(matches!(
+            Script::new("on_frame(", emulator()),
+            Err(ScriptError::Parse(_))
+        ));
+        assert!(matches!(
+            Script::new(r#"press("turbo")"#, emulator()),
+            Err(ScriptError::Runtime(_))
+        ));
+        let mut script = Script::new("on_frame(|frame| register(\"xy\"))", emulator()).unwrap();
+        assert!(matches!(script.run_frame(0), Err(ScriptError::Runtime(_))));
+    }
+}
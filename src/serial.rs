@@ -0,0 +1,230 @@
+//! Serial port, connected to another console by a `Link`, or to nothing.
+//! The bytes sent are kept for the frontends: the test ROMs of Blargg print
+//! their results on it.
+//! See https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+
+use crate::interrupts::Interrupt;
+use crate::link::Link;
+
+/// Serial transfer data
+pub const SB: u16 = 0xff01;
+/// Serial transfer control
+pub const SC: u16 = 0xff02;
+
+const SC_TRANSFER: u8 = 0x80;
+const SC_INTERNAL_CLOCK: u8 = 0x01;
+// 8 bits at 8192 Hz
+const TRANSFER_CYCLES: u32 = 4096;
+// Between the checks for a transfer of the other console
+const POLL_CYCLES: u32 = 512;
+
+#[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // T-cycles left before the end of the transfer
+    remaining: u32,
+    // Bytes sent, not drained yet
+    #[cfg_attr(feature = "serde", serde(skip))]
+    output: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    link: Option<Box<dyn Link>>,
+    // T-cycles before the next poll of the link
+    #[cfg_attr(feature = "serde", serde(skip))]
+    poll_countdown: u32,
+}
+
+/// A copy is not connected
+impl Clone for Serial {
+    fn clone(&self) -> Self {
+        Self {
+            sb: self.sb,
+            sc: self.sc,
+            remaining: self.remaining,
+            output: self.output.clone(),
+            link: None,
+            poll_countdown: 0,
+        }
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Plug the link cable, replacing the previous one
+    pub fn connect(&mut self, link: Box<dyn Link>) {
+        self.link = Some(link);
+    }
+
+    /// Unplug the link cable, returns it
+    pub fn disconnect(&mut self) -> Option<Box<dyn Link>> {
+        self.link.take()
+    }
+
+    /// Take the link cable of `previous`, replaced by a reset or a save
+    /// state
+    pub fn keep_link(&mut self, previous: &mut Self) {
+        self.link = previous.link.take();
+    }
+
+    /// Advance by `cycles` T-cycles, returns the interrupts requested
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        if self.link.is_some() && self.poll(cycles) {
+            return Interrupt::Serial.mask();
+        }
+        if self.remaining == 0 {
+            return 0;
+        }
+        self.remaining = self.remaining.saturating_sub(cycles);
+        if self.remaining > 0 {
+            return 0;
+        }
+        // The bits received are all 1 when nothing is connected
+        self.sb = self.link.as_mut().map_or(0xff, |link| link.finish());
+        self.sc &= !SC_TRANSFER;
+        Interrupt::Serial.mask()
+    }
+
+    // True when a transfer with the clock of the other console ended
+    fn poll(&mut self, cycles: u32) -> bool {
+        self.poll_countdown = self.poll_countdown.saturating_sub(cycles);
+        if self.poll_countdown > 0 {
+            return false;
+        }
+        self.poll_countdown = POLL_CYCLES;
+        let Some(link) = &mut self.link else {
+            return false;
+        };
+        // Without a transfer, the other console receives 1s
+        let waiting = self.sc == SC_TRANSFER;
+        match link.poll(if waiting { self.sb } else { 0xff }) {
+            Some(byte) if waiting => {
+                self.output.push(self.sb);
+                self.sb = byte;
+                self.sc &= !SC_TRANSFER;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB => self.sb,
+            // Bits 1 to 6 are unused
+            SC => 0x7e | self.sc,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & (SC_TRANSFER | SC_INTERNAL_CLOCK);
+                // With the external clock, the transfer waits for the other
+                // console, forever when nothing is connected
+                if self.sc == SC_TRANSFER | SC_INTERNAL_CLOCK {
+                    self.output.push(self.sb);
+                    if let Some(link) = &mut self.link {
+                        link.start(self.sb);
+                    }
+                    self.remaining = TRANSFER_CYCLES;
+                } else {
+                    self.remaining = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Move the bytes sent so far to the end of `out`
+    pub fn drain_output(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.output);
+    }
+}
+
+/// Result printed by the test ROMs of Blargg, true once `output` contains
+/// "Passed" and false for "Failed"
+pub fn test_result(output: &[u8]) -> Option<bool> {
+    let contains = |text: &[u8]| output.windows(text.len()).any(|window| window == text);
+    if contains(b"Passed") {
+        Some(true)
+    } else if contains(b"Failed") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::{Loopback, TcpLink};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_serial_transfer() {
+        let mut serial = Serial::new();
+        serial.write(SB, b'P');
+        serial.write(SC, SC_TRANSFER | SC_INTERNAL_CLOCK);
+        assert_eq!(serial.read(SC), 0xff);
+        assert_eq!(serial.tick(TRANSFER_CYCLES - 1), 0);
+        assert_eq!(serial.tick(4), Interrupt::Serial.mask());
+        assert_eq!(serial.read(SB), 0xff);
+        assert_eq!(serial.read(SC), 0x7f);
+        assert_eq!(serial.tick(TRANSFER_CYCLES), 0);
+
+        // External clock
+        serial.write(SC, SC_TRANSFER);
+        assert_eq!(serial.tick(TRANSFER_CYCLES * 2), 0);
+
+        let mut output = Vec::new();
+        serial.drain_output(&mut output);
+        assert_eq!(output, b"P");
+        serial.drain_output(&mut output);
+        assert_eq!(output, b"P");
+    }
+
+    #[test]
+    fn test_serial_link() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Loopback::default()));
+        serial.write(SB, 0x42);
+        serial.write(SC, SC_TRANSFER | SC_INTERNAL_CLOCK);
+        assert_eq!(serial.tick(TRANSFER_CYCLES), Interrupt::Serial.mask());
+        assert_eq!(serial.read(SB), 0x42);
+        assert!(serial.clone().link.is_none());
+
+        // The other console provides the clock
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut other = TcpLink::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serial.connect(Box::new(TcpLink::new(stream).unwrap()));
+        serial.write(SB, b'S');
+        serial.write(SC, SC_TRANSFER);
+        other.start(b'M');
+        let mut cycles = 0;
+        while serial.tick(4) == 0 {
+            cycles += 4;
+            assert!(cycles < 1_000_000, "No transfer");
+        }
+        assert_eq!(other.finish(), b'S');
+        assert_eq!(serial.read(SB), b'M');
+        assert_eq!(serial.read(SC), 0x7e);
+
+        let mut output = Vec::new();
+        serial.drain_output(&mut output);
+        assert_eq!(output, b"\x42S");
+    }
+
+    #[test]
+    fn test_test_result() {
+        assert_eq!(test_result(b"cpu_instrs\n\n01:ok "), None);
+        assert_eq!(test_result(b"\n\nPassed all tests\n"), Some(true));
+        assert_eq!(test_result(b"\n\nFailed #2\n"), Some(false));
+    }
+}
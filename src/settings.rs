@@ -0,0 +1,233 @@
+//! Settings of the emulator kept between runs: the recently opened ROMs and
+//! the overrides of each game, identified by the hash of its ROM.
+//!
+//! The file has one setting per line, lines starting with `#` are ignored:
+//! ```text
+//! recent /home/user/roms/tetris.gb
+//! game 5f0b6bea4cd2a513 palette green
+//! game 5f0b6bea4cd2a513 speed 2
+//! game 5f0b6bea4cd2a513 slot 1
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::palette::DmgPalette;
+
+/// Length of the list of recent ROMs
+pub const MAX_RECENT: usize = 10;
+
+/// Settings of a game replacing the global ones
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GameSettings {
+    pub palette: Option<DmgPalette>,
+    /// Emulation speed when the game is loaded, from 0.25 to 8. 1 is real time.
+    pub speed: Option<f32>,
+    /// Save state slot used by the save and load actions
+    pub slot: Option<u8>,
+}
+
+impl GameSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Settings {
+    // Most recent first
+    recent: Vec<PathBuf>,
+    games: BTreeMap<u64, GameSettings>,
+}
+
+impl Settings {
+    pub fn parse(data: &str) -> Result<Self, SettingsError> {
+        let mut settings = Self::default();
+        for line in data
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let (kind, rest) = line
+                .split_once(char::is_whitespace)
+                .ok_or(SettingsError::MissingField)?;
+            match kind {
+                "recent" => settings.recent.push(PathBuf::from(rest.trim())),
+                "game" => {
+                    let (hash, rest) = rest
+                        .trim()
+                        .split_once(char::is_whitespace)
+                        .ok_or(SettingsError::MissingField)?;
+                    let (key, value) = rest
+                        .trim()
+                        .split_once(char::is_whitespace)
+                        .ok_or(SettingsError::MissingField)?;
+                    let hash = u64::from_str_radix(hash, 16)
+                        .map_err(|_| SettingsError::InvalidValue(hash.to_string()))?;
+                    let invalid = || SettingsError::InvalidValue(value.to_string());
+                    let game = settings.games.entry(hash).or_default();
+                    match key {
+                        "palette" => game.palette = Some(value.parse().map_err(|_| invalid())?),
+                        "speed" => game.speed = Some(value.parse().map_err(|_| invalid())?),
+                        "slot" => game.slot = Some(value.parse().map_err(|_| invalid())?),
+                        _ => return Err(SettingsError::InvalidKey(key.to_string())),
+                    }
+                }
+                _ => return Err(SettingsError::InvalidKey(kind.to_string())),
+            }
+        }
+        settings.recent.truncate(MAX_RECENT);
+        Ok(settings)
+    }
+
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Self, SettingsError> {
+        let mut tmp = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut tmp))?;
+        Self::parse(&tmp)
+    }
+
+    /// Configuration file content for the current settings
+    pub fn to_config(&self) -> String {
+        let mut config = String::new();
+        for path in &self.recent {
+            config += &format!("recent {}\n", path.display());
+        }
+        for (hash, game) in &self.games {
+            if let Some(palette) = game.palette {
+                config += &format!("game {:016x} palette {}\n", hash, palette);
+            }
+            if let Some(speed) = game.speed {
+                config += &format!("game {:016x} speed {}\n", hash, speed);
+            }
+            if let Some(slot) = game.slot {
+                config += &format!("game {:016x} slot {}\n", hash, slot);
+            }
+        }
+        config
+    }
+
+    /// Save the settings, creating the parent directories if needed
+    pub fn save_file(&self, path: impl AsRef<Path>) -> Result<(), SettingsError> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(std::fs::write(path, self.to_config())?)
+    }
+
+    /// Recently opened ROMs, the most recent first
+    pub fn recent(&self) -> &[PathBuf] {
+        &self.recent
+    }
+
+    /// Move `path` to the top of the recent ROMs
+    pub fn add_recent(&mut self, path: &Path) {
+        self.recent.retain(|recent| recent != path);
+        self.recent.insert(0, path.to_path_buf());
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    /// Overrides of the game with the hash `rom_hash`, see `movie::rom_hash`
+    pub fn game(&self, rom_hash: u64) -> GameSettings {
+        self.games.get(&rom_hash).cloned().unwrap_or_default()
+    }
+
+    pub fn set_game(&mut self, rom_hash: u64, game: GameSettings) {
+        if game.is_empty() {
+            self.games.remove(&rom_hash);
+        } else {
+            self.games.insert(rom_hash, game);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    MissingField,
+    InvalidKey(String),
+    InvalidValue(String),
+    IOError(std::io::Error),
+}
+
+impl Error for SettingsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::MissingField => None,
+            Self::InvalidKey(_) => None,
+            Self::InvalidValue(_) => None,
+            Self::IOError(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(value: std::io::Error) -> Self {
+        SettingsError::IOError(value)
+    }
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingField => f.write_str("Missing value in setting"),
+            Self::InvalidKey(key) => write!(f, "Unknown setting {}", key),
+            Self::InvalidValue(value) => write!(f, "Invalid value {}", value),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let config = "# comment\nrecent /roms/a.gb\nrecent /roms/b.gb\n\
+            game 00000000000000ff palette green\ngame 00000000000000ff slot 2\n\
+            game 0000000000000001 speed 0.5\n";
+        let settings = Settings::parse(config).unwrap();
+        assert_eq!(
+            settings.recent(),
+            [PathBuf::from("/roms/a.gb"), PathBuf::from("/roms/b.gb")]
+        );
+        assert_eq!(
+            settings.game(0xff),
+            GameSettings {
+                palette: Some(DmgPalette::GREEN),
+                speed: None,
+                slot: Some(2),
+            }
+        );
+        assert_eq!(settings.game(1).speed, Some(0.5));
+        assert!(settings.game(2).is_empty());
+        assert_eq!(Settings::parse(&settings.to_config()).unwrap(), settings);
+
+        assert!(matches!(
+            Settings::parse("game ff volume 3"),
+            Err(SettingsError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            Settings::parse("game ff slot first"),
+            Err(SettingsError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_settings_recent() {
+        let mut settings = Settings::default();
+        for i in 0..=MAX_RECENT {
+            settings.add_recent(Path::new(&format!("{}.gb", i)));
+        }
+        settings.add_recent(Path::new("5.gb"));
+        assert_eq!(settings.recent().len(), MAX_RECENT);
+        assert_eq!(settings.recent()[0], Path::new("5.gb"));
+        assert_eq!(settings.recent()[1], Path::new("10.gb"));
+        assert!(!settings.recent().contains(&PathBuf::from("0.gb")));
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt::Debug;
 
-use crate::DecodeError;
+use crate::decoder::DecodeError;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum AddrRegister {
@@ -10,7 +10,6 @@ pub enum AddrRegister {
     C,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Register16 {
     AF,
@@ -21,7 +20,6 @@ pub enum Register16 {
     SP,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Register8 {
     A,
@@ -35,7 +33,6 @@ pub enum Register8 {
     H,
 }
 
-#[allow(dead_code)]
 #[derive(PartialEq, Clone, Copy)]
 pub enum Slot {
     AddrRegister(AddrRegister),
@@ -62,7 +59,6 @@ impl Debug for Slot {
 }
 
 impl Slot {
-    #[allow(dead_code)]
     pub fn parse_a16(data: &mut impl Iterator<Item = u8>) -> Result<Self, DecodeError> {
         Ok(Slot::Addr16(decode_u16(data)?))
     }
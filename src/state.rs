@@ -0,0 +1,136 @@
+//! Save states, the whole machine at a given instant. The cartridge ROM and
+//! the settings of the frontend (palette, layers, filters, muted channels...)
+//! are not saved, loading a state keeps the current ones. File layout:
+//! - magic "GBST" and format version (1 byte)
+//! - FNV-1a hash of the ROM (8 bytes, little endian)
+//! - the CPU, the memory and the components on the bus, encoded by bincode
+//!
+//! The frontends keep numbered slots next to the ROM, `game.ss1` for the slot
+//! 1 of `game.gb`.
+
+use std::path::{Path, PathBuf};
+use std::{error::Error, fmt::Display, io};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::movie::rom_hash;
+
+const MAGIC: &[u8; 4] = b"GBST";
+const VERSION: u8 = 3;
+const HEADER_SIZE: usize = 13;
+
+/// File of the save state `slot` of the ROM at `rom`
+pub fn slot_path(rom: impl AsRef<Path>, slot: u8) -> PathBuf {
+    rom.as_ref().with_extension(format!("ss{}", slot))
+}
+
+/// `state` after the header
+pub(crate) fn encode(rom: &[u8], state: &impl Serialize) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(VERSION);
+    data.extend(rom_hash(rom).to_le_bytes());
+    bincode::serialize_into(&mut data, state).expect("The machine state is serializable");
+    data
+}
+
+/// The state saved by `encode`, if it was saved with the same ROM
+pub(crate) fn decode<T: DeserializeOwned>(rom: &[u8], data: &[u8]) -> Result<T, StateError> {
+    if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+        return Err(StateError::InvalidHeader);
+    }
+    if data[4] != VERSION {
+        return Err(StateError::UnsupportedVersion(data[4]));
+    }
+    if data[5..HEADER_SIZE] != rom_hash(rom).to_le_bytes() {
+        return Err(StateError::OtherRom);
+    }
+    Ok(bincode::deserialize(&data[HEADER_SIZE..])?)
+}
+
+/// Arrays longer than the 32 elements supported by serde, as sequences
+pub(crate) mod array {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        array.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error> {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let len = values.len();
+        values
+            .try_into()
+            .map_err(|_| de::Error::invalid_length(len, &N.to_string().as_str()))
+    }
+}
+
+/// Same as `array`, for the boxed arrays
+pub(crate) mod boxed_array {
+    use serde::{Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        super::array::serialize(array, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: serde::Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<Box<[T; N]>, D::Error> {
+        super::array::deserialize(deserializer).map(Box::new)
+    }
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    /// The state was saved with another cartridge
+    OtherRom,
+    DecodeError(bincode::Error),
+    IOError(io::Error),
+}
+
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidHeader => None,
+            Self::UnsupportedVersion(_) => None,
+            Self::OtherRom => None,
+            Self::DecodeError(err) => Some(err),
+            Self::IOError(err) => Some(err),
+        }
+    }
+}
+
+impl From<bincode::Error> for StateError {
+    fn from(value: bincode::Error) -> Self {
+        StateError::DecodeError(value)
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(value: io::Error) -> Self {
+        StateError::IOError(value)
+    }
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHeader => f.write_str("Not a save state"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            Self::OtherRom => f.write_str("The state was saved with another ROM"),
+            Self::DecodeError(err) => write!(f, "Invalid save state: {}", err),
+            Self::IOError(err) => write!(f, "IO Error {}", err),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use crate::palette::DmgPalette;
+
+/// Size in bytes of a 8x8 tile encoded in 2 bits per pixel
+pub const TILE_SIZE: usize = 16;
+
+/// RGBA8 image, row by row
+#[derive(Debug, PartialEq, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0xff; width * height * 4],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        self.pixels[offset..offset + 4].try_into().unwrap()
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let offset = (y * self.width + x) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)
+    }
+}
+
+/// Color index (0-3) of each pixel of a 2bpp tile, row by row.
+/// Missing bytes at the end of `data` are treated as 0.
+pub fn decode_tile(data: &[u8]) -> [[u8; 8]; 8] {
+    let mut tile = [[0; 8]; 8];
+    for (row, pixels) in tile.iter_mut().enumerate() {
+        // The first byte holds the low bit of each pixel and the second one
+        // the high bit, leftmost pixel in bit 7.
+        let low = data.get(row * 2).copied().unwrap_or(0);
+        let high = data.get(row * 2 + 1).copied().unwrap_or(0);
+        for (column, pixel) in pixels.iter_mut().enumerate() {
+            let bit = 7 - column;
+            *pixel = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+        }
+    }
+    tile
+}
+
+/// Render the 2bpp tiles in `data` into a sheet of `tiles_per_row` tiles per
+/// row, using the raw color indexes as shades (no palette applied).
+pub fn tile_sheet(data: &[u8], tiles_per_row: usize, palette: &DmgPalette) -> Image {
+    let tile_count = data.len().div_ceil(TILE_SIZE);
+    let rows = tile_count.div_ceil(tiles_per_row).max(1);
+    let mut image = Image::new(tiles_per_row * 8, rows * 8);
+
+    for (index, tile_data) in data.chunks(TILE_SIZE).enumerate() {
+        let left = (index % tiles_per_row) * 8;
+        let top = (index / tiles_per_row) * 8;
+        for (y, row) in decode_tile(tile_data).iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                image.set_pixel(left + x, top + y, palette.shade(*color));
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADES: [[u8; 4]; 4] = DmgPalette::GREY.shades;
+
+    #[test]
+    fn test_decode_tile() {
+        // Example from the pandocs
+        let data = [
+            0x3c, 0x7e, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7e, 0x5e, 0x7e, 0x0a, 0x7c, 0x56,
+            0x38, 0x7c,
+        ];
+        let tile = decode_tile(&data);
+        assert_eq!(tile[0], [0, 2, 3, 3, 3, 3, 2, 0]);
+        assert_eq!(tile[1], [0, 3, 0, 0, 0, 0, 3, 0]);
+        assert_eq!(tile[4], [0, 3, 1, 3, 3, 3, 3, 0]);
+        assert_eq!(tile[7], [0, 2, 3, 3, 3, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_tile_truncated() {
+        let tile = decode_tile(&[0xff]);
+        assert_eq!(tile[0], [1; 8]);
+        assert_eq!(tile[1], [0; 8]);
+    }
+
+    #[test]
+    fn test_tile_sheet_layout() {
+        let mut data = vec![0; TILE_SIZE * 3];
+        // Third tile is black
+        data[TILE_SIZE * 2..].fill(0xff);
+        let image = tile_sheet(&data, 2, &DmgPalette::GREY);
+        assert_eq!((image.width, image.height), (16, 16));
+        assert_eq!(image.pixel(0, 0), SHADES[0]);
+        assert_eq!(image.pixel(15, 7), SHADES[0]);
+        assert_eq!(image.pixel(0, 8), SHADES[3]);
+        assert_eq!(image.pixel(7, 15), SHADES[3]);
+        // Unused space at the end of the sheet
+        assert_eq!(image.pixel(8, 8), SHADES[0]);
+    }
+
+    #[test]
+    fn test_tile_sheet_empty() {
+        let image = tile_sheet(&[], 16, &DmgPalette::GREY);
+        assert_eq!((image.width, image.height), (128, 8));
+    }
+}
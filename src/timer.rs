@@ -0,0 +1,143 @@
+//! Divider and timer registers
+//! See https://gbdev.io/pandocs/Timer_and_Divider_Registers.html
+
+use crate::interrupts::Interrupt;
+
+/// Divider, the upper 8 bits of an internal 16-bit counter
+pub const DIV: u16 = 0xff04;
+/// Timer counter
+pub const TIMA: u16 = 0xff05;
+/// Timer modulo, loaded into TIMA when it overflows
+pub const TMA: u16 = 0xff06;
+/// Timer control
+pub const TAC: u16 = 0xff07;
+
+const TAC_ENABLE: u8 = 0x04;
+// Bit of the internal counter clocking TIMA on its falling edge, selected by
+// the lower 2 bits of TAC: 4096 Hz, 262144 Hz, 65536 Hz and 16384 Hz
+const TAC_BITS: [u16; 4] = [9, 3, 5, 7];
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the internal counter, DIV being its upper byte
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    /// State of the signal clocking TIMA
+    fn input(&self) -> bool {
+        self.tac & TAC_ENABLE != 0
+            && self.counter & (1 << TAC_BITS[(self.tac & 0x03) as usize]) != 0
+    }
+
+    /// Increment TIMA, and request the timer interrupt when it overflows
+    fn increment(&mut self) -> u8 {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        if overflow {
+            self.tima = self.tma;
+            Interrupt::Timer.mask()
+        } else {
+            self.tima = tima;
+            0
+        }
+    }
+
+    /// Advance by `cycles` T-cycles, returns the interrupts requested
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            let input = self.input();
+            self.counter = self.counter.wrapping_add(1);
+            if input && !self.input() {
+                interrupts |= self.increment();
+            }
+        }
+        interrupts
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            // The upper 5 bits are unused
+            TAC => 0xf8 | self.tac,
+            _ => 0xff,
+        }
+    }
+
+    /// Returns the interrupts requested by the write: resetting the counter
+    /// or changing TAC can produce a falling edge.
+    pub fn write(&mut self, addr: u16, value: u8) -> u8 {
+        let input = self.input();
+        match addr {
+            DIV => self.counter = 0,
+            TIMA => self.tima = value,
+            TMA => self.tma = value,
+            TAC => self.tac = value & 0x07,
+            _ => (),
+        }
+        if input && !self.input() {
+            self.increment()
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timer_div() {
+        let mut timer = Timer::new();
+        timer.tick(255);
+        assert_eq!(timer.read(DIV), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(DIV), 1);
+        timer.write(DIV, 0x12);
+        assert_eq!(timer.read(DIV), 0);
+    }
+
+    #[test]
+    fn test_timer_overflow() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0xfe);
+        timer.write(TIMA, 0xff);
+        // 262144 Hz, one increment every 16 T-cycles
+        timer.write(TAC, TAC_ENABLE | 0x01);
+        assert_eq!(timer.tick(15), 0);
+        assert_eq!(timer.tick(1), Interrupt::Timer.mask());
+        assert_eq!(timer.read(TIMA), 0xfe);
+        timer.tick(16);
+        assert_eq!(timer.read(TIMA), 0xff);
+
+        // Disabled
+        timer.write(TAC, 0x01);
+        timer.tick(64);
+        assert_eq!(timer.read(TIMA), 0xff);
+    }
+
+    #[test]
+    fn test_timer_div_reset_edge() {
+        let mut timer = Timer::new();
+        timer.write(TAC, TAC_ENABLE | 0x01);
+        timer.tick(8);
+        // Bit 3 of the counter goes from 1 to 0
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+}
@@ -0,0 +1,420 @@
+//! Terminal disassembler editing the annotation file: scroll the listing,
+//! turn instructions into data and back, name and comment the addresses.
+
+use std::collections::BTreeMap;
+use std::io::{self, stdout};
+use std::path::PathBuf;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::Paragraph;
+use ratatui::{Frame, Terminal};
+
+use crate::annotations::{Annotation, AnnotationError, DataKind, Purpose};
+use crate::decoder::Opcode;
+use crate::disassembler::{disassemble, Banks, Listing, Options};
+
+// Bytes shown on the lines of the data regions
+const DATA_PREVIEW: usize = 8;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum RowKind {
+    Section,
+    Label,
+    Code,
+    Data,
+}
+
+struct Row {
+    address: usize,
+    // Bytes of the instruction or of the data region, 0 for the others
+    len: usize,
+    kind: RowKind,
+    text: String,
+}
+
+/// Collects the lines of the listing
+#[derive(Default)]
+struct Rows {
+    rows: Vec<Row>,
+    banks: Banks,
+    // Address of the next item, for the sections which do not have one
+    next: usize,
+}
+
+impl Rows {
+    fn push(&mut self, address: usize, len: usize, kind: RowKind, text: String) {
+        self.rows.push(Row {
+            address,
+            len,
+            kind,
+            text,
+        });
+    }
+}
+
+fn suffix(goto: Option<&str>, comment: Option<&str>) -> String {
+    let mut text = String::new();
+    if let Some(goto) = goto {
+        text += &format!(" -> {}", goto);
+    }
+    if let Some(comment) = comment {
+        text += &format!(" ; {}", comment);
+    }
+    text
+}
+
+impl Listing for Rows {
+    fn start(&mut self, banks: Banks) -> io::Result<()> {
+        self.banks = banks;
+        Ok(())
+    }
+
+    fn section(&mut self, name: &str) -> io::Result<()> {
+        self.push(self.next, 0, RowKind::Section, format!("-- {} --", name));
+        Ok(())
+    }
+
+    fn label(&mut self, address: usize, name: &str, _sources: &[usize]) -> io::Result<()> {
+        self.push(address, 0, RowKind::Label, format!("{}:", name));
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        opcode: &Opcode,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let text = format!(
+            "    {} {}{}",
+            self.banks.format(address),
+            opcode,
+            suffix(goto, comment)
+        );
+        self.push(address, bytes.len(), RowKind::Code, text);
+        self.next = address + bytes.len();
+        Ok(())
+    }
+
+    fn data(
+        &mut self,
+        address: usize,
+        bytes: &[u8],
+        kind: DataKind,
+        goto: Option<&str>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let mut preview: Vec<String> = bytes
+            .iter()
+            .take(DATA_PREVIEW)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        if bytes.len() > DATA_PREVIEW {
+            preview.push(format!("... ({} bytes)", bytes.len()));
+        }
+        let text = format!(
+            "    {} {} {}{}",
+            self.banks.format(address),
+            kind.name(),
+            preview.join(" "),
+            suffix(goto, comment)
+        );
+        self.push(address, bytes.len(), RowKind::Data, text);
+        self.next = address + bytes.len();
+        Ok(())
+    }
+}
+
+struct App {
+    rom: Vec<u8>,
+    annotations: BTreeMap<usize, Vec<Annotation>>,
+    path: PathBuf,
+    rows: Vec<Row>,
+    cursor: usize,
+    // First row on the screen
+    top: usize,
+    // Annotation being typed
+    input: Option<(Purpose, String)>,
+    modified: bool,
+    status: String,
+}
+
+impl App {
+    fn new(rom: Vec<u8>, annotations: BTreeMap<usize, Vec<Annotation>>, path: PathBuf) -> Self {
+        let mut app = Self {
+            rom,
+            annotations,
+            path,
+            rows: Vec::new(),
+            cursor: 0,
+            top: 0,
+            input: None,
+            modified: false,
+            status: String::new(),
+        };
+        app.refresh();
+        app
+    }
+
+    // Disassemble again after a change of the annotations, staying on the
+    // same address
+    fn refresh(&mut self) {
+        let current = self
+            .rows
+            .get(self.cursor)
+            .map(|row| (row.address, row.kind));
+        let mut rows = Rows::default();
+        let options = Options {
+            auto_labels: true,
+            header: true,
+            ..Default::default()
+        };
+        if let Err(err) = disassemble(self.rom.clone(), &self.annotations, &options, &mut rows) {
+            self.status = format!("Error: {}", err);
+            return;
+        }
+        self.rows = rows.rows;
+        if let Some((address, kind)) = current {
+            self.cursor = self
+                .rows
+                .iter()
+                .position(|row| row.address == address && row.kind == kind)
+                .or_else(|| self.rows.iter().position(|row| row.address >= address))
+                .unwrap_or(0);
+        }
+    }
+
+    // The instruction or the data region of the current row
+    fn item(&self) -> Option<&Row> {
+        self.rows[self.cursor..]
+            .iter()
+            .find(|row| matches!(row.kind, RowKind::Code | RowKind::Data))
+    }
+
+    /// Replace the annotations of `purpose` at `address`, removing them if
+    /// `value` is empty
+    fn set(&mut self, address: usize, purpose: Purpose, value: &str) {
+        let annotations = self.annotations.entry(address).or_default();
+        annotations.retain(|annotation| annotation.purpose != purpose);
+        if !value.is_empty() {
+            annotations.push(Annotation {
+                location: address,
+                purpose,
+                value: value.to_string(),
+            });
+        }
+        if annotations.is_empty() {
+            self.annotations.remove(&address);
+        }
+        self.modified = true;
+        self.refresh();
+    }
+
+    /// Turn the instruction at the cursor into data, or the data back into
+    /// instructions
+    fn toggle_data(&mut self) {
+        let Some(row) = self.item() else {
+            return;
+        };
+        let (address, len, kind) = (row.address, row.len, row.kind);
+        let annotated = self
+            .annotations
+            .get(&address)
+            .is_some_and(|a| a.iter().any(|a| a.purpose == Purpose::Data));
+        match kind {
+            RowKind::Code => self.set(address, Purpose::Data, &format!("db 0x{:x}", len)),
+            _ if annotated => self.set(address, Purpose::Data, ""),
+            _ => self.status = "No D annotation here".to_string(),
+        }
+    }
+
+    // Current value of an annotation, to edit it
+    fn value(&self, address: usize, purpose: &Purpose) -> String {
+        self.annotations
+            .get(&address)
+            .and_then(|a| a.iter().find(|a| a.purpose == *purpose))
+            .map(|a| a.value.clone())
+            .unwrap_or_default()
+    }
+
+    fn save(&mut self) {
+        match Annotation::save_file(&self.annotations, &self.path) {
+            Ok(()) => {
+                self.modified = false;
+                self.status = format!("Saved {}", self.path.display());
+            }
+            Err(err) => self.status = format!("Error: {}", err),
+        }
+    }
+
+    /// Returns false to quit
+    fn handle_key(&mut self, code: KeyCode, page: usize) -> bool {
+        if let Some((purpose, mut text)) = self.input.take() {
+            match code {
+                KeyCode::Enter => {
+                    if let Some(address) = self.item().map(|row| row.address) {
+                        self.set(address, purpose, text.trim());
+                    }
+                }
+                KeyCode::Esc => (),
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.input = Some((purpose, text));
+                }
+                KeyCode::Char(c) => {
+                    text.push(c);
+                    self.input = Some((purpose, text));
+                }
+                _ => self.input = Some((purpose, text)),
+            }
+            return true;
+        }
+
+        self.status.clear();
+        let last = self.rows.len().saturating_sub(1);
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::PageDown => self.cursor = (self.cursor + page).min(last),
+            KeyCode::PageUp => self.cursor = self.cursor.saturating_sub(page),
+            KeyCode::Home | KeyCode::Char('g') => self.cursor = 0,
+            KeyCode::End | KeyCode::Char('G') => self.cursor = last,
+            KeyCode::Char('d') => self.toggle_data(),
+            KeyCode::Char('w') => self.save(),
+            KeyCode::Char(c @ ('l' | 'c' | 's')) => {
+                let purpose = match c {
+                    'l' => Purpose::Label,
+                    'c' => Purpose::Comment,
+                    _ => Purpose::Section,
+                };
+                if let Some(address) = self.item().map(|row| row.address) {
+                    self.input = Some((purpose.clone(), self.value(address, &purpose)));
+                }
+            }
+            _ => (),
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [listing, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.size());
+        let height = listing.height as usize;
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + height {
+            self.top = self.cursor + 1 - height;
+        }
+
+        let lines: Vec<Line> = self.rows[self.top.min(self.rows.len())..]
+            .iter()
+            .take(height)
+            .enumerate()
+            .map(|(index, row)| {
+                let mut style = match row.kind {
+                    RowKind::Section => Style::new().fg(Color::Yellow),
+                    RowKind::Label => Style::new().add_modifier(Modifier::BOLD),
+                    RowKind::Code => Style::new(),
+                    RowKind::Data => Style::new().fg(Color::Cyan),
+                };
+                if self.top + index == self.cursor {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                Line::styled(row.text.as_str(), style)
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), listing);
+
+        let text = match &self.input {
+            Some((purpose, text)) => format!("{:?}: {}_", purpose, text),
+            None if !self.status.is_empty() => self.status.clone(),
+            None => format!(
+                "{}{}  d: code/data  l: label  c: comment  s: section  w: write  q: quit",
+                self.path.display(),
+                if self.modified { " [modified]" } else { "" }
+            ),
+        };
+        frame.render_widget(
+            Paragraph::new(text).style(Style::new().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+}
+
+/// Run the disassembler of `rom` in the terminal, the annotations are read
+/// from `path` if it exists and written back to it
+pub fn run(rom: Vec<u8>, path: PathBuf) -> Result<(), crate::Error> {
+    let annotations = match Annotation::parse_file(&path) {
+        Ok(annotations) => annotations,
+        Err(AnnotationError::IOError(err)) if err.kind() == io::ErrorKind::NotFound => {
+            BTreeMap::new()
+        }
+        Err(err) => return Err(crate::Error::from(err).in_file(path)),
+    };
+    let mut app = App::new(rom, annotations, path);
+
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let result = (|| -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| app.draw(frame))?;
+            let page = terminal.size()?.height.saturating_sub(2) as usize;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.handle_key(key.code, page) {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tui_edit() {
+        // ld a 0x12, xor a, nop
+        let mut app = App::new(
+            vec![0x3e, 0x12, 0xaf, 0x00],
+            BTreeMap::new(),
+            PathBuf::new(),
+        );
+        assert_eq!(app.rows.len(), 3);
+
+        app.handle_key(KeyCode::Char('d'), 10);
+        assert_eq!(app.value(0, &Purpose::Data), "db 0x2");
+        assert_eq!(app.rows[0].kind, RowKind::Data);
+
+        app.handle_key(KeyCode::Down, 10);
+        app.handle_key(KeyCode::Char('l'), 10);
+        for c in "start".chars() {
+            app.handle_key(KeyCode::Char(c), 10);
+        }
+        app.handle_key(KeyCode::Enter, 10);
+        assert_eq!(app.value(2, &Purpose::Label), "start");
+        // The cursor stays on the instruction, below its new label
+        assert_eq!(app.rows[app.cursor].kind, RowKind::Code);
+        assert_eq!(app.rows[app.cursor - 1].text, "start:");
+        app.cursor = 0;
+        app.handle_key(KeyCode::Char('d'), 10);
+        assert!(!app.annotations.contains_key(&0));
+        assert!(app.modified);
+        assert_eq!(Annotation::to_config(&app.annotations), "0x0002 L start\n");
+    }
+}
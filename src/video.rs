@@ -0,0 +1,156 @@
+//! Animated GIFs of the screen. A frame of the Game Boy has at most 56
+//! colors, so the frames are encoded with their exact colors. The delays of
+//! a GIF are in hundredths of a second: they alternate to keep the pace of
+//! the 59.7 frames per second, and the identical frames are merged.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::ppu::{FRAMEBUFFER_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::rewind::FRAME_RATE;
+
+pub struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    // One frame of the emulator kept out of `every`, the browsers slow down
+    // the GIFs with delays shorter than 2 hundredths
+    every: usize,
+    // Frames pushed so far
+    frames: usize,
+    // Frame not written yet, it lasts as long as the next ones are identical,
+    // and the frame it started at
+    pending: Option<(Box<[u8; FRAMEBUFFER_SIZE]>, usize)>,
+}
+
+impl GifRecorder<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, every: usize) -> Result<Self, gif::EncodingError> {
+        Self::new(BufWriter::new(File::create(path)?), every)
+    }
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Record one frame out of `every` to `writer`, looping forever
+    pub fn new(writer: W, every: usize) -> Result<Self, gif::EncodingError> {
+        let mut encoder =
+            gif::Encoder::new(writer, SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(Self {
+            encoder,
+            every: every.max(1),
+            frames: 0,
+            pending: None,
+        })
+    }
+
+    /// Append a frame of the emulator, in RGBA8
+    pub fn push(&mut self, framebuffer: &[u8; FRAMEBUFFER_SIZE]) -> Result<(), gif::EncodingError> {
+        let frame = self.frames;
+        self.frames += 1;
+        if !frame.is_multiple_of(self.every) {
+            return Ok(());
+        }
+        match &self.pending {
+            Some((pending, _)) if **pending == *framebuffer => Ok(()),
+            _ => {
+                let previous = self.pending.replace((Box::new(*framebuffer), frame));
+                match previous {
+                    Some((pixels, start)) => self.write(&pixels, start, frame),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Write the last frame and return the writer
+    pub fn finish(mut self) -> Result<W, gif::EncodingError> {
+        if let Some((pixels, start)) = self.pending.take() {
+            let end = self.frames.max(start + self.every);
+            self.write(&pixels, start, end)?;
+        }
+        Ok(self.encoder.into_inner()?)
+    }
+
+    // The frame shown from the frame `start` of the emulator to `end`
+    fn write(
+        &mut self,
+        pixels: &[u8; FRAMEBUFFER_SIZE],
+        start: usize,
+        end: usize,
+    ) -> Result<(), gif::EncodingError> {
+        let hundredths = |frame: usize| (frame as f32 * 100.0 / FRAME_RATE).round() as usize;
+        let delay = (hundredths(end) - hundredths(start)).clamp(1, u16::MAX as usize) as u16;
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        let mut indices = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT);
+        for pixel in pixels.chunks(4) {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            let index = match palette.iter().position(|&other| other == color) {
+                Some(index) => index,
+                None => {
+                    palette.push(color);
+                    palette.len() - 1
+                }
+            };
+            indices.push(index);
+        }
+        let mut frame = if palette.len() <= 256 {
+            let indices: Vec<u8> = indices.into_iter().map(|index| index as u8).collect();
+            gif::Frame::from_palette_pixels(
+                SCREEN_WIDTH as u16,
+                SCREEN_HEIGHT as u16,
+                &indices,
+                palette.concat().as_slice(),
+                None,
+            )
+        } else {
+            // Blended by the filters of the PPU, the colors are quantized
+            let mut pixels = pixels.to_vec();
+            gif::Frame::from_rgba_speed(SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &mut pixels, 10)
+        };
+        frame.delay = delay;
+        self.encoder.write_frame(&frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framebuffer(shade: u8) -> Box<[u8; FRAMEBUFFER_SIZE]> {
+        let mut framebuffer = Box::new([0xff; FRAMEBUFFER_SIZE]);
+        for (i, pixel) in framebuffer.chunks_mut(4).enumerate() {
+            if i % 7 == 0 {
+                pixel[..3].fill(shade);
+            }
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn test_gif_recorder() {
+        let mut recorder = GifRecorder::new(Vec::new(), 2).unwrap();
+        // Frames 0 to 3 identical, then 4 and 5, then 6, of which 0, 2, 4
+        // and 6 are recorded
+        for shade in [0x00, 0x00, 0x00, 0x00, 0x55, 0x55, 0xaa] {
+            recorder.push(&framebuffer(shade)).unwrap();
+        }
+        let data = recorder.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(data.as_slice()).unwrap();
+        assert_eq!(
+            (decoder.width(), decoder.height()),
+            (SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16)
+        );
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        // 4 frames of the emulator last 6.7 hundredths, then 2 and 2
+        let delays: Vec<u16> = frames.iter().map(|(delay, _)| *delay).collect();
+        assert_eq!(delays, [7, 3, 3]);
+        for ((_, pixels), shade) in frames.iter().zip([0x00, 0x55, 0xaa]) {
+            assert_eq!(pixels.as_slice(), framebuffer(shade).as_slice());
+        }
+    }
+}
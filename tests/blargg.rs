@@ -0,0 +1,66 @@
+//! The test ROMs of Blargg, run without a window up to the result they print
+//! on the serial port. They are not distributed with the emulator: the tests
+//! are skipped unless GB_TEST_ROMS is the directory of
+//! https://github.com/retrio/gb-test-roms, or of the same layout.
+//! ```shell
+//! GB_TEST_ROMS=~/gb-test-roms cargo test --release --test blargg
+//! ```
+
+use std::path::PathBuf;
+
+use gb::serial::test_result;
+use gb::Emulator;
+
+// Two minutes of emulation, the slowest ROM takes about 30 seconds
+const MAX_FRAMES: usize = 7200;
+
+fn run(rom: &str) {
+    let Some(dir) = std::env::var_os("GB_TEST_ROMS") else {
+        eprintln!("Skipping {}, GB_TEST_ROMS is not set", rom);
+        return;
+    };
+    let path = PathBuf::from(dir).join(rom);
+    let rom = gb::rom::read(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let mut emulator = Emulator::new(rom, &Default::default());
+    let mut output = Vec::new();
+    for _ in 0..MAX_FRAMES {
+        emulator.run_frame();
+        output.extend(emulator.serial_output());
+        if test_result(&output).is_some() {
+            break;
+        }
+    }
+    assert_eq!(
+        test_result(&output),
+        Some(true),
+        "{}:\n{}",
+        path.display(),
+        String::from_utf8_lossy(&output)
+    );
+}
+
+macro_rules! blargg_tests {
+    ($($name:ident => $rom:expr),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                run($rom);
+            }
+        )*
+    };
+}
+
+blargg_tests!(
+    test_cpu_instrs_special => "cpu_instrs/individual/01-special.gb",
+    test_cpu_instrs_interrupts => "cpu_instrs/individual/02-interrupts.gb",
+    test_cpu_instrs_op_sp_hl => "cpu_instrs/individual/03-op sp,hl.gb",
+    test_cpu_instrs_op_r_imm => "cpu_instrs/individual/04-op r,imm.gb",
+    test_cpu_instrs_op_rp => "cpu_instrs/individual/05-op rp.gb",
+    test_cpu_instrs_ld_r_r => "cpu_instrs/individual/06-ld r,r.gb",
+    test_cpu_instrs_jumps => "cpu_instrs/individual/07-jr,jp,call,ret,rst.gb",
+    test_cpu_instrs_misc => "cpu_instrs/individual/08-misc instrs.gb",
+    test_cpu_instrs_op_r_r => "cpu_instrs/individual/09-op r,r.gb",
+    test_cpu_instrs_bit_ops => "cpu_instrs/individual/10-bit ops.gb",
+    test_cpu_instrs_op_a_hl => "cpu_instrs/individual/11-op a,(hl).gb",
+    test_instr_timing => "instr_timing/instr_timing.gb",
+);
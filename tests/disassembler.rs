@@ -0,0 +1,146 @@
+//! Listings of small hand-assembled programs, compared to the snapshots in
+//! `tests/snapshots`. After a deliberate change of the output, review and
+//! accept the new listings with
+//! ```shell
+//! cargo insta test --review --test disassembler
+//! ```
+
+use std::collections::BTreeMap;
+
+use gb::annotations::Annotation;
+use gb::disassembler::{
+    disassemble, JsonListing, Layout, Listing, Mode, Options, RgbdsListing, TextListing,
+};
+
+// A routine waiting for the VBlank, called in a loop, with some data after it
+const PROGRAM: &[u8] = &[
+    0x00, // NOP
+    0x31, 0xfe, 0xff, // LD SP,0xfffe
+    0xcd, 0x0b, 0x00, // CALL 0x000b
+    0x18, 0xfb, // JR -5
+    0x12, 0x34, // pointer
+    0x3e, 0x91, // LD A,0x91
+    0xe0, 0x40, // LDH (LCDC),A
+    0xf0, 0x44, // LDH A,(LY)
+    0xfe, 0x90, // CP 0x90
+    0x20, 0xfa, // JR NZ,-6
+    0xcb, 0x7f, // BIT 7,A
+    0xc9, // RET
+    0xd3, // invalid
+    b'H', b'e', b'l', b'l', b'o', // string
+    0x3c, 0x7e, 0x42, 0x42, 0x7e, 0x42, 0x42, 0x00, // tile
+    0x3c, 0x7e, 0x42, 0x42, 0x7e, 0x42, 0x42, 0x00,
+];
+
+const ANNOTATIONS: &str = "\
+0x0000 S Init
+0x0004 L loop
+0x0009 D dw 0x2
+0x0009 C pointer
+0x000b L wait_vblank
+0x000f C poll LY
+0x0019 D str 0x5
+0x001e D tiles 0x10
+";
+
+fn annotations(data: &str) -> BTreeMap<usize, Vec<Annotation>> {
+    Annotation::parse(data).unwrap()
+}
+
+fn listing<L: Listing>(
+    data: &[u8],
+    annotations: &BTreeMap<usize, Vec<Annotation>>,
+    options: &Options,
+    mut listing: L,
+    into_inner: impl FnOnce(L) -> Vec<u8>,
+) -> String {
+    disassemble(data.to_vec(), annotations, options, &mut listing).unwrap();
+    String::from_utf8(into_inner(listing)).unwrap()
+}
+
+fn text(data: &[u8], annotations: &str, options: &Options) -> String {
+    listing(
+        data,
+        &self::annotations(annotations),
+        options,
+        TextListing::new(Vec::new(), true),
+        TextListing::into_inner,
+    )
+}
+
+#[test]
+fn test_snapshot_text() {
+    insta::assert_snapshot!(text(PROGRAM, ANNOTATIONS, &Options::default()));
+}
+
+#[test]
+fn test_snapshot_labels_and_xrefs() {
+    let options = Options {
+        auto_labels: true,
+        xrefs: true,
+        ..Default::default()
+    };
+    let data = "0x0019 D str 0x5\n0x001e D tiles 0x10";
+    insta::assert_snapshot!(text(PROGRAM, data, &options));
+}
+
+#[test]
+fn test_snapshot_aligned() {
+    let output = listing(
+        PROGRAM,
+        &annotations(ANNOTATIONS),
+        &Options::default(),
+        TextListing::new(Vec::new(), true).with_layout(Layout::default()),
+        TextListing::into_inner,
+    );
+    insta::assert_snapshot!(output);
+}
+
+#[test]
+fn test_snapshot_recursive() {
+    // The pointer, the invalid opcode and the string are not reached
+    let options = Options {
+        mode: Mode::Recursive(vec![0]),
+        ..Default::default()
+    };
+    insta::assert_snapshot!(text(PROGRAM, "0x000b L wait_vblank", &options));
+}
+
+#[test]
+fn test_snapshot_rgbds() {
+    let output = listing(
+        PROGRAM,
+        &annotations(ANNOTATIONS),
+        &Options::default(),
+        RgbdsListing::new(Vec::new()),
+        RgbdsListing::into_inner,
+    );
+    insta::assert_snapshot!(output);
+}
+
+#[test]
+fn test_snapshot_json() {
+    let output = listing(
+        PROGRAM,
+        &annotations(ANNOTATIONS),
+        &Options::default(),
+        JsonListing::new(Vec::new()),
+        JsonListing::into_inner,
+    );
+    insta::assert_snapshot!(output);
+}
+
+#[test]
+fn test_snapshot_header() {
+    // Entry point jumping over the header to the program
+    let mut rom = vec![0; 0x150];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x134..0x138].copy_from_slice(b"SNAP");
+    rom.extend(&PROGRAM[..0x18]);
+    let options = Options {
+        start: 0x100,
+        header: true,
+        ..Default::default()
+    };
+    insta::assert_snapshot!(text(&rom, "0x0150 L main", &options));
+}
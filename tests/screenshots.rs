@@ -0,0 +1,200 @@
+//! Frames of small ROMs drawn without a window, compared to the reference
+//! PNGs of `tests/screenshots`. A failure saves the frame and a diff, the
+//! differing pixels in red, in the temporary directory. After a deliberate
+//! change of the rendering, write the new references with
+//! ```shell
+//! GB_UPDATE_SCREENSHOTS=1 cargo test --test screenshots
+//! ```
+//! dmg-acid2 is not distributed with the emulator: its test is skipped
+//! unless GB_DMG_ACID2 is a directory with `dmg-acid2.gb` from
+//! https://github.com/mattcurrie/dmg-acid2 and its `reference-dmg.png`.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use gb::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use gb::tiles::Image;
+use gb::Emulator;
+
+// Differing pixels listed in the report
+const MAX_PIXELS: usize = 10;
+
+/// RGBA8 pixels of a PNG file
+fn read_png(path: &Path) -> Image {
+    let file = File::open(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().unwrap();
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).unwrap();
+    let pixels = buffer[..info.buffer_size()]
+        .chunks(info.color_type.samples())
+        .flat_map(|pixel| match *pixel {
+            [grey] => [grey, grey, grey, 0xff],
+            [grey, alpha] => [grey, grey, grey, alpha],
+            [r, g, b] => [r, g, b, 0xff],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        })
+        .collect();
+    Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    }
+}
+
+/// Compare the last frame of `emulator` to the PNG at `reference`
+fn check_frame(emulator: &Emulator, reference: &Path) {
+    let frame = Image {
+        width: SCREEN_WIDTH,
+        height: SCREEN_HEIGHT,
+        pixels: emulator.framebuffer().to_vec(),
+    };
+    let expected = read_png(reference);
+    assert_eq!(
+        (expected.width, expected.height),
+        (frame.width, frame.height),
+        "{}",
+        reference.display()
+    );
+
+    let mut diff = Image::new(frame.width, frame.height);
+    let mut differing = Vec::new();
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let pixel = frame.pixel(x, y);
+            if pixel == expected.pixel(x, y) {
+                // Faded, the differences stand out
+                diff.set_pixel(x, y, pixel.map(|c| c / 4 + 0xbf));
+            } else {
+                diff.set_pixel(x, y, [0xff, 0, 0, 0xff]);
+                differing.push((x, y));
+            }
+        }
+    }
+    if differing.is_empty() {
+        return;
+    }
+    let name = reference.file_stem().unwrap().to_string_lossy();
+    let dir = std::env::temp_dir();
+    let (actual_path, diff_path) = (
+        dir.join(format!("{}-actual.png", name)),
+        dir.join(format!("{}-diff.png", name)),
+    );
+    frame.save_png(&actual_path).unwrap();
+    diff.save_png(&diff_path).unwrap();
+    let pixels: Vec<String> = differing
+        .iter()
+        .take(MAX_PIXELS)
+        .map(|&(x, y)| {
+            format!(
+                "({}, {}) {:02x?} instead of {:02x?}",
+                x,
+                y,
+                frame.pixel(x, y),
+                expected.pixel(x, y)
+            )
+        })
+        .collect();
+    panic!(
+        "{} pixels differ from {}, the frame is in {} and the diff in {}:\n{}",
+        differing.len(),
+        reference.display(),
+        actual_path.display(),
+        diff_path.display(),
+        pixels.join("\n")
+    );
+}
+
+fn run(rom: Vec<u8>, frames: usize) -> Emulator {
+    let mut emulator = Emulator::new(rom, &Default::default());
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+    emulator
+}
+
+/// Compare the last frame of `emulator` to `tests/screenshots/{name}.png`,
+/// or replace it with GB_UPDATE_SCREENSHOTS
+fn check_screenshot(emulator: &Emulator, name: &str) {
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/screenshots")
+        .join(format!("{}.png", name));
+    if std::env::var_os("GB_UPDATE_SCREENSHOTS").is_some() {
+        emulator.mmu().ppu().screenshot(&reference).unwrap();
+    } else {
+        check_frame(emulator, &reference);
+    }
+}
+
+// Turns the LCD off in VBlank, copies the 2 tiles at 0x0200 to tiles 1 and
+// 2, fills the background map with a checkerboard of tiles 0 and 1, and
+// sets BGP: LDH A,(LY); CP 0x90; JR C,-6; XOR A; LDH (LCDC),A;
+// LD HL,0x8010; LD DE,0x0200; LD B,0x20; CALL 0x0300; LD HL,0x9800;
+// LD A,L; SWAP A; RRCA; XOR L; AND 1; LD (HL+),A; LD A,H; CP 0x9c;
+// JR NZ,-13; LD A,0xe4; LDH (BGP),A
+const SETUP: &[u8] = &[
+    0xf0, 0x44, 0xfe, 0x90, 0x38, 0xfa, 0xaf, 0xe0, 0x40, 0x21, 0x10, 0x80, 0x11, 0x00, 0x02, 0x06,
+    0x20, 0xcd, 0x00, 0x03, 0x21, 0x00, 0x98, 0x7d, 0xcb, 0x37, 0x0f, 0xad, 0xe6, 0x01, 0x22, 0x7c,
+    0xfe, 0x9c, 0x20, 0xf3, 0x3e, 0xe4, 0xe0, 0x47,
+];
+
+// Copies B bytes from DE to HL: LD A,(DE); LD (HL+),A; INC DE; DEC B;
+// JR NZ,-6; RET
+const COPY: &[u8] = &[0x1a, 0x22, 0x13, 0x05, 0x20, 0xfa, 0xc9];
+
+// A ring in the 4 shades, and an arrow
+const TILES: &[u8] = &[
+    0x3c, 0x3c, 0x42, 0x7e, 0x81, 0xff, 0xa5, 0xdb, 0x81, 0xff, 0x99, 0xe7, 0x42, 0x7e, 0x3c, 0x3c,
+    0x18, 0x18, 0x3c, 0x3c, 0x7e, 0x7e, 0xff, 0xff, 0x18, 0x00, 0x18, 0x00, 0x18, 0x00, 0x18, 0x00,
+];
+
+/// ROM running `program` after `SETUP`, with the tiles and `data` at 0x0220
+fn rom(program: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    // NOP; JP 0x0150
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    let code = [SETUP, program].concat();
+    rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+    rom[0x200..0x200 + TILES.len()].copy_from_slice(TILES);
+    rom[0x220..0x220 + data.len()].copy_from_slice(data);
+    rom[0x300..0x300 + COPY.len()].copy_from_slice(COPY);
+    rom
+}
+
+#[test]
+fn test_screenshot_background() {
+    // LD A,0x91; LDH (LCDC),A; JR -2
+    let rom = rom(&[0x3e, 0x91, 0xe0, 0x40, 0x18, 0xfe], &[]);
+    check_screenshot(&run(rom, 10), "background");
+}
+
+#[test]
+fn test_screenshot_sprites_and_window() {
+    // LD HL,0xfe00; LD DE,0x0220; LD B,8; CALL 0x0300; WY 40, WX 87, SCX 4,
+    // SCY 2, OBP0 0xe4, OBP1 0x9c; LDH (LCDC) with the window and the
+    // sprites; JR -2
+    let program = [
+        0x21, 0x00, 0xfe, 0x11, 0x20, 0x02, 0x06, 0x08, 0xcd, 0x00, 0x03, 0x3e, 0x28, 0xe0, 0x4a,
+        0x3e, 0x57, 0xe0, 0x4b, 0x3e, 0x04, 0xe0, 0x43, 0x3e, 0x02, 0xe0, 0x42, 0x3e, 0xe4, 0xe0,
+        0x48, 0x3e, 0x9c, 0xe0, 0x49, 0x3e, 0xf3, 0xe0, 0x40, 0x18, 0xfe,
+    ];
+    // The arrow at (50, 60), and at (100, 64) upside down with OBP1 over the
+    // window
+    let oam = [76, 58, 2, 0x00, 80, 108, 2, 0x50];
+    let rom = rom(&program, &oam);
+    check_screenshot(&run(rom, 10), "sprites_and_window");
+}
+
+#[test]
+fn test_screenshot_dmg_acid2() {
+    let Some(dir) = std::env::var_os("GB_DMG_ACID2") else {
+        eprintln!("Skipping dmg-acid2, GB_DMG_ACID2 is not set");
+        return;
+    };
+    let dir = PathBuf::from(dir);
+    let path = dir.join("dmg-acid2.gb");
+    let rom = gb::rom::read(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    check_frame(&run(rom, 60), &dir.join("reference-dmg.png"));
+}
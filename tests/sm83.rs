@@ -0,0 +1,161 @@
+//! The single instruction tests of the SM83: for each opcode, thousands of
+//! vectors giving the registers and the memory before and after the
+//! instruction, and the bus activity of each M-cycle. They run on a flat
+//! memory without any hardware. They are not distributed with the emulator:
+//! the test is skipped unless GB_SM83_TESTS is the directory of the JSON
+//! files of https://github.com/SingleStepTests/sm83 (v1).
+//! ```shell
+//! GB_SM83_TESTS=~/sm83/v1 cargo test --release --test sm83
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gb::cpu::{Cpu, Registers};
+use gb::mmu::{Access, Mmu};
+use serde_json::Value;
+
+// Failures printed, most come from a few opcodes
+const MAX_FAILURES: usize = 20;
+
+fn number(state: &Value, key: &str) -> u16 {
+    state[key]
+        .as_u64()
+        .unwrap_or_else(|| panic!("Missing {} in {}", key, state)) as u16
+}
+
+fn registers(state: &Value) -> Registers {
+    let r8 = |key| number(state, key) as u8;
+    Registers {
+        a: r8("a"),
+        f: r8("f"),
+        b: r8("b"),
+        c: r8("c"),
+        d: r8("d"),
+        e: r8("e"),
+        h: r8("h"),
+        l: r8("l"),
+        sp: number(state, "sp"),
+        pc: number(state, "pc"),
+    }
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|entry| match (entry[0].as_u64(), entry[1].as_u64()) {
+            (Some(addr), Some(value)) => (addr as u16, value as u8),
+            _ => panic!("Invalid RAM entry {}", entry),
+        })
+        .collect()
+}
+
+/// Reads and writes expected, the internal M-cycles are null
+fn bus_activity(cycles: &[Value]) -> Vec<Access> {
+    cycles
+        .iter()
+        .filter_map(|cycle| {
+            let addr = cycle[0].as_u64()? as u16;
+            let value = cycle[1].as_u64()? as u8;
+            match cycle[2].as_str()? {
+                kind if kind.contains('w') => Some(Access::Write(addr, value)),
+                kind if kind.contains('r') => Some(Access::Read(addr, value)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Run the vector, returns what differs from the final state expected
+fn run(vector: &Value) -> Option<String> {
+    let (initial, expected) = (&vector["initial"], &vector["final"]);
+    let mut mmu = Mmu::flat();
+    for (addr, value) in ram(initial) {
+        mmu.write(addr, value);
+    }
+    let mut cpu = Cpu::new(registers(initial));
+    cpu.set_ime(number(initial, "ime") != 0);
+
+    mmu.set_tracing(true);
+    let cycles = cpu.step(&mut mmu);
+    mmu.set_tracing(false);
+    let mut accesses = Vec::new();
+    mmu.drain_accesses(&mut accesses);
+
+    let mut errors = Vec::new();
+    if *cpu.registers() != registers(expected) {
+        errors.push(format!(
+            "registers {:x?}, expected {:x?}",
+            cpu.registers(),
+            registers(expected)
+        ));
+    }
+    if cpu.ime() != (number(expected, "ime") != 0) {
+        errors.push(format!("IME {}", cpu.ime()));
+    }
+    for (addr, value) in ram(expected) {
+        if mmu.peek(addr) != value {
+            errors.push(format!(
+                "{:04x} is {:02x}, expected {:02x}",
+                addr,
+                mmu.peek(addr),
+                value
+            ));
+        }
+    }
+    let expected_cycles = vector["cycles"].as_array().map_or(&[][..], Vec::as_slice);
+    if cycles != expected_cycles.len() as u32 * 4 {
+        errors.push(format!(
+            "{} T-cycles, expected {}",
+            cycles,
+            expected_cycles.len() * 4
+        ));
+    }
+    if accesses != bus_activity(expected_cycles) {
+        errors.push(format!(
+            "bus activity {:x?}, expected {:x?}",
+            accesses,
+            bus_activity(expected_cycles)
+        ));
+    }
+    (!errors.is_empty()).then(|| errors.join(", "))
+}
+
+fn run_file(path: &Path, failures: &mut Vec<String>) -> usize {
+    let data = fs::read(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    let vectors: Vec<Value> =
+        serde_json::from_slice(&data).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+    for vector in &vectors {
+        if let Some(error) = run(vector) {
+            failures.push(format!("{}: {}", vector["name"], error));
+        }
+    }
+    vectors.len()
+}
+
+#[test]
+fn test_sm83() {
+    let Some(dir) = std::env::var_os("GB_SM83_TESTS") else {
+        eprintln!("Skipping the SM83 tests, GB_SM83_TESTS is not set");
+        return;
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("{}: {}", PathBuf::from(&dir).display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No JSON file in {:?}", dir);
+
+    let mut failures = Vec::new();
+    let total: usize = paths.iter().map(|path| run_file(path, &mut failures)).sum();
+    assert!(
+        failures.is_empty(),
+        "{} of {} vectors failed:\n{}",
+        failures.len(),
+        total,
+        failures[..failures.len().min(MAX_FAILURES)].join("\n")
+    );
+}